@@ -32,22 +32,77 @@ mod app {
 #[cfg(feature="nrf5x")]
     type Tonic = crate::nrf5x::MonotonicRtc<crate::pac::RTC0>;
 
-    use embedded_ble::{Ble, link_layer, gap};
+    use embedded_ble::{Ble, link_layer, gap, att, gatt, gatt_client, smp, privacy};
 
 // choose the hardware controller
 #[cfg(feature="nrf5x")]
     use embedded_ble::nrf5x as HCI;
 
+    /// the interval of the advertising events (also times the address rotation)
+    const ADV_INTERVAL_MS: u32 = 3000;
+
+    // battery service
+    const BATTERY_LEVEL_HANDLE: att::Handle = gatt::APPLICATION_HANDLE_START + 2;
+    embedded_ble::attribute_table! {
+        const ATTRIBUTES = [
+            primary_service(att::Uuid::Uuid16(0x180F)),
+            characteristic(att::Uuid::Uuid16(0x2A19), gatt::PROPERTY_READ | gatt::PROPERTY_NOTIFY, att::PERMISSION_READ, &[100]),
+            client_characteristic_configuration(),
+        ];
+    }
+
+    /// provides the (dynamic) battery level
+    pub struct Battery {
+        level: u8,
+        rng: crate::pac::RNG,
+    }
+    impl gatt::CharacteristicHandler for Battery {
+        fn read(&mut self, handle: att::Handle, offset: usize, buffer: &mut [u8]) -> Option<Result<usize, att::ErrorCode>> {
+            match handle {
+                BATTERY_LEVEL_HANDLE => Some(att::read_value(&[self.level], offset, buffer)),
+                _ => None,
+            }
+        }
+
+        fn configuration_changed(&mut self, value_handle: att::Handle, notify: bool, _indicate: bool) {
+            rprintln!("characteristic {} notify {}", value_handle, notify);
+        }
+    }
+    impl smp::PairingHandler for Battery {
+        fn random(&mut self, bytes: &mut [u8]) {
+            // bias corrected hardware random numbers
+            self.rng.config.write(|w| w.dercen().enabled());
+            self.rng.tasks_start.write(|w| unsafe { w.bits(1) });
+            for byte in bytes.iter_mut() {
+                while self.rng.events_valrdy.read().bits() == 0 {}
+                self.rng.events_valrdy.write(|w| unsafe { w.bits(0) });
+                *byte = self.rng.value.read().value().bits();
+            }
+            self.rng.tasks_stop.write(|w| unsafe { w.bits(1) });
+        }
+
+        fn pairing_complete(&mut self, keys: &smp::Keys) {
+            rprintln!("paired (bonded {})", keys.bonded);
+        }
+
+        fn pairing_failed(&mut self, failure: smp::Failure) {
+            rprintln!("pairing failed {:?}", failure);
+        }
+    }
+    /// no client procedures are started
+    impl gatt_client::ClientHandler for Battery {}
+
     #[shared]
     struct Shared {
         ble: Ble<'static>,
+        battery: Battery,
     }
 
     #[local]
     struct Local {
     }
 
-    #[init]
+    #[init(local=[bond_storage: Option<HCI::Nrf5xFlashStorage> = None])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         rtt_init_print!();
         rprintln!("init");
@@ -56,9 +111,26 @@ mod app {
 #[cfg(feature="nrf5x")]
         crate::nrf5x::init_clocks(cx.device.CLOCK);
 
+// the identity root of the device is its IRK (the same across reboots for bonded peers)
+#[cfg(feature="nrf5x")]
+        let mut irk = [0; 16];
+#[cfg(feature="nrf5x")]
+        for (index, word) in cx.device.FICR.ir.iter().enumerate() {
+            irk[(4 * index)..(4 * index + 4)].copy_from_slice(&word.read().bits().to_le_bytes());
+        }
+
 // initialize HCI
 #[cfg(feature="nrf5x")]
-        let hci = HCI::Nrf5xHci::new(cx.device.RADIO, HCI::RadioMode::Ble1Mbit, cx.device.FICR);
+        let mut hci = HCI::Nrf5xHci::new(cx.device.RADIO, HCI::RadioMode::Ble1Mbit, cx.device.FICR);
+// encrypt links in hardware
+#[cfg(feature="nrf5x")]
+        hci.enable_ccm(cx.device.CCM);
+// resolve the private addresses of bonded peers in hardware
+#[cfg(feature="nrf5x")]
+        hci.enable_aar(cx.device.AAR);
+// time connection events (detecting missed events and supervision timeouts)
+#[cfg(feature="nrf5x")]
+        hci.enable_timer(cx.device.TIMER0);
 
         // create the BLE instance
         let info = gap::AdFields {
@@ -66,13 +138,24 @@ mod app {
             flags: Some(gap::FLAGS_LE_GENERAL_DISCOVERABLE | gap::FLAGS_BR_EDR_NOT_SUPPORTED),
             ..gap::AdFields::default()
         };
-        let ble = Ble::new(hci, info);
+        let mut ble = Ble::with_attributes(hci, info, ATTRIBUTES);
+        // Just Works pairing (no display or keyboard)
+        ble.set_pairing(smp::Config::default());
+        let mut battery = Battery{ level: 100, rng: cx.device.RNG };
+        // advertise with resolvable private addresses (rotated every privacy::RPA_TIMEOUT_MS of the
+        // advertising events below)
+#[cfg(feature="nrf5x")]
+        ble.set_privacy(irk, &mut battery);
+        ble.set_address_timeout(privacy::RPA_TIMEOUT_MS, ADV_INTERVAL_MS);
+// persist bonds in the last flash pages (of the 256K xxAB package)
+#[cfg(feature="nrf5x")]
+        ble.set_bond_storage(cx.local.bond_storage.insert(HCI::Nrf5xFlashStorage::new(cx.device.NVMC, 0x4_0000 - 0x4000)));
 
         // upon rtic start, begin advertising
         ble_advertiser::spawn().unwrap();
 
         // return rtic values
-        (Shared { ble, },
+        (Shared { ble, battery },
          Local { },
 #[cfg(feature="nrf5x")]
          init::Monotonics(crate::nrf5x::MonotonicRtc::new(cx.device.RTC0)))
@@ -101,30 +184,29 @@ mod app {
             }
         });
         // continue advertisement forever
-        ble_advertiser::spawn_after(3.secs()).unwrap();
+        ble_advertiser::spawn_after((ADV_INTERVAL_MS as u64).millis()).unwrap();
     }
 
     // schedule RADIO for **highest** priority
     #[task(binds=RADIO, shared=[ble], priority=8)]
     fn ble_handler(mut cx:ble_handler::Context) {
         cx.shared.ble.lock(|ble| {
-            ble.handle_packet();
-            // let has_work = ble.radio_event();
-            // if has_work {
-            //     ble_worker::spawn().ok();
-            // }
+            let has_work = ble.handle_packet();
+            if has_work {
+                ble_worker::spawn().ok();
+            }
         });
     }
 
     // schedule for high priority (apps responsive to state changes)
-    // #[task(shared=[ble], priority=7)]
-    // fn ble_worker(mut cx:ble_worker::Context) {
-    //     cx.shared.ble.lock(|_ble| {
-    //         rprintln!("ble work...");
-    //         // ble.work();
-    //         rprintln!("ble work done");
-    //     });
-    // }
+    #[task(shared=[ble, battery], priority=7)]
+    fn ble_worker(cx:ble_worker::Context) {
+        (cx.shared.ble, cx.shared.battery).lock(|ble, battery| {
+            rprintln!("ble work...");
+            ble.work(battery);
+            rprintln!("ble work done");
+        });
+    }
 }
 
 /// nrf5x support --------------------------------------------------
@@ -0,0 +1,1032 @@
+use num_enum::{TryFromPrimitive};
+use core::convert::TryFrom;
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+
+/// Core_v5.3 Vol 3, Part F, 3.2.8 (default ATT_MTU for LE)
+pub const ATT_MTU_MIN:u16 = 23;
+/// largest ATT_MTU supported - LL payload (251) less the L2CAP header (4)
+pub const ATT_MTU_MAX:u16 = 247;
+/// a transaction (i.e. an indication) fails if not completed within 30s
+/// Core_v5.3 Vol 3, Part F, 3.3.3
+pub const ATT_TIMEOUT_MS:u32 = 30_000;
+
+/// Core_v5.3 Vol 3, Part F, 3.2.2
+pub type Handle = u16;
+pub const HANDLE_MIN:Handle = 0x0001;
+pub const HANDLE_MAX:Handle = 0xFFFF;
+
+/// Core_v5.3 Vol 3, Part B, 2.5.1
+const BLUETOOTH_BASE_UUID:u128 = 0x00000000_0000_1000_8000_00805F9B34FB;
+
+/// Core_v5.3 Vol 3, Part F, 3.2.1
+#[derive(Copy, Clone, Debug)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128(u128),
+}
+impl Uuid {
+    /// the full 128-bit form (16-bit UUIDs are aliases into the Bluetooth Base UUID)
+    pub fn to_u128(&self) -> u128 {
+        match self {
+            Uuid::Uuid16(uuid) => BLUETOOTH_BASE_UUID | ((*uuid as u128) << 96),
+            Uuid::Uuid128(uuid) => *uuid,
+        }
+    }
+
+    /// number of octets used on air
+    pub const fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(..) => 2,
+            Uuid::Uuid128(..) => 16,
+        }
+    }
+
+    /// a UUID always occupies octets on air
+    pub const fn is_empty(&self) -> bool { false }
+
+    /// write the UUID (little endian), returns the number of octets written
+    pub fn write(&self, buffer: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => buffer[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => buffer[..16].copy_from_slice(&uuid.to_le_bytes()),
+        }
+        return self.len();
+    }
+
+    /// parse an on-air UUID (only 2 and 16 octet UUIDs are valid in ATT)
+    pub fn from_slice(bytes: &[u8]) -> Option<Uuid> {
+        match bytes.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(bytes);
+                Some(Uuid::Uuid128(u128::from_le_bytes(uuid)))
+            }
+            _ => None,
+        }
+    }
+}
+impl PartialEq for Uuid {
+    fn eq(&self, other: &Self) -> bool { self.to_u128() == other.to_u128() }
+}
+impl Eq for Uuid {}
+
+/// GATT declarations used by ATT for grouping (Core_v5.3 Vol 3, Part G, 3.1)
+pub const PRIMARY_SERVICE_UUID:Uuid = Uuid::Uuid16(0x2800);
+pub const SECONDARY_SERVICE_UUID:Uuid = Uuid::Uuid16(0x2801);
+
+/// attribute permissions (Core_v5.3 Vol 3, Part F, 3.2.5)
+pub const PERMISSION_READ:u8                    = 1 << 0;
+pub const PERMISSION_WRITE:u8                   = 1 << 1;
+pub const PERMISSION_READ_ENCRYPTED:u8          = 1 << 2;
+pub const PERMISSION_READ_AUTHENTICATED:u8      = 1 << 3;
+pub const PERMISSION_WRITE_ENCRYPTED:u8         = 1 << 4;
+pub const PERMISSION_WRITE_AUTHENTICATED:u8     = 1 << 5;
+pub const PERMISSION_AUTHORIZATION:u8           = 1 << 6;
+/// allow Signed Write Command (Core_v5.3 Vol 3, Part F, 3.4.5.4)
+pub const PERMISSION_SIGNED_WRITE:u8            = 1 << 7;
+
+/// security of the link the ATT bearer is running on
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum SecurityLevel {
+    NoSecurity,
+    /// encrypted with an unauthenticated key
+    Encrypted,
+    /// encrypted with an authenticated (MITM protected) key
+    Authenticated,
+}
+
+#[derive(Debug, TryFromPrimitive, Copy, Clone, PartialEq)]
+#[repr(u8)]
+/// Core_v5.3 Vol 3, Part F, 3.4.8
+pub enum Opcode {
+    ErrorRsp                    = 0x01,
+    ExchangeMtuReq              = 0x02,
+    ExchangeMtuRsp              = 0x03,
+    FindInformationReq          = 0x04,
+    FindInformationRsp          = 0x05,
+    FindByTypeValueReq          = 0x06,
+    FindByTypeValueRsp          = 0x07,
+    ReadByTypeReq               = 0x08,
+    ReadByTypeRsp               = 0x09,
+    ReadReq                     = 0x0A,
+    ReadRsp                     = 0x0B,
+    ReadBlobReq                 = 0x0C,
+    ReadBlobRsp                 = 0x0D,
+    ReadMultipleReq             = 0x0E,
+    ReadMultipleRsp             = 0x0F,
+    ReadByGroupTypeReq          = 0x10,
+    ReadByGroupTypeRsp          = 0x11,
+    WriteReq                    = 0x12,
+    WriteRsp                    = 0x13,
+    PrepareWriteReq             = 0x16,
+    PrepareWriteRsp             = 0x17,
+    ExecuteWriteReq             = 0x18,
+    ExecuteWriteRsp             = 0x19,
+    ReadMultipleVariableReq     = 0x20,
+    ReadMultipleVariableRsp     = 0x21,
+    MultipleHandleValueNtf      = 0x23,
+    HandleValueNtf              = 0x1B,
+    HandleValueInd              = 0x1D,
+    HandleValueCfm              = 0x1E,
+    WriteCmd                    = 0x52,
+    SignedWriteCmd              = 0xD2,
+}
+impl Opcode {
+    /// Core_v5.3 Vol 3, Part F, 3.3.1 (command flag)
+    const COMMAND_FLAG:u8 = 1 << 6;
+
+    /// responses, notifications and indications (the odd opcodes) are sent by servers, the other PDUs
+    /// by clients
+    pub fn is_from_server(opcode: u8) -> bool { opcode & 1 == 1 }
+}
+
+#[derive(Debug, TryFromPrimitive, Copy, Clone, PartialEq)]
+#[repr(u8)]
+/// Core_v5.3 Vol 3, Part F, 3.4.1.1
+pub enum ErrorCode {
+    InvalidHandle                   = 0x01,
+    ReadNotPermitted                = 0x02,
+    WriteNotPermitted               = 0x03,
+    InvalidPdu                      = 0x04,
+    InsufficientAuthentication      = 0x05,
+    RequestNotSupported             = 0x06,
+    InvalidOffset                   = 0x07,
+    InsufficientAuthorization       = 0x08,
+    PrepareQueueFull                = 0x09,
+    AttributeNotFound               = 0x0A,
+    AttributeNotLong                = 0x0B,
+    EncryptionKeySizeTooShort       = 0x0C,
+    InvalidAttributeValueLength     = 0x0D,
+    UnlikelyError                   = 0x0E,
+    InsufficientEncryption          = 0x0F,
+    UnsupportedGroupType            = 0x10,
+    InsufficientResources           = 0x11,
+    DatabaseOutOfSync               = 0x12,
+    ValueNotAllowed                 = 0x13,
+    /// first of the application error codes (0x80 - 0x9F)
+    ApplicationError                = 0x80,
+    /// Core Specification Supplement Part B, 1.2 (Common Profile and Service Error Codes)
+    CccdImproperlyConfigured        = 0xFD,
+    ProcedureAlreadyInProgress      = 0xFE,
+    OutOfRange                      = 0xFF,
+}
+
+/// an entry in the attribute database
+pub struct Attribute<'a> {
+    pub handle: Handle,
+    pub uuid: Uuid,
+    /// see PERMISSION_* constants
+    pub permissions: u8,
+    /// static value (dynamic values are provided by AttributeDatabase::read)
+    pub value: &'a [u8],
+}
+
+/// the attributes served over ATT
+pub trait AttributeDatabase {
+    /// all attributes, ordered by increasing handle
+    fn attributes(&self) -> &[Attribute<'_>];
+
+    /// copy the value (starting at offset) into buffer, returns the number of octets copied
+    fn read(&mut self, handle: Handle, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        return match self.attributes().iter().find(|a| a.handle == handle) {
+            Some(attribute) => read_value(attribute.value, offset, buffer),
+            None => Err(ErrorCode::InvalidHandle),
+        };
+    }
+
+    /// write the value starting at offset (static values can't be written)
+    fn write(&mut self, _handle: Handle, _offset: usize, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::WriteNotPermitted)
+    }
+}
+impl<'a> AttributeDatabase for [Attribute<'a>] {
+    fn attributes(&self) -> &[Attribute<'_>] { self }
+}
+impl<'a, const N:usize> AttributeDatabase for [Attribute<'a>; N] {
+    fn attributes(&self) -> &[Attribute<'_>] { self }
+}
+
+/// copy a value (starting at offset) into buffer, returns the number of octets copied
+pub fn read_value(value: &[u8], offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+    if offset > value.len() {
+        return Err(ErrorCode::InvalidOffset);
+    }
+    let size = core::cmp::min(value.len() - offset, buffer.len());
+    buffer[..size].copy_from_slice(&value[offset..(offset + size)]);
+    return Ok(size);
+}
+
+/// storage for queued (prepared) writes
+const PREPARE_QUEUE_SIZE:usize = 256;
+/// prepared write entry header (handle:2, offset:2, length:1)
+const PREPARE_ENTRY_HEADER_SIZE:usize = 5;
+/// signature appended to a Signed Write Command (sign counter:4, MAC:8)
+const SIGNATURE_SIZE:usize = 12;
+
+/// Attribute Protocol server (one per ATT bearer)
+/// Core_v5.3 Vol 3, Part F
+pub struct AttServer {
+    mtu: u16,
+    /// Enhanced ATT bearer (the ATT_MTU is that of the L2CAP channel)
+    enhanced: bool,
+    security: SecurityLevel,
+    authorized: bool,
+    prepare_queue: [u8; PREPARE_QUEUE_SIZE],
+    prepare_queue_len: usize,
+    indication_pending: bool,
+    /// peer's Connection Signature Resolving Key (for Signed Write Command)
+    signing_key: Option<[u8;16]>,
+    sign_counter: Option<u32>,
+}
+impl Default for AttServer {
+    fn default() -> Self { Self::new() }
+}
+impl AttServer {
+    pub fn new() -> Self {
+        Self {
+            mtu: ATT_MTU_MIN,
+            enhanced: false,
+            security: SecurityLevel::NoSecurity,
+            authorized: false,
+            prepare_queue: [0; PREPARE_QUEUE_SIZE],
+            prepare_queue_len: 0,
+            indication_pending: false,
+            signing_key: None,
+            sign_counter: None,
+        }
+    }
+
+    /// server of an Enhanced ATT bearer (over an L2CAP credit based channel)
+    /// Core_v5.3 Vol 3, Part F, 3.2.11
+    pub fn enhanced(mtu: u16) -> Self {
+        Self {
+            mtu: mtu.clamp(ATT_MTU_MIN, ATT_MTU_MAX),
+            enhanced: true,
+            ..Self::new()
+        }
+    }
+
+    /// the current ATT_MTU
+    pub fn mtu(&self) -> u16 { self.mtu }
+
+    /// update the ATT_MTU (as exchanged by the client of this device, the bearer has a single ATT_MTU)
+    pub fn set_mtu(&mut self, mtu: u16) { self.mtu = mtu.clamp(ATT_MTU_MIN, ATT_MTU_MAX); }
+
+    /// true if serving an Enhanced ATT bearer
+    pub fn is_enhanced(&self) -> bool { self.enhanced }
+
+    /// update the security of the underlying link
+    pub fn set_security(&mut self, security: SecurityLevel) { self.security = security; }
+
+    /// grant (or revoke) access to attributes requiring authorization
+    pub fn set_authorized(&mut self, authorized: bool) { self.authorized = authorized; }
+
+    /// set the peer's CSRK (little endian, as distributed by SMP) and its last known sign counter
+    pub fn set_signing_key(&mut self, csrk: [u8;16], sign_counter: Option<u32>) {
+        self.signing_key = Some(csrk);
+        self.sign_counter = sign_counter;
+    }
+
+    /// the last sign counter accepted from the peer (to be persisted when bonded)
+    pub fn sign_counter(&self) -> Option<u32> { self.sign_counter }
+
+    /// true while an indication is awaiting its confirmation
+    pub fn is_indication_pending(&self) -> bool { self.indication_pending }
+
+    /// handle a request from the client, returns the size of the response (0 if none)
+    /// Core_v5.3 Vol 3, Part F, 3.4
+    pub fn handle<D: AttributeDatabase + ?Sized>(&mut self, db: &mut D, request: &[u8], response: &mut [u8]) -> usize
+    {
+        if request.is_empty() {
+            return 0;
+        }
+        // limit responses to the ATT_MTU
+        let mtu = core::cmp::min(self.mtu as usize, response.len());
+        let response = &mut response[..mtu];
+        // PDUs exceeding the ATT_MTU are invalid (commands are dropped)
+        if request.len() > mtu {
+            if (request[0] & Opcode::COMMAND_FLAG) != 0 {
+                return 0;
+            }
+            return Self::error(request[0], 0, ErrorCode::InvalidPdu, response);
+        }
+
+        let opcode = match Opcode::try_from(request[0]) {
+            Ok(opcode) => opcode,
+            Err(_) => {
+                // unknown commands are ignored, unknown requests are rejected
+                if (request[0] & Opcode::COMMAND_FLAG) != 0 {
+                    return 0;
+                }
+                return Self::error(request[0], 0, ErrorCode::RequestNotSupported, response);
+            }
+        };
+
+        let result = match opcode {
+            Opcode::ExchangeMtuReq => self.exchange_mtu(request, response),
+            Opcode::FindInformationReq => self.find_information(db, request, response),
+            Opcode::FindByTypeValueReq => self.find_by_type_value(db, request, response),
+            Opcode::ReadByTypeReq => self.read_by_type(db, request, response),
+            Opcode::ReadReq => self.read(db, request, response),
+            Opcode::ReadBlobReq => self.read_blob(db, request, response),
+            Opcode::ReadMultipleReq
+            | Opcode::ReadMultipleVariableReq => self.read_multiple(db, opcode, request, response),
+            Opcode::ReadByGroupTypeReq => self.read_by_group_type(db, request, response),
+            Opcode::WriteReq => self.write(db, request, response),
+            Opcode::PrepareWriteReq => self.prepare_write(db, request, response),
+            Opcode::ExecuteWriteReq => self.execute_write(db, request, response),
+            Opcode::WriteCmd => {
+                if request.len() >= 3 {
+                    let handle = u16::from_le_bytes([request[1], request[2]]);
+                    // commands have no response (errors are dropped)
+                    self.check_write(db, handle)
+                        .and_then(|_| db.write(handle, 0, &request[3..])).ok();
+                }
+                Ok(0)
+            }
+            Opcode::SignedWriteCmd => {
+                self.signed_write(db, request);
+                Ok(0)
+            }
+            Opcode::HandleValueCfm => {
+                self.indication_pending = false;
+                Ok(0)
+            }
+            // responses, notifications and indications are for the client
+            _ => Ok(0),
+        };
+
+        return match result {
+            Ok(size) => size,
+            Err((handle, code)) => Self::error(request[0], handle, code, response),
+        };
+    }
+
+    /// write an Error Response
+    /// Core_v5.3 Vol 3, Part F, 3.4.1.1
+    pub fn error(request_opcode: u8, handle: Handle, code: ErrorCode, response: &mut [u8]) -> usize {
+        response[0] = Opcode::ErrorRsp as u8;
+        response[1] = request_opcode;
+        response[2..4].copy_from_slice(&handle.to_le_bytes());
+        response[4] = code as u8;
+        return 5;
+    }
+
+    /// write a Handle Value Notification, returns the size of the PDU
+    /// Core_v5.3 Vol 3, Part F, 3.4.7.1
+    pub fn notification(&self, handle: Handle, value: &[u8], buffer: &mut [u8]) -> usize {
+        return self.handle_value(Opcode::HandleValueNtf, handle, value, buffer);
+    }
+
+    /// write a Handle Value Indication (None if the previous indication is unconfirmed)
+    /// Core_v5.3 Vol 3, Part F, 3.4.7.2
+    pub fn indication(&mut self, handle: Handle, value: &[u8], buffer: &mut [u8]) -> Option<usize> {
+        if self.indication_pending {
+            return None;
+        }
+        self.indication_pending = true;
+        return Some(self.handle_value(Opcode::HandleValueInd, handle, value, buffer));
+    }
+
+    fn handle_value(&self, opcode: Opcode, handle: Handle, value: &[u8], buffer: &mut [u8]) -> usize {
+        const HEADER_SIZE:usize = 3;
+        let size = core::cmp::min(value.len(), core::cmp::min(self.mtu as usize, buffer.len()) - HEADER_SIZE);
+        buffer[0] = opcode as u8;
+        buffer[1..3].copy_from_slice(&handle.to_le_bytes());
+        buffer[HEADER_SIZE..(HEADER_SIZE + size)].copy_from_slice(&value[..size]);
+        return HEADER_SIZE + size;
+    }
+
+    fn check_read<D: AttributeDatabase + ?Sized>(&self, db: &D, handle: Handle) -> Result<(), ErrorCode> {
+        let permissions = match db.attributes().iter().find(|a| a.handle == handle) {
+            Some(attribute) => attribute.permissions,
+            None => return Err(ErrorCode::InvalidHandle),
+        };
+        if (permissions & PERMISSION_READ) == 0 {
+            return Err(ErrorCode::ReadNotPermitted);
+        }
+        return self.check_security(permissions, PERMISSION_READ_ENCRYPTED, PERMISSION_READ_AUTHENTICATED);
+    }
+
+    fn check_write<D: AttributeDatabase + ?Sized>(&self, db: &D, handle: Handle) -> Result<(), ErrorCode> {
+        let permissions = match db.attributes().iter().find(|a| a.handle == handle) {
+            Some(attribute) => attribute.permissions,
+            None => return Err(ErrorCode::InvalidHandle),
+        };
+        if (permissions & PERMISSION_WRITE) == 0 {
+            return Err(ErrorCode::WriteNotPermitted);
+        }
+        return self.check_security(permissions, PERMISSION_WRITE_ENCRYPTED, PERMISSION_WRITE_AUTHENTICATED);
+    }
+
+    fn check_security(&self, permissions: u8, encrypted: u8, authenticated: u8) -> Result<(), ErrorCode> {
+        if (permissions & authenticated) != 0 && self.security < SecurityLevel::Authenticated {
+            return Err(ErrorCode::InsufficientAuthentication);
+        }
+        if (permissions & encrypted) != 0 && self.security < SecurityLevel::Encrypted {
+            return Err(ErrorCode::InsufficientEncryption);
+        }
+        if (permissions & PERMISSION_AUTHORIZATION) != 0 && ! self.authorized {
+            return Err(ErrorCode::InsufficientAuthorization);
+        }
+        return Ok(());
+    }
+
+    /// parse the handle range common to the find/read-by requests
+    fn handle_range(request: &[u8]) -> Result<(Handle, Handle), (Handle, ErrorCode)> {
+        if request.len() < 5 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let start = u16::from_le_bytes([request[1], request[2]]);
+        let end = u16::from_le_bytes([request[3], request[4]]);
+        if start == 0 || start > end {
+            return Err((start, ErrorCode::InvalidHandle));
+        }
+        return Ok((start, end));
+    }
+
+    /// the last handle of the group started by attribute (at index)
+    fn group_end(attributes: &[Attribute], index: usize) -> Handle {
+        let uuid = attributes[index].uuid;
+        if uuid != PRIMARY_SERVICE_UUID && uuid != SECONDARY_SERVICE_UUID {
+            return attributes[index].handle;
+        }
+        return match attributes[(index + 1)..].iter()
+                        .find(|a| a.uuid == PRIMARY_SERVICE_UUID || a.uuid == SECONDARY_SERVICE_UUID) {
+            Some(next) => next.handle - 1,
+            None => attributes[attributes.len() - 1].handle,
+        };
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.2
+    fn exchange_mtu(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        if request.len() != 3 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        // not used on Enhanced ATT bearers
+        if self.enhanced {
+            return Err((0, ErrorCode::RequestNotSupported));
+        }
+        let client_mtu = u16::from_le_bytes([request[1], request[2]]);
+        self.mtu = client_mtu.clamp(ATT_MTU_MIN, ATT_MTU_MAX);
+
+        response[0] = Opcode::ExchangeMtuRsp as u8;
+        response[1..3].copy_from_slice(&ATT_MTU_MAX.to_le_bytes());
+        return Ok(3);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.3.1
+    fn find_information<D: AttributeDatabase + ?Sized>(&self, db: &D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        let (start, end) = Self::handle_range(request)?;
+
+        const FORMAT_UUID_16:u8 = 0x01;
+        const FORMAT_UUID_128:u8 = 0x02;
+        let mut size = 2;
+        let mut uuid_len = 0;
+        for attribute in db.attributes().iter().filter(|a| a.handle >= start && a.handle <= end) {
+            // every entry must use the same format as the first
+            let len = attribute.uuid.len();
+            if uuid_len == 0 {
+                uuid_len = len;
+            } else if uuid_len != len {
+                break;
+            }
+            if response.len() < (size + 2 + len) {
+                break;
+            }
+            response[size..(size + 2)].copy_from_slice(&attribute.handle.to_le_bytes());
+            size += 2;
+            size += attribute.uuid.write(&mut response[size..]);
+        }
+        if uuid_len == 0 {
+            return Err((start, ErrorCode::AttributeNotFound));
+        }
+        response[0] = Opcode::FindInformationRsp as u8;
+        response[1] = if uuid_len == 2 { FORMAT_UUID_16 } else { FORMAT_UUID_128 };
+        return Ok(size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.3.3
+    fn find_by_type_value<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        let (start, end) = Self::handle_range(request)?;
+        if request.len() < 7 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let uuid = Uuid::Uuid16(u16::from_le_bytes([request[5], request[6]]));
+        let value = &request[7..];
+
+        let mut size = 1;
+        let mut index = 0;
+        while index < db.attributes().len() {
+            let attribute = &db.attributes()[index];
+            let handle = attribute.handle;
+            if handle >= start && handle <= end && attribute.uuid == uuid {
+                let group_end = Self::group_end(db.attributes(), index);
+                let mut current = [0; ATT_MTU_MAX as usize];
+                if let Ok(len) = db.read(handle, 0, &mut current) {
+                    if current[..len] == *value {
+                        if response.len() < (size + 4) {
+                            break;
+                        }
+                        response[size..(size + 2)].copy_from_slice(&handle.to_le_bytes());
+                        response[(size + 2)..(size + 4)].copy_from_slice(&group_end.to_le_bytes());
+                        size += 4;
+                    }
+                }
+            }
+            index += 1;
+        }
+        if size == 1 {
+            return Err((start, ErrorCode::AttributeNotFound));
+        }
+        response[0] = Opcode::FindByTypeValueRsp as u8;
+        return Ok(size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.4.1
+    fn read_by_type<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        let (start, end) = Self::handle_range(request)?;
+        let uuid = match Uuid::from_slice(&request[5..]) {
+            Some(uuid) => uuid,
+            None => return Err((0, ErrorCode::InvalidPdu)),
+        };
+        return self.read_list(db, start, end, |a| a.uuid == uuid, false, Opcode::ReadByTypeRsp, response);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.4.9
+    fn read_by_group_type<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        let (start, end) = Self::handle_range(request)?;
+        let uuid = match Uuid::from_slice(&request[5..]) {
+            Some(uuid) => uuid,
+            None => return Err((0, ErrorCode::InvalidPdu)),
+        };
+        if uuid != PRIMARY_SERVICE_UUID && uuid != SECONDARY_SERVICE_UUID {
+            return Err((start, ErrorCode::UnsupportedGroupType));
+        }
+        return self.read_list(db, start, end, |a| a.uuid == uuid, true, Opcode::ReadByGroupTypeRsp, response);
+    }
+
+    /// build the (handle, [group end,] value) list of Read By Type/Read By Group Type responses
+    #[allow(clippy::too_many_arguments)]
+    fn read_list<D, F>(&self, db: &mut D, start: Handle, end: Handle, matches: F, grouped: bool,
+                       opcode: Opcode, response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)>
+        where D: AttributeDatabase + ?Sized, F: Fn(&Attribute) -> bool
+    {
+        // values are limited to (ATT_MTU - header) or 251/253 octets (the length field is one octet)
+        let header_size = if grouped { 4 } else { 2 };
+        let value_max = core::cmp::min(response.len() - 2 - header_size, 255 - header_size);
+
+        let mut size = 2;
+        let mut entry_size = 0;
+        let mut index = 0;
+        while index < db.attributes().len() {
+            let attribute = &db.attributes()[index];
+            let handle = attribute.handle;
+            if handle < start || handle > end || ! matches(attribute) {
+                index += 1;
+                continue;
+            }
+            let group_end = if grouped { Self::group_end(db.attributes(), index) } else { handle };
+
+            // the first attribute reports access errors, later attributes end the list
+            if let Err(code) = self.check_read(db, handle) {
+                if entry_size == 0 {
+                    return Err((handle, code));
+                }
+                break;
+            }
+            let mut value = [0; ATT_MTU_MAX as usize];
+            let len = match db.read(handle, 0, &mut value[..value_max]) {
+                Ok(len) => len,
+                Err(code) => {
+                    if entry_size == 0 {
+                        return Err((handle, code));
+                    }
+                    break;
+                }
+            };
+
+            // every entry must be the same size as the first
+            if entry_size == 0 {
+                entry_size = header_size + len;
+            } else if entry_size != (header_size + len) {
+                break;
+            }
+            if response.len() < (size + entry_size) {
+                break;
+            }
+            response[size..(size + 2)].copy_from_slice(&handle.to_le_bytes());
+            size += 2;
+            if grouped {
+                response[size..(size + 2)].copy_from_slice(&group_end.to_le_bytes());
+                size += 2;
+            }
+            response[size..(size + len)].copy_from_slice(&value[..len]);
+            size += len;
+
+            index += 1;
+        }
+        if entry_size == 0 {
+            return Err((start, ErrorCode::AttributeNotFound));
+        }
+        response[0] = opcode as u8;
+        response[1] = entry_size as u8;
+        return Ok(size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.4.3
+    fn read<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        if request.len() != 3 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let handle = u16::from_le_bytes([request[1], request[2]]);
+        self.check_read(db, handle).map_err(|code| (handle, code))?;
+        let size = db.read(handle, 0, &mut response[1..]).map_err(|code| (handle, code))?;
+        response[0] = Opcode::ReadRsp as u8;
+        return Ok(1 + size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.4.5
+    fn read_blob<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        if request.len() != 5 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let handle = u16::from_le_bytes([request[1], request[2]]);
+        let offset = u16::from_le_bytes([request[3], request[4]]) as usize;
+        self.check_read(db, handle).map_err(|code| (handle, code))?;
+        let size = db.read(handle, offset, &mut response[1..]).map_err(|code| (handle, code))?;
+        response[0] = Opcode::ReadBlobRsp as u8;
+        return Ok(1 + size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.4.7 (Read Multiple) and 3.4.4.11 (Read Multiple Variable)
+    fn read_multiple<D: AttributeDatabase + ?Sized>(&self, db: &mut D, opcode: Opcode, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        let handles = &request[1..];
+        if handles.len() < 4 || !handles.len().is_multiple_of(2) {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let variable = opcode == Opcode::ReadMultipleVariableReq;
+
+        let mut size = 1;
+        for handle in handles.chunks(2).map(|h| u16::from_le_bytes([h[0], h[1]])) {
+            self.check_read(db, handle).map_err(|code| (handle, code))?;
+            if variable {
+                // each value is prefixed with its length (the full length, even if truncated)
+                let mut value = [0; ATT_MTU_MAX as usize];
+                let len = db.read(handle, 0, &mut value).map_err(|code| (handle, code))?;
+                if response.len() < (size + 2) {
+                    break;
+                }
+                response[size..(size + 2)].copy_from_slice(&(len as u16).to_le_bytes());
+                size += 2;
+                let copy = core::cmp::min(len, response.len() - size);
+                response[size..(size + copy)].copy_from_slice(&value[..copy]);
+                size += copy;
+            } else {
+                // values are concatenated, the last may be truncated
+                size += db.read(handle, 0, &mut response[size..]).map_err(|code| (handle, code))?;
+            }
+        }
+        response[0] = if variable { Opcode::ReadMultipleVariableRsp as u8 } else { Opcode::ReadMultipleRsp as u8 };
+        return Ok(size);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.5.1
+    fn write<D: AttributeDatabase + ?Sized>(&self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        if request.len() < 3 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let handle = u16::from_le_bytes([request[1], request[2]]);
+        self.check_write(db, handle).map_err(|code| (handle, code))?;
+        db.write(handle, 0, &request[3..]).map_err(|code| (handle, code))?;
+        response[0] = Opcode::WriteRsp as u8;
+        return Ok(1);
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.5.4
+    fn signed_write<D: AttributeDatabase + ?Sized>(&mut self, db: &mut D, request: &[u8]) {
+        if request.len() < (3 + SIGNATURE_SIZE) {
+            return;
+        }
+        let handle = u16::from_le_bytes([request[1], request[2]]);
+        let permitted = db.attributes().iter()
+                            .any(|a| a.handle == handle && (a.permissions & PERMISSION_SIGNED_WRITE) != 0);
+        if ! permitted {
+            return;
+        }
+        // signatures that can't be verified are dropped (Core_v5.3 Vol 3, Part C, 10.4.2)
+        let csrk = match self.signing_key {
+            Some(csrk) => csrk,
+            None => return,
+        };
+        let message = &request[..(request.len() - SIGNATURE_SIZE)];
+        let signature = &request[(request.len() - SIGNATURE_SIZE)..];
+        let sign_counter = u32::from_le_bytes([signature[0], signature[1], signature[2], signature[3]]);
+        if let Some(last) = self.sign_counter {
+            if sign_counter <= last {
+                return;
+            }
+        }
+        if signature[4..] != sign(&csrk, message, sign_counter) {
+            return;
+        }
+        self.sign_counter = Some(sign_counter);
+        db.write(handle, 0, &message[3..]).ok();
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.6.1
+    fn prepare_write<D: AttributeDatabase + ?Sized>(&mut self, db: &D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        if request.len() < 5 {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+        let handle = u16::from_le_bytes([request[1], request[2]]);
+        self.check_write(db, handle).map_err(|code| (handle, code))?;
+
+        // queue the handle, offset and value
+        let value = &request[5..];
+        let entry_size = PREPARE_ENTRY_HEADER_SIZE + value.len();
+        if (self.prepare_queue_len + entry_size) > PREPARE_QUEUE_SIZE {
+            return Err((handle, ErrorCode::PrepareQueueFull));
+        }
+        let entry = &mut self.prepare_queue[self.prepare_queue_len..(self.prepare_queue_len + entry_size)];
+        entry[..4].copy_from_slice(&request[1..5]);
+        entry[4] = value.len() as u8;
+        entry[PREPARE_ENTRY_HEADER_SIZE..].copy_from_slice(value);
+        self.prepare_queue_len += entry_size;
+
+        // echo the request
+        response[..request.len()].copy_from_slice(request);
+        response[0] = Opcode::PrepareWriteRsp as u8;
+        return Ok(request.len());
+    }
+
+    /// Core_v5.3 Vol 3, Part F, 3.4.6.3
+    fn execute_write<D: AttributeDatabase + ?Sized>(&mut self, db: &mut D, request: &[u8], response: &mut [u8]) -> Result<usize, (Handle, ErrorCode)> {
+        const CANCEL_ALL:u8 = 0x00;
+        const WRITE_ALL:u8 = 0x01;
+        if request.len() != 2 || (request[1] != CANCEL_ALL && request[1] != WRITE_ALL) {
+            return Err((0, ErrorCode::InvalidPdu));
+        }
+
+        // the queue is cleared regardless of the outcome
+        let queue_len = self.prepare_queue_len;
+        self.prepare_queue_len = 0;
+        if request[1] == WRITE_ALL {
+            let mut position = 0;
+            while position < queue_len {
+                let entry = &self.prepare_queue[position..];
+                let handle = u16::from_le_bytes([entry[0], entry[1]]);
+                let offset = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+                let len = entry[4] as usize;
+                let value = &entry[PREPARE_ENTRY_HEADER_SIZE..(PREPARE_ENTRY_HEADER_SIZE + len)];
+                db.write(handle, offset, value).map_err(|code| (handle, code))?;
+                position += PREPARE_ENTRY_HEADER_SIZE + len;
+            }
+        }
+        response[0] = Opcode::ExecuteWriteRsp as u8;
+        return Ok(1);
+    }
+}
+
+/// compute the MAC of a signed ATT PDU (the 64 most significant bits of AES-CMAC, little endian)
+/// Core_v5.3 Vol 3, Part H, 2.4.5
+pub fn sign(csrk: &[u8;16], message: &[u8], sign_counter: u32) -> [u8;8] {
+    // the security functions are defined MSB first, so swap everything from on-air order
+    let mut key = *csrk;
+    key.reverse();
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(&key).unwrap();
+    for byte in sign_counter.to_le_bytes().iter().rev().chain(message.iter().rev()) {
+        mac.update(&[*byte]);
+    }
+    let tag = mac.finalize().into_bytes();
+    let mut result = [0; 8];
+    result.copy_from_slice(&tag[..8]);
+    result.reverse();
+    return result;
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod att_server {
+    use super::*;
+
+    const RESPONSE_SIZE:usize = ATT_MTU_MAX as usize;
+
+    const SERVICE_A:[u8;2] = 0x180F_u16.to_le_bytes();
+    const SERVICE_B:[u8;2] = 0x181A_u16.to_le_bytes();
+    fn database() -> [Attribute<'static>; 5] {
+        [
+            Attribute{ handle:1, uuid:PRIMARY_SERVICE_UUID, permissions:PERMISSION_READ, value:&SERVICE_A },
+            Attribute{ handle:2, uuid:Uuid::Uuid16(0x2A19), permissions:PERMISSION_READ, value:&[100] },
+            Attribute{ handle:3, uuid:Uuid::Uuid16(0x2A00), permissions:PERMISSION_READ, value:b"a long value exceeding the default ATT_MTU" },
+            Attribute{ handle:4, uuid:PRIMARY_SERVICE_UUID, permissions:PERMISSION_READ, value:&SERVICE_B },
+            Attribute{ handle:5, uuid:Uuid::Uuid128(0x1234), permissions:PERMISSION_READ_ENCRYPTED | PERMISSION_READ, value:&[1] },
+        ]
+    }
+
+    /// accepts writes to handle 2 (storing the last write)
+    struct WritableDb<'a> {
+        attributes: [Attribute<'a>; 5],
+        written: [u8; 64],
+        written_len: usize,
+    }
+    impl<'a> AttributeDatabase for WritableDb<'a> {
+        fn attributes(&self) -> &[Attribute<'_>] { &self.attributes }
+        fn write(&mut self, handle: Handle, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+            if handle != 2 { return Err(ErrorCode::WriteNotPermitted); }
+            self.written[offset..(offset + data.len())].copy_from_slice(data);
+            self.written_len = offset + data.len();
+            Ok(())
+        }
+    }
+    fn writable_database() -> WritableDb<'static> {
+        let mut attributes = database();
+        attributes[1].permissions |= PERMISSION_WRITE | PERMISSION_SIGNED_WRITE;
+        WritableDb{ attributes, written: [0; 64], written_len: 0 }
+    }
+
+    #[test]
+    fn uuid_16_matches_128() {
+        assert_eq!(Uuid::Uuid16(0x2800), Uuid::Uuid128(0x00002800_0000_1000_8000_00805F9B34FB));
+        assert_ne!(Uuid::Uuid16(0x2801), Uuid::Uuid128(0x00002800_0000_1000_8000_00805F9B34FB));
+    }
+    #[test]
+    fn exchange_mtu() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ExchangeMtuReq as u8, 100, 0], &mut response);
+        assert_eq!(3, size);
+        assert_eq!(Opcode::ExchangeMtuRsp as u8, response[0]);
+        assert_eq!(ATT_MTU_MAX.to_le_bytes(), response[1..3]);
+        assert_eq!(100, server.mtu());
+        // the MTU of an enhanced bearer is that of its channel
+        let mut server = AttServer::enhanced(64);
+        let size = server.handle(&mut database(), &[Opcode::ExchangeMtuReq as u8, 100, 0], &mut response);
+        assert_eq!(ErrorCode::RequestNotSupported as u8, response[size - 1]);
+        assert_eq!(64, server.mtu());
+    }
+    #[test]
+    fn unsupported_request() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[0x3F], &mut response);
+        assert_eq!([Opcode::ErrorRsp as u8, 0x3F, 0, 0, ErrorCode::RequestNotSupported as u8], response[..size]);
+        // unknown commands are silently dropped
+        assert_eq!(0, server.handle(&mut database(), &[0x7F], &mut response));
+    }
+    #[test]
+    fn find_information() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::FindInformationReq as u8, 1, 0, 0xFF, 0xFF], &mut response);
+        // stops at the first 128-bit UUID
+        assert_eq!([Opcode::FindInformationRsp as u8, 0x01,
+                    1, 0, 0x00, 0x28, 2, 0, 0x19, 0x2A, 3, 0, 0x00, 0x2A, 4, 0, 0x00, 0x28], response[..size]);
+    }
+    #[test]
+    fn find_information_invalid_range() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::FindInformationReq as u8, 3, 0, 2, 0], &mut response);
+        assert_eq!([Opcode::ErrorRsp as u8, Opcode::FindInformationReq as u8, 3, 0, ErrorCode::InvalidHandle as u8], response[..size]);
+    }
+    #[test]
+    fn find_by_type_value() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::FindByTypeValueReq as u8, 1, 0, 0xFF, 0xFF, 0x00, 0x28, 0x1A, 0x18], &mut response);
+        assert_eq!([Opcode::FindByTypeValueRsp as u8, 4, 0, 5, 0], response[..size]);
+    }
+    #[test]
+    fn read_by_group_type() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ReadByGroupTypeReq as u8, 1, 0, 0xFF, 0xFF, 0x00, 0x28], &mut response);
+        assert_eq!([Opcode::ReadByGroupTypeRsp as u8, 6, 1, 0, 3, 0, 0x0F, 0x18, 4, 0, 5, 0, 0x1A, 0x18], response[..size]);
+    }
+    #[test]
+    fn read_by_group_type_unsupported() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ReadByGroupTypeReq as u8, 1, 0, 0xFF, 0xFF, 0x19, 0x2A], &mut response);
+        assert_eq!(ErrorCode::UnsupportedGroupType as u8, response[size - 1]);
+    }
+    #[test]
+    fn read_by_type() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ReadByTypeReq as u8, 1, 0, 0xFF, 0xFF, 0x19, 0x2A], &mut response);
+        assert_eq!([Opcode::ReadByTypeRsp as u8, 3, 2, 0, 100], response[..size]);
+    }
+    #[test]
+    fn read_and_read_blob() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let value = database()[2].value;
+        // read is limited to ATT_MTU - 1
+        let size = server.handle(&mut database(), &[Opcode::ReadReq as u8, 3, 0], &mut response);
+        assert_eq!(ATT_MTU_MIN as usize, size);
+        assert_eq!(value[..(size - 1)], response[1..size]);
+        // read the remainder
+        let size = server.handle(&mut database(), &[Opcode::ReadBlobReq as u8, 3, 0, 22, 0], &mut response);
+        assert_eq!(Opcode::ReadBlobRsp as u8, response[0]);
+        assert_eq!(value[22..], response[1..size]);
+        // offset beyond the value
+        let size = server.handle(&mut database(), &[Opcode::ReadBlobReq as u8, 3, 0, 0xFF, 0], &mut response);
+        assert_eq!(ErrorCode::InvalidOffset as u8, response[size - 1]);
+    }
+    #[test]
+    fn read_insufficient_encryption() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ReadReq as u8, 5, 0], &mut response);
+        assert_eq!([Opcode::ErrorRsp as u8, Opcode::ReadReq as u8, 5, 0, ErrorCode::InsufficientEncryption as u8], response[..size]);
+        server.set_security(SecurityLevel::Encrypted);
+        let size = server.handle(&mut database(), &[Opcode::ReadReq as u8, 5, 0], &mut response);
+        assert_eq!([Opcode::ReadRsp as u8, 1], response[..size]);
+    }
+    #[test]
+    fn read_multiple() {
+        let mut server = AttServer::new();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut database(), &[Opcode::ReadMultipleReq as u8, 2, 0, 1, 0], &mut response);
+        assert_eq!([Opcode::ReadMultipleRsp as u8, 100, 0x0F, 0x18], response[..size]);
+        let size = server.handle(&mut database(), &[Opcode::ReadMultipleVariableReq as u8, 2, 0, 1, 0], &mut response);
+        assert_eq!([Opcode::ReadMultipleVariableRsp as u8, 1, 0, 100, 2, 0, 0x0F, 0x18], response[..size]);
+    }
+    #[test]
+    fn write() {
+        let mut server = AttServer::new();
+        let mut db = writable_database();
+        let mut response = [0; RESPONSE_SIZE];
+        let size = server.handle(&mut db, &[Opcode::WriteReq as u8, 2, 0, 42], &mut response);
+        assert_eq!([Opcode::WriteRsp as u8], response[..size]);
+        assert_eq!([42], db.written[..db.written_len]);
+        let size = server.handle(&mut db, &[Opcode::WriteReq as u8, 1, 0, 42], &mut response);
+        assert_eq!(ErrorCode::WriteNotPermitted as u8, response[size - 1]);
+    }
+    #[test]
+    fn prepare_and_execute_write() {
+        let mut server = AttServer::new();
+        let mut db = writable_database();
+        let mut response = [0; RESPONSE_SIZE];
+        let request = [Opcode::PrepareWriteReq as u8, 2, 0, 0, 0, 1, 2];
+        let size = server.handle(&mut db, &request, &mut response);
+        assert_eq!(Opcode::PrepareWriteRsp as u8, response[0]);
+        assert_eq!(request[1..], response[1..size]);
+        server.handle(&mut db, &[Opcode::PrepareWriteReq as u8, 2, 0, 2, 0, 3], &mut response);
+        assert_eq!(0, db.written_len);
+        let size = server.handle(&mut db, &[Opcode::ExecuteWriteReq as u8, 0x01], &mut response);
+        assert_eq!([Opcode::ExecuteWriteRsp as u8], response[..size]);
+        assert_eq!([1, 2, 3], db.written[..db.written_len]);
+    }
+    #[test]
+    fn prepare_write_exceeding_mtu() {
+        let mut server = AttServer::new();
+        let mut db = writable_database();
+        let mut response = [0; RESPONSE_SIZE];
+        let mut request = [0; ATT_MTU_MIN as usize + 1];
+        request[..5].copy_from_slice(&[Opcode::PrepareWriteReq as u8, 2, 0, 0, 0]);
+        let size = server.handle(&mut db, &request, &mut response);
+        assert_eq!([Opcode::ErrorRsp as u8, Opcode::PrepareWriteReq as u8, 0, 0, ErrorCode::InvalidPdu as u8], response[..size]);
+        // nothing was queued
+        let size = server.handle(&mut db, &[Opcode::ExecuteWriteReq as u8, 0x01], &mut response);
+        assert_eq!([Opcode::ExecuteWriteRsp as u8], response[..size]);
+        assert_eq!(0, db.written_len);
+    }
+    #[test]
+    fn signed_write() {
+        let mut server = AttServer::new();
+        let mut db = writable_database();
+        let mut response = [0; RESPONSE_SIZE];
+        let csrk = [0xA5; 16];
+        let mut request = [0; 4 + SIGNATURE_SIZE];
+        request[..4].copy_from_slice(&[Opcode::SignedWriteCmd as u8, 2, 0, 7]);
+        request[4..8].copy_from_slice(&1_u32.to_le_bytes());
+        let mac = sign(&csrk, &request[..4], 1);
+        request[8..].copy_from_slice(&mac);
+        // dropped without a key
+        assert_eq!(0, server.handle(&mut db, &request, &mut response));
+        assert_eq!(0, db.written_len);
+        server.set_signing_key(csrk, None);
+        assert_eq!(0, server.handle(&mut db, &request, &mut response));
+        assert_eq!([7], db.written[..db.written_len]);
+        assert_eq!(Some(1), server.sign_counter());
+    }
+    #[test]
+    fn indication_confirmation() {
+        let mut server = AttServer::new();
+        let mut buffer = [0; RESPONSE_SIZE];
+        assert_eq!(Some(4), server.indication(2, &[1], &mut buffer));
+        assert_eq!([Opcode::HandleValueInd as u8, 2, 0, 1], buffer[..4]);
+        assert_eq!(None, server.indication(2, &[1], &mut buffer));
+        server.handle(&mut database(), &[Opcode::HandleValueCfm as u8], &mut buffer);
+        assert!(! server.is_indication_pending());
+        assert_eq!(4, server.notification(2, &[1], &mut buffer));
+    }
+}
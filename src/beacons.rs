@@ -0,0 +1,426 @@
+use crate::gap::{self, AdFields, AdvertisingData};
+use crate::link_layer::ADV_DATA_SIZE_MAX;
+
+/// beacons are non-connectable and always discoverable
+const FLAGS:u8 = gap::FLAGS_LE_GENERAL_DISCOVERABLE | gap::FLAGS_BR_EDR_NOT_SUPPORTED;
+
+/// the manufacturer specific data or service data of a beacon
+pub type Payload = heapless::Vec<u8, ADV_DATA_SIZE_MAX>;
+
+/// the AD structures of flags and the payload
+fn advertising_data(fields: AdFields) -> AdvertisingData {
+    // beacon payloads always fit
+    return AdvertisingData::from_fields(&AdFields { flags: Some(FLAGS), ..fields }).unwrap();
+}
+
+/// the manufacturer specific data of the company in advertising data
+fn manufacturer_data(ad_data: &[u8], company_id: Option<u16>) -> Option<&[u8]> {
+    return gap::ad_structures(ad_data)
+        .filter(|(ad_type, data)| *ad_type == 0xFF && data.len() >= 2)
+        .map(|(_, data)| data)
+        .find(|data| company_id.is_none_or(|id| data[..2] == id.to_le_bytes()));
+}
+
+/// Apple iBeacon
+/// https://developer.apple.com/ibeacon/
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IBeacon {
+    /// proximity UUID (as displayed, most significant octet first)
+    pub uuid: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    /// RSSI at 1m (dBm)
+    pub measured_power: i8,
+}
+impl IBeacon {
+    const COMPANY_ID:u16 = 0x004C;
+    const TYPE:u8 = 0x02;
+    const LENGTH:u8 = 0x15;
+
+    /// the manufacturer specific data (including the company identifier)
+    pub fn payload(&self) -> Payload {
+        let mut payload = Payload::new();
+        // big endian, unlike the rest of Bluetooth
+        payload.extend_from_slice(&Self::COMPANY_ID.to_le_bytes()).unwrap();
+        payload.extend_from_slice(&[Self::TYPE, Self::LENGTH]).unwrap();
+        payload.extend_from_slice(&self.uuid).unwrap();
+        payload.extend_from_slice(&self.major.to_be_bytes()).unwrap();
+        payload.extend_from_slice(&self.minor.to_be_bytes()).unwrap();
+        payload.push(self.measured_power as u8).unwrap();
+        return payload;
+    }
+
+    /// the ad fields of the payload (see payload)
+    pub fn ad_fields(payload: &Payload) -> AdFields<'_> {
+        return AdFields { flags: Some(FLAGS), manufacturer_specific_data: Some(payload), ..AdFields::default() };
+    }
+
+    pub fn advertising_data(&self) -> AdvertisingData {
+        return advertising_data(AdFields { manufacturer_specific_data: Some(&self.payload()), ..AdFields::default() });
+    }
+
+    /// the iBeacon of received advertising data
+    pub fn read(ad_data: &[u8]) -> Option<Self> {
+        let data = manufacturer_data(ad_data, Some(Self::COMPANY_ID))?;
+        if data.len() != 25 || data[2..4] != [Self::TYPE, Self::LENGTH] {
+            return None;
+        }
+        let mut uuid = [0; 16];
+        uuid.copy_from_slice(&data[4..20]);
+        return Some(Self {
+            uuid,
+            major: u16::from_be_bytes([data[20], data[21]]),
+            minor: u16::from_be_bytes([data[22], data[23]]),
+            measured_power: data[24] as i8,
+        });
+    }
+}
+
+/// AltBeacon
+/// https://github.com/AltBeacon/spec
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AltBeacon {
+    /// the beacon's manufacturer
+    pub company_id: u16,
+    /// (i.e. a 16 octet organizational unit followed by a 4 octet identifier)
+    pub id: [u8; 20],
+    /// RSSI at 1m (dBm)
+    pub reference_rssi: i8,
+    pub manufacturer_reserved: u8,
+}
+impl AltBeacon {
+    const BEACON_CODE:[u8; 2] = [0xBE, 0xAC];
+
+    /// the manufacturer specific data (including the company identifier)
+    pub fn payload(&self) -> Payload {
+        let mut payload = Payload::new();
+        payload.extend_from_slice(&self.company_id.to_le_bytes()).unwrap();
+        payload.extend_from_slice(&Self::BEACON_CODE).unwrap();
+        payload.extend_from_slice(&self.id).unwrap();
+        payload.extend_from_slice(&[self.reference_rssi as u8, self.manufacturer_reserved]).unwrap();
+        return payload;
+    }
+
+    /// the ad fields of the payload (see payload)
+    pub fn ad_fields(payload: &Payload) -> AdFields<'_> {
+        return AdFields { flags: Some(FLAGS), manufacturer_specific_data: Some(payload), ..AdFields::default() };
+    }
+
+    pub fn advertising_data(&self) -> AdvertisingData {
+        return advertising_data(AdFields { manufacturer_specific_data: Some(&self.payload()), ..AdFields::default() });
+    }
+
+    /// the AltBeacon of received advertising data
+    pub fn read(ad_data: &[u8]) -> Option<Self> {
+        let data = manufacturer_data(ad_data, None)?;
+        if data.len() != 26 || data[2..4] != Self::BEACON_CODE {
+            return None;
+        }
+        let mut id = [0; 20];
+        id.copy_from_slice(&data[4..24]);
+        return Some(Self {
+            company_id: u16::from_le_bytes([data[0], data[1]]),
+            id,
+            reference_rssi: data[24] as i8,
+            manufacturer_reserved: data[25],
+        });
+    }
+}
+
+/// the URL of an Eddystone-URL frame, compressed by its scheme prefix and expansion codes
+/// https://github.com/google/eddystone/tree/master/eddystone-url
+#[derive(Clone, Debug, PartialEq)]
+pub struct EddystoneUrl {
+    /// the scheme prefix followed by the encoded URL
+    encoded: heapless::Vec<u8, 18>,
+}
+impl EddystoneUrl {
+    const SCHEMES:[&'static str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+    const EXPANSIONS:[&'static str; 14] = [
+        ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/",
+        ".com", ".org", ".edu", ".net", ".info", ".biz", ".gov",
+    ];
+
+    /// None if the scheme isn't http(s) or the encoded URL exceeds 17 octets
+    pub fn new(url: &str) -> Option<Self> {
+        let (scheme, prefix) = Self::SCHEMES.iter().enumerate().find(|(_, prefix)| url.starts_with(*prefix))?;
+        let mut encoded = heapless::Vec::new();
+        encoded.push(scheme as u8).ok()?;
+        let mut rest = &url[prefix.len()..];
+        while let Some(character) = rest.bytes().next() {
+            // the longest expansion matching (i.e. ".com/" rather than ".com")
+            match Self::EXPANSIONS.iter().position(|expansion| rest.starts_with(expansion)) {
+                Some(code) => {
+                    encoded.push(code as u8).ok()?;
+                    rest = &rest[Self::EXPANSIONS[code].len()..];
+                }
+                None if character > 0x20 && character < 0x7F => {
+                    encoded.push(character).ok()?;
+                    rest = &rest[1..];
+                }
+                None => return None,
+            }
+        }
+        return Some(Self { encoded });
+    }
+
+    fn read(encoded: &[u8]) -> Option<Self> {
+        if encoded.is_empty() || encoded[0] as usize >= Self::SCHEMES.len() {
+            return None;
+        }
+        return heapless::Vec::from_slice(encoded).ok().map(|encoded| Self { encoded });
+    }
+}
+impl core::fmt::Display for EddystoneUrl {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(Self::SCHEMES[self.encoded[0] as usize])?;
+        for byte in &self.encoded[1..] {
+            match Self::EXPANSIONS.get(*byte as usize) {
+                Some(expansion) => f.write_str(expansion)?,
+                None => write!(f, "{}", *byte as char)?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Eddystone frames (service data of the Eddystone service)
+/// https://github.com/google/eddystone/blob/master/protocol-specification.md
+#[derive(Clone, Debug, PartialEq)]
+pub enum Eddystone {
+    Uid {
+        /// RSSI at 0m (dBm)
+        tx_power: i8,
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+    Url {
+        /// RSSI at 0m (dBm)
+        tx_power: i8,
+        url: EddystoneUrl,
+    },
+    /// telemetry (unencrypted)
+    Tlm {
+        /// 0 if not supported
+        battery_mv: u16,
+        /// in 1/256 degrees Celsius (8.8 fixed point), TEMPERATURE_UNSUPPORTED if not supported
+        temperature: i16,
+        /// advertising PDUs sent since power-up
+        adv_count: u32,
+        /// time since power-up in 0.1s
+        uptime: u32,
+    },
+    Eid {
+        /// RSSI at 0m (dBm)
+        tx_power: i8,
+        /// the ephemeral identifier (see eddystone_eid)
+        eid: [u8; 8],
+    },
+}
+impl Eddystone {
+    pub const SERVICE_UUID:u16 = 0xFEAA;
+    pub const TEMPERATURE_UNSUPPORTED:i16 = i16::MIN;
+    const FRAME_UID:u8 = 0x00;
+    const FRAME_URL:u8 = 0x10;
+    const FRAME_TLM:u8 = 0x20;
+    const FRAME_EID:u8 = 0x30;
+    const TLM_VERSION:u8 = 0x00;
+
+    /// the service data (following the service UUID)
+    pub fn payload(&self) -> Payload {
+        let mut payload = Payload::new();
+        // frames are at most 20 octets
+        match self {
+            Eddystone::Uid { tx_power, namespace, instance } => {
+                payload.extend_from_slice(&[Self::FRAME_UID, *tx_power as u8]).unwrap();
+                payload.extend_from_slice(namespace).unwrap();
+                payload.extend_from_slice(instance).unwrap();
+                // reserved
+                payload.extend_from_slice(&[0, 0]).unwrap();
+            }
+            Eddystone::Url { tx_power, url } => {
+                payload.extend_from_slice(&[Self::FRAME_URL, *tx_power as u8]).unwrap();
+                payload.extend_from_slice(&url.encoded).unwrap();
+            }
+            Eddystone::Tlm { battery_mv, temperature, adv_count, uptime } => {
+                payload.extend_from_slice(&[Self::FRAME_TLM, Self::TLM_VERSION]).unwrap();
+                payload.extend_from_slice(&battery_mv.to_be_bytes()).unwrap();
+                payload.extend_from_slice(&temperature.to_be_bytes()).unwrap();
+                payload.extend_from_slice(&adv_count.to_be_bytes()).unwrap();
+                payload.extend_from_slice(&uptime.to_be_bytes()).unwrap();
+            }
+            Eddystone::Eid { tx_power, eid } => {
+                payload.extend_from_slice(&[Self::FRAME_EID, *tx_power as u8]).unwrap();
+                payload.extend_from_slice(eid).unwrap();
+            }
+        }
+        return payload;
+    }
+
+    /// the ad fields of the payload (see payload)
+    pub fn ad_fields(payload: &Payload) -> AdFields<'_> {
+        return AdFields {
+            flags: Some(FLAGS),
+            complete_list_service_uuid_16: Some(&[Self::SERVICE_UUID]),
+            service_data_uuid_16: Some((Self::SERVICE_UUID, payload)),
+            ..AdFields::default()
+        };
+    }
+
+    pub fn advertising_data(&self) -> AdvertisingData {
+        return advertising_data(Self::ad_fields(&self.payload()));
+    }
+
+    /// the Eddystone frame of received advertising data
+    pub fn read(ad_data: &[u8]) -> Option<Self> {
+        let (_, data) = gap::ad_structures(ad_data)
+            .find(|(ad_type, data)| *ad_type == 0x16 && data.len() >= 4 && data[..2] == Self::SERVICE_UUID.to_le_bytes())?;
+        let frame = &data[2..];
+        return match (frame[0], frame.len()) {
+            (Self::FRAME_UID, 18) | (Self::FRAME_UID, 20) => {
+                let mut namespace = [0; 10];
+                namespace.copy_from_slice(&frame[2..12]);
+                let mut instance = [0; 6];
+                instance.copy_from_slice(&frame[12..18]);
+                Some(Eddystone::Uid { tx_power: frame[1] as i8, namespace, instance })
+            }
+            (Self::FRAME_URL, 3..=20) => Some(Eddystone::Url { tx_power: frame[1] as i8, url: EddystoneUrl::read(&frame[2..])? }),
+            (Self::FRAME_TLM, 14) if frame[1] == Self::TLM_VERSION => Some(Eddystone::Tlm {
+                battery_mv: u16::from_be_bytes([frame[2], frame[3]]),
+                temperature: i16::from_be_bytes([frame[4], frame[5]]),
+                adv_count: u32::from_be_bytes([frame[6], frame[7], frame[8], frame[9]]),
+                uptime: u32::from_be_bytes([frame[10], frame[11], frame[12], frame[13]]),
+            }),
+            (Self::FRAME_EID, 10) => {
+                let mut eid = [0; 8];
+                eid.copy_from_slice(&frame[2..]);
+                Some(Eddystone::Eid { tx_power: frame[1] as i8, eid })
+            }
+            _ => None,
+        };
+    }
+}
+
+/// the ephemeral identifier of Eddystone-EID from the identity key (as registered) at the time
+/// (seconds since the beacon's epoch), which changes every 2^exponent seconds (0 to 15)
+/// https://github.com/google/eddystone/tree/master/eddystone-eid
+pub fn eddystone_eid(identity_key: &[u8; 16], exponent: u8, time_s: u32) -> [u8; 8] {
+    let mut temporary = [0; 16];
+    temporary[11] = 0xFF;
+    temporary[14..].copy_from_slice(&((time_s >> 16) as u16).to_be_bytes());
+    let temporary_key = crate::smp::e(identity_key, &temporary);
+
+    let mut data = [0; 16];
+    data[11] = exponent;
+    data[12..].copy_from_slice(&(time_s & !((1 << (exponent & 0x0F)) - 1)).to_be_bytes());
+    let mut eid = [0; 8];
+    eid.copy_from_slice(&crate::smp::e(&temporary_key, &data)[..8]);
+    return eid;
+}
+
+/// the RSSI (dBm) at the distance (cm) from a beacon with the RSSI at 1m (i.e. measured_power) per free
+/// space path loss, 20 log10(distance / 1m) dB
+pub fn rssi_at(reference_rssi: i8, distance_cm: u32) -> i8 {
+    // 1dB steps (10^(1/20) = 1.122) of the distance (um), rounded at the half dB (10^(1/40) = 1.059)
+    let distance = distance_cm.max(1) as u64 * 10_000;
+    let mut path_loss:i32 = 0;
+    let mut threshold:u64 = 1_059_254;
+    while distance >= threshold && path_loss < 256 {
+        threshold += threshold * 122_018 / 1_000_000;
+        path_loss += 1;
+    }
+    // closer than 1m
+    let mut threshold:u64 = 944_061;
+    while distance < threshold && path_loss > -128 {
+        threshold = threshold * 1_000_000 / 1_122_018;
+        path_loss -= 1;
+    }
+    return (reference_rssi as i32 - path_loss).clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod beacon_payloads {
+    use super::*;
+
+    const UUID:[u8; 16] = [0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0];
+
+    #[test]
+    fn ibeacon() {
+        let beacon = IBeacon { uuid: UUID, major: 0x0102, minor: 0x0304, measured_power: -59 };
+        let data = beacon.advertising_data();
+        let bytes = data.as_bytes();
+        assert_eq!(30, bytes.len());
+        assert_eq!([0x02, 0x01, 0x06, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15], bytes[..9]);
+        assert_eq!(UUID, bytes[9..25]);
+        assert_eq!([0x01, 0x02, 0x03, 0x04, 0xC5], bytes[25..]);
+        assert_eq!(Some(beacon), IBeacon::read(bytes));
+        assert_eq!(None, AltBeacon::read(bytes));
+        assert_eq!(None, Eddystone::read(bytes));
+
+        let payload = beacon.payload();
+        let mut buffer = [0; ADV_DATA_SIZE_MAX];
+        let size = IBeacon::ad_fields(&payload).write(&mut buffer);
+        assert_eq!(bytes, &buffer[..size]);
+    }
+
+    #[test]
+    fn altbeacon() {
+        let beacon = AltBeacon { company_id: 0x0118, id: [0xA5; 20], reference_rssi: -65, manufacturer_reserved: 0 };
+        let data = beacon.advertising_data();
+        let bytes = data.as_bytes();
+        assert_eq!([0x02, 0x01, 0x06, 0x1B, 0xFF, 0x18, 0x01, 0xBE, 0xAC], bytes[..9]);
+        assert_eq!([0xBF, 0x00], bytes[29..]);
+        assert_eq!(Some(beacon), AltBeacon::read(bytes));
+        assert_eq!(None, IBeacon::read(bytes));
+    }
+
+    #[test]
+    fn eddystone() {
+        let uid = Eddystone::Uid { tx_power: -20, namespace: [1; 10], instance: [2; 6] };
+        let data = uid.advertising_data();
+        let bytes = data.as_bytes();
+        assert_eq!(31, bytes.len());
+        assert_eq!([0x02, 0x01, 0x06, 0x03, 0x03, 0xAA, 0xFE, 0x17, 0x16, 0xAA, 0xFE, 0x00, 0xEC], bytes[..13]);
+        assert_eq!(Some(uid), Eddystone::read(bytes));
+
+        let url = EddystoneUrl::new("https://www.example.com/beacon").unwrap();
+        assert_eq!([0x01, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00, b'b', b'e', b'a', b'c', b'o', b'n'], url.encoded[..]);
+        assert_eq!("https://www.example.com/beacon", format!("{}", url));
+        let frame = Eddystone::Url { tx_power: -20, url };
+        assert_eq!(Some(frame.clone()), Eddystone::read(frame.advertising_data().as_bytes()));
+        assert_eq!(None, EddystoneUrl::new("ftp://example.com"));
+        assert_eq!(None, EddystoneUrl::new("https://a.very.long.example/url"));
+
+        let tlm = Eddystone::Tlm { battery_mv: 3000, temperature: 21 * 256 + 128, adv_count: 1000, uptime: 36000 };
+        assert_eq!([0x20, 0x00, 0x0B, 0xB8, 0x15, 0x80, 0, 0, 0x03, 0xE8, 0, 0, 0x8C, 0xA0], tlm.payload()[..]);
+        assert_eq!(Some(tlm.clone()), Eddystone::read(tlm.advertising_data().as_bytes()));
+
+        let eid = Eddystone::Eid { tx_power: -20, eid: eddystone_eid(&[0; 16], 10, 0x12345678) };
+        assert_eq!(Some(eid.clone()), Eddystone::read(eid.advertising_data().as_bytes()));
+    }
+
+    #[test]
+    fn eid_rotation() {
+        let key = [0x5A; 16];
+        // unchanged within the rotation period
+        assert_eq!(eddystone_eid(&key, 10, 0x0001_0400), eddystone_eid(&key, 10, 0x0001_07FF));
+        assert_ne!(eddystone_eid(&key, 10, 0x0001_0400), eddystone_eid(&key, 10, 0x0001_0800));
+        assert_ne!(eddystone_eid(&key, 10, 0x0001_0400), eddystone_eid(&key, 11, 0x0001_0400));
+    }
+
+    #[test]
+    fn path_loss() {
+        assert_eq!(-59, rssi_at(-59, 100));
+        // 6dB per doubling of the distance
+        assert_eq!(-65, rssi_at(-59, 200));
+        assert_eq!(-79, rssi_at(-59, 1000));
+        assert_eq!(-99, rssi_at(-59, 10000));
+        // stronger when closer than 1m
+        assert_eq!(-53, rssi_at(-59, 50));
+        assert_eq!(-128, rssi_at(-59, u32::MAX));
+    }
+}
@@ -0,0 +1,468 @@
+use crate::gatt::{ClientConfiguration, CCCD_MAX};
+use crate::link_layer::{DeviceAddress, AddressKind};
+use crate::smp;
+use crate::privacy::AddressResolver;
+
+/// maximum number of bonds (the least recently used bond is evicted for a new one)
+pub const BONDS_MAX:usize = 4;
+/// size of a serialized bond
+pub const RECORD_SIZE:usize = 108;
+
+/// identifies (the version of) a record, erased storage reads as 0xFF
+const RECORD_MAGIC:u8 = 0xB1;
+const FLAG_IRK:u8 = 1 << 0;
+const FLAG_CSRK:u8 = 1 << 1;
+const FLAG_SIGN_COUNTER:u8 = 1 << 2;
+const FLAG_AUTHENTICATED:u8 = 1 << 3;
+const FLAG_SECURE_CONNECTIONS:u8 = 1 << 4;
+
+/// the keys and state of a bonded peer
+/// Core_v5.3 Vol 3, Part C, 9.4.4
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bond {
+    /// the peer's identity address
+    pub identity: DeviceAddress,
+    /// identity resolving key of the peer (little endian)
+    pub irk: Option<[u8; 16]>,
+    /// long term key (little endian)
+    pub ltk: [u8; 16],
+    pub ediv: u16,
+    pub rand: [u8; 8],
+    pub key_size: u8,
+    /// connection signature resolving key of the peer (little endian)
+    pub csrk: Option<[u8; 16]>,
+    /// the last counter of a signed write from the peer
+    pub sign_counter: Option<u32>,
+    /// the peer's Client Characteristic Configuration
+    pub cccds: ClientConfiguration,
+    /// MITM protected (i.e. not Just Works)
+    pub authenticated: bool,
+    pub secure_connections: bool,
+}
+impl Bond {
+    /// the bond of a peer upon pairing
+    pub fn new(identity: DeviceAddress, keys: &smp::Keys) -> Self {
+        Self {
+            identity,
+            irk: keys.peer_irk,
+            ltk: keys.ltk,
+            ediv: keys.ediv,
+            rand: keys.rand,
+            key_size: keys.key_size,
+            csrk: keys.peer_csrk,
+            sign_counter: None,
+            cccds: [0; CCCD_MAX],
+            authenticated: keys.authenticated,
+            secure_connections: keys.secure_connections,
+        }
+    }
+
+    /// the keys to encrypt a reconnection with
+    pub fn keys(&self) -> smp::Keys {
+        smp::Keys {
+            ltk: self.ltk,
+            key_size: self.key_size,
+            ediv: self.ediv,
+            rand: self.rand,
+            authenticated: self.authenticated,
+            secure_connections: self.secure_connections,
+            bonded: true,
+            peer_irk: self.irk,
+            peer_identity: Some(self.identity),
+            peer_csrk: self.csrk,
+        }
+    }
+
+    /// serialize the bond along with its usage sequence
+    fn write(&self, sequence: u32, record: &mut [u8; RECORD_SIZE]) {
+        *record = [0; RECORD_SIZE];
+        record[0] = RECORD_MAGIC;
+        record[1] = match self.identity.kind {
+            AddressKind::Public => 0,
+            AddressKind::RandomStatic => 1,
+            AddressKind::ResolvablePrivate => 2,
+            AddressKind::NonResolvablePrivate => 3,
+        };
+        record[2..8].copy_from_slice(&self.identity.bytes);
+        let mut flags = 0;
+        if let Some(irk) = self.irk {
+            flags |= FLAG_IRK;
+            record[36..52].copy_from_slice(&irk);
+        }
+        if let Some(csrk) = self.csrk {
+            flags |= FLAG_CSRK;
+            record[52..68].copy_from_slice(&csrk);
+        }
+        if let Some(sign_counter) = self.sign_counter {
+            flags |= FLAG_SIGN_COUNTER;
+            record[68..72].copy_from_slice(&sign_counter.to_le_bytes());
+        }
+        if self.authenticated {
+            flags |= FLAG_AUTHENTICATED;
+        }
+        if self.secure_connections {
+            flags |= FLAG_SECURE_CONNECTIONS;
+        }
+        record[8] = flags;
+        record[9] = self.key_size;
+        record[10..26].copy_from_slice(&self.ltk);
+        record[26..28].copy_from_slice(&self.ediv.to_le_bytes());
+        record[28..36].copy_from_slice(&self.rand);
+        record[72..76].copy_from_slice(&sequence.to_le_bytes());
+        for (index, cccd) in self.cccds.iter().enumerate() {
+            record[(76 + 2 * index)..(78 + 2 * index)].copy_from_slice(&cccd.to_le_bytes());
+        }
+    }
+
+    /// deserialize a bond and its usage sequence (None if the record is erased or invalid)
+    fn read(record: &[u8; RECORD_SIZE]) -> Option<(Self, u32)> {
+        if record[0] != RECORD_MAGIC {
+            return None;
+        }
+        let mut bytes = [0; 6];
+        bytes.copy_from_slice(&record[2..8]);
+        let kind = match record[1] {
+            0 => AddressKind::Public,
+            1 => AddressKind::RandomStatic,
+            2 => AddressKind::ResolvablePrivate,
+            3 => AddressKind::NonResolvablePrivate,
+            _ => return None,
+        };
+        let identity = DeviceAddress { kind, bytes };
+        let flags = record[8];
+        let key = |offset: usize| {
+            let mut key = [0; 16];
+            key.copy_from_slice(&record[offset..(offset + 16)]);
+            key
+        };
+        let mut rand = [0; 8];
+        rand.copy_from_slice(&record[28..36]);
+        let mut cccds = [0; CCCD_MAX];
+        for (index, cccd) in cccds.iter_mut().enumerate() {
+            *cccd = u16::from_le_bytes([record[76 + 2 * index], record[77 + 2 * index]]);
+        }
+        let bond = Self {
+            identity,
+            irk: if (flags & FLAG_IRK) != 0 { Some(key(36)) } else { None },
+            ltk: key(10),
+            ediv: u16::from_le_bytes([record[26], record[27]]),
+            rand,
+            key_size: record[9],
+            csrk: if (flags & FLAG_CSRK) != 0 { Some(key(52)) } else { None },
+            sign_counter: if (flags & FLAG_SIGN_COUNTER) != 0 {
+                Some(u32::from_le_bytes([record[68], record[69], record[70], record[71]]))
+            } else {
+                None
+            },
+            cccds,
+            authenticated: (flags & FLAG_AUTHENTICATED) != 0,
+            secure_connections: (flags & FLAG_SECURE_CONNECTIONS) != 0,
+        };
+        let sequence = u32::from_le_bytes([record[72], record[73], record[74], record[75]]);
+        return Some((bond, sequence));
+    }
+}
+
+/// persistent storage of BONDS_MAX records (i.e. flash pages), only written from Ble::work (writes may
+/// block for milliseconds)
+pub trait BondStorage: Send {
+    /// read the record of a slot, returns false if the slot is empty
+    fn read(&mut self, slot: usize, record: &mut [u8; RECORD_SIZE]) -> bool;
+
+    /// (over)write the record of a slot
+    fn write(&mut self, slot: usize, record: &[u8; RECORD_SIZE]);
+
+    /// empty a slot
+    fn erase(&mut self, slot: usize);
+}
+
+/// volatile storage (i.e. for tests and hosts without flash)
+pub struct MemoryStorage {
+    records: [Option<[u8; RECORD_SIZE]>; BONDS_MAX],
+}
+impl Default for MemoryStorage {
+    fn default() -> Self { Self::new() }
+}
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self { records: [None; BONDS_MAX] }
+    }
+}
+impl BondStorage for MemoryStorage {
+    fn read(&mut self, slot: usize, record: &mut [u8; RECORD_SIZE]) -> bool {
+        return match self.records[slot] {
+            Some(stored) => {
+                *record = stored;
+                true
+            }
+            None => false,
+        };
+    }
+
+    fn write(&mut self, slot: usize, record: &[u8; RECORD_SIZE]) {
+        self.records[slot] = Some(*record);
+    }
+
+    fn erase(&mut self, slot: usize) {
+        self.records[slot] = None;
+    }
+}
+
+/// the bonded peers (cached from the storage, new bonds are written through to their slot, changes to
+/// existing bonds are written by flush)
+pub struct BondStore<'a> {
+    storage: &'a mut dyn BondStorage,
+    bonds: [Option<Bond>; BONDS_MAX],
+    /// the usage sequence of each slot (the lowest is the least recently used), kept in RAM and
+    /// persisted along with the next write of the bond
+    used: [u32; BONDS_MAX],
+    sequence: u32,
+    /// slots changed since their last write
+    dirty: [bool; BONDS_MAX],
+}
+impl<'a> BondStore<'a> {
+    /// load the bonds of the storage
+    pub fn new(storage: &'a mut dyn BondStorage) -> Self {
+        let mut store = Self { storage, bonds: [None; BONDS_MAX], used: [0; BONDS_MAX], sequence: 0, dirty: [false; BONDS_MAX] };
+        let mut record = [0; RECORD_SIZE];
+        for slot in 0..BONDS_MAX {
+            if ! store.storage.read(slot, &mut record) {
+                continue;
+            }
+            match Bond::read(&record) {
+                Some((bond, sequence)) => {
+                    store.bonds[slot] = Some(bond);
+                    store.used[slot] = sequence;
+                    store.sequence = store.sequence.max(sequence);
+                }
+                // unknown (i.e. corrupted) record
+                None => store.storage.erase(slot),
+            }
+        }
+        return store;
+    }
+
+    pub fn bonds(&self) -> impl Iterator<Item = &Bond> {
+        self.bonds.iter().flatten()
+    }
+
+    /// the bond of a peer (by its identity address)
+    pub fn find(&self, identity: &DeviceAddress) -> Option<&Bond> {
+        self.bonds().find(|bond| bond.identity == *identity)
+    }
+
+    /// the bond of a peer by its identity address or a resolvable private address (resolved with the
+    /// IRKs of the bonds)
+    pub fn resolve<R: AddressResolver>(&self, resolver: &mut R, address: &DeviceAddress) -> Option<&Bond> {
+        if let Some(bond) = self.find(address) {
+            return Some(bond);
+        }
+        if address.kind != AddressKind::ResolvablePrivate {
+            return None;
+        }
+        let mut irks = [[0; 16]; BONDS_MAX];
+        let mut slots = [0; BONDS_MAX];
+        let mut count = 0;
+        for (slot, bond) in self.bonds.iter().enumerate() {
+            if let Some(irk) = bond.and_then(|bond| bond.irk) {
+                irks[count] = irk;
+                slots[count] = slot;
+                count += 1;
+            }
+        }
+        return resolver.resolve(&irks[..count], address).and_then(|index| self.bonds[slots[index]].as_ref());
+    }
+
+    fn slot(&self, identity: &DeviceAddress) -> Option<usize> {
+        self.bonds.iter().position(|bond| bond.is_some_and(|bond| bond.identity == *identity))
+    }
+
+    /// mark a slot as the most recently used
+    fn use_slot(&mut self, slot: usize) {
+        if self.used[slot] != self.sequence {
+            self.sequence += 1;
+            self.used[slot] = self.sequence;
+        }
+    }
+
+    fn save(&mut self, slot: usize) {
+        self.dirty[slot] = false;
+        if let Some(bond) = self.bonds[slot] {
+            let mut record = [0; RECORD_SIZE];
+            bond.write(self.used[slot], &mut record);
+            self.storage.write(slot, &record);
+        }
+    }
+
+    /// store (or replace) the bond of a peer, returns the identity of the least recently used bond
+    /// evicted to make room
+    pub fn store(&mut self, bond: Bond) -> Option<DeviceAddress> {
+        let mut evicted = None;
+        let slot = match (self.slot(&bond.identity), self.bonds.iter().position(|bond| bond.is_none())) {
+            (Some(slot), _) | (None, Some(slot)) => slot,
+            (None, None) => {
+                let slot = (0..BONDS_MAX).min_by_key(|slot| self.used[*slot]).unwrap();
+                evicted = self.bonds[slot].map(|bond| bond.identity);
+                slot
+            }
+        };
+        self.bonds[slot] = Some(bond);
+        self.sequence += 1;
+        self.used[slot] = self.sequence;
+        self.save(slot);
+        return evicted;
+    }
+
+    /// the peer reconnected (it becomes the most recently used bond, without writing the storage)
+    pub fn touch(&mut self, identity: &DeviceAddress) {
+        if let Some(slot) = self.slot(identity) {
+            self.use_slot(slot);
+        }
+    }
+
+    /// update the Client Characteristic Configuration and signing counter of a bond (marked for flush
+    /// only if changed)
+    pub fn update(&mut self, identity: &DeviceAddress, cccds: ClientConfiguration, sign_counter: Option<u32>) {
+        let slot = match self.slot(identity) {
+            Some(slot) => slot,
+            None => return,
+        };
+        if let Some(bond) = self.bonds[slot].as_mut() {
+            let sign_counter = sign_counter.or(bond.sign_counter);
+            if bond.cccds != cccds || bond.sign_counter != sign_counter {
+                bond.cccds = cccds;
+                bond.sign_counter = sign_counter;
+                self.dirty[slot] = true;
+            }
+        }
+    }
+
+    /// true if updated bonds await a flush
+    pub fn has_work(&self) -> bool {
+        self.dirty.iter().any(|dirty| *dirty)
+    }
+
+    /// write the updated bonds to the storage, returns true if any were written
+    pub fn flush(&mut self) -> bool {
+        let mut written = false;
+        for slot in 0..BONDS_MAX {
+            if self.dirty[slot] {
+                self.save(slot);
+                written = true;
+            }
+        }
+        return written;
+    }
+
+    /// delete the bond of a peer, returns false if it wasn't bonded
+    pub fn remove(&mut self, identity: &DeviceAddress) -> bool {
+        return match self.slot(identity) {
+            Some(slot) => {
+                self.bonds[slot] = None;
+                self.dirty[slot] = false;
+                self.storage.erase(slot);
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// delete all bonds
+    pub fn clear(&mut self) {
+        for slot in 0..BONDS_MAX {
+            self.dirty[slot] = false;
+            if self.bonds[slot].take().is_some() {
+                self.storage.erase(slot);
+            }
+        }
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod bond_store {
+    use super::*;
+
+    fn bond(last: u8) -> Bond {
+        let keys = smp::Keys {
+            ltk: [last; 16],
+            key_size: 16,
+            ediv: 0x1234,
+            rand: [last; 8],
+            authenticated: true,
+            secure_connections: false,
+            bonded: true,
+            peer_irk: None,
+            peer_identity: None,
+            peer_csrk: None,
+        };
+        return Bond::new(DeviceAddress::random([1, 2, 3, 4, 5, 0xC0 | last]), &keys);
+    }
+
+    #[test]
+    fn persistence() {
+        let mut storage = MemoryStorage::new();
+        let mut stored = bond(1);
+        stored.irk = Some([0x11; 16]);
+        stored.csrk = Some([0x22; 16]);
+        stored.cccds[3] = crate::gatt::CCCD_INDICATION;
+        {
+            let mut store = BondStore::new(&mut storage);
+            assert_eq!(None, store.store(stored));
+            store.update(&stored.identity, stored.cccds, Some(7));
+            assert!(store.has_work());
+            assert!(store.flush());
+            assert!(! store.has_work());
+        }
+
+        // reboot
+        let store = BondStore::new(&mut storage);
+        let loaded = store.find(&stored.identity).unwrap();
+        assert_eq!(Some(7), loaded.sign_counter);
+        assert_eq!(Bond { sign_counter: Some(7), ..stored }, *loaded);
+        assert_eq!(1, store.bonds().count());
+        assert!(store.find(&DeviceAddress::public([1, 2, 3, 4, 5, 0xC1])).is_none());
+
+        // the peer reconnects with a resolvable private address
+        let rpa = crate::privacy::resolvable_private_address(&[0x11; 16], &[1, 2, 3]);
+        assert_eq!(Some(loaded), store.resolve(&mut crate::privacy::SoftwareResolver, &rpa));
+        let other = crate::privacy::resolvable_private_address(&[0x33; 16], &[1, 2, 3]);
+        assert_eq!(None, store.resolve(&mut crate::privacy::SoftwareResolver, &other));
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let mut storage = MemoryStorage::new();
+        {
+            let mut store = BondStore::new(&mut storage);
+            for last in 0..(BONDS_MAX as u8) {
+                assert_eq!(None, store.store(bond(last)));
+            }
+            // replacing an existing bond evicts none
+            assert_eq!(None, store.store(bond(1)));
+            // reconnecting reorders the bonds without a write
+            store.touch(&bond(0).identity);
+            assert!(! store.has_work());
+            assert_eq!(Some(bond(2).identity), store.store(bond(10)));
+            // the usage order is persisted with the next write of the bond
+            store.touch(&bond(3).identity);
+            store.update(&bond(3).identity, [1; CCCD_MAX], None);
+            store.flush();
+        }
+
+        // the usage order of the written bonds survives a reboot
+        let mut store = BondStore::new(&mut storage);
+        assert_eq!(Some(bond(0).identity), store.store(bond(11)));
+        assert_eq!(Some(bond(1).identity), store.store(bond(12)));
+        assert!(store.find(&bond(3).identity).is_some());
+
+        assert!(store.remove(&bond(3).identity));
+        assert!(! store.remove(&bond(3).identity));
+        assert_eq!(None, store.store(bond(13)));
+        store.clear();
+        assert_eq!(0, store.bonds().count());
+    }
+}
@@ -0,0 +1,230 @@
+use core::convert::TryFrom;
+
+use crate::link_layer::{Channel, DATA_CHANNEL_COUNT};
+
+/// all data channels used
+pub const ALL_DATA_CHANNELS:u64 = (1 << DATA_CHANNEL_COUNT) - 1;
+/// channel maps use at least 2 channels - Core_v5.3 Vol 6, Part B, 4.5.8.1
+pub const USED_CHANNELS_MIN:u32 = 2;
+
+/// when a data channel is classified bad
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Thresholds {
+    /// channels are classified once they have seen this many packets (received, CRC errors and missed)
+    pub packets_min: u16,
+    /// bad above this share (percent) of CRC errors
+    pub crc_error_percent_max: u8,
+    /// bad above this share (percent) of missed packets
+    pub missed_percent_max: u8,
+    /// bad below this average RSSI (dBm)
+    pub rssi_min: i8,
+    /// the counts of a channel are halved once they reach this many packets, so the classification
+    /// follows the recent quality
+    pub window: u16,
+}
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            packets_min: 8,
+            crc_error_percent_max: 25,
+            missed_percent_max: 25,
+            rssi_min: -90,
+            window: 64,
+        }
+    }
+}
+
+/// the quality observed on a data channel
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ChannelStats {
+    pub received: u16,
+    pub crc_errors: u16,
+    /// connection events without a packet from the peer
+    pub missed: u16,
+    /// running average (dBm) of the packets received with an RSSI
+    pub rssi: Option<i8>,
+}
+impl ChannelStats {
+    pub fn packets(&self) -> u16 {
+        return self.received.saturating_add(self.crc_errors).saturating_add(self.missed);
+    }
+
+    fn age(&mut self, window: u16) {
+        if self.packets() >= window {
+            self.received /= 2;
+            self.crc_errors /= 2;
+            self.missed /= 2;
+        }
+    }
+
+    fn average_rssi(&mut self, rssi: Option<i8>) {
+        self.rssi = match (self.rssi, rssi) {
+            // an eighth of each new sample
+            (Some(average), Some(rssi)) => Some((average as i16 + (rssi as i16 - average as i16) / 8) as i8),
+            (average, rssi) => rssi.or(average),
+        };
+    }
+}
+
+/// classifies the data channels from their packet loss and RSSI, combined with the host's classification
+/// (Core_v5.3 Vol 4, Part E, 7.8.19), into a channel map. Classification only: the channel map of a
+/// connection is updated by its central (LL_CHANNEL_MAP_IND), which this peripheral doesn't act as, so
+/// the map is for the host to pass on (see host_channel_classification).
+pub struct ChannelClassifier {
+    thresholds: Thresholds,
+    stats: [ChannelStats; DATA_CHANNEL_COUNT as usize],
+    /// the channels the host hasn't classified bad
+    host_map: u64,
+}
+impl ChannelClassifier {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            stats: [ChannelStats::default(); DATA_CHANNEL_COUNT as usize],
+            host_map: ALL_DATA_CHANNELS,
+        }
+    }
+
+    pub fn thresholds(&self) -> &Thresholds { &self.thresholds }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) { self.thresholds = thresholds; }
+
+    /// the channels the host classifies bad are cleared in the map (they are never used)
+    pub fn set_host_classification(&mut self, channel_map: u64) {
+        self.host_map = channel_map & ALL_DATA_CHANNELS;
+    }
+
+    /// forget the observed quality (i.e. for a new connection)
+    pub fn reset(&mut self) {
+        self.stats = [ChannelStats::default(); DATA_CHANNEL_COUNT as usize];
+    }
+
+    /// None for advertising channels
+    pub fn stats(&self, channel: Channel) -> Option<&ChannelStats> {
+        return self.stats.get(channel as usize);
+    }
+
+    pub fn received(&mut self, channel: Channel, rssi: Option<i8>) {
+        let window = self.thresholds.window;
+        if let Some(stats) = self.stats.get_mut(channel as usize) {
+            stats.age(window);
+            stats.received = stats.received.saturating_add(1);
+            stats.average_rssi(rssi);
+        }
+    }
+
+    pub fn crc_error(&mut self, channel: Channel, rssi: Option<i8>) {
+        let window = self.thresholds.window;
+        if let Some(stats) = self.stats.get_mut(channel as usize) {
+            stats.age(window);
+            stats.crc_errors = stats.crc_errors.saturating_add(1);
+            stats.average_rssi(rssi);
+        }
+    }
+
+    pub fn missed(&mut self, channel: Channel) {
+        let window = self.thresholds.window;
+        if let Some(stats) = self.stats.get_mut(channel as usize) {
+            stats.age(window);
+            stats.missed = stats.missed.saturating_add(1);
+        }
+    }
+
+    /// whether the observed quality of the channel is below the thresholds (regardless of the host)
+    pub fn is_bad(&self, channel: Channel) -> bool {
+        let stats = match self.stats(channel) {
+            Some(stats) => stats,
+            None => return false,
+        };
+        let packets = stats.packets() as u32;
+        if packets == 0 || packets < self.thresholds.packets_min as u32 {
+            return false;
+        }
+        let percent = |count:u16| count as u32 * 100 / packets;
+        return percent(stats.crc_errors) > self.thresholds.crc_error_percent_max as u32
+               || percent(stats.missed) > self.thresholds.missed_percent_max as u32
+               || stats.rssi.is_some_and(|rssi| rssi < self.thresholds.rssi_min);
+    }
+
+    /// the good channels the host allows, the host's classification alone if fewer than
+    /// USED_CHANNELS_MIN remain (or all channels if the host leaves fewer)
+    pub fn channel_map(&self) -> u64 {
+        let mut good = 0;
+        for channel in 0..DATA_CHANNEL_COUNT {
+            if ! self.is_bad(Channel::try_from(channel).unwrap()) {
+                good |= 1 << channel;
+            }
+        }
+        return match ((good & self.host_map).count_ones(), self.host_map.count_ones()) {
+            (used, _) if used >= USED_CHANNELS_MIN => good & self.host_map,
+            (_, used) if used >= USED_CHANNELS_MIN => self.host_map,
+            _ => ALL_DATA_CHANNELS,
+        };
+    }
+
+    /// the channel map as the Channel_Map parameter of HCI LE Set Host Channel Classification (bit 0
+    /// for CH0), e.g. for the host of the central - Core_v5.3 Vol 4, Part E, 7.8.19
+    pub fn host_channel_classification(&self) -> [u8; 5] {
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&self.channel_map().to_le_bytes()[..5]);
+        return channel_map;
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod channel_classification {
+    use super::*;
+
+    #[test]
+    fn classification() {
+        let mut classifier = ChannelClassifier::new(Thresholds::default());
+        // too few packets to classify
+        for _ in 0..4 {
+            classifier.crc_error(Channel::CH3, None);
+        }
+        assert!(! classifier.is_bad(Channel::CH3));
+        for _ in 0..4 {
+            classifier.received(Channel::CH3, None);
+            classifier.missed(Channel::CH5);
+            classifier.missed(Channel::CH5);
+            classifier.received(Channel::CH7, Some(-95));
+            classifier.received(Channel::CH7, Some(-95));
+        }
+        // half CRC errors, all missed and a weak signal
+        assert!(classifier.is_bad(Channel::CH3));
+        assert!(classifier.is_bad(Channel::CH5));
+        assert!(classifier.is_bad(Channel::CH7));
+        assert_eq!(Some(-95), classifier.stats(Channel::CH7).unwrap().rssi);
+        assert_eq!(ALL_DATA_CHANNELS & ! (1 << 3 | 1 << 5 | 1 << 7), classifier.channel_map());
+        assert_eq!([0x57, 0xFF, 0xFF, 0xFF, 0x1F], classifier.host_channel_classification());
+
+        // the channel recovers as old counts are halved
+        for _ in 0..64 {
+            classifier.received(Channel::CH3, None);
+        }
+        assert!(! classifier.is_bad(Channel::CH3));
+        assert!(classifier.stats(Channel::CH3).unwrap().packets() < 64);
+        assert_eq!(None, classifier.stats(Channel::CH37));
+    }
+
+    #[test]
+    fn host_classification() {
+        let mut classifier = ChannelClassifier::new(Thresholds::default());
+        classifier.set_host_classification(0b111);
+        for _ in 0..8 {
+            classifier.missed(Channel::CH0);
+        }
+        assert_eq!(0b110, classifier.channel_map());
+        // at least 2 channels remain used
+        for _ in 0..8 {
+            classifier.missed(Channel::CH1);
+        }
+        assert_eq!(0b111, classifier.channel_map());
+        classifier.set_host_classification(0b1);
+        assert_eq!(ALL_DATA_CHANNELS, classifier.channel_map());
+    }
+}
@@ -0,0 +1,983 @@
+use core::convert::TryFrom;
+
+use crate::link_layer::{self, AccessAddress, Channel, ConnectIndPdu, ControlOpcode, CrcInit, CteInfo,
+                        DataPduHeader, Llid, DeviceAddress, PDU_SIZE_MAX};
+use crate::l2cap;
+use crate::encryption::{Ccm, Session};
+
+/// largest LL data payload without the Data Length Extension
+/// Core_v5.3 Vol 6, Part B, 4.5.10
+pub const DATA_PAYLOAD_SIZE_MIN:usize = 27;
+
+/// Core_v5.3 Vol 6, Part B, 4.6 (FeatureSet, only LE Ping is supported)
+const FEATURES:u64 = 1 << 4;
+/// Core_v5.3 Vol 6, Part B, 4.6 (Connection CTE Response, once enabled see set_cte_response)
+const FEATURE_CONNECTION_CTE_RESPONSE:u64 = 1 << 18;
+/// Core_v5.3 Vol 6, Part B, 2.4.2.13 (Bluetooth Core 5.3, no company identifier)
+const VERSION:[u8;5] = [0x0C, 0xFF, 0xFF, 0x00, 0x00];
+/// L2CAP frames waiting to be sent (i.e. a segmented SDU and its credits)
+pub const L2CAP_QUEUE_SIZE:usize = 2 * l2cap::FRAME_SIZE_MAX;
+/// Core_v5.3 Vol 1, Part F, 2 (Remote User Terminated Connection)
+pub const REMOTE_USER_TERMINATED:u8 = 0x13;
+/// Core_v5.3 Vol 1, Part F, 2 (PIN or Key Missing)
+pub const PIN_OR_KEY_MISSING:u8 = 0x06;
+/// Core_v5.3 Vol 1, Part F, 2 (Connection Terminated due to MIC Failure)
+pub const MIC_FAILURE:u8 = 0x3D;
+/// Core_v5.3 Vol 1, Part F, 2 (Unsupported LL Parameter Value)
+pub const UNSUPPORTED_LL_PARAMETER_VALUE:u8 = 0x20;
+/// the unit (us) of the connection interval and the transmit window
+const INTERVAL_UNIT_US:u32 = 1250;
+/// CONNECT_IND on the LE 1M PHY (preamble, access address, 36 octet PDU and CRC)
+const CONNECT_IND_DURATION_US:u32 = 44 * 8;
+/// the sleep clock accuracy (ppm) of this peripheral
+const SCA_PPM:u32 = 50;
+/// a connection event the central didn't close is given up this long before the next anchor point
+const EVENT_CLOSE_MARGIN_US:u32 = 500;
+/// connection events without a packet of the central before the connection is established that end it
+/// Core_v5.3 Vol 6, Part B, 4.5.2
+const ESTABLISHMENT_EVENTS_MAX:u32 = 6;
+
+/// encryption start and pause procedures - Core_v5.3 Vol 6, Part B, 5.1.3
+#[derive(Copy, Clone, PartialEq)]
+enum EncryptionState {
+    Unencrypted,
+    /// LL_ENC_REQ received, waiting for the host's LTK (see start_encryption)
+    KeyRequest { ediv: u16, rand: [u8; 8] },
+    /// LL_ENC_RSP to send, followed by LL_START_ENC_REQ (or LL_REJECT_IND without a key)
+    Response { key: bool },
+    StartRequest,
+    Reject,
+    /// waiting for the central's LL_START_ENC_RSP
+    Starting,
+    /// LL_START_ENC_RSP to send
+    StartResponse,
+    Encrypted,
+    /// LL_PAUSE_ENC_RSP to send
+    PauseResponse,
+    /// waiting for the central's (unencrypted) LL_PAUSE_ENC_RSP
+    Pausing,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum TxState {
+    /// the last PDU was acknowledged
+    Free,
+    /// a PDU has been prepared but not sent
+    Staged,
+    /// a PDU has been sent and awaits acknowledgement
+    Sent,
+}
+
+/// Link Layer connection in the peripheral role
+/// Core_v5.3 Vol 6, Part B, 4.5
+pub struct Connection {
+    pub peer: DeviceAddress,
+    access_address: AccessAddress,
+    crc_init: CrcInit,
+    /// connection interval (1.25ms units)
+    interval: u16,
+    latency: u16,
+    /// supervision timeout (10ms units)
+    timeout: u16,
+    channel_map: u64,
+    hop: u8,
+    unmapped_channel: u8,
+    channel: Channel,
+    event_counter: u16,
+    /// time since the first connection event (1.25ms units)
+    clock: u32,
+    /// the time (us, of the radio's timer) the central's first packet of the last connection event it was
+    /// received in began, or the reference of the transmit window (see transmit_window)
+    anchor_us: u32,
+    /// connection events since the anchor point
+    anchor_events: u16,
+    /// the offset from the anchor point and the size (1.25ms units) of the transmit window of the first
+    /// connection event, or the one after a connection update
+    transmit_window: (u16, u8),
+    /// the central's sleep clock accuracy (ppm)
+    central_sca_ppm: u32,
+    /// a packet of the central was received in the current connection event
+    event_open: bool,
+    /// the clock of the last packet of the central, None until the connection is established
+    received_clock: Option<u32>,
+    /// channel map to apply at the instant
+    pending_channel_map: Option<(u64, u16)>,
+    /// connection parameters (interval, latency, timeout) to apply at the instant
+    pending_update: Option<(u16, u16, u16, u16)>,
+    /// the transmit window (offset, size) of the connection update
+    pending_window: (u16, u8),
+    /// sequence number
+    sn: bool,
+    /// next expected sequence number
+    nesn: bool,
+    /// the PDU being sent (retransmitted until acknowledged)
+    tx_state: TxState,
+    tx_llid: Llid,
+    /// the constant tone extension following the PDU being sent
+    tx_cte: Option<CteInfo>,
+    tx: [u8; PDU_SIZE_MAX],
+    tx_len: usize,
+    /// L2CAP frames waiting to be sent (fragmented into LL PDUs)
+    l2cap: [u8; L2CAP_QUEUE_SIZE],
+    l2cap_len: usize,
+    l2cap_sent: usize,
+    /// end of the frame being sent
+    frame_end: usize,
+    /// L2CAP frame being recombined
+    rx: [u8; l2cap::FRAME_SIZE_MAX],
+    rx_len: usize,
+    rx_fragment: usize,
+    version_sent: bool,
+    encryption: EncryptionState,
+    /// SKDm || SKDs and IVm || IVs (as sent)
+    skd: [u8; 16],
+    iv: [u8; 8],
+    session: Option<Session>,
+    rx_encrypted: bool,
+    tx_encrypted: bool,
+    /// the received PDU was decrypted (its packet counter is consumed once accepted)
+    rx_decrypted: bool,
+    /// reason of the local termination (sent once the transmit slot is free)
+    terminate: Option<u8>,
+    terminated: bool,
+    /// the CTE types (see CteType::bit) sent in response to LL_CTE_REQ, none if 0
+    cte_types: u8,
+    /// LL_CTE_REQ to send
+    cte_request: Option<CteInfo>,
+    /// the CTE requested from the central, until its LL_CTE_RSP (or rejection)
+    cte_requested: Option<CteInfo>,
+    /// the RSSI (dBm) of the last packet received from the central
+    rssi: Option<i8>,
+}
+impl Connection {
+    pub fn new(connect_ind: ConnectIndPdu) -> Self {
+        let ll_data = &connect_ind.ll_data;
+        let mut connection = Self {
+            peer: connect_ind.init_a,
+            access_address: ll_data.access_address,
+            crc_init: ll_data.crc_init,
+            interval: ll_data.interval,
+            latency: ll_data.latency,
+            timeout: ll_data.timeout,
+            channel_map: ll_data.channel_map,
+            hop: ll_data.hop,
+            unmapped_channel: 0,
+            channel: Channel::CH0,
+            // incremented to 0 for the first connection event
+            event_counter: u16::MAX,
+            clock: 0,
+            anchor_us: 0,
+            anchor_events: 0,
+            transmit_window: (ll_data.win_offset, ll_data.win_size),
+            // Core_v5.3 Vol 6, Part B, 2.3.3.1 (the upper bound of the SCA field's range)
+            central_sca_ppm: [500, 250, 150, 100, 75, 50, 30, 20][ll_data.sca as usize & 0b111],
+            event_open: false,
+            received_clock: None,
+            pending_channel_map: None,
+            pending_update: None,
+            pending_window: (0, 0),
+            sn: false,
+            nesn: false,
+            tx_state: TxState::Free,
+            tx_llid: Llid::Continuation,
+            tx_cte: None,
+            tx: [0; PDU_SIZE_MAX],
+            tx_len: 0,
+            l2cap: [0; L2CAP_QUEUE_SIZE],
+            l2cap_len: 0,
+            l2cap_sent: 0,
+            frame_end: 0,
+            rx: [0; l2cap::FRAME_SIZE_MAX],
+            rx_len: 0,
+            rx_fragment: 0,
+            version_sent: false,
+            encryption: EncryptionState::Unencrypted,
+            skd: [0; 16],
+            iv: [0; 8],
+            session: None,
+            rx_encrypted: false,
+            tx_encrypted: false,
+            rx_decrypted: false,
+            terminate: None,
+            terminated: false,
+            cte_types: 0,
+            cte_request: None,
+            cte_requested: None,
+            rssi: None,
+        };
+        connection.next_event();
+        connection.clock = 0;
+        connection.anchor_events = 0;
+        return connection;
+    }
+
+    pub fn access_address(&self) -> AccessAddress { self.access_address }
+    pub fn crc_init(&self) -> CrcInit { self.crc_init }
+    /// the channel of the current connection event
+    pub fn channel(&self) -> Channel { self.channel }
+    pub fn event_counter(&self) -> u16 { self.event_counter }
+    /// 37 bit bitmap of the used data channels
+    pub fn channel_map(&self) -> u64 { self.channel_map }
+    /// connection interval (1.25ms units)
+    pub fn interval(&self) -> u16 { self.interval }
+    pub fn latency(&self) -> u16 { self.latency }
+    /// supervision timeout (10ms units)
+    pub fn timeout(&self) -> u16 { self.timeout }
+    /// true once either side has terminated the connection
+    pub fn is_terminated(&self) -> bool { self.terminated }
+    /// time since the first connection event (ms)
+    pub fn elapsed_ms(&self) -> u32 { self.clock * 5 / 4 }
+
+    /// true once encryption has started (and until it is paused)
+    pub fn is_encrypted(&self) -> bool { self.encryption == EncryptionState::Encrypted }
+
+    /// the (EDIV, Rand) identifying the LTK the central requested (see start_encryption)
+    pub fn key_request(&self) -> Option<(u16, [u8; 8])> {
+        return match self.encryption {
+            EncryptionState::KeyRequest { ediv, rand } => Some((ediv, rand)),
+            _ => None,
+        };
+    }
+
+    /// reply to the key request with the LTK (little endian, None if missing) and 12 random octets (SKDs, IVs)
+    /// Core_v5.3 Vol 6, Part B, 5.1.3.1
+    pub fn start_encryption(&mut self, ltk: Option<&[u8; 16]>, random: &[u8; 12]) {
+        if self.key_request().is_none() {
+            return;
+        }
+        self.skd[8..].copy_from_slice(&random[..8]);
+        self.iv[4..].copy_from_slice(&random[8..]);
+        self.session = ltk.map(|ltk| Session::new(ltk, &self.skd, &self.iv));
+        self.encryption = EncryptionState::Response { key: self.session.is_some() };
+    }
+
+    /// decrypt a received (new) data PDU in place, returns false if its MIC doesn't match
+    pub(crate) fn decrypt<C: Ccm>(&mut self, buffer: &mut [u8], ccm: &mut C) -> bool {
+        let length = buffer[1] as usize;
+        if ! self.rx_encrypted || length == 0 {
+            return true;
+        }
+        let session = match self.session.as_ref() {
+            Some(session) => session,
+            None => return false,
+        };
+        let header = buffer[0];
+        // the payload follows the CTEInfo octet of a PDU with a CTE
+        let offset = DataPduHeader::read(buffer).map_or(DataPduHeader::SIZE, |header| header.size());
+        let payload = &mut buffer[offset..(offset + length)];
+        return match session.decrypt(ccm, header, payload, length) {
+            Some(length) => {
+                buffer[1] = length as u8;
+                self.rx_decrypted = true;
+                true
+            }
+            None => false,
+        };
+    }
+
+    /// end the connection without the termination procedure (i.e. upon a MIC failure)
+    pub(crate) fn abort(&mut self) {
+        self.terminated = true;
+    }
+
+    /// terminate the connection (with an HCI error code as the reason)
+    /// Core_v5.3 Vol 6, Part B, 5.1.6
+    pub fn terminate(&mut self, reason: u8) {
+        if self.terminate.is_none() {
+            self.terminate = Some(reason);
+        }
+    }
+
+    /// respond to the central's LL_CTE_REQ with the CTE types (see CteType::bit), none if 0
+    /// Core_v5.3 Vol 6, Part B, 5.1.23
+    pub fn set_cte_response(&mut self, cte_types: u8) {
+        self.cte_types = cte_types;
+    }
+
+    /// request a constant tone extension of at least the length from the central (to sample its IQ data),
+    /// returns false while a request is outstanding
+    /// Core_v5.3 Vol 6, Part B, 5.1.23
+    pub fn request_cte(&mut self, cte_info: CteInfo) -> bool {
+        if self.cte_request.is_some() || self.cte_requested.is_some() {
+            return false;
+        }
+        self.cte_request = Some(cte_info);
+        return true;
+    }
+
+    /// the CTE requested from the central (its LL_CTE_RSP is expected)
+    pub fn cte_requested(&self) -> Option<CteInfo> { self.cte_requested }
+
+    /// the constant tone extension following the PDU being sent
+    pub(crate) fn tx_cte(&self) -> Option<CteInfo> { self.tx_cte }
+
+    /// the RSSI (dBm) of the last packet received from the central (that the radio sampled)
+    pub fn rssi(&self) -> Option<i8> { self.rssi }
+
+    pub(crate) fn set_rssi(&mut self, rssi: Option<i8>) {
+        if rssi.is_some() {
+            self.rssi = rssi;
+        }
+    }
+
+    /// the transmit window of the first connection event follows the end of CONNECT_IND, which began at
+    /// the time (us) - Core_v5.3 Vol 6, Part B, 4.5.3
+    pub(crate) fn set_connect_ind_time(&mut self, timestamp_us: u32) {
+        // transmitWindowDelay
+        self.anchor_us = timestamp_us.wrapping_add(CONNECT_IND_DURATION_US + INTERVAL_UNIT_US);
+    }
+
+    /// a packet of the central (with a valid CRC) began at the time (us), the first of a connection event
+    /// is its anchor point - Core_v5.3 Vol 6, Part B, 4.5.1
+    pub(crate) fn received(&mut self, timestamp_us: u32) {
+        if ! self.event_open {
+            self.event_open = true;
+            self.anchor_us = timestamp_us;
+            self.anchor_events = 0;
+            self.transmit_window = (0, 0);
+        }
+        self.received_clock = Some(self.clock);
+    }
+
+    /// the time (us) by which the central's next packet has to begin: before the next anchor point within
+    /// an open connection event (the central closed it otherwise), or by the end of the transmit window of
+    /// the next one widened by the clock drift since the anchor point - Core_v5.3 Vol 6, Part B, 4.5.7
+    pub(crate) fn deadline_us(&self) -> u32 {
+        let interval_us = self.interval as u64 * INTERVAL_UNIT_US as u64;
+        if self.event_open {
+            return self.anchor_us.wrapping_add((interval_us as u32).saturating_sub(EVENT_CLOSE_MARGIN_US));
+        }
+        let (offset, size) = self.transmit_window;
+        let since_anchor_us = self.anchor_events as u64 * interval_us + offset as u64 * INTERVAL_UNIT_US as u64;
+        let widening_us = (self.central_sca_ppm + SCA_PPM) as u64 * since_anchor_us / 1_000_000 + 16;
+        let window_us = size as u64 * INTERVAL_UNIT_US as u64;
+        return self.anchor_us.wrapping_add((since_anchor_us + window_us + widening_us) as u32);
+    }
+
+    /// nothing of the central was received by the deadline (see deadline_us), it closed the connection
+    /// event or the event was missed, advances to the next connection event and returns the channel of a
+    /// missed one. The connection ends once the central hasn't been received for the supervision timeout
+    /// (or six connection events before it is established) - Core_v5.3 Vol 6, Part B, 4.5.2
+    pub(crate) fn receive_timeout(&mut self) -> Option<Channel> {
+        let missed = match self.event_open {
+            true => None,
+            false => Some(self.channel),
+        };
+        self.next_event();
+        let lost = match self.received_clock {
+            Some(received_clock) => self.clock - received_clock >= self.timeout as u32 * 8,
+            None => self.clock >= ESTABLISHMENT_EVENTS_MAX * self.interval as u32,
+        };
+        if lost {
+            self.terminated = true;
+        }
+        return missed;
+    }
+
+    /// advance to the next connection event (applying updates at their instant)
+    pub fn next_event(&mut self) {
+        self.event_counter = self.event_counter.wrapping_add(1);
+        self.clock = self.clock.saturating_add(self.interval as u32);
+        self.event_open = false;
+        self.anchor_events = self.anchor_events.saturating_add(1);
+        if let Some((channel_map, instant)) = self.pending_channel_map {
+            if instant == self.event_counter {
+                self.channel_map = channel_map;
+                self.pending_channel_map = None;
+            }
+        }
+        if let Some((interval, latency, timeout, instant)) = self.pending_update {
+            if instant == self.event_counter {
+                // the transmit window follows the anchor point of the instant at the old interval
+                // Core_v5.3 Vol 6, Part B, 5.1.1
+                self.anchor_us = self.anchor_us.wrapping_add((self.anchor_events as u32).wrapping_mul(self.interval as u32 * INTERVAL_UNIT_US));
+                self.anchor_events = 0;
+                self.transmit_window = self.pending_window;
+                self.interval = interval;
+                self.latency = latency;
+                self.timeout = timeout;
+                self.pending_update = None;
+            }
+        }
+        self.unmapped_channel = (self.unmapped_channel + self.hop) % link_layer::DATA_CHANNEL_COUNT;
+        self.channel = link_layer::select_data_channel(self.unmapped_channel, self.channel_map);
+    }
+
+    /// process the acknowledgement of a received PDU
+    pub(crate) fn acknowledge(&mut self, header: &DataPduHeader) {
+        if header.nesn != self.sn && self.tx_state == TxState::Sent {
+            self.sn = ! self.sn;
+            self.tx_state = TxState::Free;
+            // the connection ends once LL_TERMINATE_IND is acknowledged
+            if self.tx_llid == Llid::Control && self.tx[0] == ControlOpcode::LL_TERMINATE_IND as u8 {
+                self.terminated = true;
+            }
+        }
+    }
+
+    /// true if the received PDU carries new data (not a retransmission)
+    pub(crate) fn is_new(&self, header: &DataPduHeader) -> bool {
+        header.sn == self.nesn
+    }
+
+    /// acknowledge the received data (once it has been consumed)
+    pub(crate) fn accept(&mut self) {
+        self.nesn = ! self.nesn;
+        if self.rx_decrypted {
+            self.rx_decrypted = false;
+            if let Some(session) = self.session.as_mut() {
+                session.received();
+            }
+        }
+    }
+
+    /// recombine a received L2CAP fragment, returns the complete frame
+    /// Core_v5.3 Vol 6, Part B, 2.4 (LLID)
+    pub(crate) fn recombine(&mut self, llid: Llid, fragment: &[u8]) -> Option<&[u8]> {
+        match llid {
+            Llid::Start => self.rx_len = 0,
+            Llid::Continuation => if self.rx_len == 0 || self.rx_frame_len().is_some() {
+                // no frame to continue
+                return None;
+            }
+            Llid::Control => return None,
+        }
+        if (self.rx_len + fragment.len()) > self.rx.len() {
+            self.rx_len = 0;
+            return None;
+        }
+        self.rx[self.rx_len..(self.rx_len + fragment.len())].copy_from_slice(fragment);
+        self.rx_len += fragment.len();
+        self.rx_fragment = fragment.len();
+        return match self.rx_frame_len() {
+            Some(length) => Some(&self.rx[..length]),
+            None => None,
+        };
+    }
+
+    /// undo the last fragment (it wasn't accepted, the peer will retransmit it)
+    pub(crate) fn reject_fragment(&mut self) {
+        self.rx_len -= self.rx_fragment;
+        self.rx_fragment = 0;
+    }
+
+    /// the length of the recombined frame (None while incomplete)
+    fn rx_frame_len(&self) -> Option<usize> {
+        if self.rx_len < l2cap::HEADER_SIZE {
+            return None;
+        }
+        let length = l2cap::HEADER_SIZE + u16::from_le_bytes([self.rx[0], self.rx[1]]) as usize;
+        if self.rx_len < length {
+            return None;
+        }
+        return Some(length);
+    }
+
+    /// true if all queued L2CAP frames have been sent
+    pub fn can_send(&self) -> bool { self.l2cap_len == 0 }
+
+    /// queue an L2CAP frame (returns false if the queue is full)
+    pub fn send_l2cap(&mut self, cid: l2cap::ChannelId, payload: &[u8]) -> bool {
+        if (self.l2cap_len + l2cap::HEADER_SIZE + payload.len()) > self.l2cap.len() {
+            return false;
+        }
+        let size = l2cap::write_header(cid, payload.len(), &mut self.l2cap[self.l2cap_len..]);
+        self.l2cap[(self.l2cap_len + size)..(self.l2cap_len + size + payload.len())].copy_from_slice(payload);
+        self.l2cap_len += size + payload.len();
+        return true;
+    }
+
+    /// queue (complete) L2CAP frames (returns false if the queue is full)
+    pub fn send_l2cap_frames(&mut self, frames: &[u8]) -> bool {
+        if (self.l2cap_len + frames.len()) > self.l2cap.len() {
+            return false;
+        }
+        self.l2cap[self.l2cap_len..(self.l2cap_len + frames.len())].copy_from_slice(frames);
+        self.l2cap_len += frames.len();
+        return true;
+    }
+
+    /// true if more data is queued beyond the next PDU
+    pub(crate) fn has_more_data(&self) -> bool {
+        self.tx_state != TxState::Free && self.l2cap_len > 0
+    }
+
+    /// stage the next PDU of the encryption procedure, returns false if there is none
+    fn encryption_response(&mut self) -> bool {
+        match self.encryption {
+            EncryptionState::Response { key } => {
+                let mut data = [0; 12];
+                data[..8].copy_from_slice(&self.skd[8..]);
+                data[8..].copy_from_slice(&self.iv[4..]);
+                self.control_response(ControlOpcode::LL_ENC_RSP, &data);
+                self.encryption = if key { EncryptionState::StartRequest } else { EncryptionState::Reject };
+            }
+            EncryptionState::StartRequest => {
+                // sent unencrypted, the central's response is encrypted
+                self.control_response(ControlOpcode::LL_START_ENC_REQ, &[]);
+                self.rx_encrypted = true;
+                self.encryption = EncryptionState::Starting;
+            }
+            EncryptionState::Reject => {
+                self.control_response(ControlOpcode::LL_REJECT_IND, &[PIN_OR_KEY_MISSING]);
+                self.encryption = EncryptionState::Unencrypted;
+            }
+            EncryptionState::StartResponse => {
+                self.control_response(ControlOpcode::LL_START_ENC_RSP, &[]);
+                self.tx_encrypted = true;
+                self.encryption = EncryptionState::Encrypted;
+            }
+            EncryptionState::PauseResponse => {
+                // sent encrypted, the central's response is unencrypted
+                self.control_response(ControlOpcode::LL_PAUSE_ENC_RSP, &[]);
+                self.rx_encrypted = false;
+                self.encryption = EncryptionState::Pausing;
+            }
+            _ => return false,
+        }
+        return true;
+    }
+
+    /// true while the encryption procedure holds back data PDUs
+    fn is_encryption_pending(&self) -> bool {
+        ! matches!(self.encryption, EncryptionState::Unencrypted | EncryptionState::Encrypted)
+    }
+
+    /// write the next PDU to send (retransmitting the unacknowledged PDU), returns its size
+    pub(crate) fn transmit<C: Ccm>(&mut self, buffer: &mut [u8], ccm: &mut C) -> usize {
+        if self.tx_state == TxState::Free {
+            // send the queued L2CAP frames, otherwise an empty PDU
+            if let Some(reason) = self.terminate {
+                self.control_response(ControlOpcode::LL_TERMINATE_IND, &[reason]);
+            } else if self.encryption_response() {
+                // control PDU staged
+            } else if let (Some(cte_info), false) = (self.cte_request, self.is_encryption_pending()) {
+                self.control_response(ControlOpcode::LL_CTE_REQ, &[cte_info.octet()]);
+                self.cte_request = None;
+                self.cte_requested = Some(cte_info);
+            } else if self.l2cap_len > 0 && ! self.is_encryption_pending() {
+                // fragment the frames (no Data Length Extension)
+                if self.l2cap_sent == self.frame_end {
+                    let length = u16::from_le_bytes([self.l2cap[self.l2cap_sent], self.l2cap[self.l2cap_sent + 1]]) as usize;
+                    self.frame_end = self.l2cap_sent + l2cap::HEADER_SIZE + length;
+                    self.tx_llid = Llid::Start;
+                } else {
+                    self.tx_llid = Llid::Continuation;
+                }
+                let size = core::cmp::min(DATA_PAYLOAD_SIZE_MIN, self.frame_end - self.l2cap_sent);
+                self.tx[..size].copy_from_slice(&self.l2cap[self.l2cap_sent..(self.l2cap_sent + size)]);
+                self.tx_len = size;
+                self.tx_cte = None;
+                self.l2cap_sent += size;
+                if self.l2cap_sent == self.l2cap_len {
+                    self.l2cap_len = 0;
+                    self.l2cap_sent = 0;
+                    self.frame_end = 0;
+                }
+            } else {
+                self.tx_len = 0;
+                self.tx_llid = Llid::Continuation;
+                self.tx_cte = None;
+            }
+        }
+        // encrypt new PDUs, empty PDUs aren't encrypted (Core_v5.3 Vol 6, Part E, 1)
+        if self.tx_state != TxState::Sent && self.tx_encrypted && self.tx_len > 0 {
+            if let Some(session) = self.session.as_mut() {
+                self.tx_len = session.encrypt(ccm, self.tx_llid as u8, &mut self.tx, self.tx_len);
+            }
+        }
+        self.tx_state = TxState::Sent;
+
+        let header = DataPduHeader {
+            llid: self.tx_llid,
+            nesn: self.nesn,
+            sn: self.sn,
+            md: self.l2cap_len > 0 && ! self.is_encryption_pending(),
+            length: self.tx_len as u8,
+            cte_info: self.tx_cte,
+        };
+        let size = header.write(buffer);
+        buffer[size..(size + self.tx_len)].copy_from_slice(&self.tx[..self.tx_len]);
+        return size + self.tx_len;
+    }
+
+    /// stage an LL control PDU
+    fn control_response(&mut self, opcode: ControlOpcode, data: &[u8]) {
+        self.tx[0] = opcode as u8;
+        self.tx[1..(1 + data.len())].copy_from_slice(data);
+        self.tx_len = 1 + data.len();
+        self.tx_llid = Llid::Control;
+        self.tx_cte = None;
+        self.tx_state = TxState::Staged;
+    }
+
+    /// handle an LL control PDU, returns false if it can't be accepted yet
+    /// Core_v5.3 Vol 6, Part B, 5.1
+    pub(crate) fn control(&mut self, payload: &[u8]) -> bool {
+        if payload.is_empty() {
+            return true;
+        }
+        // responses require the transmit slot
+        if self.tx_state != TxState::Free {
+            return false;
+        }
+        let data = &payload[1..];
+        match ControlOpcode::try_from(payload[0]) {
+            Ok(ControlOpcode::LL_CONNECTION_UPDATE_IND) => if data.len() >= 11 {
+                self.pending_window = (u16::from_le_bytes([data[1], data[2]]), data[0]);
+                self.pending_update = Some((
+                    u16::from_le_bytes([data[3], data[4]]),
+                    u16::from_le_bytes([data[5], data[6]]),
+                    u16::from_le_bytes([data[7], data[8]]),
+                    u16::from_le_bytes([data[9], data[10]]),
+                ));
+            }
+            Ok(ControlOpcode::LL_CHANNEL_MAP_IND) => if data.len() >= 7 {
+                let mut channel_map = [0; 8];
+                channel_map[..5].copy_from_slice(&data[..5]);
+                self.pending_channel_map = Some((u64::from_le_bytes(channel_map), u16::from_le_bytes([data[5], data[6]])));
+            }
+            Ok(ControlOpcode::LL_TERMINATE_IND) => {
+                self.terminated = true;
+            }
+            Ok(ControlOpcode::LL_ENC_REQ) => if data.len() >= 22 && self.encryption == EncryptionState::Unencrypted {
+                let mut rand = [0; 8];
+                rand.copy_from_slice(&data[..8]);
+                self.skd[..8].copy_from_slice(&data[10..18]);
+                self.iv[..4].copy_from_slice(&data[18..22]);
+                self.encryption = EncryptionState::KeyRequest { ediv: u16::from_le_bytes([data[8], data[9]]), rand };
+            }
+            Ok(ControlOpcode::LL_START_ENC_RSP) => if self.encryption == EncryptionState::Starting {
+                self.encryption = EncryptionState::StartResponse;
+            }
+            Ok(ControlOpcode::LL_PAUSE_ENC_REQ) => if self.encryption == EncryptionState::Encrypted {
+                self.encryption = EncryptionState::PauseResponse;
+            }
+            Ok(ControlOpcode::LL_PAUSE_ENC_RSP) => if self.encryption == EncryptionState::Pausing {
+                self.tx_encrypted = false;
+                self.session = None;
+                self.encryption = EncryptionState::Unencrypted;
+            }
+            Ok(ControlOpcode::LL_FEATURE_REQ) | Ok(ControlOpcode::LL_PERIPHERAL_FEATURE_REQ) => {
+                let features = match self.cte_types {
+                    0 => FEATURES,
+                    _ => FEATURES | FEATURE_CONNECTION_CTE_RESPONSE,
+                };
+                self.control_response(ControlOpcode::LL_FEATURE_RSP, &features.to_le_bytes());
+            }
+            Ok(ControlOpcode::LL_VERSION_IND) => {
+                // only sent once per connection
+                if ! self.version_sent {
+                    self.version_sent = true;
+                    self.control_response(ControlOpcode::LL_VERSION_IND, &VERSION);
+                }
+            }
+            Ok(ControlOpcode::LL_PING_REQ) => {
+                self.control_response(ControlOpcode::LL_PING_RSP, &[]);
+            }
+            Ok(ControlOpcode::LL_LENGTH_REQ) => {
+                // no Data Length Extension (27 octets, 328us)
+                let mut lengths = [0; 8];
+                lengths[0..2].copy_from_slice(&(DATA_PAYLOAD_SIZE_MIN as u16).to_le_bytes());
+                lengths[2..4].copy_from_slice(&328_u16.to_le_bytes());
+                lengths[4..6].copy_from_slice(&(DATA_PAYLOAD_SIZE_MIN as u16).to_le_bytes());
+                lengths[6..8].copy_from_slice(&328_u16.to_le_bytes());
+                self.control_response(ControlOpcode::LL_LENGTH_RSP, &lengths);
+            }
+            Ok(ControlOpcode::LL_CTE_REQ) => if ! data.is_empty() {
+                // the minimum length and type requested (laid out as CTEInfo)
+                match CteInfo::from_octet(data[0]) {
+                    Some(cte_info) if self.cte_types & cte_info.cte_type.bit() != 0 => {
+                        self.control_response(ControlOpcode::LL_CTE_RSP, &[]);
+                        self.tx_cte = Some(cte_info);
+                    }
+                    _ => {
+                        self.control_response(ControlOpcode::LL_REJECT_EXT_IND,
+                                              &[ControlOpcode::LL_CTE_REQ as u8, UNSUPPORTED_LL_PARAMETER_VALUE]);
+                    }
+                }
+            }
+            Ok(ControlOpcode::LL_CTE_RSP) => {
+                self.cte_requested = None;
+            }
+            // the central doesn't support (or rejected) the CTE request
+            Ok(ControlOpcode::LL_UNKNOWN_RSP) | Ok(ControlOpcode::LL_REJECT_EXT_IND)
+                if data.first() == Some(&(ControlOpcode::LL_CTE_REQ as u8)) => {
+                self.cte_requested = None;
+            }
+            Ok(ControlOpcode::LL_UNKNOWN_RSP)
+            | Ok(ControlOpcode::LL_REJECT_IND)
+            | Ok(ControlOpcode::LL_FEATURE_RSP)
+            | Ok(ControlOpcode::LL_PING_RSP)
+            | Ok(ControlOpcode::LL_LENGTH_RSP) => { /* responses to procedures we don't initiate */ }
+            _ => {
+                self.control_response(ControlOpcode::LL_UNKNOWN_RSP, &[payload[0]]);
+            }
+        }
+        return true;
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod connection_peripheral {
+    use super::*;
+    use crate::encryption::SoftwareCcm;
+    use crate::link_layer::LlData;
+
+    fn connection(hop: u8, channel_map: u64) -> Connection {
+        Connection::new(ConnectIndPdu {
+            init_a: DeviceAddress::public([0; 6]),
+            adv_a: DeviceAddress::public([0; 6]),
+            ll_data: LlData {
+                access_address: 0x12345678, crc_init: 0x555555, win_size: 1, win_offset: 0,
+                interval: 24, latency: 0, timeout: 100, channel_map, hop, sca: 0,
+            },
+        })
+    }
+
+    #[test]
+    fn channel_selection_1() {
+        // Core_v5.3 Vol 6, Part C, 3.1 (all channels used, hop 10)
+        let mut connection = connection(10, 0x1F_FFFF_FFFF);
+        assert_eq!(Channel::CH10, connection.channel());
+        connection.next_event();
+        assert_eq!(Channel::CH20, connection.channel());
+        connection.next_event();
+        assert_eq!(Channel::CH30, connection.channel());
+        connection.next_event();
+        assert_eq!(Channel::CH3, connection.channel());
+    }
+    #[test]
+    fn channel_selection_1_remapped() {
+        // only channels 0-8 used, unmapped channel 10 remaps to index (10 % 9)
+        let connection = connection(10, 0x1FF);
+        assert_eq!(Channel::CH1, connection.channel());
+    }
+    #[test]
+    fn acknowledgement() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        // empty PDU from the central (sn:0, nesn:0)
+        let header = DataPduHeader{ llid:Llid::Continuation, nesn:false, sn:false, md:false, length:0, cte_info:None };
+        connection.acknowledge(&header);
+        assert!(connection.is_new(&header));
+        connection.accept();
+        assert!(connection.send_l2cap(l2cap::ATT_CID, &[0x13]));
+        assert_eq!(7, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([(Llid::Start as u8) | (1 << 2), 5, 1, 0, 4, 0, 0x13], buffer[..7]);
+        // not acknowledged, retransmit
+        connection.acknowledge(&header);
+        assert_eq!(7, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        // acknowledged (nesn:1), send an empty PDU
+        let header = DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:true, md:false, length:0, cte_info:None };
+        connection.acknowledge(&header);
+        assert!(connection.is_new(&header));
+        connection.accept();
+        assert_eq!(2, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([(Llid::Continuation as u8) | (1 << 3), 0], buffer[..2]);
+    }
+    #[test]
+    fn unknown_control() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        assert!(connection.control(&[0xEE]));
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_UNKNOWN_RSP as u8, 0xEE], buffer[2..4]);
+    }
+    #[test]
+    fn cte_response() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        let request = CteInfo { time: 20, cte_type: link_layer::CteType::AoD2us };
+        // rejected until enabled
+        assert!(connection.control(&[ControlOpcode::LL_CTE_REQ as u8, request.octet()]));
+        assert_eq!(5, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_REJECT_EXT_IND as u8, ControlOpcode::LL_CTE_REQ as u8, UNSUPPORTED_LL_PARAMETER_VALUE], buffer[2..5]);
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:false, md:false, length:0, cte_info:None });
+
+        // LL_CTE_RSP followed by the requested CTE (the CP bit and CTEInfo)
+        connection.set_cte_response(link_layer::CteType::AoA.bit() | link_layer::CteType::AoD2us.bit());
+        assert!(connection.control(&[ControlOpcode::LL_CTE_REQ as u8, request.octet()]));
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([0x2B, 1, request.octet(), ControlOpcode::LL_CTE_RSP as u8], buffer[..4]);
+        assert_eq!(Some(request), DataPduHeader::read(&buffer).unwrap().cte_info);
+        // retransmitted with the CTE
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!(Some(request), connection.tx_cte());
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:false, sn:false, md:false, length:0, cte_info:None });
+        assert_eq!(2, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!(None, connection.tx_cte());
+    }
+    #[test]
+    fn cte_request() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        let request = CteInfo { time: 2, cte_type: link_layer::CteType::AoA };
+        assert!(connection.request_cte(request));
+        assert!(! connection.request_cte(request));
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_CTE_REQ as u8, 0x02], buffer[2..4]);
+        assert_eq!(Some(request), connection.cte_requested());
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:false, md:false, length:0, cte_info:None });
+        assert!(connection.control(&[ControlOpcode::LL_CTE_RSP as u8]));
+        assert_eq!(None, connection.cte_requested());
+
+        // a central without CTE support
+        assert!(connection.request_cte(request));
+        connection.transmit(&mut buffer, &mut SoftwareCcm);
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:false, sn:false, md:false, length:0, cte_info:None });
+        assert!(connection.control(&[ControlOpcode::LL_UNKNOWN_RSP as u8, ControlOpcode::LL_CTE_REQ as u8]));
+        assert_eq!(None, connection.cte_requested());
+    }
+    #[test]
+    fn channel_map_instant() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        assert!(connection.control(&[ControlOpcode::LL_CHANNEL_MAP_IND as u8, 0x01, 0, 0, 0, 0, 2, 0]));
+        connection.next_event();
+        assert_eq!(Channel::CH10, connection.channel());
+        // only CH0 remains at the instant
+        connection.next_event();
+        assert_eq!(Channel::CH0, connection.channel());
+    }
+    #[test]
+    fn local_termination() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        connection.terminate(REMOTE_USER_TERMINATED);
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_TERMINATE_IND as u8, REMOTE_USER_TERMINATED], buffer[2..4]);
+        assert!(! connection.is_terminated());
+        // terminated once acknowledged
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:false, md:false, length:0, cte_info:None });
+        assert!(connection.is_terminated());
+    }
+    #[test]
+    fn fragmentation() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        assert!(connection.send_l2cap(l2cap::ATT_CID, &[0x55; 30]));
+        assert_eq!(2 + DATA_PAYLOAD_SIZE_MIN, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([(Llid::Start as u8) | (1 << 4), DATA_PAYLOAD_SIZE_MIN as u8, 30, 0, 4, 0], buffer[..6]);
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:false, md:false, length:0, cte_info:None });
+        assert_eq!(2 + 7, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([Llid::Continuation as u8 | (1 << 3), 7], buffer[..2]);
+        assert!(connection.can_send());
+    }
+    #[test]
+    fn encryption_start() {
+        // Core_v5.3 Vol 6, Part C, 1 (sample data)
+        const LTK:[u8; 16] = [0xBF, 0x01, 0xFB, 0x9D, 0x4E, 0xF3, 0xBC, 0x36, 0xD8, 0x74, 0xF5, 0x39, 0x41, 0x38, 0x68, 0x4C];
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        let ack = |sn: bool| DataPduHeader{ llid:Llid::Continuation, nesn: ! sn, sn:false, md:false, length:0, cte_info:None };
+
+        // LL_ENC_REQ (Rand, EDIV, SKDm, IVm)
+        assert!(connection.control(&[ControlOpcode::LL_ENC_REQ as u8,
+                                     0x90, 0x78, 0x56, 0x34, 0x12, 0xEF, 0xCD, 0xAB, 0x74, 0x24,
+                                     0x13, 0x02, 0xF1, 0xE0, 0xDF, 0xCE, 0xBD, 0xAC,
+                                     0x24, 0xAB, 0xDC, 0xBA]));
+        assert_eq!(Some((0x2474, [0x90, 0x78, 0x56, 0x34, 0x12, 0xEF, 0xCD, 0xAB])), connection.key_request());
+        // queued data waits for the procedure
+        assert!(connection.send_l2cap(l2cap::ATT_CID, &[0x13]));
+        assert_eq!(2, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        connection.acknowledge(&ack(false));
+
+        // LL_ENC_RSP (SKDs, IVs) then LL_START_ENC_REQ
+        connection.start_encryption(Some(&LTK), &[0x79, 0x68, 0x57, 0x46, 0x35, 0x24, 0x13, 0x02, 0xBE, 0xBA, 0xAF, 0xDE]);
+        assert_eq!(2 + 13, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_ENC_RSP as u8, 0x79, 0x68], buffer[2..5]);
+        connection.acknowledge(&ack(true));
+        assert_eq!(3, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!(ControlOpcode::LL_START_ENC_REQ as u8, buffer[2]);
+        connection.acknowledge(&ack(false));
+
+        // the central's (encrypted) LL_START_ENC_RSP
+        let mut pdu = [0x0F, 5, 0x9F, 0xCD, 0xA7, 0xF4, 0x48];
+        assert!(connection.decrypt(&mut pdu, &mut SoftwareCcm));
+        assert_eq!([0x0F, 1, ControlOpcode::LL_START_ENC_RSP as u8], pdu[..3]);
+        assert!(connection.control(&pdu[2..3]));
+        connection.accept();
+
+        // the encrypted LL_START_ENC_RSP
+        assert_eq!(2 + 5, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([0xA3, 0x4C, 0x13, 0xA4, 0x15], buffer[2..7]);
+        assert!(connection.is_encrypted());
+
+        // a tampered PDU fails the MIC
+        let mut pdu = [0x0E, 5, 0x9F, 0xCD, 0xA7, 0xF4, 0x48];
+        assert!(! connection.decrypt(&mut pdu, &mut SoftwareCcm));
+    }
+    #[test]
+    fn missing_key() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        assert!(connection.control(&[ControlOpcode::LL_ENC_REQ as u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        connection.start_encryption(None, &[0; 12]);
+        assert_eq!(2 + 13, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        connection.acknowledge(&DataPduHeader{ llid:Llid::Continuation, nesn:true, sn:false, md:false, length:0, cte_info:None });
+        assert_eq!(4, connection.transmit(&mut buffer, &mut SoftwareCcm));
+        assert_eq!([ControlOpcode::LL_REJECT_IND as u8, PIN_OR_KEY_MISSING], buffer[2..4]);
+        assert!(! connection.is_encrypted());
+    }
+    #[test]
+    fn recombination() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        assert_eq!(None, connection.recombine(Llid::Continuation, &[1, 2]));
+        assert_eq!(None, connection.recombine(Llid::Start, &[3, 0, 4, 0, 0x0A]));
+        // rejected, then retransmitted
+        assert_eq!(Some(&[3, 0, 4, 0, 0x0A, 0x0B, 0x0C][..]), connection.recombine(Llid::Continuation, &[0x0B, 0x0C]));
+        connection.reject_fragment();
+        assert_eq!(Some(&[3, 0, 4, 0, 0x0A, 0x0B, 0x0C][..]), connection.recombine(Llid::Continuation, &[0x0B, 0x0C]));
+    }
+    #[test]
+    fn anchor_timing() {
+        // 30ms interval, 500ppm central
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        // the transmit window (1.25ms) follows CONNECT_IND and transmitWindowDelay
+        connection.set_connect_ind_time(1000);
+        assert_eq!(1000 + 352 + 1250 + 1250 + 16, connection.deadline_us());
+        // the central's first packet anchors the connection event, which it closes before the next one
+        connection.received(5000);
+        connection.received(5500);
+        assert_eq!(5000 + 30000 - 500, connection.deadline_us());
+        // widened by the drift of the interval
+        connection.next_event();
+        assert_eq!(5000 + 30000 + 16 + 16, connection.deadline_us());
+        // the transmit window of the update follows the anchor point of its instant
+        assert!(connection.control(&[ControlOpcode::LL_CONNECTION_UPDATE_IND as u8, 2, 4, 0, 40, 0, 0, 0, 100, 0, 2, 0]));
+        connection.received(35000);
+        connection.next_event();
+        assert_eq!(40, connection.interval());
+        assert_eq!(35000 + 30000 + 5000 + 2500 + 2 + 16, connection.deadline_us());
+    }
+    #[test]
+    fn supervision_timeout() {
+        // 30ms interval, 1s supervision timeout
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        connection.received(0);
+        // the central closed the connection event
+        assert_eq!(None, connection.receive_timeout());
+        for _ in 0..32 {
+            let channel = connection.channel();
+            assert_eq!(Some(channel), connection.receive_timeout());
+        }
+        assert!(! connection.is_terminated());
+        assert!(connection.receive_timeout().is_some());
+        assert!(connection.is_terminated());
+    }
+    #[test]
+    fn establishment_timeout() {
+        let mut connection = connection(5, 0x1F_FFFF_FFFF);
+        for _ in 0..5 {
+            assert!(connection.receive_timeout().is_some());
+        }
+        assert!(! connection.is_terminated());
+        connection.receive_timeout();
+        assert!(connection.is_terminated());
+    }
+}
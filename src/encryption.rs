@@ -0,0 +1,151 @@
+use aes::Aes128;
+use aes::cipher::{BlockEncrypt, generic_array::GenericArray};
+use ccm::{AeadInPlace, KeyInit};
+use ccm::consts::{U4, U13};
+
+/// Message Integrity Check appended to encrypted payloads
+/// Core_v5.3 Vol 6, Part E, 1
+pub const MIC_SIZE:usize = 4;
+/// the header bits authenticated by the MIC (LLID, CP and RFU)
+/// Core_v5.3 Vol 6, Part E, 2.2
+const AAD_MASK:u8 = 0xE3;
+
+/// AES-CCM for LL data PDUs (key MSB first, nonce as per Core_v5.3 Vol 6, Part E, 2.1)
+pub trait Ccm {
+    /// encrypt the payload in place appending the MIC (payload has room for MIC_SIZE more octets),
+    /// returns the encrypted length
+    fn encrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> usize;
+
+    /// decrypt the payload (including the MIC) in place, returns the decrypted length
+    /// (None if the MIC doesn't match)
+    fn decrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> Option<usize>;
+}
+
+/// AES-CCM in software (for hardware without a CCM peripheral)
+pub struct SoftwareCcm;
+type Aes128Ccm = ccm::Ccm<Aes128, U4, U13>;
+impl Ccm for SoftwareCcm {
+    fn encrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> usize {
+        let cipher = Aes128Ccm::new(GenericArray::from_slice(key));
+        let mic = cipher.encrypt_in_place_detached(GenericArray::from_slice(nonce), &[header & AAD_MASK], &mut payload[..length])
+                        .unwrap();
+        payload[length..(length + MIC_SIZE)].copy_from_slice(&mic);
+        return length + MIC_SIZE;
+    }
+
+    fn decrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> Option<usize> {
+        if length < MIC_SIZE {
+            return None;
+        }
+        let length = length - MIC_SIZE;
+        let mut mic = [0; MIC_SIZE];
+        mic.copy_from_slice(&payload[length..(length + MIC_SIZE)]);
+        let cipher = Aes128Ccm::new(GenericArray::from_slice(key));
+        return match cipher.decrypt_in_place_detached(GenericArray::from_slice(nonce), &[header & AAD_MASK],
+                                                      &mut payload[..length], GenericArray::from_slice(&mic)) {
+            Ok(()) => Some(length),
+            Err(_) => None,
+        };
+    }
+}
+
+/// the keys and packet counters of an encrypted connection
+/// Core_v5.3 Vol 6, Part B, 5.1.3.1
+pub struct Session {
+    /// session key (MSB first)
+    key: [u8; 16],
+    /// IVm followed by IVs (as sent)
+    iv: [u8; 8],
+    tx_counter: u64,
+    rx_counter: u64,
+}
+impl Session {
+    /// derive the session from the LTK (little endian), SKDm || SKDs and IVm || IVs (as sent)
+    pub fn new(ltk: &[u8; 16], skd: &[u8; 16], iv: &[u8; 8]) -> Self {
+        // SK = e(LTK, SKD) with MSB first values
+        let mut key = [0; 16];
+        for (to, from) in key.iter_mut().zip(ltk.iter().rev()) {
+            *to = *from;
+        }
+        let mut block = GenericArray::clone_from_slice(skd);
+        block.reverse();
+        Aes128::new(GenericArray::from_slice(&key)).encrypt_block(&mut block);
+
+        let mut session = Self { key: [0; 16], iv: *iv, tx_counter: 0, rx_counter: 0 };
+        session.key.copy_from_slice(&block);
+        return session;
+    }
+
+    /// the session key (MSB first)
+    pub fn key(&self) -> &[u8; 16] { &self.key }
+
+    /// the CCM nonce of a packet - Core_v5.3 Vol 6, Part E, 2.1
+    pub fn nonce(&self, counter: u64, from_central: bool) -> [u8; 13] {
+        let mut nonce = [0; 13];
+        nonce[..5].copy_from_slice(&counter.to_le_bytes()[..5]);
+        nonce[4] = (nonce[4] & 0x7F) | ((from_central as u8) << 7);
+        nonce[5..].copy_from_slice(&self.iv);
+        return nonce;
+    }
+
+    /// encrypt the next (non-empty) payload to the central, returns its length including the MIC
+    pub(crate) fn encrypt<C: Ccm>(&mut self, ccm: &mut C, header: u8, payload: &mut [u8], length: usize) -> usize {
+        let nonce = self.nonce(self.tx_counter, false);
+        self.tx_counter += 1;
+        return ccm.encrypt(&self.key, &nonce, header, payload, length);
+    }
+
+    /// decrypt the next (non-empty) payload from the central (see received)
+    pub(crate) fn decrypt<C: Ccm>(&self, ccm: &mut C, header: u8, payload: &mut [u8], length: usize) -> Option<usize> {
+        let nonce = self.nonce(self.rx_counter, true);
+        return ccm.decrypt(&self.key, &nonce, header, payload, length);
+    }
+
+    /// the decrypted payload was accepted (retransmissions reuse the packet counter)
+    pub(crate) fn received(&mut self) {
+        self.rx_counter += 1;
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod aes_ccm {
+    use super::*;
+
+    // Core_v5.3 Vol 6, Part C, 1 (sample data)
+    const LTK:[u8; 16] = [0xBF, 0x01, 0xFB, 0x9D, 0x4E, 0xF3, 0xBC, 0x36, 0xD8, 0x74, 0xF5, 0x39, 0x41, 0x38, 0x68, 0x4C];
+    const SKD:[u8; 16] = [0x13, 0x02, 0xF1, 0xE0, 0xDF, 0xCE, 0xBD, 0xAC, 0x79, 0x68, 0x57, 0x46, 0x35, 0x24, 0x13, 0x02];
+    const IV:[u8; 8] = [0x24, 0xAB, 0xDC, 0xBA, 0xBE, 0xBA, 0xAF, 0xDE];
+
+    #[test]
+    fn session_key() {
+        let session = Session::new(&LTK, &SKD, &IV);
+        assert_eq!(session.key(), &[0x99, 0xAD, 0x1B, 0x52, 0x26, 0xA3, 0x7E, 0x3E,
+                                    0x05, 0x8E, 0x3B, 0x8E, 0x27, 0xC2, 0xC6, 0x66]);
+        assert_eq!(session.nonce(0, true), [0, 0, 0, 0, 0x80, 0x24, 0xAB, 0xDC, 0xBA, 0xBE, 0xBA, 0xAF, 0xDE]);
+    }
+
+    #[test]
+    fn start_enc_rsp() {
+        let mut session = Session::new(&LTK, &SKD, &IV);
+        let mut ccm = SoftwareCcm;
+
+        // LL_START_ENC_RSP1 (central to peripheral)
+        let mut payload = [0x9F, 0xCD, 0xA7, 0xF4, 0x48];
+        assert_eq!(Some(1), session.decrypt(&mut ccm, 0x0F, &mut payload, 5));
+        assert_eq!(0x06, payload[0]);
+        session.received();
+
+        // LL_START_ENC_RSP2 (peripheral to central)
+        let mut payload = [0x06, 0, 0, 0, 0];
+        assert_eq!(5, session.encrypt(&mut ccm, 0x07, &mut payload, 1));
+        assert_eq!([0xA3, 0x4C, 0x13, 0xA4, 0x15], payload);
+
+        // tampered
+        let mut payload = [0x9F, 0xCD, 0xA7, 0xF4, 0x49];
+        assert_eq!(None, Session::new(&LTK, &SKD, &IV).decrypt(&mut ccm, 0x0F, &mut payload, 5));
+    }
+}
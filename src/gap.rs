@@ -1,9 +1,18 @@
+use crate::link_layer::{Address, BigInfo, DeviceAddress, ADV_DATA_SIZE_MAX};
+use crate::smp;
+
 /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999589
 pub const FLAGS_LE_LIMITED_DISCOVERABLE:u8      = 1 << 0;
 pub const FLAGS_LE_GENERAL_DISCOVERABLE:u8      = 1 << 1;
 pub const FLAGS_BR_EDR_NOT_SUPPORTED:u8         = 1 << 2;
 pub const FLAGS_SIMULTANEOUS_LE_AND_BR_EDR:u8   = 1 << 3;
 
+/// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999768
+pub const OOB_FLAGS_OOB_DATA_PRESENT:u8             = 1 << 0;
+pub const OOB_FLAGS_LE_SUPPORTED:u8                 = 1 << 1;
+pub const OOB_FLAGS_SIMULTANEOUS_LE_AND_BR_EDR:u8   = 1 << 2;
+pub const OOB_FLAGS_RANDOM_ADDRESS:u8               = 1 << 3;
+
 /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1005365
 type LeBluetoothDeviceAddress = [u8;7];
 
@@ -44,45 +53,45 @@ pub struct AdFields<'a> {
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999686
     pub tx_power_level: Option<i8>,
 
+    /// LE Secure Connections confirm and random values
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999709
-    // TODO implement
-    pub _secure_simple_pairing_oob: u8,
+    pub le_secure_connections_oob: Option<&'a smp::OobData>,
 
-    /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999768
-    // TODO implement
-    pub _security_manager_oob: u8,
+    /// see OOB_FLAGS_* (https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999768)
+    pub security_manager_oob_flags: Option<u8>,
 
+    /// TK of LE legacy pairing (MSB first)
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999818
-    // TODO implement
-    pub _security_manager_tk_value: u8,
+    pub security_manager_tk_value: Option<&'a [u8; 16]>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999838
-    // TODO implement
-    pub _peripheral_connection_interval_range: u8,
+    /// (min, max) in units of 1.25ms, 0xFFFF for no specific minimum or maximum
+    pub peripheral_connection_interval_range: Option<(u16, u16)>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999871
-    // TODO implement
-    pub _service_solicitation: u8,
+    pub list_service_solicitation_uuid_16: Option<&'a [u16]>,
+    pub list_service_solicitation_uuid_32: Option<&'a [u32]>,
+    pub list_service_solicitation_uuid_128: Option<&'a [u128]>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999894
-    // TODO implement
-    pub _service_data: u8,
+    /// (service uuid, service data)
+    pub service_data_uuid_16: Option<(u16, &'a [u8])>,
+    pub service_data_uuid_32: Option<(u32, &'a [u8])>,
+    pub service_data_uuid_128: Option<(u128, &'a [u8])>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999913
     /// https://specificationrefs.bluetooth.com/assigned-values/Appearance%20Values.pdf
     pub appearance: Option<u16>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999932
-    // TODO implement
-    pub _public_target_address: u8,
+    pub public_target_address: Option<&'a [Address]>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.999953
-    // TODO implement
-    pub _random_target_address:u8,
+    pub random_target_address: Option<&'a [Address]>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1004048
-    // TODO implement
-    pub _advertising_interval:u8,
+    /// in units of 0.625ms
+    pub advertising_interval: Option<u16>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1005265
     pub le_bluetooth_device_address: Option<&'a LeBluetoothDeviceAddress>,
@@ -94,288 +103,772 @@ pub struct AdFields<'a> {
     pub uri: Option<&'a str>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1054608
-    // TODO implement
-    pub _le_supported_features: u8,
+    /// FeatureSet bitmap (Core_v5.3 Vol 6, Part B, 4.6), trailing zero octets are omitted
+    pub le_supported_features: Option<u64>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1055008
     // TODO implement
     pub _channel_map_update_indication: u8,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1176955
-    // TODO implement
-    pub _biginfo: u8,
+    /// only valid in the ACAD of periodic advertising (see BigInfo::read_acad)
+    pub biginfo: Option<&'a BigInfo>,
 
     /// https://www.bluetooth.org/docman/handlers/DownloadDoc.ashx?doc_id=519976#G3.1177011
-    // TODO implement
-    pub _broadcast_code: u8,
+    /// the Broadcast_Code of an encrypted BIG (sent in OOB data only)
+    pub broadcast_code: Option<&'a [u8; 16]>,
+
+    /// Core Specification Supplement, Part A, 1.22 (4 to 32 octets of UTF-8)
+    pub broadcast_name: Option<&'a str>,
+
+    /// the order and priority of the ad structures (flags first by default)
+    pub layout: AdLayout<'a>,
+}
+
+/// the AD structures of AdFields
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AdField {
+    IncompleteListServiceUuid16,
+    CompleteListServiceUuid16,
+    IncompleteListServiceUuid32,
+    CompleteListServiceUuid32,
+    IncompleteListServiceUuid128,
+    CompleteListServiceUuid128,
+    LocalName,
+    ShortName,
+    Flags,
+    ManufacturerSpecificData,
+    TxPowerLevel,
+    PeripheralConnectionIntervalRange,
+    ListServiceSolicitationUuid16,
+    ListServiceSolicitationUuid32,
+    ListServiceSolicitationUuid128,
+    ServiceDataUuid16,
+    ServiceDataUuid32,
+    ServiceDataUuid128,
+    Appearance,
+    PublicTargetAddress,
+    RandomTargetAddress,
+    AdvertisingInterval,
+    LeBluetoothDeviceAddress,
+    LeRole,
+    Uri,
+    SecurityManagerTkValue,
+    SecurityManagerOobFlags,
+    /// the confirm value of le_secure_connections_oob
+    LeSecureConnectionsConfirmValue,
+    /// the random value of le_secure_connections_oob
+    LeSecureConnectionsRandomValue,
+    LeSupportedFeatures,
+    BigInfo,
+    BroadcastCode,
+    BroadcastName,
+}
+impl AdField {
+    pub const ALL:[AdField; 33] = [
+        AdField::IncompleteListServiceUuid16,
+        AdField::CompleteListServiceUuid16,
+        AdField::IncompleteListServiceUuid32,
+        AdField::CompleteListServiceUuid32,
+        AdField::IncompleteListServiceUuid128,
+        AdField::CompleteListServiceUuid128,
+        AdField::LocalName,
+        AdField::ShortName,
+        AdField::Flags,
+        AdField::ManufacturerSpecificData,
+        AdField::TxPowerLevel,
+        AdField::PeripheralConnectionIntervalRange,
+        AdField::ListServiceSolicitationUuid16,
+        AdField::ListServiceSolicitationUuid32,
+        AdField::ListServiceSolicitationUuid128,
+        AdField::ServiceDataUuid16,
+        AdField::ServiceDataUuid32,
+        AdField::ServiceDataUuid128,
+        AdField::Appearance,
+        AdField::PublicTargetAddress,
+        AdField::RandomTargetAddress,
+        AdField::AdvertisingInterval,
+        AdField::LeBluetoothDeviceAddress,
+        AdField::LeRole,
+        AdField::Uri,
+        AdField::SecurityManagerTkValue,
+        AdField::SecurityManagerOobFlags,
+        AdField::LeSecureConnectionsConfirmValue,
+        AdField::LeSecureConnectionsRandomValue,
+        AdField::LeSupportedFeatures,
+        AdField::BigInfo,
+        AdField::BroadcastCode,
+        AdField::BroadcastName,
+    ];
+
+    fn ad_type(self) -> DataTypes {
+        return match self {
+            AdField::IncompleteListServiceUuid16 => DataTypes::Incomplete16bitServiceUuids,
+            AdField::CompleteListServiceUuid16 => DataTypes::Complete16bitServiceUuids,
+            AdField::IncompleteListServiceUuid32 => DataTypes::Incomplete32bitServiceUuids,
+            AdField::CompleteListServiceUuid32 => DataTypes::Complete32bitServiceUuids,
+            AdField::IncompleteListServiceUuid128 => DataTypes::Incomplete128bitServiceUuids,
+            AdField::CompleteListServiceUuid128 => DataTypes::Complete128bitServiceUuids,
+            AdField::LocalName => DataTypes::CompleteLocalName,
+            AdField::ShortName => DataTypes::ShortenedLocalName,
+            AdField::Flags => DataTypes::Flags,
+            AdField::ManufacturerSpecificData => DataTypes::ManufacturerSpecificData,
+            AdField::TxPowerLevel => DataTypes::TxPowerLevel,
+            AdField::PeripheralConnectionIntervalRange => DataTypes::SlaveConnectionIntervalRange,
+            AdField::ListServiceSolicitationUuid16 => DataTypes::List16bitServiceSolicitation,
+            AdField::ListServiceSolicitationUuid32 => DataTypes::List32bitServiceSolicitation,
+            AdField::ListServiceSolicitationUuid128 => DataTypes::List128bitServiceSolicitation,
+            AdField::ServiceDataUuid16 => DataTypes::ServiceDataUuid16,
+            AdField::ServiceDataUuid32 => DataTypes::ServiceData32bitUuid,
+            AdField::ServiceDataUuid128 => DataTypes::ServiceData128bitUuid,
+            AdField::Appearance => DataTypes::Appearance,
+            AdField::PublicTargetAddress => DataTypes::PublicTargetAddress,
+            AdField::RandomTargetAddress => DataTypes::RandomTargetAddress,
+            AdField::AdvertisingInterval => DataTypes::AdvertisingInterval,
+            AdField::LeBluetoothDeviceAddress => DataTypes::LeBluetoothDeviceAddress,
+            AdField::LeRole => DataTypes::LeRole,
+            AdField::Uri => DataTypes::Uri,
+            AdField::SecurityManagerTkValue => DataTypes::SecurityManagerTkValue,
+            AdField::SecurityManagerOobFlags => DataTypes::SecurityManagerOutOfBandFlags,
+            AdField::LeSecureConnectionsConfirmValue => DataTypes::LeSecureConnectionsConfirmValue,
+            AdField::LeSecureConnectionsRandomValue => DataTypes::LeSecureConnectionsRandomValue,
+            AdField::LeSupportedFeatures => DataTypes::LeSupportedFeatures,
+            AdField::BigInfo => DataTypes::BIGInfo,
+            AdField::BroadcastCode => DataTypes::BroadCastCode,
+            AdField::BroadcastName => DataTypes::BroadcastName,
+        };
+    }
+}
+
+/// a set of AdField
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AdFieldSet(u64);
+impl AdFieldSet {
+    pub fn contains(&self, field: AdField) -> bool {
+        return self.0 & (1 << field as u64) != 0;
+    }
+
+    pub fn insert(&mut self, field: AdField) {
+        self.0 |= 1 << field as u64;
+    }
+
+    pub fn remove(&mut self, field: AdField) {
+        self.0 &= !(1 << field as u64);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0 == 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = AdField> + '_ {
+        return AdField::ALL.iter().copied().filter(move |field| self.contains(*field));
+    }
+
+    fn union(&self, other: AdFieldSet) -> AdFieldSet {
+        return AdFieldSet(self.0 | other.0);
+    }
+}
+
+/// the order ad structures are placed in, which is also their priority: when space is tight the
+/// later ones are left out first (though they still take space left by earlier ones that didn't fit),
+/// fields missing from the order are never placed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AdLayout<'a> {
+    order: &'a [AdField],
+}
+impl<'a> AdLayout<'a> {
+    /// flags first (scanners tend to expect them there), then what identifies the device and its
+    /// services, leaving the URI out first
+    pub const DEFAULT_ORDER:[AdField; 33] = [
+        AdField::Flags,
+        AdField::CompleteListServiceUuid16,
+        AdField::IncompleteListServiceUuid16,
+        AdField::CompleteListServiceUuid32,
+        AdField::IncompleteListServiceUuid32,
+        AdField::CompleteListServiceUuid128,
+        AdField::IncompleteListServiceUuid128,
+        AdField::LocalName,
+        AdField::ShortName,
+        AdField::BroadcastName,
+        AdField::Appearance,
+        AdField::TxPowerLevel,
+        AdField::ManufacturerSpecificData,
+        AdField::ServiceDataUuid16,
+        AdField::ServiceDataUuid32,
+        AdField::ServiceDataUuid128,
+        AdField::ListServiceSolicitationUuid16,
+        AdField::ListServiceSolicitationUuid32,
+        AdField::ListServiceSolicitationUuid128,
+        AdField::PeripheralConnectionIntervalRange,
+        AdField::AdvertisingInterval,
+        AdField::LeRole,
+        AdField::LeBluetoothDeviceAddress,
+        AdField::PublicTargetAddress,
+        AdField::RandomTargetAddress,
+        AdField::LeSupportedFeatures,
+        AdField::BigInfo,
+        AdField::SecurityManagerOobFlags,
+        AdField::SecurityManagerTkValue,
+        AdField::BroadcastCode,
+        AdField::LeSecureConnectionsConfirmValue,
+        AdField::LeSecureConnectionsRandomValue,
+        AdField::Uri,
+    ];
+
+    /// the fields in order of placement and priority (each at most once)
+    pub const fn new(order: &'a [AdField]) -> Self {
+        Self { order }
+    }
+
+    pub fn order(&self) -> &'a [AdField] { self.order }
+}
+impl Default for AdLayout<'_> {
+    fn default() -> Self {
+        Self::new(&Self::DEFAULT_ORDER)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AdError {
+    /// the AD structures that didn't fit, the others were written (size octets of advertising data
+    /// and scan_rsp_size octets of scan response data)
+    Overflow { size: usize, scan_rsp_size: usize, omitted: AdFieldSet },
+    /// manufacturer specific data must start with the 2 octet company identifier
+    InvalidManufacturerData,
 }
 
+// specification allows this to be larger than one-byte, but only
+//      single byte types are currently defined
+const AD_TYPE_SIZE:usize = 1;
+const PDU_ADV_STRUCTURE_LENGTH_SIZE:usize = 1;
+
 impl<'a> AdFields<'a> {
-    /// places ad structures as long as they will fit in packet
-    pub fn write(&'a self, buffer: &'a mut [u8]) -> usize
+    /// places ad structures (in layout order) as long as they will fit in packet
+    pub fn write(&self, buffer: &mut [u8]) -> usize
     {
-        let mut ad_size = 0;
+        return self.write_fields(self.layout.order.iter().copied(), buffer).0;
+    }
+
+    /// places all ad structures, or reports those that didn't fit (or are missing from the layout)
+    pub fn try_write(&self, buffer: &mut [u8]) -> Result<usize, AdError>
+    {
+        self.validate()?;
+        let (size, omitted) = self.write_fields(self.layout.order.iter().copied(), buffer);
+        let omitted = omitted.union(self.unplaced());
+        return match omitted.is_empty() {
+            true => Ok(size),
+            false => Err(AdError::Overflow { size, scan_rsp_size: 0, omitted }),
+        };
+    }
+
+    /// places the ad structures in the advertising data, spilling those that don't fit into the scan
+    /// response data, a local_name that doesn't fit in the advertising data is also advertised as a
+    /// truncated short_name (unless short_name is set)
+    /// returns the sizes of the advertising and scan response data
+    pub fn try_write_split(&self, adv_data: &mut [u8], scan_rsp_data: &mut [u8]) -> Result<(usize, usize), AdError>
+    {
+        self.validate()?;
+        let (mut size, spilled) = self.write_fields(self.layout.order.iter().copied(), adv_data);
+        let spill = self.layout.order.iter().copied().filter(|field| spilled.contains(*field));
+        let (mut scan_rsp_size, omitted) = self.write_fields(spill, scan_rsp_data);
+        let mut omitted = omitted.union(self.unplaced());
 
-        // specification allows this to be larger than one-byte, but only
-        //      single byte types are currently defined
-        const AD_TYPE_SIZE:usize = 1;
-        const PDU_ADV_STRUCTURE_LENGTH_SIZE:usize = 1;
-
-        // append incomplete service uuid 16 list
-        match self.incomplete_list_service_uuid_16 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (2 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (2 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Incomplete16bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 2)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 2;
+        // Core Specification Supplement, Part A, 1.2 (a shortened name is a prefix of the name)
+        match (self.local_name, self.short_name, spilled.contains(AdField::LocalName)) {
+            (Some(name), None, true) => {
+                let mut short_size = Self::write_short_name(name, &mut adv_data[size..]);
+                size += short_size;
+                if short_size == 0 && omitted.contains(AdField::LocalName) {
+                    short_size = Self::write_short_name(name, &mut scan_rsp_data[scan_rsp_size..]);
+                    scan_rsp_size += short_size;
+                }
+                if short_size > 0 {
+                    omitted.remove(AdField::LocalName);
                 }
             }
-            None => {}
+            _ => {}
         }
-        // append complete service uuid 16 list
-        match self.complete_list_service_uuid_16 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (2 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (2 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Complete16bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 2)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 2;
-                }
+        return match omitted.is_empty() {
+            true => Ok((size, scan_rsp_size)),
+            false => Err(AdError::Overflow { size, scan_rsp_size, omitted }),
+        };
+    }
+
+    /// the exact size of all ad structures in the layout (the buffer size try_write needs)
+    pub fn encoded_size(&self) -> usize {
+        return self.layout.order.iter().map(|field| self.structure_size(*field)).sum();
+    }
+
+    /// the size of the ad structure of the field (0 if the field isn't set)
+    pub fn structure_size(&self, field: AdField) -> usize {
+        return self.payload_size(field).map_or(0, |payload_size| PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + payload_size);
+    }
+
+    /// the set fields missing from the layout
+    fn unplaced(&self) -> AdFieldSet {
+        let mut unplaced = AdFieldSet::default();
+        for field in AdField::ALL {
+            if self.payload_size(field).is_some() && ! self.layout.order.contains(&field) {
+                unplaced.insert(field);
             }
-            None => {}
         }
-        // append incomplete service uuid 32 list
-        match self.incomplete_list_service_uuid_32 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (4 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (4 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Incomplete32bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 4)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 4;
-                }
+        return unplaced;
+    }
+
+    /// the name truncated to the space left (at a character boundary), returns 0 if no character fits
+    fn write_short_name(name: &str, buffer: &mut [u8]) -> usize {
+        let mut length = buffer.len().saturating_sub(PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE).min(name.len());
+        while ! name.is_char_boundary(length) {
+            length -= 1;
+        }
+        if length == 0 {
+            return 0;
+        }
+        let short = AdFields { short_name: Some(&name[..length]), ..AdFields::default() };
+        return short.write_field(AdField::ShortName, buffer).unwrap_or(0);
+    }
+
+    fn validate(&self) -> Result<(), AdError> {
+        return match self.manufacturer_specific_data {
+            Some(data) if data.len() < 2 => Err(AdError::InvalidManufacturerData),
+            _ => Ok(()),
+        };
+    }
+
+    /// places the ad structures of the fields in order as long as they fit, returns the size and
+    /// the fields that didn't fit
+    fn write_fields(&self, fields: impl Iterator<Item = AdField>, buffer: &mut [u8]) -> (usize, AdFieldSet) {
+        let mut ad_size = 0;
+        let mut omitted = AdFieldSet::default();
+        for field in fields {
+            match self.write_field(field, &mut buffer[ad_size..]) {
+                Some(size) => ad_size += size,
+                None => omitted.insert(field),
             }
-            None => {}
         }
-        // append complete service uuid 32 list
-        match self.complete_list_service_uuid_32 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (4 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (4 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Complete32bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 4)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 4;
+        return (ad_size, omitted);
+    }
+
+    /// places the ad structure of the field, returns its size (0 if the field isn't set)
+    /// or None if it doesn't fit
+    fn write_field(&self, field: AdField, buffer: &mut [u8]) -> Option<usize> {
+        let payload_size = match self.payload_size(field) {
+            Some(payload_size) => payload_size,
+            None => return Some(0),
+        };
+        let ad_size = self.structure_size(field);
+        // the length octet limits the size of the ad structure
+        if buffer.len() < ad_size || AD_TYPE_SIZE + payload_size > u8::MAX as usize {
+            return None;
+        }
+        // set ad structure length
+        buffer[0] = (AD_TYPE_SIZE + payload_size) as u8;
+        // set ad structure type
+        buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE] = field.ad_type() as u8;
+        // set ad structure payload
+        self.write_payload(field, &mut buffer[(PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE)..ad_size]);
+        return Some(ad_size);
+    }
+
+    /// the size of the ad structure payload (None if the field isn't set)
+    fn payload_size(&self, field: AdField) -> Option<usize> {
+        return match field {
+            AdField::IncompleteListServiceUuid16 => self.incomplete_list_service_uuid_16.map(|uuids| 2 * uuids.len()),
+            AdField::CompleteListServiceUuid16 => self.complete_list_service_uuid_16.map(|uuids| 2 * uuids.len()),
+            AdField::IncompleteListServiceUuid32 => self.incomplete_list_service_uuid_32.map(|uuids| 4 * uuids.len()),
+            AdField::CompleteListServiceUuid32 => self.complete_list_service_uuid_32.map(|uuids| 4 * uuids.len()),
+            AdField::IncompleteListServiceUuid128 => self.incomplete_list_service_uuid_128.map(|uuids| 16 * uuids.len()),
+            AdField::CompleteListServiceUuid128 => self.complete_list_service_uuid_128.map(|uuids| 16 * uuids.len()),
+            AdField::LocalName => self.local_name.map(str::len),
+            AdField::ShortName => self.short_name.map(str::len),
+            AdField::Flags => self.flags.map(|_| 1),
+            // manufacturer data must have 2 byte company identifier to be valid
+            AdField::ManufacturerSpecificData => self.manufacturer_specific_data.filter(|data| data.len() >= 2).map(<[u8]>::len),
+            AdField::TxPowerLevel => self.tx_power_level.map(|_| 1),
+            AdField::PeripheralConnectionIntervalRange => self.peripheral_connection_interval_range.map(|_| 4),
+            AdField::ListServiceSolicitationUuid16 => self.list_service_solicitation_uuid_16.map(|uuids| 2 * uuids.len()),
+            AdField::ListServiceSolicitationUuid32 => self.list_service_solicitation_uuid_32.map(|uuids| 4 * uuids.len()),
+            AdField::ListServiceSolicitationUuid128 => self.list_service_solicitation_uuid_128.map(|uuids| 16 * uuids.len()),
+            AdField::ServiceDataUuid16 => self.service_data_uuid_16.map(|(_, data)| 2 + data.len()),
+            AdField::ServiceDataUuid32 => self.service_data_uuid_32.map(|(_, data)| 4 + data.len()),
+            AdField::ServiceDataUuid128 => self.service_data_uuid_128.map(|(_, data)| 16 + data.len()),
+            AdField::Appearance => self.appearance.map(|_| 2),
+            AdField::PublicTargetAddress => self.public_target_address.map(|addresses| 6 * addresses.len()),
+            AdField::RandomTargetAddress => self.random_target_address.map(|addresses| 6 * addresses.len()),
+            AdField::AdvertisingInterval => self.advertising_interval.map(|_| 2),
+            AdField::LeBluetoothDeviceAddress => self.le_bluetooth_device_address.map(|address| address.len()),
+            AdField::LeRole => self.le_role.map(|_| 1),
+            AdField::Uri => self.uri.map(str::len),
+            AdField::SecurityManagerTkValue => self.security_manager_tk_value.map(|_| 16),
+            AdField::SecurityManagerOobFlags => self.security_manager_oob_flags.map(|_| 1),
+            AdField::LeSecureConnectionsConfirmValue | AdField::LeSecureConnectionsRandomValue => self.le_secure_connections_oob.map(|_| 16),
+            // at least one octet, without the trailing zero octets
+            AdField::LeSupportedFeatures => self.le_supported_features.map(|features| {
+                features.to_le_bytes().iter().rposition(|byte| *byte != 0).map_or(1, |last| last + 1)
+            }),
+            AdField::BigInfo => self.biginfo.map(BigInfo::size),
+            AdField::BroadcastCode => self.broadcast_code.map(|_| 16),
+            AdField::BroadcastName => self.broadcast_name.map(str::len),
+        };
+    }
+
+    /// write the ad structure payload of a set field (the buffer has its payload size)
+    fn write_payload(&self, field: AdField, buffer: &mut [u8]) {
+        match field {
+            AdField::IncompleteListServiceUuid16 | AdField::CompleteListServiceUuid16 | AdField::ListServiceSolicitationUuid16 => {
+                let uuids = match field {
+                    AdField::IncompleteListServiceUuid16 => self.incomplete_list_service_uuid_16,
+                    AdField::CompleteListServiceUuid16 => self.complete_list_service_uuid_16,
+                    _ => self.list_service_solicitation_uuid_16,
+                };
+                for (to, uuid) in buffer.chunks_mut(2).zip(uuids.unwrap_or(&[])) {
+                    to.copy_from_slice(&uuid.to_le_bytes());
                 }
             }
-            None => {}
-        }
-        // append incomplete service uuid 128 list
-        match self.incomplete_list_service_uuid_128 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (16 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (16 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Incomplete128bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 16)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 16;
+            AdField::IncompleteListServiceUuid32 | AdField::CompleteListServiceUuid32 | AdField::ListServiceSolicitationUuid32 => {
+                let uuids = match field {
+                    AdField::IncompleteListServiceUuid32 => self.incomplete_list_service_uuid_32,
+                    AdField::CompleteListServiceUuid32 => self.complete_list_service_uuid_32,
+                    _ => self.list_service_solicitation_uuid_32,
+                };
+                for (to, uuid) in buffer.chunks_mut(4).zip(uuids.unwrap_or(&[])) {
+                    to.copy_from_slice(&uuid.to_le_bytes());
                 }
             }
-            None => {}
-        }
-        // append complete service uuid 128 list
-        match self.complete_list_service_uuid_128 {
-            Some(uuids) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + (16 * uuids.len())) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + (16 * uuids.len())) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Complete128bitServiceUuids as u8;
-                ad_size += 1;
-                // set ad structure payload
-                for uuid in uuids {
-                    buffer[ad_size..(ad_size + 16)].copy_from_slice(&uuid.to_le_bytes());
-                    ad_size += 16;
+            AdField::IncompleteListServiceUuid128 | AdField::CompleteListServiceUuid128 | AdField::ListServiceSolicitationUuid128 => {
+                let uuids = match field {
+                    AdField::IncompleteListServiceUuid128 => self.incomplete_list_service_uuid_128,
+                    AdField::CompleteListServiceUuid128 => self.complete_list_service_uuid_128,
+                    _ => self.list_service_solicitation_uuid_128,
+                };
+                for (to, uuid) in buffer.chunks_mut(16).zip(uuids.unwrap_or(&[])) {
+                    to.copy_from_slice(&uuid.to_le_bytes());
                 }
             }
-            None => {}
-        }
-
-        // append local_name
-        match self.local_name {
-            Some(name) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + name.len()) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + name.len()) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::CompleteLocalName as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + name.len())].copy_from_slice(name.as_bytes());
-                ad_size += name.len();
+            AdField::LocalName => buffer.copy_from_slice(self.local_name.unwrap_or_default().as_bytes()),
+            AdField::ShortName => buffer.copy_from_slice(self.short_name.unwrap_or_default().as_bytes()),
+            AdField::Flags => buffer[0] = self.flags.unwrap_or(0),
+            AdField::ManufacturerSpecificData => buffer.copy_from_slice(self.manufacturer_specific_data.unwrap_or_default()),
+            AdField::TxPowerLevel => buffer[0] = self.tx_power_level.unwrap_or(0) as u8,
+            AdField::PeripheralConnectionIntervalRange => {
+                let (min, max) = self.peripheral_connection_interval_range.unwrap_or_default();
+                buffer[..2].copy_from_slice(&min.to_le_bytes());
+                buffer[2..].copy_from_slice(&max.to_le_bytes());
             }
-            None => {}
-        }
-        // append short_name
-        match self.short_name {
-            Some(name) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + name.len()) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + name.len()) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::ShortenedLocalName as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + name.len())].copy_from_slice(name.as_bytes());
-                ad_size += name.len();
+            AdField::ServiceDataUuid16 => {
+                let (uuid, data) = self.service_data_uuid_16.unwrap_or_default();
+                buffer[..2].copy_from_slice(&uuid.to_le_bytes());
+                buffer[2..].copy_from_slice(data);
             }
-            None => {}
-        }
-
-        // append flags
-        match self.flags {
-            Some(flags) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 1) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + 1) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Flags as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size] = flags as u8;
-                ad_size += 1;
+            AdField::ServiceDataUuid32 => {
+                let (uuid, data) = self.service_data_uuid_32.unwrap_or_default();
+                buffer[..4].copy_from_slice(&uuid.to_le_bytes());
+                buffer[4..].copy_from_slice(data);
             }
-            None => {}
-        }
-
-        // append manufacturer data
-        match self.manufacturer_specific_data {
-            Some(data) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + data.len()) {
-                // manufacturer data must have 2 byte company identifier to be valid
-                assert!(data.len() >= 2);
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + data.len()) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::ManufacturerSpecificData as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + data.len())].copy_from_slice(data);
-                ad_size += data.len();
+            AdField::ServiceDataUuid128 => {
+                let (uuid, data) = self.service_data_uuid_128.unwrap_or_default();
+                buffer[..16].copy_from_slice(&uuid.to_le_bytes());
+                buffer[16..].copy_from_slice(data);
             }
-            None => {}
-        }
-
-        // append tx_power_level
-        match self.tx_power_level {
-            Some(level) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 1) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + 1) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::TxPowerLevel as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size] = level as u8;
-                ad_size += 1;
+            AdField::Appearance => buffer.copy_from_slice(&self.appearance.unwrap_or(0).to_le_bytes()),
+            AdField::PublicTargetAddress | AdField::RandomTargetAddress => {
+                let addresses = match field {
+                    AdField::PublicTargetAddress => self.public_target_address,
+                    _ => self.random_target_address,
+                };
+                for (to, address) in buffer.chunks_mut(6).zip(addresses.unwrap_or(&[])) {
+                    to.copy_from_slice(address);
+                }
+            }
+            AdField::AdvertisingInterval => buffer.copy_from_slice(&self.advertising_interval.unwrap_or(0).to_le_bytes()),
+            AdField::LeBluetoothDeviceAddress => buffer.copy_from_slice(self.le_bluetooth_device_address.unwrap_or(&[0; 7])),
+            AdField::LeRole => buffer[0] = self.le_role.map_or(0, |role| role as u8),
+            AdField::Uri => buffer.copy_from_slice(self.uri.unwrap_or_default().as_bytes()),
+            // 128-bit values are held MSB first but sent little endian
+            AdField::SecurityManagerTkValue => Self::write_reversed(self.security_manager_tk_value.unwrap_or(&[0; 16]), buffer),
+            AdField::SecurityManagerOobFlags => buffer[0] = self.security_manager_oob_flags.unwrap_or(0),
+            AdField::LeSecureConnectionsConfirmValue => match self.le_secure_connections_oob {
+                Some(oob) => Self::write_reversed(&oob.confirm, buffer),
+                None => {}
+            }
+            AdField::LeSecureConnectionsRandomValue => match self.le_secure_connections_oob {
+                Some(oob) => Self::write_reversed(&oob.random, buffer),
+                None => {}
+            }
+            AdField::LeSupportedFeatures => {
+                let length = buffer.len();
+                buffer.copy_from_slice(&self.le_supported_features.unwrap_or(0).to_le_bytes()[..length]);
             }
-            None => {}
+            AdField::BigInfo => match self.biginfo {
+                Some(biginfo) => { biginfo.write(buffer); }
+                None => {}
+            }
+            AdField::BroadcastCode => buffer.copy_from_slice(self.broadcast_code.unwrap_or(&[0; 16])),
+            AdField::BroadcastName => buffer.copy_from_slice(self.broadcast_name.unwrap_or_default().as_bytes()),
         }
+    }
 
-        // append appearance
-        match self.appearance {
-            Some(id) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 2) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + 2) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Appearance as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + 2)].copy_from_slice(&id.to_le_bytes());
-                ad_size += 2;
-            }
-            None => {}
+    fn write_reversed(value: &[u8; 16], buffer: &mut [u8]) {
+        for (to, from) in buffer.iter_mut().zip(value.iter().rev()) {
+            *to = *from;
         }
+    }
+}
 
-        // append le device address
-        match self.le_bluetooth_device_address {
-            Some(address) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + address.len()) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + address.len()) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::LeBluetoothDeviceAddress as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + address.len())].copy_from_slice(address);
-                ad_size += address.len();
-            }
-            None => {}
+/// owned, encoded advertising (or scan response) data with a setter per AD type, for updates at
+/// runtime (see Ble::set_advertising_data)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AdvertisingData {
+    data: heapless::Vec<u8, ADV_DATA_SIZE_MAX>,
+}
+impl AdvertisingData {
+    pub fn new() -> Self {
+        Self { data: heapless::Vec::new() }
+    }
+
+    /// encoded AD structures (None if they don't fit or are malformed)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let size:usize = ad_structures(bytes).map(|(_, payload)| PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + payload.len()).sum();
+        if size != bytes.len() {
+            return None;
         }
+        return heapless::Vec::from_slice(bytes).ok().map(|data| Self { data });
+    }
 
-        // append le role
-        match self.le_role{
-            Some(role) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 1) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + 1) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::LeRole as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size] = role as u8;
-                ad_size += 1;
-            }
-            None => {}
+    /// the ad structures of the fields (see AdFields::try_write)
+    pub fn from_fields(fields: &AdFields) -> Result<Self, AdError> {
+        let mut buffer = [0; ADV_DATA_SIZE_MAX];
+        let size = fields.try_write(&mut buffer)?;
+        return Ok(Self { data: heapless::Vec::from_slice(&buffer[..size]).unwrap() });
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data;
+    }
+
+    /// the payload of the field's ad structure
+    pub fn get(&self, field: AdField) -> Option<&[u8]> {
+        return ad_structures(&self.data).find(|(ad_type, _)| *ad_type == field.ad_type() as u8).map(|(_, payload)| payload);
+    }
+
+    /// remove the field's ad structure, returns false if it wasn't present
+    pub fn remove(&mut self, field: AdField) -> bool {
+        return self.replace(field, &[]) == Some(true);
+    }
+
+    /// set the ad structure of the field from its value in fields (replacing it in place if present,
+    /// removing it if unset), the data is unchanged if it doesn't fit
+    pub fn set(&mut self, field: AdField, fields: &AdFields) -> Result<(), AdError> {
+        if field == AdField::ManufacturerSpecificData {
+            fields.validate()?;
         }
+        let mut structure = [0; ADV_DATA_SIZE_MAX];
+        let size = fields.write_field(field, &mut structure);
+        return match size.and_then(|size| self.replace(field, &structure[..size])) {
+            Some(_) => Ok(()),
+            None => {
+                let mut omitted = AdFieldSet::default();
+                omitted.insert(field);
+                Err(AdError::Overflow { size: self.data.len(), scan_rsp_size: 0, omitted })
+            }
+        };
+    }
 
-        // append uri
-        match self.uri {
-            Some(uri) => if buffer.len() >= (ad_size + PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + uri.len()) {
-                // set ad structure length
-                buffer[ad_size] = (AD_TYPE_SIZE + uri.len()) as u8;
-                ad_size += 1;
-                // set ad structure type
-                buffer[ad_size] = DataTypes::Uri as u8;
-                ad_size += 1;
-                // set ad structure payload
-                buffer[ad_size..(ad_size + uri.len())].copy_from_slice(uri.as_bytes());
-                ad_size += uri.len();
+    /// replace the field's ad structure (appending if absent), returns whether it was present or
+    /// None if the result doesn't fit
+    fn replace(&mut self, field: AdField, structure: &[u8]) -> Option<bool> {
+        let mut start = 0;
+        let mut found = None;
+        for (ad_type, payload) in ad_structures(&self.data) {
+            let size = PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + payload.len();
+            if ad_type == field.ad_type() as u8 {
+                found = Some((start, start + size));
+                break;
             }
-            None => {}
+            start += size;
         }
+        let (start, end) = found.unwrap_or((self.data.len(), self.data.len()));
+        let mut data = heapless::Vec::<u8, ADV_DATA_SIZE_MAX>::new();
+        data.extend_from_slice(&self.data[..start]).ok()?;
+        data.extend_from_slice(structure).ok()?;
+        data.extend_from_slice(&self.data[end..]).ok()?;
+        self.data = data;
+        return Some(found.is_some());
+    }
+
+    pub fn set_flags(&mut self, flags: u8) -> Result<(), AdError> {
+        return self.set(AdField::Flags, &AdFields { flags: Some(flags), ..AdFields::default() });
+    }
+
+    pub fn set_local_name(&mut self, name: &str) -> Result<(), AdError> {
+        return self.set(AdField::LocalName, &AdFields { local_name: Some(name), ..AdFields::default() });
+    }
+
+    pub fn set_short_name(&mut self, name: &str) -> Result<(), AdError> {
+        return self.set(AdField::ShortName, &AdFields { short_name: Some(name), ..AdFields::default() });
+    }
+
+    pub fn set_incomplete_list_service_uuid_16(&mut self, uuids: &[u16]) -> Result<(), AdError> {
+        return self.set(AdField::IncompleteListServiceUuid16, &AdFields { incomplete_list_service_uuid_16: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_incomplete_list_service_uuid_32(&mut self, uuids: &[u32]) -> Result<(), AdError> {
+        return self.set(AdField::IncompleteListServiceUuid32, &AdFields { incomplete_list_service_uuid_32: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_incomplete_list_service_uuid_128(&mut self, uuids: &[u128]) -> Result<(), AdError> {
+        return self.set(AdField::IncompleteListServiceUuid128, &AdFields { incomplete_list_service_uuid_128: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_complete_list_service_uuid_16(&mut self, uuids: &[u16]) -> Result<(), AdError> {
+        return self.set(AdField::CompleteListServiceUuid16, &AdFields { complete_list_service_uuid_16: Some(uuids), ..AdFields::default() });
+    }
 
-        return ad_size;
+    pub fn set_complete_list_service_uuid_32(&mut self, uuids: &[u32]) -> Result<(), AdError> {
+        return self.set(AdField::CompleteListServiceUuid32, &AdFields { complete_list_service_uuid_32: Some(uuids), ..AdFields::default() });
     }
+
+    pub fn set_complete_list_service_uuid_128(&mut self, uuids: &[u128]) -> Result<(), AdError> {
+        return self.set(AdField::CompleteListServiceUuid128, &AdFields { complete_list_service_uuid_128: Some(uuids), ..AdFields::default() });
+    }
+
+    /// data starts with the 2 octet company identifier
+    pub fn set_manufacturer_specific_data(&mut self, data: &[u8]) -> Result<(), AdError> {
+        return self.set(AdField::ManufacturerSpecificData, &AdFields { manufacturer_specific_data: Some(data), ..AdFields::default() });
+    }
+
+    pub fn set_tx_power_level(&mut self, level: i8) -> Result<(), AdError> {
+        return self.set(AdField::TxPowerLevel, &AdFields { tx_power_level: Some(level), ..AdFields::default() });
+    }
+
+    /// (min, max) in units of 1.25ms, 0xFFFF for no specific minimum or maximum
+    pub fn set_peripheral_connection_interval_range(&mut self, min: u16, max: u16) -> Result<(), AdError> {
+        return self.set(AdField::PeripheralConnectionIntervalRange, &AdFields { peripheral_connection_interval_range: Some((min, max)), ..AdFields::default() });
+    }
+
+    pub fn set_list_service_solicitation_uuid_16(&mut self, uuids: &[u16]) -> Result<(), AdError> {
+        return self.set(AdField::ListServiceSolicitationUuid16, &AdFields { list_service_solicitation_uuid_16: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_list_service_solicitation_uuid_32(&mut self, uuids: &[u32]) -> Result<(), AdError> {
+        return self.set(AdField::ListServiceSolicitationUuid32, &AdFields { list_service_solicitation_uuid_32: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_list_service_solicitation_uuid_128(&mut self, uuids: &[u128]) -> Result<(), AdError> {
+        return self.set(AdField::ListServiceSolicitationUuid128, &AdFields { list_service_solicitation_uuid_128: Some(uuids), ..AdFields::default() });
+    }
+
+    pub fn set_service_data_uuid_16(&mut self, uuid: u16, data: &[u8]) -> Result<(), AdError> {
+        return self.set(AdField::ServiceDataUuid16, &AdFields { service_data_uuid_16: Some((uuid, data)), ..AdFields::default() });
+    }
+
+    pub fn set_service_data_uuid_32(&mut self, uuid: u32, data: &[u8]) -> Result<(), AdError> {
+        return self.set(AdField::ServiceDataUuid32, &AdFields { service_data_uuid_32: Some((uuid, data)), ..AdFields::default() });
+    }
+
+    pub fn set_service_data_uuid_128(&mut self, uuid: u128, data: &[u8]) -> Result<(), AdError> {
+        return self.set(AdField::ServiceDataUuid128, &AdFields { service_data_uuid_128: Some((uuid, data)), ..AdFields::default() });
+    }
+
+    pub fn set_appearance(&mut self, appearance: u16) -> Result<(), AdError> {
+        return self.set(AdField::Appearance, &AdFields { appearance: Some(appearance), ..AdFields::default() });
+    }
+
+    pub fn set_public_target_address(&mut self, addresses: &[Address]) -> Result<(), AdError> {
+        return self.set(AdField::PublicTargetAddress, &AdFields { public_target_address: Some(addresses), ..AdFields::default() });
+    }
+
+    pub fn set_random_target_address(&mut self, addresses: &[Address]) -> Result<(), AdError> {
+        return self.set(AdField::RandomTargetAddress, &AdFields { random_target_address: Some(addresses), ..AdFields::default() });
+    }
+
+    /// in units of 0.625ms
+    pub fn set_advertising_interval(&mut self, interval: u16) -> Result<(), AdError> {
+        return self.set(AdField::AdvertisingInterval, &AdFields { advertising_interval: Some(interval), ..AdFields::default() });
+    }
+
+    pub fn set_le_bluetooth_device_address(&mut self, address: &[u8; 7]) -> Result<(), AdError> {
+        return self.set(AdField::LeBluetoothDeviceAddress, &AdFields { le_bluetooth_device_address: Some(address), ..AdFields::default() });
+    }
+
+    pub fn set_le_role(&mut self, role: LeRole) -> Result<(), AdError> {
+        return self.set(AdField::LeRole, &AdFields { le_role: Some(role), ..AdFields::default() });
+    }
+
+    pub fn set_uri(&mut self, uri: &str) -> Result<(), AdError> {
+        return self.set(AdField::Uri, &AdFields { uri: Some(uri), ..AdFields::default() });
+    }
+
+    /// TK of LE legacy pairing (MSB first)
+    pub fn set_security_manager_tk_value(&mut self, tk: &[u8; 16]) -> Result<(), AdError> {
+        return self.set(AdField::SecurityManagerTkValue, &AdFields { security_manager_tk_value: Some(tk), ..AdFields::default() });
+    }
+
+    /// see OOB_FLAGS_*
+    pub fn set_security_manager_oob_flags(&mut self, flags: u8) -> Result<(), AdError> {
+        return self.set(AdField::SecurityManagerOobFlags, &AdFields { security_manager_oob_flags: Some(flags), ..AdFields::default() });
+    }
+
+    /// the LE Secure Connections confirm value (MSB first), both OOB values exceed advertising data
+    pub fn set_le_secure_connections_confirm_value(&mut self, confirm: &[u8; 16]) -> Result<(), AdError> {
+        let oob = smp::OobData { random: [0; 16], confirm: *confirm };
+        return self.set(AdField::LeSecureConnectionsConfirmValue, &AdFields { le_secure_connections_oob: Some(&oob), ..AdFields::default() });
+    }
+
+    /// the LE Secure Connections random value (MSB first)
+    pub fn set_le_secure_connections_random_value(&mut self, random: &[u8; 16]) -> Result<(), AdError> {
+        let oob = smp::OobData { random: *random, confirm: [0; 16] };
+        return self.set(AdField::LeSecureConnectionsRandomValue, &AdFields { le_secure_connections_oob: Some(&oob), ..AdFields::default() });
+    }
+
+    /// FeatureSet bitmap (Core_v5.3 Vol 6, Part B, 4.6)
+    pub fn set_le_supported_features(&mut self, features: u64) -> Result<(), AdError> {
+        return self.set(AdField::LeSupportedFeatures, &AdFields { le_supported_features: Some(features), ..AdFields::default() });
+    }
+
+    /// only valid in the ACAD of periodic advertising
+    pub fn set_biginfo(&mut self, biginfo: &BigInfo) -> Result<(), AdError> {
+        return self.set(AdField::BigInfo, &AdFields { biginfo: Some(biginfo), ..AdFields::default() });
+    }
+
+    /// the Broadcast_Code of an encrypted BIG (OOB data only)
+    pub fn set_broadcast_code(&mut self, code: &[u8; 16]) -> Result<(), AdError> {
+        return self.set(AdField::BroadcastCode, &AdFields { broadcast_code: Some(code), ..AdFields::default() });
+    }
+
+    pub fn set_broadcast_name(&mut self, name: &str) -> Result<(), AdError> {
+        return self.set(AdField::BroadcastName, &AdFields { broadcast_name: Some(name), ..AdFields::default() });
+    }
+}
+
+/// an advertisement received on an advertising channel (see Ble::advertising_report)
+/// Core_v5.3 Vol 4, Part E, 7.7.65.2
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdvertisingReport {
+    pub address: DeviceAddress,
+    pub data: AdvertisingData,
+    /// dBm, None if the radio didn't sample it
+    pub rssi: Option<i8>,
+}
+
+/// the AD structures (AD type, data) of advertising, scan response or OOB data,
+/// up to the first empty or malformed one (Core_v5.3 Vol 3, Part C, 11)
+pub fn ad_structures(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = data;
+    return core::iter::from_fn(move || {
+        let length = *rest.first()? as usize;
+        if length == 0 || rest.len() < 1 + length {
+            return None;
+        }
+        let structure = (rest[1], &rest[2..1 + length]);
+        rest = &rest[1 + length..];
+        return Some(structure);
+    });
 }
 
 /// https://btprodspecificationrefs.blob.core.windows.net/assigned-numbers/Assigned%20Number%20Types/Generic%20Access%20Profile.pdf
 #[allow(unused)]
-enum DataTypes {
+pub(crate) enum DataTypes {
     Flags                           = 0x01,
     Incomplete16bitServiceUuids     = 0x02,
     Complete16bitServiceUuids       = 0x03,
@@ -597,6 +1090,191 @@ mod adfields_write {
         assert_eq!(le_role as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE]);
     }
     #[test]
+    fn peripheral_connection_interval_range() {
+        let range = (0x0006, 0xFFFF);
+        let ad_fields = AdFields{ peripheral_connection_interval_range:Some(range), ..AdFields::default() };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        const AD_FIELD_SIZE:usize = 4;
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + AD_FIELD_SIZE), size);
+        assert_eq!((size - PDU_ADV_STRUCTURE_LENGTH_SIZE), buffer[0] as usize);
+        assert_eq!(DataTypes::SlaveConnectionIntervalRange as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!([0x06, 0x00, 0xFF, 0xFF], buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+    }
+    #[test]
+    fn list_service_solicitation() {
+        let uuids_16:[u16;2] = [0xA55A, 0x1234];
+        let uuids_32:[u32;1] = [0xA55A5AA5];
+        let uuids_128:[u128;1] = [0xA55A5AA5A55A5AA5];
+        let ad_fields = AdFields{
+            list_service_solicitation_uuid_16:Some(&uuids_16),
+            list_service_solicitation_uuid_32:Some(&uuids_32),
+            list_service_solicitation_uuid_128:Some(&uuids_128),
+            ..AdFields::default()
+        };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!(3 * (PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE) + 4 + 4 + 16, size);
+        assert_eq!([5, DataTypes::List16bitServiceSolicitation as u8, 0x5A, 0xA5, 0x34, 0x12], buffer[..6]);
+        assert_eq!([5, DataTypes::List32bitServiceSolicitation as u8], buffer[6..8]);
+        assert_eq!(uuids_32[0].to_le_bytes(), buffer[8..12]);
+        assert_eq!([17, DataTypes::List128bitServiceSolicitation as u8], buffer[12..14]);
+        assert_eq!(uuids_128[0].to_le_bytes(), buffer[14..size]);
+    }
+    #[test]
+    fn service_data() {
+        let data:[u8;3] = [1, 2, 3];
+        let ad_fields = AdFields{ service_data_uuid_16:Some((0x180F, &data)), ..AdFields::default() };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 2 + data.len()), size);
+        assert_eq!((size - PDU_ADV_STRUCTURE_LENGTH_SIZE), buffer[0] as usize);
+        assert_eq!(DataTypes::ServiceDataUuid16 as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!([0x0F, 0x18, 1, 2, 3], buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+
+        let ad_fields = AdFields{ service_data_uuid_32:Some((0xA55A5AA5, &data)), ..AdFields::default() };
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 4 + data.len()), size);
+        assert_eq!(DataTypes::ServiceData32bitUuid as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!([0xA5, 0x5A, 0x5A, 0xA5, 1, 2, 3], buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+
+        let uuid:u128 = 0xA55A5AA5A55A5AA5;
+        let ad_fields = AdFields{ service_data_uuid_128:Some((uuid, &[])), ..AdFields::default() };
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 16), size);
+        assert_eq!(DataTypes::ServiceData128bitUuid as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!(uuid.to_le_bytes(), buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+    }
+    #[test]
+    fn target_address() {
+        let addresses:[Address;2] = [[1, 2, 3, 4, 5, 6], [7, 8, 9, 10, 11, 12]];
+        let ad_fields = AdFields{ public_target_address:Some(&addresses), random_target_address:Some(&addresses[1..]), ..AdFields::default() };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!(2 * (PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE) + (3 * 6), size);
+        assert_eq!([13, DataTypes::PublicTargetAddress as u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12], buffer[..14]);
+        assert_eq!([7, DataTypes::RandomTargetAddress as u8, 7, 8, 9, 10, 11, 12], buffer[14..size]);
+    }
+    #[test]
+    fn advertising_interval() {
+        let interval = 0x0800;
+        let ad_fields = AdFields{ advertising_interval:Some(interval), ..AdFields::default() };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        const AD_FIELD_SIZE:usize = 2;
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + AD_FIELD_SIZE), size);
+        assert_eq!((size - PDU_ADV_STRUCTURE_LENGTH_SIZE), buffer[0] as usize);
+        assert_eq!(DataTypes::AdvertisingInterval as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!(interval.to_le_bytes(), buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+    }
+    #[test]
+    fn le_supported_features() {
+        let ad_fields = AdFields{ le_supported_features:Some((1 << 4) | (1 << 8)), ..AdFields::default() };
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!((PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE + 2), size);
+        assert_eq!((size - PDU_ADV_STRUCTURE_LENGTH_SIZE), buffer[0] as usize);
+        assert_eq!(DataTypes::LeSupportedFeatures as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
+        assert_eq!([0x10, 0x01], buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
+
+        // no features still takes one octet
+        let ad_fields = AdFields{ le_supported_features:Some(0), ..AdFields::default() };
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!([2, DataTypes::LeSupportedFeatures as u8, 0x00], buffer[..size]);
+    }
+    #[test]
+    fn le_secure_connections_oob() {
+        let oob = smp::OobData { random: [0x11; 16], confirm: [0x22; 16] };
+        let tk = [0x33; 16];
+        let ad_fields = AdFields{
+            le_secure_connections_oob:Some(&oob),
+            security_manager_oob_flags:Some(OOB_FLAGS_OOB_DATA_PRESENT | OOB_FLAGS_LE_SUPPORTED),
+            security_manager_tk_value:Some(&tk),
+            ..AdFields::default()
+        };
+        let mut buffer:[u8; 64] = [0; 64];
+        let size = ad_fields.write(&mut buffer);
+        assert_eq!(4 * (PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE) + 16 + 1 + (2 * 16), size);
+        assert_eq!([2, DataTypes::SecurityManagerOutOfBandFlags as u8, 0x03], buffer[..3]);
+        assert_eq!([17, DataTypes::SecurityManagerTkValue as u8], buffer[3..5]);
+        assert_eq!([17, DataTypes::LeSecureConnectionsConfirmValue as u8], buffer[21..23]);
+        assert_eq!(Some(oob), smp::OobData::read(&buffer[..size]));
+
+        // both values are required
+        assert_eq!(None, smp::OobData::read(&buffer[..39]));
+        // the random value doesn't fit
+        let ad_fields = AdFields{ le_secure_connections_oob:Some(&oob), ..AdFields::default() };
+        let mut omitted = AdFieldSet::default();
+        omitted.insert(AdField::LeSecureConnectionsRandomValue);
+        assert_eq!(Err(AdError::Overflow { size: 18, scan_rsp_size: 0, omitted }), ad_fields.try_write(&mut buffer[..35]));
+    }
+    #[test]
+    fn try_write() {
+        let ad_fields = AdFields{ flags:Some(FLAGS_LE_GENERAL_DISCOVERABLE), local_name:Some("A LONG LOCAL NAME"), uri:Some("URI"), ..AdFields::default() };
+        let mut buffer:[u8; 16] = [0; 16];
+        let mut omitted = AdFieldSet::default();
+        omitted.insert(AdField::LocalName);
+        assert_eq!(Err(AdError::Overflow { size: 8, scan_rsp_size: 0, omitted }), ad_fields.try_write(&mut buffer));
+        assert_eq!(vec![AdField::LocalName], omitted.iter().collect::<Vec<_>>());
+        assert_eq!([2, DataTypes::Flags as u8, FLAGS_LE_GENERAL_DISCOVERABLE], buffer[..3]);
+        assert_eq!(Ok(27), ad_fields.try_write(&mut [0; ADV_PDU_SIZE_MAX]));
+
+        // the company identifier is missing
+        let ad_fields = AdFields{ manufacturer_specific_data:Some(&[0]), ..AdFields::default() };
+        assert_eq!(Err(AdError::InvalidManufacturerData), ad_fields.try_write(&mut buffer));
+        assert_eq!(0, ad_fields.write(&mut buffer));
+    }
+    #[test]
+    fn try_write_split() {
+        let name = "A VERY LONG LOCAL NAME";
+        let ad_fields = AdFields{ flags:Some(FLAGS_LE_GENERAL_DISCOVERABLE), local_name:Some(name), appearance:Some(0x03C1), ..AdFields::default() };
+        let mut adv_data:[u8; 16] = [0; 16];
+        let mut scan_rsp_data:[u8; 31] = [0; 31];
+        // the name spills into the scan response, a shortened name takes the rest of the advertising data
+        assert_eq!(Ok((16, 24)), ad_fields.try_write_split(&mut adv_data, &mut scan_rsp_data));
+        assert_eq!([2, DataTypes::Flags as u8, FLAGS_LE_GENERAL_DISCOVERABLE], adv_data[..3]);
+        assert_eq!([3, DataTypes::Appearance as u8, 0xC1, 0x03], adv_data[3..7]);
+        assert_eq!([8, DataTypes::ShortenedLocalName as u8], adv_data[7..9]);
+        assert_eq!(*b"A VERY ", adv_data[9..]);
+        assert_eq!([23, DataTypes::CompleteLocalName as u8], scan_rsp_data[..2]);
+        assert_eq!(*name.as_bytes(), scan_rsp_data[2..24]);
+
+        // neither has room for the name
+        let (adv_data, scan_rsp_data) = (&mut [0; 9], &mut [0; 8]);
+        assert_eq!(Ok((7, 8)), ad_fields.try_write_split(adv_data, scan_rsp_data));
+        assert_eq!([7, DataTypes::ShortenedLocalName as u8], scan_rsp_data[..2]);
+        assert_eq!(*b"A VERY", scan_rsp_data[2..]);
+
+        // an explicit short name isn't replaced
+        let ad_fields = AdFields{ short_name:Some("SHORT"), ..ad_fields };
+        let mut omitted = AdFieldSet::default();
+        omitted.insert(AdField::LocalName);
+        assert_eq!(Err(AdError::Overflow { size: 14, scan_rsp_size: 0, omitted }), ad_fields.try_write_split(&mut [0; 16], &mut [0; 4]));
+    }
+    #[test]
+    fn layout() {
+        let ad_fields = AdFields{ uri:Some("https://example.com"), local_name:Some("NAME"), flags:Some(FLAGS_LE_GENERAL_DISCOVERABLE), ..AdFields::default() };
+        assert_eq!(3 + 6 + 21, ad_fields.encoded_size());
+        assert_eq!(6, ad_fields.structure_size(AdField::LocalName));
+        assert_eq!(0, ad_fields.structure_size(AdField::Appearance));
+
+        // flags come first and the uri is left out first
+        let mut buffer:[u8; 16] = [0; 16];
+        assert_eq!(9, ad_fields.write(&mut buffer));
+        assert_eq!([2, DataTypes::Flags as u8, FLAGS_LE_GENERAL_DISCOVERABLE, 5, DataTypes::CompleteLocalName as u8], buffer[..5]);
+
+        // the application's order, without flags
+        let order = [AdField::Uri, AdField::LocalName];
+        let ad_fields = AdFields{ layout:AdLayout::new(&order), ..ad_fields };
+        assert_eq!(6 + 21, ad_fields.encoded_size());
+        let mut buffer:[u8; ADV_PDU_SIZE_MAX] = [0; ADV_PDU_SIZE_MAX];
+        let mut omitted = AdFieldSet::default();
+        omitted.insert(AdField::Flags);
+        assert_eq!(Err(AdError::Overflow { size: 27, scan_rsp_size: 0, omitted }), ad_fields.try_write(&mut buffer));
+        assert_eq!([20, DataTypes::Uri as u8], buffer[..2]);
+        assert_eq!([5, DataTypes::CompleteLocalName as u8], buffer[21..23]);
+    }
+    #[test]
     fn uri() {
         let uri = "URI";
         let ad_fields = AdFields{ uri:Some(uri), ..AdFields::default() };
@@ -607,4 +1285,92 @@ mod adfields_write {
         assert_eq!(DataTypes::Uri as u8, buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE]);
         assert_eq!(*uri.as_bytes(), buffer[PDU_ADV_STRUCTURE_LENGTH_SIZE + AD_TYPE_SIZE..size]);
     }
-}
\ No newline at end of file
+    #[test]
+    fn broadcast() {
+        let biginfo = BigInfo {
+            big_offset: 100, big_offset_units: false, iso_interval: 8, num_bis: 2, nse: 4, bn: 2,
+            sub_interval: 2500, pto: 0, bis_spacing: 5000, irc: 2, max_pdu: 40, seed_access_address: 0x12345678,
+            sdu_interval: 10000, max_sdu: 40, base_crc_init: 0xABCD, channel_map: 0x1F_FFFF_FFFF,
+            phy: crate::link_layer::Phy::Le2M, bis_payload_count: 0, framed: false, encryption: None,
+        };
+        // the ACAD of an AUX_SYNC_IND and the broadcast name of its AUX_ADV_IND
+        let acad_fields = AdFields{ biginfo:Some(&biginfo), ..AdFields::default() };
+        let mut acad = [0; 64];
+        let size = acad_fields.try_write(&mut acad).unwrap();
+        assert_eq!(2 + BigInfo::SIZE, size);
+        assert_eq!([34, DataTypes::BIGInfo as u8], acad[..2]);
+        assert_eq!(Some(biginfo), BigInfo::read_acad(&acad[..size]));
+
+        let mut data = AdvertisingData::new();
+        data.set_broadcast_name("Gate 12").unwrap();
+        assert_eq!(Some(&b"Gate 12"[..]), data.get(AdField::BroadcastName));
+
+        let code = *b"0000000000000000";
+        let oob = AdFields{ broadcast_code:Some(&code), ..AdFields::default() };
+        let mut buffer = [0; 18];
+        assert_eq!(Ok(18), oob.try_write(&mut buffer));
+        assert_eq!([17, DataTypes::BroadCastCode as u8], buffer[..2]);
+    }
+}
+#[cfg(test)]
+mod advertising_data {
+    use super::*;
+
+    #[test]
+    fn setters() {
+        let fields = AdFields{ flags:Some(FLAGS_LE_GENERAL_DISCOVERABLE), manufacturer_specific_data:Some(&[0xFF, 0xFF, 100]), local_name:Some("NAME"), ..AdFields::default() };
+        let mut data = AdvertisingData::from_fields(&fields).unwrap();
+        assert_eq!(3 + 6 + 5, data.as_bytes().len());
+
+        // replaced in place
+        data.set_manufacturer_specific_data(&[0xFF, 0xFF, 99]).unwrap();
+        assert_eq!(Some(&[0xFF, 0xFF, 99][..]), data.get(AdField::ManufacturerSpecificData));
+        assert_eq!(2 + 6 + 5, data.as_bytes().iter().position(|byte| *byte == 99).unwrap());
+        data.set_appearance(0x03C1).unwrap();
+        assert_eq!([3, DataTypes::Appearance as u8, 0xC1, 0x03], data.as_bytes()[14..]);
+
+        // unchanged if it doesn't fit
+        let before = data.clone();
+        let mut omitted = AdFieldSet::default();
+        omitted.insert(AdField::LocalName);
+        assert_eq!(Err(AdError::Overflow { size: 18, scan_rsp_size: 0, omitted }), data.set_local_name("A MUCH LONGER NAME"));
+        assert_eq!(before, data);
+        assert_eq!(Err(AdError::InvalidManufacturerData), data.set_manufacturer_specific_data(&[0xFF]));
+
+        assert!(data.remove(AdField::LocalName));
+        assert!(! data.remove(AdField::LocalName));
+        assert_eq!(None, data.get(AdField::LocalName));
+        data.set_local_name("NEW NAME").unwrap();
+        assert_eq!(Some(&b"NEW NAME"[..]), data.get(AdField::LocalName));
+
+        assert_eq!(Some(data.clone()), AdvertisingData::from_bytes(data.as_bytes()));
+        // truncated ad structure
+        assert_eq!(None, AdvertisingData::from_bytes(&[3, DataTypes::Appearance as u8, 0xC1]));
+    }
+
+    #[test]
+    fn uuid_address_and_oob_setters() {
+        let mut data = AdvertisingData::new();
+        data.set_incomplete_list_service_uuid_16(&[0x180F, 0x181A]).unwrap();
+        assert_eq!(Some(&[0x0F, 0x18, 0x1A, 0x18][..]), data.get(AdField::IncompleteListServiceUuid16));
+        data.set_list_service_solicitation_uuid_32(&[0x12345678]).unwrap();
+        assert_eq!(Some(&[0x78, 0x56, 0x34, 0x12][..]), data.get(AdField::ListServiceSolicitationUuid32));
+        data.set_random_target_address(&[[1, 2, 3, 4, 5, 6]]).unwrap();
+        assert_eq!(Some(&[1, 2, 3, 4, 5, 6][..]), data.get(AdField::RandomTargetAddress));
+        data.set_le_supported_features(0x0100).unwrap();
+        assert_eq!(Some(&[0x00, 0x01][..]), data.get(AdField::LeSupportedFeatures));
+        data.set_security_manager_oob_flags(OOB_FLAGS_LE_SUPPORTED).unwrap();
+        assert_eq!(Some(&[OOB_FLAGS_LE_SUPPORTED][..]), data.get(AdField::SecurityManagerOobFlags));
+
+        // sent little endian
+        let mut confirm = [0; 16];
+        confirm[0] = 0x22;
+        let mut data = AdvertisingData::new();
+        data.set_le_secure_connections_confirm_value(&confirm).unwrap();
+        assert_eq!(Some(0x22), data.get(AdField::LeSecureConnectionsConfirmValue).map(|value| value[15]));
+        assert!(data.set_le_secure_connections_random_value(&[0x11; 16]).is_err());
+        data.remove(AdField::LeSecureConnectionsConfirmValue);
+        data.set_le_secure_connections_random_value(&[0x11; 16]).unwrap();
+        assert_eq!(Some(&[0x11; 16][..]), data.get(AdField::LeSecureConnectionsRandomValue));
+    }
+}
@@ -0,0 +1,597 @@
+use crate::att::{self, Attribute, AttributeDatabase, AttServer, ErrorCode, Handle, Uuid};
+use crate::gap::AdFields;
+
+/// Core_v5.3 Vol 3, Part G, 3 (attribute types of the GATT declarations)
+pub const INCLUDE_UUID:Uuid = Uuid::Uuid16(0x2802);
+pub const CHARACTERISTIC_UUID:Uuid = Uuid::Uuid16(0x2803);
+pub const CHARACTERISTIC_EXTENDED_PROPERTIES_UUID:Uuid = Uuid::Uuid16(0x2900);
+pub const CHARACTERISTIC_USER_DESCRIPTION_UUID:Uuid = Uuid::Uuid16(0x2901);
+pub const CLIENT_CHARACTERISTIC_CONFIGURATION_UUID:Uuid = Uuid::Uuid16(0x2902);
+pub const SERVER_CHARACTERISTIC_CONFIGURATION_UUID:Uuid = Uuid::Uuid16(0x2903);
+pub const CHARACTERISTIC_PRESENTATION_FORMAT_UUID:Uuid = Uuid::Uuid16(0x2904);
+
+/// https://btprodspecificationrefs.blob.core.windows.net/assigned-numbers/Assigned%20Number%20Types/Assigned_Numbers.pdf
+pub const GENERIC_ACCESS_SERVICE_UUID:Uuid = Uuid::Uuid16(0x1800);
+pub const GENERIC_ATTRIBUTE_SERVICE_UUID:Uuid = Uuid::Uuid16(0x1801);
+pub const DEVICE_NAME_UUID:Uuid = Uuid::Uuid16(0x2A00);
+pub const APPEARANCE_UUID:Uuid = Uuid::Uuid16(0x2A01);
+pub const SERVICE_CHANGED_UUID:Uuid = Uuid::Uuid16(0x2A05);
+
+/// characteristic properties (Core_v5.3 Vol 3, Part G, 3.3.1.1)
+pub const PROPERTY_BROADCAST:u8                     = 1 << 0;
+pub const PROPERTY_READ:u8                          = 1 << 1;
+pub const PROPERTY_WRITE_WITHOUT_RESPONSE:u8        = 1 << 2;
+pub const PROPERTY_WRITE:u8                         = 1 << 3;
+pub const PROPERTY_NOTIFY:u8                        = 1 << 4;
+pub const PROPERTY_INDICATE:u8                      = 1 << 5;
+pub const PROPERTY_AUTHENTICATED_SIGNED_WRITES:u8   = 1 << 6;
+pub const PROPERTY_EXTENDED_PROPERTIES:u8           = 1 << 7;
+
+/* handles of the services generated by attribute_table! */
+pub const GENERIC_ACCESS_SERVICE_HANDLE:Handle = 1;
+pub const DEVICE_NAME_HANDLE:Handle = 3;
+pub const APPEARANCE_HANDLE:Handle = 5;
+pub const GENERIC_ATTRIBUTE_SERVICE_HANDLE:Handle = 6;
+pub const SERVICE_CHANGED_HANDLE:Handle = 8;
+pub const SERVICE_CHANGED_CCCD_HANDLE:Handle = 9;
+/// first handle available to the application's services
+pub const APPLICATION_HANDLE_START:Handle = 10;
+
+/// the on-air (little endian) bytes of a UUID (only the first `uuid.len()` are used)
+pub const fn uuid_bytes(uuid: Uuid) -> [u8;16] {
+    return match uuid {
+        Uuid::Uuid16(uuid) => {
+            let bytes = uuid.to_le_bytes();
+            [bytes[0], bytes[1], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        }
+        Uuid::Uuid128(uuid) => uuid.to_le_bytes(),
+    };
+}
+
+/// the value of a characteristic declaration (only the first `3 + uuid.len()` are used)
+/// Core_v5.3 Vol 3, Part G, 3.3.1
+pub const fn characteristic_declaration(properties: u8, value_handle: Handle, uuid: Uuid) -> [u8;19] {
+    let mut declaration = [0; 19];
+    declaration[0] = properties;
+    let handle = value_handle.to_le_bytes();
+    declaration[1] = handle[0];
+    declaration[2] = handle[1];
+    let uuid = uuid_bytes(uuid);
+    let mut i = 0;
+    while i < uuid.len() {
+        declaration[3 + i] = uuid[i];
+        i += 1;
+    }
+    return declaration;
+}
+
+/// the leading bytes of a (compile time) declaration value
+pub const fn truncate(bytes: &'static [u8], len: usize) -> &'static [u8] {
+    return bytes.split_at(len).0;
+}
+
+/// Builds a `const` attribute table (stored in flash) for a GattServer.
+///
+/// The Generic Access and Generic Attribute services (with Service Changed) are
+/// generated first, application entries are assigned handles from
+/// APPLICATION_HANDLE_START in the order listed:
+/// ```ignore
+/// use embedded_ble::att::{Uuid, PERMISSION_READ};
+/// use embedded_ble::gatt::{PROPERTY_READ, PROPERTY_NOTIFY};
+/// embedded_ble::attribute_table! {
+///     pub const ATTRIBUTES = [
+///         primary_service(Uuid::Uuid16(0x180F)),
+///         characteristic(Uuid::Uuid16(0x2A19), PROPERTY_READ | PROPERTY_NOTIFY, PERMISSION_READ, &[100]),
+///         client_characteristic_configuration(),
+///         descriptor(Uuid::Uuid16(0x2901), PERMISSION_READ, b"battery level"),
+///     ];
+/// }
+/// ```
+#[macro_export]
+macro_rules! attribute_table {
+    ($(#[$meta:meta])* $vis:vis const $name:ident = [ $($entries:tt)* ];) => {
+        $(#[$meta])*
+        $vis const $name: &[$crate::att::Attribute<'static>] = $crate::attribute_table!(@entries (1) []
+            primary_service($crate::gatt::GENERIC_ACCESS_SERVICE_UUID),
+            characteristic($crate::gatt::DEVICE_NAME_UUID, $crate::gatt::PROPERTY_READ, $crate::att::PERMISSION_READ, &[]),
+            characteristic($crate::gatt::APPEARANCE_UUID, $crate::gatt::PROPERTY_READ, $crate::att::PERMISSION_READ, &[]),
+            primary_service($crate::gatt::GENERIC_ATTRIBUTE_SERVICE_UUID),
+            // all handles may have changed (the table is only known at compile time)
+            characteristic($crate::gatt::SERVICE_CHANGED_UUID, $crate::gatt::PROPERTY_INDICATE, 0, &[0x01, 0x00, 0xFF, 0xFF]),
+            client_characteristic_configuration(),
+            $($entries)*);
+    };
+
+    (@entries ($handle:expr) [$($table:tt)*]) => { &[ $($table)* ] };
+
+    (@entries ($handle:expr) [$($table:tt)*] primary_service($uuid:expr) $(, $($rest:tt)*)?) => {
+        $crate::attribute_table!(@entries ($handle + 1) [$($table)*
+            $crate::att::Attribute{ handle: $handle, uuid: $crate::att::PRIMARY_SERVICE_UUID,
+                                    permissions: $crate::att::PERMISSION_READ,
+                                    value: $crate::gatt::truncate(&$crate::gatt::uuid_bytes($uuid), $uuid.len()) },
+        ] $($($rest)*)?)
+    };
+
+    (@entries ($handle:expr) [$($table:tt)*] secondary_service($uuid:expr) $(, $($rest:tt)*)?) => {
+        $crate::attribute_table!(@entries ($handle + 1) [$($table)*
+            $crate::att::Attribute{ handle: $handle, uuid: $crate::att::SECONDARY_SERVICE_UUID,
+                                    permissions: $crate::att::PERMISSION_READ,
+                                    value: $crate::gatt::truncate(&$crate::gatt::uuid_bytes($uuid), $uuid.len()) },
+        ] $($($rest)*)?)
+    };
+
+    (@entries ($handle:expr) [$($table:tt)*] characteristic($uuid:expr, $properties:expr, $permissions:expr, $value:expr) $(, $($rest:tt)*)?) => {
+        $crate::attribute_table!(@entries ($handle + 2) [$($table)*
+            $crate::att::Attribute{ handle: $handle, uuid: $crate::gatt::CHARACTERISTIC_UUID,
+                                    permissions: $crate::att::PERMISSION_READ,
+                                    value: $crate::gatt::truncate(
+                                        &$crate::gatt::characteristic_declaration($properties, $handle + 1, $uuid),
+                                        3 + $uuid.len()) },
+            $crate::att::Attribute{ handle: $handle + 1, uuid: $uuid, permissions: $permissions, value: $value },
+        ] $($($rest)*)?)
+    };
+
+    (@entries ($handle:expr) [$($table:tt)*] descriptor($uuid:expr, $permissions:expr, $value:expr) $(, $($rest:tt)*)?) => {
+        $crate::attribute_table!(@entries ($handle + 1) [$($table)*
+            $crate::att::Attribute{ handle: $handle, uuid: $uuid, permissions: $permissions, value: $value },
+        ] $($($rest)*)?)
+    };
+
+    (@entries ($handle:expr) [$($table:tt)*] client_characteristic_configuration() $(, $($rest:tt)*)?) => {
+        $crate::attribute_table!(@entries ($handle + 1) [$($table)*
+            $crate::att::Attribute{ handle: $handle, uuid: $crate::gatt::CLIENT_CHARACTERISTIC_CONFIGURATION_UUID,
+                                    permissions: $crate::att::PERMISSION_READ | $crate::att::PERMISSION_WRITE,
+                                    value: &[0, 0] },
+        ] $($($rest)*)?)
+    };
+}
+
+/// application hooks for dynamic characteristics
+///
+/// called from Ble::work (outside of the radio interrupt)
+pub trait CharacteristicHandler {
+    /// provide the value (starting at offset for long reads), None serves the value from the attribute table
+    fn read(&mut self, _handle: Handle, _offset: usize, _buffer: &mut [u8]) -> Option<Result<usize, ErrorCode>> {
+        None
+    }
+
+    /// accept a write to a writable attribute (or reject it with an ATT error code)
+    fn write(&mut self, _handle: Handle, _offset: usize, _data: &[u8]) -> Result<(), ErrorCode> {
+        Err(ErrorCode::WriteNotPermitted)
+    }
+
+    /// the client changed the Client Characteristic Configuration of a characteristic (value handle)
+    fn configuration_changed(&mut self, _handle: Handle, _notify: bool, _indicate: bool) {}
+}
+/// serve only the values of the attribute table
+impl CharacteristicHandler for () {}
+
+/// Client Characteristic Configuration bits (Core_v5.3 Vol 3, Part G, 3.3.3.3)
+pub const CCCD_NOTIFICATION:u16 = 1 << 0;
+pub const CCCD_INDICATION:u16 = 1 << 1;
+/// maximum number of Client Characteristic Configuration descriptors in an attribute table
+pub const CCCD_MAX:usize = 16;
+
+/// the configuration of each CCCD (in table order)
+pub type ClientConfiguration = [u16; CCCD_MAX];
+
+/// reasons a notification or indication wasn't sent
+#[derive(Debug, PartialEq)]
+pub enum NotifyError {
+    NotConnected,
+    /// the client hasn't enabled notifications (or indications) of the characteristic
+    NotEnabled,
+    /// the previous indication awaits its confirmation
+    IndicationPending,
+    /// the transmit queue is full (retry later)
+    Busy,
+}
+
+/// GATT server over an attribute table built by attribute_table!
+/// Core_v5.3 Vol 3, Part G
+pub struct GattServer<'a> {
+    attributes: &'a [Attribute<'a>],
+    device_name: &'a [u8],
+    appearance: [u8;2],
+    /// configuration of the connected client
+    cccds: ClientConfiguration,
+}
+impl<'a> GattServer<'a> {
+    /// the Generic Access service reports the local_name and appearance of the advertisement
+    pub fn new(attributes: &'a [Attribute<'a>], ad_fields: &AdFields<'a>) -> Self
+    {
+        debug_assert!(attributes.len() >= (APPLICATION_HANDLE_START - 1) as usize
+                      && attributes[(DEVICE_NAME_HANDLE - 1) as usize].uuid == DEVICE_NAME_UUID,
+                      "attribute table must be built by attribute_table!");
+        debug_assert!(attributes.iter().filter(|a| a.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION_UUID).count() <= CCCD_MAX,
+                      "too many Client Characteristic Configuration descriptors");
+        Self {
+            attributes,
+            device_name: match ad_fields.local_name {
+                Some(name) => name.as_bytes(),
+                None => &[],
+            },
+            appearance: ad_fields.appearance.unwrap_or(0).to_le_bytes(),
+            cccds: [0; CCCD_MAX],
+        }
+    }
+
+    /// handle an ATT request, returns the size of the response (0 if none)
+    pub fn handle<H: CharacteristicHandler>(&mut self, att: &mut AttServer, handler: &mut H,
+                                            request: &[u8], response: &mut [u8]) -> usize
+    {
+        att.handle(&mut Dispatch{ server: self, handler }, request, response)
+    }
+
+    /// the client's configuration of a characteristic (value handle)
+    pub fn configuration(&self, handle: Handle) -> u16 {
+        return match self.descriptor_cccd_index(handle) {
+            Some(index) => self.cccds[index],
+            None => 0,
+        };
+    }
+
+    /// forget the client configurations
+    pub fn reset_configuration(&mut self) {
+        self.cccds = [0; CCCD_MAX];
+    }
+
+    /// a client connected with the configuration persisted in its bond (Core_v5.3 Vol 3, Part G,
+    /// 3.3.3.3), otherwise none
+    pub fn connected(&mut self, cccds: ClientConfiguration) {
+        self.cccds = cccds;
+    }
+
+    /// the configuration of the connected client (persisted in the bond of a bonded client)
+    pub fn client_configuration(&self) -> ClientConfiguration {
+        return self.cccds;
+    }
+
+    /// the client disconnected
+    pub fn disconnected(&mut self) {
+        self.reset_configuration();
+    }
+
+    /// write a Handle Value Notification (if enabled by the client), returns the size of the PDU
+    /// Core_v5.3 Vol 3, Part G, 4.10
+    pub fn notification(&self, att: &AttServer, handle: Handle, value: &[u8], buffer: &mut [u8]) -> Result<usize, NotifyError> {
+        if (self.configuration(handle) & CCCD_NOTIFICATION) == 0 {
+            return Err(NotifyError::NotEnabled);
+        }
+        return Ok(att.notification(handle, value, buffer));
+    }
+
+    /// write a Handle Value Indication (if enabled by the client), returns the size of the PDU
+    /// Core_v5.3 Vol 3, Part G, 4.11
+    pub fn indication(&self, att: &mut AttServer, handle: Handle, value: &[u8], buffer: &mut [u8]) -> Result<usize, NotifyError> {
+        if (self.configuration(handle) & CCCD_INDICATION) == 0 {
+            return Err(NotifyError::NotEnabled);
+        }
+        return match att.indication(handle, value, buffer) {
+            Some(size) => Ok(size),
+            None => Err(NotifyError::IndicationPending),
+        };
+    }
+
+    /// the index of the CCCD (by its handle) within the table
+    fn cccd_index(&self, handle: Handle) -> Option<usize> {
+        let mut index = 0;
+        for attribute in self.attributes.iter() {
+            if attribute.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION_UUID {
+                if attribute.handle == handle {
+                    return Some(index);
+                }
+                index += 1;
+            }
+        }
+        return None;
+    }
+
+    /// the index of the CCCD among the descriptors of a characteristic (value handle), which extend up
+    /// to the next declaration (Core_v5.3 Vol 3, Part G, 3.3)
+    fn descriptor_cccd_index(&self, handle: Handle) -> Option<usize> {
+        let declaration = [att::PRIMARY_SERVICE_UUID, att::SECONDARY_SERVICE_UUID, INCLUDE_UUID, CHARACTERISTIC_UUID];
+        let cccd = self.attributes.iter()
+            .skip_while(|a| a.handle <= handle)
+            .take_while(|a| ! declaration.contains(&a.uuid))
+            .find(|a| a.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION_UUID)?;
+        return self.cccd_index(cccd.handle);
+    }
+
+    /// the declaration of the characteristic owning the attribute
+    fn characteristic(&self, handle: Handle) -> Option<&Attribute<'a>> {
+        self.attributes.iter().rev()
+            .filter(|a| a.handle < handle)
+            .find(|a| a.uuid == CHARACTERISTIC_UUID)
+    }
+
+    fn read_with<H: CharacteristicHandler>(&mut self, handler: &mut H, handle: Handle, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        let attribute = match self.attributes.iter().find(|a| a.handle == handle) {
+            Some(attribute) => attribute,
+            None => return Err(ErrorCode::InvalidHandle),
+        };
+        if handle == DEVICE_NAME_HANDLE {
+            return att::read_value(self.device_name, offset, buffer);
+        }
+        if handle == APPEARANCE_HANDLE {
+            return att::read_value(&self.appearance, offset, buffer);
+        }
+        if attribute.uuid == CLIENT_CHARACTERISTIC_CONFIGURATION_UUID {
+            let index = self.cccd_index(handle).unwrap();
+            return att::read_value(&self.cccds[index].to_le_bytes(), offset, buffer);
+        }
+        // declarations are always static
+        let declaration = [att::PRIMARY_SERVICE_UUID, att::SECONDARY_SERVICE_UUID, INCLUDE_UUID, CHARACTERISTIC_UUID];
+        if ! declaration.contains(&attribute.uuid) {
+            if let Some(result) = handler.read(handle, offset, buffer) {
+                return result;
+            }
+        }
+        return att::read_value(attribute.value, offset, buffer);
+    }
+
+    fn write_with<H: CharacteristicHandler>(&mut self, handler: &mut H, handle: Handle, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        let index = match self.cccd_index(handle) {
+            Some(index) => index,
+            None => return handler.write(handle, offset, data),
+        };
+
+        // Client Characteristic Configuration (Core_v5.3 Vol 3, Part G, 3.3.3.3)
+        if offset != 0 {
+            return Err(ErrorCode::AttributeNotLong);
+        }
+        if data.len() != 2 {
+            return Err(ErrorCode::InvalidAttributeValueLength);
+        }
+        let configuration = u16::from_le_bytes([data[0], data[1]]);
+        let properties = match self.characteristic(handle) {
+            Some(declaration) => declaration.value[0],
+            None => 0,
+        };
+        if ((configuration & CCCD_NOTIFICATION) != 0 && (properties & PROPERTY_NOTIFY) == 0)
+            || ((configuration & CCCD_INDICATION) != 0 && (properties & PROPERTY_INDICATE) == 0)
+            || (configuration & !(CCCD_NOTIFICATION | CCCD_INDICATION)) != 0
+        {
+            return Err(ErrorCode::CccdImproperlyConfigured);
+        }
+        if self.cccds[index] != configuration {
+            self.cccds[index] = configuration;
+            // the characteristic value precedes its descriptors
+            let value_handle = self.characteristic(handle).map(|declaration| declaration.handle + 1).unwrap_or(handle);
+            handler.configuration_changed(value_handle,
+                                          (configuration & CCCD_NOTIFICATION) != 0,
+                                          (configuration & CCCD_INDICATION) != 0);
+        }
+        return Ok(());
+    }
+}
+impl<'a> AttributeDatabase for GattServer<'a> {
+    fn attributes(&self) -> &[Attribute<'_>] { self.attributes }
+
+    fn read(&mut self, handle: Handle, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.read_with(&mut (), handle, offset, buffer)
+    }
+
+    fn write(&mut self, handle: Handle, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.write_with(&mut (), handle, offset, data)
+    }
+}
+
+/// binds the application's handler to the server for the duration of a request
+struct Dispatch<'s, 'a, H: CharacteristicHandler> {
+    server: &'s mut GattServer<'a>,
+    handler: &'s mut H,
+}
+impl<'s, 'a, H: CharacteristicHandler> AttributeDatabase for Dispatch<'s, 'a, H> {
+    fn attributes(&self) -> &[Attribute<'_>] { self.server.attributes }
+
+    fn read(&mut self, handle: Handle, offset: usize, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.server.read_with(self.handler, handle, offset, buffer)
+    }
+
+    fn write(&mut self, handle: Handle, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        self.server.write_with(self.handler, handle, offset, data)
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod gatt_server {
+    use super::*;
+    use crate::att::{Opcode, ATT_MTU_MAX, PERMISSION_READ, PERMISSION_WRITE, PRIMARY_SERVICE_UUID};
+
+    crate::attribute_table! {
+        const ATTRIBUTES = [
+            primary_service(Uuid::Uuid16(0x180F)),
+            characteristic(Uuid::Uuid16(0x2A19), PROPERTY_READ | PROPERTY_NOTIFY, PERMISSION_READ, &[100]),
+            client_characteristic_configuration(),
+            secondary_service(Uuid::Uuid128(0xA55A)),
+            characteristic(Uuid::Uuid128(0x5AA5), PROPERTY_READ | PROPERTY_WRITE, PERMISSION_READ | PERMISSION_WRITE, b"value"),
+            descriptor(CHARACTERISTIC_USER_DESCRIPTION_UUID, PERMISSION_READ, b"user description"),
+            characteristic(Uuid::Uuid16(0x2A37), PROPERTY_NOTIFY, 0, &[]),
+            descriptor(CHARACTERISTIC_USER_DESCRIPTION_UUID, PERMISSION_READ, b"heart rate"),
+            client_characteristic_configuration(),
+        ];
+    }
+
+    #[test]
+    fn generated_handles() {
+        for (index, attribute) in ATTRIBUTES.iter().enumerate() {
+            assert_eq!((index + 1) as Handle, attribute.handle);
+        }
+        assert_eq!(GENERIC_ACCESS_SERVICE_UUID.to_u128().to_le_bytes()[12..14], *ATTRIBUTES[(GENERIC_ACCESS_SERVICE_HANDLE - 1) as usize].value);
+        assert_eq!(DEVICE_NAME_UUID, ATTRIBUTES[(DEVICE_NAME_HANDLE - 1) as usize].uuid);
+        assert_eq!(APPEARANCE_UUID, ATTRIBUTES[(APPEARANCE_HANDLE - 1) as usize].uuid);
+        assert_eq!(GENERIC_ATTRIBUTE_SERVICE_UUID.to_u128().to_le_bytes()[12..14], *ATTRIBUTES[(GENERIC_ATTRIBUTE_SERVICE_HANDLE - 1) as usize].value);
+        assert_eq!(SERVICE_CHANGED_UUID, ATTRIBUTES[(SERVICE_CHANGED_HANDLE - 1) as usize].uuid);
+        assert_eq!(CLIENT_CHARACTERISTIC_CONFIGURATION_UUID, ATTRIBUTES[(SERVICE_CHANGED_CCCD_HANDLE - 1) as usize].uuid);
+        assert_eq!(PRIMARY_SERVICE_UUID, ATTRIBUTES[(APPLICATION_HANDLE_START - 1) as usize].uuid);
+    }
+    #[test]
+    fn characteristic_declarations() {
+        // 16-bit characteristic
+        assert_eq!([PROPERTY_READ | PROPERTY_NOTIFY, 12, 0, 0x19, 0x2A], *ATTRIBUTES[10].value);
+        // 128-bit characteristic
+        let declaration = ATTRIBUTES[14].value;
+        assert_eq!(19, declaration.len());
+        assert_eq!([PROPERTY_READ | PROPERTY_WRITE, 16, 0], declaration[..3]);
+        assert_eq!(0x5AA5_u128.to_le_bytes(), declaration[3..]);
+    }
+    #[test]
+    fn device_name_and_appearance() {
+        let ad_fields = AdFields{ local_name:Some("GATT Demo"), appearance:Some(0x03C1), ..AdFields::default() };
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        let size = server.handle(&mut gatt, &[Opcode::ReadReq as u8, DEVICE_NAME_HANDLE as u8, 0], &mut response);
+        assert_eq!(b"GATT Demo", &response[1..size]);
+        let size = server.handle(&mut gatt, &[Opcode::ReadReq as u8, APPEARANCE_HANDLE as u8, 0], &mut response);
+        assert_eq!([0xC1, 0x03], response[1..size]);
+    }
+    #[test]
+    fn discover_primary_services() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        let size = server.handle(&mut gatt, &[Opcode::ReadByGroupTypeReq as u8, 1, 0, 0xFF, 0xFF, 0x00, 0x28], &mut response);
+        assert_eq!([Opcode::ReadByGroupTypeRsp as u8, 6,
+                    1, 0, 5, 0, 0x00, 0x18,
+                    6, 0, 9, 0, 0x01, 0x18,
+                    10, 0, 13, 0, 0x0F, 0x18], response[..size]);
+    }
+
+    /// counts on the sensor value, accepts writes of one octet
+    #[derive(Default)]
+    struct Sensor {
+        reads: u8,
+        written: Option<u8>,
+        configuration: Option<(Handle, bool, bool)>,
+    }
+    impl CharacteristicHandler for Sensor {
+        fn read(&mut self, handle: Handle, _offset: usize, buffer: &mut [u8]) -> Option<Result<usize, ErrorCode>> {
+            if handle != 12 {
+                return None;
+            }
+            self.reads += 1;
+            buffer[0] = self.reads;
+            Some(Ok(1))
+        }
+        fn write(&mut self, _handle: Handle, _offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+            if data.len() != 1 {
+                return Err(ErrorCode::InvalidAttributeValueLength);
+            }
+            self.written = Some(data[0]);
+            Ok(())
+        }
+        fn configuration_changed(&mut self, handle: Handle, notify: bool, indicate: bool) {
+            self.configuration = Some((handle, notify, indicate));
+        }
+    }
+
+    #[test]
+    fn handler_read() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut sensor = Sensor::default();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::ReadReq as u8, 12, 0], &mut response);
+        assert_eq!([Opcode::ReadRsp as u8, 1], response[..size]);
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::ReadReq as u8, 12, 0], &mut response);
+        assert_eq!([Opcode::ReadRsp as u8, 2], response[..size]);
+        // other values are served from the table
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::ReadReq as u8, 16, 0], &mut response);
+        assert_eq!(b"value", &response[1..size]);
+    }
+    #[test]
+    fn handler_write() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut sensor = Sensor::default();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::WriteReq as u8, 16, 0, 7], &mut response);
+        assert_eq!([Opcode::WriteRsp as u8], response[..size]);
+        assert_eq!(Some(7), sensor.written);
+        // rejected by the handler
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::WriteReq as u8, 16, 0, 7, 8], &mut response);
+        assert_eq!([Opcode::ErrorRsp as u8, Opcode::WriteReq as u8, 16, 0, ErrorCode::InvalidAttributeValueLength as u8], response[..size]);
+        // not writable
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::WriteReq as u8, 12, 0, 7], &mut response);
+        assert_eq!(ErrorCode::WriteNotPermitted as u8, response[size - 1]);
+    }
+    #[test]
+    fn configuration_changed() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut sensor = Sensor::default();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::WriteReq as u8, 13, 0, 0x01, 0x00], &mut response);
+        assert_eq!([Opcode::WriteRsp as u8], response[..size]);
+        assert_eq!(Some((12, true, false)), sensor.configuration);
+        assert_eq!(CCCD_NOTIFICATION, gatt.configuration(12));
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::ReadReq as u8, 13, 0], &mut response);
+        assert_eq!([Opcode::ReadRsp as u8, 0x01, 0x00], response[..size]);
+        // the battery level doesn't support indications
+        let size = gatt.handle(&mut server, &mut sensor, &[Opcode::WriteReq as u8, 13, 0, 0x02, 0x00], &mut response);
+        assert_eq!(ErrorCode::CccdImproperlyConfigured as u8, response[size - 1]);
+    }
+    #[test]
+    fn notification_requires_configuration() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut buffer = [0; ATT_MTU_MAX as usize];
+        assert_eq!(Err(NotifyError::NotEnabled), gatt.notification(&server, 12, &[50], &mut buffer));
+        gatt.handle(&mut server, &mut (), &[Opcode::WriteReq as u8, 13, 0, 0x01, 0x00], &mut buffer);
+        assert_eq!(Ok(4), gatt.notification(&server, 12, &[50], &mut buffer));
+        assert_eq!([Opcode::HandleValueNtf as u8, 12, 0, 50], buffer[..4]);
+    }
+    #[test]
+    fn configuration_after_other_descriptors() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut buffer = [0; ATT_MTU_MAX as usize];
+        // the CCCD (21) of the heart rate (19) follows its user description (20)
+        gatt.handle(&mut server, &mut (), &[Opcode::WriteReq as u8, 21, 0, 0x01, 0x00], &mut buffer);
+        assert_eq!(CCCD_NOTIFICATION, gatt.configuration(19));
+        assert_eq!(Ok(4), gatt.notification(&server, 19, &[72], &mut buffer));
+        // the descriptors of a characteristic end at the next declaration
+        assert_eq!(0, gatt.configuration(16));
+        assert_eq!(Err(NotifyError::NotEnabled), gatt.notification(&server, 16, &[72], &mut buffer));
+    }
+    #[test]
+    fn single_outstanding_indication() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut buffer = [0; ATT_MTU_MAX as usize];
+        gatt.handle(&mut server, &mut (), &[Opcode::WriteReq as u8, SERVICE_CHANGED_CCCD_HANDLE as u8, 0, 0x02, 0x00], &mut buffer);
+        assert_eq!(Ok(7), gatt.indication(&mut server, SERVICE_CHANGED_HANDLE, &[1, 0, 0xFF, 0xFF], &mut buffer));
+        assert_eq!(Err(NotifyError::IndicationPending), gatt.indication(&mut server, SERVICE_CHANGED_HANDLE, &[1, 0, 0xFF, 0xFF], &mut buffer));
+        gatt.handle(&mut server, &mut (), &[Opcode::HandleValueCfm as u8], &mut buffer);
+        assert_eq!(Ok(7), gatt.indication(&mut server, SERVICE_CHANGED_HANDLE, &[1, 0, 0xFF, 0xFF], &mut buffer));
+    }
+    #[test]
+    fn restored_configuration() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let mut server = AttServer::new();
+        let mut buffer = [0; ATT_MTU_MAX as usize];
+        gatt.connected([0; CCCD_MAX]);
+        gatt.handle(&mut server, &mut (), &[Opcode::WriteReq as u8, 13, 0, 0x01, 0x00], &mut buffer);
+        let cccds = gatt.client_configuration();
+        assert_eq!(CCCD_NOTIFICATION, cccds[1]);
+        gatt.disconnected();
+        assert_eq!(0, gatt.configuration(12));
+        // the configuration of a bonded client is restored upon reconnection
+        gatt.connected(cccds);
+        assert_eq!(CCCD_NOTIFICATION, gatt.configuration(12));
+    }
+}
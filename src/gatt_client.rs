@@ -0,0 +1,782 @@
+use core::convert::TryFrom;
+use core::slice::ChunksExact;
+
+use crate::att::{ErrorCode, Handle, Opcode, Uuid, ATT_MTU_MAX, ATT_MTU_MIN, HANDLE_MAX, HANDLE_MIN,
+                 PRIMARY_SERVICE_UUID, SECONDARY_SERVICE_UUID};
+use crate::gatt::{CHARACTERISTIC_UUID, INCLUDE_UUID};
+
+/// room for the values of a long (or reliable) write, each value is preceded by (handle:2, length:2)
+pub const WRITE_QUEUE_SIZE:usize = 512 + 4;
+
+/// reasons a procedure couldn't be started
+#[derive(Debug, PartialEq)]
+pub enum ClientError {
+    /// a procedure is in progress (ATT allows a single outstanding request)
+    Busy,
+    /// the value doesn't fit the ATT_MTU (or the write queue)
+    TooLong,
+    /// there is no connection (see Ble::discover_primary_services)
+    NotConnected,
+}
+
+/// a service declaration
+#[derive(Debug, PartialEq)]
+pub struct Service {
+    pub start: Handle,
+    /// last handle of the service
+    pub end: Handle,
+    pub uuid: Uuid,
+}
+
+/// an include declaration (Core_v5.3 Vol 3, Part G, 3.2)
+#[derive(Debug, PartialEq)]
+pub struct IncludedService {
+    pub handle: Handle,
+    pub start: Handle,
+    pub end: Handle,
+    /// None for 128-bit UUIDs (read the service declaration at start)
+    pub uuid: Option<Uuid>,
+}
+
+/// a characteristic declaration (Core_v5.3 Vol 3, Part G, 3.3.1)
+#[derive(Debug, PartialEq)]
+pub struct Characteristic {
+    pub handle: Handle,
+    pub properties: u8,
+    pub value_handle: Handle,
+    pub uuid: Uuid,
+}
+
+/// a characteristic descriptor
+#[derive(Debug, PartialEq)]
+pub struct Descriptor {
+    pub handle: Handle,
+    pub uuid: Uuid,
+}
+
+/// services of a Read By Group Type (or Find By Type Value) Response
+pub struct Services<'p> {
+    records: ChunksExact<'p, u8>,
+    /// the UUID searched for (Find By Type Value Response)
+    uuid: Option<Uuid>,
+}
+impl<'p> Iterator for Services<'p> {
+    type Item = Service;
+    fn next(&mut self) -> Option<Service> {
+        let record = self.records.next()?;
+        let uuid = match self.uuid {
+            Some(uuid) => uuid,
+            None => Uuid::from_slice(&record[4..])?,
+        };
+        return Some(Service {
+            start: u16::from_le_bytes([record[0], record[1]]),
+            end: u16::from_le_bytes([record[2], record[3]]),
+            uuid,
+        });
+    }
+}
+
+/// include declarations of a Read By Type Response
+pub struct IncludedServices<'p> {
+    records: ChunksExact<'p, u8>,
+}
+impl<'p> Iterator for IncludedServices<'p> {
+    type Item = IncludedService;
+    fn next(&mut self) -> Option<IncludedService> {
+        let record = self.records.next()?;
+        return Some(IncludedService {
+            handle: u16::from_le_bytes([record[0], record[1]]),
+            start: u16::from_le_bytes([record[2], record[3]]),
+            end: u16::from_le_bytes([record[4], record[5]]),
+            uuid: Uuid::from_slice(&record[6..]),
+        });
+    }
+}
+
+/// characteristic declarations of a Read By Type Response
+pub struct Characteristics<'p> {
+    records: ChunksExact<'p, u8>,
+    /// only report characteristics of this UUID
+    uuid: Option<Uuid>,
+}
+impl<'p> Iterator for Characteristics<'p> {
+    type Item = Characteristic;
+    fn next(&mut self) -> Option<Characteristic> {
+        loop {
+            let record = self.records.next()?;
+            let uuid = Uuid::from_slice(&record[5..])?;
+            match self.uuid {
+                Some(expected) if expected != uuid => continue,
+                _ => {}
+            }
+            return Some(Characteristic {
+                handle: u16::from_le_bytes([record[0], record[1]]),
+                properties: record[2],
+                value_handle: u16::from_le_bytes([record[3], record[4]]),
+                uuid,
+            });
+        }
+    }
+}
+
+/// descriptors of a Find Information Response
+pub struct Descriptors<'p> {
+    records: ChunksExact<'p, u8>,
+}
+impl<'p> Iterator for Descriptors<'p> {
+    type Item = Descriptor;
+    fn next(&mut self) -> Option<Descriptor> {
+        let record = self.records.next()?;
+        return Some(Descriptor {
+            handle: u16::from_le_bytes([record[0], record[1]]),
+            uuid: Uuid::from_slice(&record[2..])?,
+        });
+    }
+}
+
+/// results of the procedures (and values sent by the server)
+pub enum Event<'p> {
+    /// the ATT_MTU of the bearer
+    MtuExchanged { mtu: u16 },
+    Services(Services<'p>),
+    IncludedServices(IncludedServices<'p>),
+    Characteristics(Characteristics<'p>),
+    Descriptors(Descriptors<'p>),
+    /// (part of) a value, starting at offset for long reads
+    Value { handle: Handle, offset: usize, value: &'p [u8] },
+    /// the write completed (the first handle of a reliable write)
+    Written { handle: Handle },
+    /// the server didn't echo the value of a reliable write (the writes were cancelled)
+    WriteCancelled { handle: Handle },
+    Notification { handle: Handle, value: &'p [u8] },
+    /// the confirmation is sent by the client
+    Indication { handle: Handle, value: &'p [u8] },
+    Error { handle: Handle, code: ErrorCode },
+}
+
+/// receives the results of the procedures started on Ble (see Ble::work)
+pub trait ClientHandler {
+    /// a result of the current procedure, or a value sent by the server
+    fn client_event(&mut self, _event: Event) {}
+}
+/// ignore the results
+impl ClientHandler for () {}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Procedure {
+    Idle,
+    ExchangeMtu { mtu: u16 },
+    /// discovery of the service group type (primary or secondary) up to the end handle
+    DiscoverServices { group: Uuid, end: Handle },
+    DiscoverServicesByUuid { uuid: Uuid, end: Handle },
+    FindIncludedServices { end: Handle },
+    DiscoverCharacteristics { uuid: Option<Uuid>, end: Handle },
+    DiscoverDescriptors { end: Handle },
+    Read { handle: Handle, offset: usize, long: bool },
+    Write { handle: Handle },
+    /// prepared writes of the queue (position of the current value, offset within the value)
+    QueuedWrite { reliable: bool, position: usize, offset: usize },
+    ExecuteWrite { handle: Handle },
+}
+
+/// Generic Attribute Profile client (one per ATT bearer)
+///
+/// Procedures write their requests into the provided buffer, the responses of the server are
+/// passed to handle() which reports the results and writes any further request of the procedure.
+/// Core_v5.3 Vol 3, Part G, 4
+pub struct GattClient {
+    mtu: u16,
+    procedure: Procedure,
+    /// values of a long (or reliable) write
+    queue: [u8; WRITE_QUEUE_SIZE],
+    queue_len: usize,
+}
+impl Default for GattClient {
+    fn default() -> Self { Self::new() }
+}
+impl GattClient {
+    pub fn new() -> Self {
+        Self {
+            mtu: ATT_MTU_MIN,
+            procedure: Procedure::Idle,
+            queue: [0; WRITE_QUEUE_SIZE],
+            queue_len: 0,
+        }
+    }
+
+    /// the current ATT_MTU
+    pub fn mtu(&self) -> u16 { self.mtu }
+
+    /// update the ATT_MTU (as exchanged with the server)
+    pub fn set_mtu(&mut self, mtu: u16) { self.mtu = mtu; }
+
+    /// true once the last procedure has completed
+    pub fn is_idle(&self) -> bool { self.procedure == Procedure::Idle }
+
+    /// negotiate the ATT_MTU (up to the mtu the client can receive)
+    /// Core_v5.3 Vol 3, Part G, 4.3.1
+    pub fn exchange_mtu(&mut self, mtu: u16, request: &mut [u8]) -> Result<usize, ClientError> {
+        let mtu = mtu.clamp(ATT_MTU_MIN, ATT_MTU_MAX);
+        self.start(Procedure::ExchangeMtu{ mtu })?;
+        request[0] = Opcode::ExchangeMtuReq as u8;
+        request[1..3].copy_from_slice(&mtu.to_le_bytes());
+        return Ok(3);
+    }
+
+    /// Core_v5.3 Vol 3, Part G, 4.4.1
+    pub fn discover_primary_services(&mut self, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::DiscoverServices{ group: PRIMARY_SERVICE_UUID, end: HANDLE_MAX })?;
+        return Ok(Self::range_request(Opcode::ReadByGroupTypeReq, HANDLE_MIN, HANDLE_MAX, &PRIMARY_SERVICE_UUID, request));
+    }
+
+    /// discover the secondary services (if the server supports grouping them)
+    pub fn discover_secondary_services(&mut self, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::DiscoverServices{ group: SECONDARY_SERVICE_UUID, end: HANDLE_MAX })?;
+        return Ok(Self::range_request(Opcode::ReadByGroupTypeReq, HANDLE_MIN, HANDLE_MAX, &SECONDARY_SERVICE_UUID, request));
+    }
+
+    /// Core_v5.3 Vol 3, Part G, 4.4.2
+    pub fn discover_primary_services_by_uuid(&mut self, uuid: Uuid, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::DiscoverServicesByUuid{ uuid, end: HANDLE_MAX })?;
+        return Ok(Self::find_by_type_value(HANDLE_MIN, HANDLE_MAX, &uuid, request));
+    }
+
+    /// find the included services of a service (start..=end)
+    /// Core_v5.3 Vol 3, Part G, 4.5.1
+    pub fn find_included_services(&mut self, start: Handle, end: Handle, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::FindIncludedServices{ end })?;
+        return Ok(Self::range_request(Opcode::ReadByTypeReq, start, end, &INCLUDE_UUID, request));
+    }
+
+    /// discover the characteristics of a service (start..=end), optionally only those of a UUID
+    /// Core_v5.3 Vol 3, Part G, 4.6.1 and 4.6.2
+    pub fn discover_characteristics(&mut self, start: Handle, end: Handle, uuid: Option<Uuid>, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::DiscoverCharacteristics{ uuid, end })?;
+        return Ok(Self::range_request(Opcode::ReadByTypeReq, start, end, &CHARACTERISTIC_UUID, request));
+    }
+
+    /// discover the descriptors of a characteristic (from its value handle + 1 to the end of the characteristic)
+    /// Core_v5.3 Vol 3, Part G, 4.7.1
+    pub fn discover_descriptors(&mut self, start: Handle, end: Handle, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::DiscoverDescriptors{ end })?;
+        return Ok(Self::find_information(start, end, request));
+    }
+
+    /// Core_v5.3 Vol 3, Part G, 4.8.1
+    pub fn read(&mut self, handle: Handle, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::Read{ handle, offset: 0, long: false })?;
+        return Ok(Self::handle_request(Opcode::ReadReq, handle, request));
+    }
+
+    /// read a value longer than the ATT_MTU (reported in parts)
+    /// Core_v5.3 Vol 3, Part G, 4.8.3
+    pub fn read_long(&mut self, handle: Handle, request: &mut [u8]) -> Result<usize, ClientError> {
+        self.start(Procedure::Read{ handle, offset: 0, long: true })?;
+        return Ok(Self::handle_request(Opcode::ReadReq, handle, request));
+    }
+
+    /// Core_v5.3 Vol 3, Part G, 4.9.3
+    pub fn write(&mut self, handle: Handle, value: &[u8], request: &mut [u8]) -> Result<usize, ClientError> {
+        if value.len() > (self.mtu as usize - 3) {
+            return Err(ClientError::TooLong);
+        }
+        self.start(Procedure::Write{ handle })?;
+        return Ok(Self::value_request(Opcode::WriteReq, handle, value, request));
+    }
+
+    /// write without a response (allowed during other procedures)
+    /// Core_v5.3 Vol 3, Part G, 4.9.1
+    pub fn write_without_response(&self, handle: Handle, value: &[u8], request: &mut [u8]) -> Result<usize, ClientError> {
+        if value.len() > (self.mtu as usize - 3) {
+            return Err(ClientError::TooLong);
+        }
+        return Ok(Self::value_request(Opcode::WriteCmd, handle, value, request));
+    }
+
+    /// write a value longer than the ATT_MTU
+    /// Core_v5.3 Vol 3, Part G, 4.9.4
+    pub fn write_long(&mut self, handle: Handle, value: &[u8], request: &mut [u8]) -> Result<usize, ClientError> {
+        return self.queued_write(&[(handle, value)], false, request);
+    }
+
+    /// write several values atomically (each prepared write is verified before executing)
+    /// Core_v5.3 Vol 3, Part G, 4.9.5
+    pub fn reliable_write(&mut self, values: &[(Handle, &[u8])], request: &mut [u8]) -> Result<usize, ClientError> {
+        return self.queued_write(values, true, request);
+    }
+
+    /// enable (or disable) notifications and indications of a characteristic (by its CCCD)
+    /// Core_v5.3 Vol 3, Part G, 4.12.3
+    pub fn subscribe(&mut self, cccd_handle: Handle, notify: bool, indicate: bool, request: &mut [u8]) -> Result<usize, ClientError> {
+        let mut configuration = 0;
+        if notify {
+            configuration |= crate::gatt::CCCD_NOTIFICATION;
+        }
+        if indicate {
+            configuration |= crate::gatt::CCCD_INDICATION;
+        }
+        return self.write(cccd_handle, &configuration.to_le_bytes(), request);
+    }
+
+    /// handle a PDU from the server, returns the result and the size of the next request (0 if none)
+    pub fn handle<'p>(&mut self, pdu: &'p [u8], request: &mut [u8]) -> (Option<Event<'p>>, usize) {
+        if pdu.is_empty() {
+            return (None, 0);
+        }
+        let opcode = match Opcode::try_from(pdu[0]) {
+            Ok(opcode) => opcode,
+            Err(_) => return (None, 0),
+        };
+
+        // values sent by the server (regardless of procedure)
+        match opcode {
+            Opcode::HandleValueNtf | Opcode::HandleValueInd => {
+                if pdu.len() < 3 {
+                    return (None, 0);
+                }
+                let handle = u16::from_le_bytes([pdu[1], pdu[2]]);
+                let value = &pdu[3..];
+                if opcode == Opcode::HandleValueNtf {
+                    return (Some(Event::Notification{ handle, value }), 0);
+                }
+                request[0] = Opcode::HandleValueCfm as u8;
+                return (Some(Event::Indication{ handle, value }), 1);
+            }
+            Opcode::ErrorRsp => return self.error(pdu),
+            _ => {}
+        }
+
+        let procedure = self.procedure;
+        self.procedure = Procedure::Idle;
+        return match (procedure, opcode) {
+            (Procedure::ExchangeMtu{ mtu }, Opcode::ExchangeMtuRsp) if pdu.len() == 3 => {
+                let server_mtu = u16::from_le_bytes([pdu[1], pdu[2]]);
+                self.mtu = core::cmp::min(mtu, server_mtu).max(ATT_MTU_MIN);
+                (Some(Event::MtuExchanged{ mtu: self.mtu }), 0)
+            }
+            (Procedure::DiscoverServices{ group, end }, Opcode::ReadByGroupTypeRsp) => {
+                let records = match Self::records(pdu, 4) {
+                    Some(records) => records,
+                    None => return (None, 0),
+                };
+                let mut size = 0;
+                if let Some(last) = Self::last_handle(records.clone(), 2) {
+                    if last < end {
+                        self.procedure = procedure;
+                        size = Self::range_request(Opcode::ReadByGroupTypeReq, last + 1, end, &group, request);
+                    }
+                }
+                (Some(Event::Services(Services{ records, uuid: None })), size)
+            }
+            (Procedure::DiscoverServicesByUuid{ uuid, end }, Opcode::FindByTypeValueRsp) => {
+                let records = pdu[1..].chunks_exact(4);
+                let mut size = 0;
+                if let Some(last) = Self::last_handle(records.clone(), 2) {
+                    if last < end {
+                        self.procedure = procedure;
+                        size = Self::find_by_type_value(last + 1, end, &uuid, request);
+                    }
+                }
+                (Some(Event::Services(Services{ records, uuid: Some(uuid) })), size)
+            }
+            (Procedure::FindIncludedServices{ end }, Opcode::ReadByTypeRsp) => {
+                let records = match Self::records(pdu, 6) {
+                    Some(records) => records,
+                    None => return (None, 0),
+                };
+                let size = self.continue_read_by_type(procedure, records.clone(), end, &INCLUDE_UUID, request);
+                (Some(Event::IncludedServices(IncludedServices{ records })), size)
+            }
+            (Procedure::DiscoverCharacteristics{ uuid, end }, Opcode::ReadByTypeRsp) => {
+                let records = match Self::records(pdu, 7) {
+                    Some(records) => records,
+                    None => return (None, 0),
+                };
+                let size = self.continue_read_by_type(procedure, records.clone(), end, &CHARACTERISTIC_UUID, request);
+                (Some(Event::Characteristics(Characteristics{ records, uuid })), size)
+            }
+            (Procedure::DiscoverDescriptors{ end }, Opcode::FindInformationRsp) => {
+                let record_size = match pdu.get(1) {
+                    Some(1) => 2 + 2,
+                    Some(2) => 2 + 16,
+                    _ => return (None, 0),
+                };
+                let records = pdu[2..].chunks_exact(record_size);
+                let mut size = 0;
+                if let Some(last) = Self::last_handle(records.clone(), 0) {
+                    if last < end {
+                        self.procedure = procedure;
+                        size = Self::find_information(last + 1, end, request);
+                    }
+                }
+                (Some(Event::Descriptors(Descriptors{ records })), size)
+            }
+            (Procedure::Read{ handle, offset, long }, Opcode::ReadRsp)
+            | (Procedure::Read{ handle, offset, long }, Opcode::ReadBlobRsp) => {
+                let value = &pdu[1..];
+                let mut size = 0;
+                // a full response may continue
+                if long && value.len() == (self.mtu as usize - 1) {
+                    let next = offset + value.len();
+                    self.procedure = Procedure::Read{ handle, offset: next, long };
+                    request[0] = Opcode::ReadBlobReq as u8;
+                    request[1..3].copy_from_slice(&handle.to_le_bytes());
+                    request[3..5].copy_from_slice(&(next as u16).to_le_bytes());
+                    size = 5;
+                }
+                (Some(Event::Value{ handle, offset, value }), size)
+            }
+            (Procedure::Write{ handle }, Opcode::WriteRsp) => (Some(Event::Written{ handle }), 0),
+            (Procedure::QueuedWrite{ reliable, position, offset }, Opcode::PrepareWriteRsp) => {
+                let (handle, value) = self.queued_value(position);
+                let chunk = &value[offset..core::cmp::min(value.len(), offset + self.mtu as usize - 5)];
+                if reliable && ! Self::is_echo(pdu, handle, offset, chunk) {
+                    // cancel the prepared writes
+                    self.procedure = Procedure::ExecuteWrite{ handle: 0 };
+                    request[0] = Opcode::ExecuteWriteReq as u8;
+                    request[1] = 0x00;
+                    return (Some(Event::WriteCancelled{ handle }), 2);
+                }
+                let (position, offset) = match offset + chunk.len() {
+                    next if next < value.len() => (position, next),
+                    _ => (position + 4 + value.len(), 0),
+                };
+                if position < self.queue_len {
+                    self.procedure = Procedure::QueuedWrite{ reliable, position, offset };
+                    return (None, self.prepare_write(position, offset, request));
+                }
+                self.procedure = Procedure::ExecuteWrite{ handle: self.queued_value(0).0 };
+                request[0] = Opcode::ExecuteWriteReq as u8;
+                request[1] = 0x01;
+                (None, 2)
+            }
+            (Procedure::ExecuteWrite{ handle }, Opcode::ExecuteWriteRsp) => {
+                self.queue_len = 0;
+                // nothing to report upon cancellation
+                match handle {
+                    0 => (None, 0),
+                    _ => (Some(Event::Written{ handle }), 0),
+                }
+            }
+            _ => {
+                // unexpected response, the procedure continues
+                self.procedure = procedure;
+                (None, 0)
+            }
+        };
+    }
+
+    fn start(&mut self, procedure: Procedure) -> Result<(), ClientError> {
+        if ! self.is_idle() {
+            return Err(ClientError::Busy);
+        }
+        self.procedure = procedure;
+        return Ok(());
+    }
+
+    fn error<'p>(&mut self, pdu: &'p [u8]) -> (Option<Event<'p>>, usize) {
+        if pdu.len() < 5 || self.procedure == Procedure::Idle {
+            return (None, 0);
+        }
+        let handle = u16::from_le_bytes([pdu[2], pdu[3]]);
+        let code = ErrorCode::try_from(pdu[4]).unwrap_or(ErrorCode::ApplicationError);
+        let procedure = self.procedure;
+        self.procedure = Procedure::Idle;
+        return match (procedure, code) {
+            // the end of a discovery
+            (Procedure::DiscoverServices{..}, ErrorCode::AttributeNotFound)
+            | (Procedure::DiscoverServicesByUuid{..}, ErrorCode::AttributeNotFound)
+            | (Procedure::FindIncludedServices{..}, ErrorCode::AttributeNotFound)
+            | (Procedure::DiscoverCharacteristics{..}, ErrorCode::AttributeNotFound)
+            | (Procedure::DiscoverDescriptors{..}, ErrorCode::AttributeNotFound) => (None, 0),
+            // the end of a long read (the value was a multiple of the response size)
+            (Procedure::Read{ offset, .. }, ErrorCode::AttributeNotLong)
+            | (Procedure::Read{ offset, .. }, ErrorCode::InvalidOffset) if offset > 0 => (None, 0),
+            _ => {
+                self.queue_len = 0;
+                (Some(Event::Error{ handle, code }), 0)
+            }
+        };
+    }
+
+    /// the records of a response with a length field (Read By Type, Read By Group Type)
+    fn records(pdu: &[u8], min: usize) -> Option<ChunksExact<'_, u8>> {
+        let length = *pdu.get(1)? as usize;
+        if length < min {
+            return None;
+        }
+        return Some(pdu[2..].chunks_exact(length));
+    }
+
+    /// the handle (at offset) of the last record
+    fn last_handle(records: ChunksExact<'_, u8>, offset: usize) -> Option<Handle> {
+        let record = records.last()?;
+        return Some(u16::from_le_bytes([record[offset], record[offset + 1]]));
+    }
+
+    fn continue_read_by_type(&mut self, procedure: Procedure, records: ChunksExact<'_, u8>, end: Handle, uuid: &Uuid, request: &mut [u8]) -> usize {
+        if let Some(last) = Self::last_handle(records, 0) {
+            if last < end {
+                self.procedure = procedure;
+                return Self::range_request(Opcode::ReadByTypeReq, last + 1, end, uuid, request);
+            }
+        }
+        return 0;
+    }
+
+    fn queued_write(&mut self, values: &[(Handle, &[u8])], reliable: bool, request: &mut [u8]) -> Result<usize, ClientError> {
+        if ! self.is_idle() {
+            return Err(ClientError::Busy);
+        }
+        let size: usize = values.iter().map(|(_, value)| 4 + value.len()).sum();
+        if values.is_empty() || size > WRITE_QUEUE_SIZE {
+            return Err(ClientError::TooLong);
+        }
+        let mut position = 0;
+        for (handle, value) in values.iter() {
+            self.queue[position..(position + 2)].copy_from_slice(&handle.to_le_bytes());
+            self.queue[(position + 2)..(position + 4)].copy_from_slice(&(value.len() as u16).to_le_bytes());
+            self.queue[(position + 4)..(position + 4 + value.len())].copy_from_slice(value);
+            position += 4 + value.len();
+        }
+        self.queue_len = position;
+        self.procedure = Procedure::QueuedWrite{ reliable, position: 0, offset: 0 };
+        return Ok(self.prepare_write(0, 0, request));
+    }
+
+    /// the value queued at position
+    fn queued_value(&self, position: usize) -> (Handle, &[u8]) {
+        let handle = u16::from_le_bytes([self.queue[position], self.queue[position + 1]]);
+        let length = u16::from_le_bytes([self.queue[position + 2], self.queue[position + 3]]) as usize;
+        return (handle, &self.queue[(position + 4)..(position + 4 + length)]);
+    }
+
+    /// true if a Prepare Write Response echoes the prepared write
+    fn is_echo(pdu: &[u8], handle: Handle, offset: usize, chunk: &[u8]) -> bool {
+        return pdu.len() == (5 + chunk.len())
+            && pdu[1..3] == handle.to_le_bytes()
+            && pdu[3..5] == (offset as u16).to_le_bytes()
+            && pdu[5..] == *chunk;
+    }
+
+    fn prepare_write(&self, position: usize, offset: usize, request: &mut [u8]) -> usize {
+        let (handle, value) = self.queued_value(position);
+        let chunk = &value[offset..core::cmp::min(value.len(), offset + self.mtu as usize - 5)];
+        request[0] = Opcode::PrepareWriteReq as u8;
+        request[1..3].copy_from_slice(&handle.to_le_bytes());
+        request[3..5].copy_from_slice(&(offset as u16).to_le_bytes());
+        request[5..(5 + chunk.len())].copy_from_slice(chunk);
+        return 5 + chunk.len();
+    }
+
+    /// Read By Type or Read By Group Type Request
+    fn range_request(opcode: Opcode, start: Handle, end: Handle, uuid: &Uuid, request: &mut [u8]) -> usize {
+        request[0] = opcode as u8;
+        request[1..3].copy_from_slice(&start.to_le_bytes());
+        request[3..5].copy_from_slice(&end.to_le_bytes());
+        return 5 + uuid.write(&mut request[5..]);
+    }
+
+    /// Find By Type Value Request (of a primary service UUID)
+    fn find_by_type_value(start: Handle, end: Handle, uuid: &Uuid, request: &mut [u8]) -> usize {
+        let size = Self::range_request(Opcode::FindByTypeValueReq, start, end, &PRIMARY_SERVICE_UUID, request);
+        return size + uuid.write(&mut request[size..]);
+    }
+
+    fn find_information(start: Handle, end: Handle, request: &mut [u8]) -> usize {
+        request[0] = Opcode::FindInformationReq as u8;
+        request[1..3].copy_from_slice(&start.to_le_bytes());
+        request[3..5].copy_from_slice(&end.to_le_bytes());
+        return 5;
+    }
+
+    fn handle_request(opcode: Opcode, handle: Handle, request: &mut [u8]) -> usize {
+        request[0] = opcode as u8;
+        request[1..3].copy_from_slice(&handle.to_le_bytes());
+        return 3;
+    }
+
+    fn value_request(opcode: Opcode, handle: Handle, value: &[u8], request: &mut [u8]) -> usize {
+        let size = Self::handle_request(opcode, handle, request);
+        request[size..(size + value.len())].copy_from_slice(value);
+        return size + value.len();
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod client_procedures {
+    use super::*;
+    use crate::att::{AttServer, AttributeDatabase, ATT_MTU_MAX, PERMISSION_READ, PERMISSION_WRITE};
+    use crate::gap::AdFields;
+    use crate::gatt::{GattServer, CLIENT_CHARACTERISTIC_CONFIGURATION_UUID, CHARACTERISTIC_USER_DESCRIPTION_UUID,
+                      PROPERTY_READ, PROPERTY_NOTIFY, PROPERTY_WRITE};
+
+    const LONG_VALUE: &[u8] = b"a value longer than the minimum ATT_MTU of 23 octets";
+    crate::attribute_table! {
+        const ATTRIBUTES = [
+            primary_service(Uuid::Uuid16(0x180D)),
+            characteristic(Uuid::Uuid16(0x2A37), PROPERTY_NOTIFY, 0, &[]),
+            client_characteristic_configuration(),
+            characteristic(Uuid::Uuid16(0x2A38), PROPERTY_READ, PERMISSION_READ, &[1]),
+            primary_service(Uuid::Uuid128(0xA55A)),
+            characteristic(Uuid::Uuid128(0x5AA5), PROPERTY_READ | PROPERTY_WRITE, PERMISSION_READ | PERMISSION_WRITE, LONG_VALUE),
+            descriptor(CHARACTERISTIC_USER_DESCRIPTION_UUID, PERMISSION_READ, b"long"),
+        ];
+    }
+
+    /// run a procedure against the server, returning the events as debug strings
+    fn run<D: AttributeDatabase + ?Sized>(client: &mut GattClient, server: &mut AttServer, db: &mut D,
+                                          request: &mut [u8], mut size: usize) -> Vec<String>
+    {
+        let mut events = Vec::new();
+        let mut response = [0; ATT_MTU_MAX as usize];
+        while size > 0 {
+            let response_size = server.handle(db, &request[..size], &mut response);
+            let (event, next) = client.handle(&response[..response_size], request);
+            match event {
+                Some(Event::MtuExchanged{ mtu }) => events.push(format!("mtu {}", mtu)),
+                Some(Event::Services(services)) => events.extend(services.map(|s| format!("{:?}", s))),
+                Some(Event::Characteristics(characteristics)) => events.extend(characteristics.map(|c| format!("{:?}", c))),
+                Some(Event::Descriptors(descriptors)) => events.extend(descriptors.map(|d| format!("{:?}", d))),
+                Some(Event::Value{ offset, value, .. }) => events.push(format!("{} {}", offset, String::from_utf8_lossy(value))),
+                Some(Event::Written{ handle }) => events.push(format!("written {}", handle)),
+                Some(Event::Error{ handle, code }) => events.push(format!("{:?} {}", code, handle)),
+                Some(_) => events.push("other".into()),
+                None => {}
+            }
+            size = next;
+        }
+        assert!(client.is_idle());
+        return events;
+    }
+
+    #[test]
+    fn discover_services() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.discover_primary_services(&mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(4, events.len());
+        assert_eq!(format!("{:?}", Service{ start: 10, end: 15, uuid: Uuid::Uuid16(0x180D) }), events[2]);
+        assert_eq!(format!("{:?}", Service{ start: 16, end: 19, uuid: Uuid::Uuid128(0xA55A) }), events[3]);
+        // by uuid
+        let size = client.discover_primary_services_by_uuid(Uuid::Uuid128(0xA55A), &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec![format!("{:?}", Service{ start: 16, end: 19, uuid: Uuid::Uuid128(0xA55A) })], events);
+    }
+    #[test]
+    fn exchange_mtu() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.exchange_mtu(100, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec!["mtu 100".to_string()], events);
+        assert_eq!((100, 100), (client.mtu(), server.mtu()));
+        // long values are read in fewer parts
+        let size = client.read_long(18, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec![format!("0 {}", String::from_utf8_lossy(LONG_VALUE))], events);
+    }
+    #[test]
+    fn discover_characteristics_and_descriptors() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.discover_characteristics(10, 15, None, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec![format!("{:?}", Characteristic{ handle: 11, properties: PROPERTY_NOTIFY, value_handle: 12, uuid: Uuid::Uuid16(0x2A37) }),
+                        format!("{:?}", Characteristic{ handle: 14, properties: PROPERTY_READ, value_handle: 15, uuid: Uuid::Uuid16(0x2A38) })],
+                   events);
+        let size = client.discover_characteristics(10, 15, Some(Uuid::Uuid16(0x2A38)), &mut request).unwrap();
+        assert_eq!(1, run(&mut client, &mut server, &mut gatt, &mut request, size).len());
+        let size = client.discover_descriptors(13, 13, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec![format!("{:?}", Descriptor{ handle: 13, uuid: CLIENT_CHARACTERISTIC_CONFIGURATION_UUID })], events);
+    }
+    #[test]
+    fn read_long() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.read_long(18, &mut request).unwrap();
+        assert_eq!(Err(ClientError::Busy), client.read(18, &mut request));
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec![format!("0 {}", String::from_utf8_lossy(&LONG_VALUE[..22])),
+                        format!("22 {}", String::from_utf8_lossy(&LONG_VALUE[22..44])),
+                        format!("44 {}", String::from_utf8_lossy(&LONG_VALUE[44..]))],
+                   events);
+        // errors are reported
+        let size = client.read(12, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec!["ReadNotPermitted 12".to_string()], events);
+    }
+
+    struct Values {
+        written: Vec<(Handle, usize, Vec<u8>)>,
+    }
+    impl AttributeDatabase for Values {
+        fn attributes(&self) -> &[crate::att::Attribute<'_>] { ATTRIBUTES }
+        fn write(&mut self, handle: Handle, offset: usize, data: &[u8]) -> Result<(), ErrorCode> {
+            self.written.push((handle, offset, data.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_long_and_reliable() {
+        let mut values = Values{ written: Vec::new() };
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.write_long(18, LONG_VALUE, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut values, &mut request, size);
+        assert_eq!(vec!["written 18".to_string()], events);
+        let written: Vec<u8> = values.written.iter().flat_map(|(_, _, data)| data.clone()).collect();
+        assert_eq!(LONG_VALUE, &written[..]);
+        // reliable write of several values
+        values.written.clear();
+        let size = client.reliable_write(&[(18, b"one"), (18, b"two")], &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut values, &mut request, size);
+        assert_eq!(vec!["written 18".to_string()], events);
+        assert_eq!(2, values.written.len());
+        // too short an ATT_MTU for a write request
+        assert_eq!(Err(ClientError::TooLong), client.write(18, LONG_VALUE, &mut request));
+    }
+    #[test]
+    fn reliable_write_verification() {
+        let mut client = GattClient::new();
+        let mut request = [0; ATT_MTU_MAX as usize];
+        client.reliable_write(&[(18, b"one")], &mut request).unwrap();
+        // the server corrupted the value
+        let (event, size) = client.handle(&[Opcode::PrepareWriteRsp as u8, 18, 0, 0, 0, b'o', b'n', b'x'], &mut request);
+        assert!(matches!(event, Some(Event::WriteCancelled{ handle: 18 })));
+        assert_eq!([Opcode::ExecuteWriteReq as u8, 0x00], request[..size]);
+        let (event, _) = client.handle(&[Opcode::ExecuteWriteRsp as u8], &mut request);
+        assert!(event.is_none());
+        assert!(client.is_idle());
+    }
+    #[test]
+    fn subscriptions() {
+        let ad_fields = AdFields::default();
+        let mut gatt = GattServer::new(ATTRIBUTES, &ad_fields);
+        let (mut client, mut server) = (GattClient::new(), AttServer::new());
+        let mut request = [0; ATT_MTU_MAX as usize];
+        let size = client.subscribe(13, true, false, &mut request).unwrap();
+        let events = run(&mut client, &mut server, &mut gatt, &mut request, size);
+        assert_eq!(vec!["written 13".to_string()], events);
+        assert_eq!(crate::gatt::CCCD_NOTIFICATION, gatt.configuration(12));
+        // indications are confirmed
+        let (event, size) = client.handle(&[Opcode::HandleValueInd as u8, 8, 0, 1, 0, 0xFF, 0xFF], &mut request);
+        assert!(matches!(event, Some(Event::Indication{ handle: 8, .. })));
+        assert_eq!([Opcode::HandleValueCfm as u8], request[..size]);
+    }
+}
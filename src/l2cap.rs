@@ -0,0 +1,462 @@
+use num_enum::{TryFromPrimitive};
+use core::convert::TryFrom;
+
+use crate::att::ATT_MTU_MAX;
+
+/// Core_v5.3 Vol 3, Part A, 2.1 (channel identifiers)
+pub type ChannelId = u16;
+/// fixed channels of the LE-U logical link
+pub const ATT_CID:ChannelId = 0x0004;
+pub const LE_SIGNALING_CID:ChannelId = 0x0005;
+pub const SMP_CID:ChannelId = 0x0006;
+/// dynamically allocated channels (LE-U)
+pub const DYNAMIC_CID_MIN:ChannelId = 0x0040;
+pub const DYNAMIC_CID_MAX:ChannelId = 0x007F;
+
+/// Core_v5.3 Vol 3, Part A, 3.1 (length:2, channel id:2)
+pub const HEADER_SIZE:usize = 4;
+
+/// Enhanced ATT bearers (https://www.bluetooth.com/specifications/assigned-numbers/ - SPSM)
+pub const EATT_PSM:u16 = 0x0027;
+/// number of credit based channels (i.e. Enhanced ATT bearers)
+pub const ECFC_CHANNELS_MAX:usize = 2;
+/// MTU and MPS of our credit based channels (an SDU fits a single K-frame)
+pub const ECFC_MTU:u16 = ATT_MTU_MAX;
+pub const ECFC_MPS:u16 = ATT_MTU_MAX + 2;
+/// credits granted to the peer (returned as SDUs are handled)
+pub const ECFC_CREDITS:u16 = 4;
+/// Core_v5.3 Vol 3, Part A, 4.25 (smallest MTU and MPS)
+const ECFC_MTU_MIN:u16 = 64;
+
+/// largest frame received (a K-frame of ECFC_MPS)
+pub const FRAME_SIZE_MAX:usize = HEADER_SIZE + ECFC_MPS as usize;
+/// Core_v5.3 Vol 3, Part A, 4 (signaling MTU of LE-U)
+pub const SIGNALING_MTU:usize = 23;
+
+/// parse a (complete) basic information frame, returns the channel and payload
+/// Core_v5.3 Vol 3, Part A, 3.1
+pub fn read(frame: &[u8]) -> Option<(ChannelId, &[u8])> {
+    if frame.len() < HEADER_SIZE {
+        return None;
+    }
+    let length = u16::from_le_bytes([frame[0], frame[1]]) as usize;
+    let cid = u16::from_le_bytes([frame[2], frame[3]]);
+    if frame.len() < (HEADER_SIZE + length) {
+        return None;
+    }
+    return Some((cid, &frame[HEADER_SIZE..(HEADER_SIZE + length)]));
+}
+
+/// write a basic information frame header, returns the size of the header
+pub fn write_header(cid: ChannelId, payload_len: usize, buffer: &mut [u8]) -> usize {
+    buffer[0..2].copy_from_slice(&(payload_len as u16).to_le_bytes());
+    buffer[2..4].copy_from_slice(&cid.to_le_bytes());
+    return HEADER_SIZE;
+}
+
+#[derive(Debug, TryFromPrimitive, Copy, Clone, PartialEq)]
+#[repr(u8)]
+/// Core_v5.3 Vol 3, Part A, 4 (LE-U signaling commands)
+pub enum SignalingCode {
+    CommandRejectRsp                    = 0x01,
+    DisconnectionReq                    = 0x06,
+    DisconnectionRsp                    = 0x07,
+    ConnectionParameterUpdateReq        = 0x12,
+    ConnectionParameterUpdateRsp        = 0x13,
+    LeCreditBasedConnectionReq          = 0x14,
+    LeCreditBasedConnectionRsp          = 0x15,
+    FlowControlCreditInd                = 0x16,
+    CreditBasedConnectionReq            = 0x17,
+    CreditBasedConnectionRsp            = 0x18,
+    CreditBasedReconfigureReq           = 0x19,
+    CreditBasedReconfigureRsp           = 0x1A,
+}
+
+/// Core_v5.3 Vol 3, Part A, 4.1 (Command Reject reasons)
+const COMMAND_NOT_UNDERSTOOD:u16 = 0x0000;
+const INVALID_CID:u16 = 0x0002;
+
+/// Core_v5.3 Vol 3, Part A, 4.26 (L2CAP_CREDIT_BASED_CONNECTION_RSP results)
+const ALL_CONNECTIONS_SUCCESSFUL:u16 = 0x0000;
+const SPSM_NOT_SUPPORTED:u16 = 0x0002;
+const INSUFFICIENT_RESOURCES:u16 = 0x0004;
+const INSUFFICIENT_ENCRYPTION:u16 = 0x0008;
+const INVALID_SOURCE_CID:u16 = 0x0009;
+const SOURCE_CID_ALREADY_ALLOCATED:u16 = 0x000A;
+const INVALID_PARAMETERS:u16 = 0x000C;
+
+/// Core_v5.3 Vol 3, Part A, 4.28 (L2CAP_CREDIT_BASED_RECONFIGURE_RSP results)
+const RECONFIGURATION_SUCCESSFUL:u16 = 0x0000;
+const MTU_REDUCTION_NOT_ALLOWED:u16 = 0x0001;
+const INVALID_DESTINATION_CID:u16 = 0x0003;
+
+/// a channel in the LE Enhanced Credit Based Flow Control mode
+/// Core_v5.3 Vol 3, Part A, 10.2
+pub struct CreditBasedChannel {
+    pub local_cid: ChannelId,
+    pub peer_cid: ChannelId,
+    pub peer_mtu: u16,
+    pub peer_mps: u16,
+    /// K-frames we can send
+    pub peer_credits: u16,
+    /// K-frames the peer can send
+    pub credits: u16,
+    /// SDU being reassembled
+    sdu: [u8; ECFC_MTU as usize],
+    sdu_len: usize,
+    received: usize,
+}
+impl CreditBasedChannel {
+    fn new(local_cid: ChannelId, peer_cid: ChannelId, peer_mtu: u16, peer_mps: u16, peer_credits: u16) -> Self {
+        Self {
+            local_cid,
+            peer_cid,
+            peer_mtu,
+            peer_mps,
+            peer_credits,
+            credits: ECFC_CREDITS,
+            sdu: [0; ECFC_MTU as usize],
+            sdu_len: 0,
+            received: 0,
+        }
+    }
+
+    /// reassemble a received K-frame (payload), returns the complete SDU
+    /// Core_v5.3 Vol 3, Part A, 3.4
+    pub fn receive(&mut self, payload: &[u8]) -> Option<&[u8]> {
+        if self.credits == 0 {
+            // the peer ignored flow control
+            return None;
+        }
+        self.credits -= 1;
+
+        if self.received == self.sdu_len {
+            // first K-frame of an SDU (SDU length:2)
+            if payload.len() < 2 {
+                return None;
+            }
+            self.sdu_len = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+            self.received = 0;
+            if self.sdu_len > self.sdu.len() {
+                self.sdu_len = 0;
+                return None;
+            }
+            return self.append(&payload[2..]);
+        }
+        return self.append(payload);
+    }
+
+    fn append(&mut self, data: &[u8]) -> Option<&[u8]> {
+        if (self.received + data.len()) > self.sdu_len {
+            // invalid SDU
+            self.sdu_len = 0;
+            self.received = 0;
+            return None;
+        }
+        self.sdu[self.received..(self.received + data.len())].copy_from_slice(data);
+        self.received += data.len();
+        if self.received == self.sdu_len {
+            return Some(&self.sdu[..self.sdu_len]);
+        }
+        return None;
+    }
+
+    /// write the K-frames of an SDU (segmented by the peer's MPS), returns the size of the frames
+    /// (None if the peer hasn't granted enough credits)
+    pub fn send(&mut self, sdu: &[u8], buffer: &mut [u8]) -> Option<usize> {
+        let mps = self.peer_mps as usize;
+        let frames = (2 + sdu.len()).div_ceil(mps);
+        if (frames as u16) > self.peer_credits || sdu.len() > (self.peer_mtu as usize) {
+            return None;
+        }
+        self.peer_credits -= frames as u16;
+
+        let mut size = 0;
+        let mut sent = 0;
+        for frame in 0..frames {
+            let header = if frame == 0 { 2 } else { 0 };
+            let length = core::cmp::min(mps - header, sdu.len() - sent);
+            size += write_header(self.peer_cid, header + length, &mut buffer[size..]);
+            if frame == 0 {
+                buffer[size..(size + 2)].copy_from_slice(&(sdu.len() as u16).to_le_bytes());
+            }
+            size += header;
+            buffer[size..(size + length)].copy_from_slice(&sdu[sent..(sent + length)]);
+            size += length;
+            sent += length;
+        }
+        return Some(size);
+    }
+}
+
+/// LE signaling channel (credit based channels for Enhanced ATT)
+/// Core_v5.3 Vol 3, Part A, 4
+pub struct Signaling {
+    channels: [Option<CreditBasedChannel>; ECFC_CHANNELS_MAX],
+    identifier: u8,
+    /// Enhanced ATT bearers require an encrypted link (Core_v5.3 Vol 3, Part G, 5.4)
+    encrypted: bool,
+}
+impl Default for Signaling {
+    fn default() -> Self { Self::new() }
+}
+impl Signaling {
+    pub fn new() -> Self {
+        Self {
+            channels: [None, None],
+            identifier: 0,
+            encrypted: false,
+        }
+    }
+
+    /// update the encryption of the underlying link
+    pub fn set_encrypted(&mut self, encrypted: bool) { self.encrypted = encrypted; }
+
+    /// the channel at index (of ECFC_CHANNELS_MAX)
+    pub fn channel(&self, index: usize) -> Option<&CreditBasedChannel> {
+        self.channels[index].as_ref()
+    }
+
+    pub fn channel_mut(&mut self, index: usize) -> Option<&mut CreditBasedChannel> {
+        self.channels[index].as_mut()
+    }
+
+    /// the index of a channel (by its local cid)
+    pub fn index(&self, local_cid: ChannelId) -> Option<usize> {
+        self.channels.iter().position(|channel| channel.as_ref().is_some_and(|c| c.local_cid == local_cid))
+    }
+
+    /// handle a signaling packet, returns the size of the response (0 if none)
+    pub fn handle(&mut self, packet: &[u8], response: &mut [u8]) -> usize {
+        if packet.len() < 4 {
+            return 0;
+        }
+        let identifier = packet[1];
+        let length = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+        if packet.len() < (4 + length) {
+            return 0;
+        }
+        let data = &packet[4..(4 + length)];
+
+        return match SignalingCode::try_from(packet[0]) {
+            Ok(SignalingCode::CreditBasedConnectionReq) => self.connect(identifier, data, response),
+            Ok(SignalingCode::CreditBasedReconfigureReq) => self.reconfigure(identifier, data, response),
+            Ok(SignalingCode::DisconnectionReq) if data.len() == 4 => {
+                let local_cid = u16::from_le_bytes([data[0], data[1]]);
+                let peer_cid = u16::from_le_bytes([data[2], data[3]]);
+                match self.channels.iter_mut().find(|c| c.as_ref().is_some_and(|c| c.local_cid == local_cid && c.peer_cid == peer_cid)) {
+                    Some(channel) => {
+                        *channel = None;
+                        Self::command(SignalingCode::DisconnectionRsp, identifier, data, response)
+                    }
+                    None => Self::reject(identifier, INVALID_CID, data, response),
+                }
+            }
+            Ok(SignalingCode::FlowControlCreditInd) if data.len() == 4 => {
+                let peer_cid = u16::from_le_bytes([data[0], data[1]]);
+                let credits = u16::from_le_bytes([data[2], data[3]]);
+                if let Some(channel) = self.channels.iter_mut().flatten().find(|c| c.peer_cid == peer_cid) {
+                    channel.peer_credits = channel.peer_credits.saturating_add(credits);
+                }
+                0
+            }
+            // responses to requests we don't send
+            Ok(SignalingCode::CommandRejectRsp)
+            | Ok(SignalingCode::DisconnectionRsp)
+            | Ok(SignalingCode::ConnectionParameterUpdateRsp)
+            | Ok(SignalingCode::LeCreditBasedConnectionRsp)
+            | Ok(SignalingCode::CreditBasedConnectionRsp)
+            | Ok(SignalingCode::CreditBasedReconfigureRsp) => 0,
+            _ => Self::reject(identifier, COMMAND_NOT_UNDERSTOOD, &[], response),
+        };
+    }
+
+    /// write a Flow Control Credit Indication returning the credits consumed by the peer (0 if none)
+    /// Core_v5.3 Vol 3, Part A, 4.24
+    pub fn return_credits(&mut self, index: usize, frame: &mut [u8]) -> usize {
+        let channel = match self.channels[index].as_mut() {
+            Some(channel) => channel,
+            None => return 0,
+        };
+        let credits = ECFC_CREDITS - channel.credits;
+        if credits == 0 {
+            return 0;
+        }
+        channel.credits = ECFC_CREDITS;
+        let mut data = [0; 4];
+        data[0..2].copy_from_slice(&channel.local_cid.to_le_bytes());
+        data[2..4].copy_from_slice(&credits.to_le_bytes());
+        self.identifier = self.identifier.wrapping_add(1).max(1);
+        let size = Self::command(SignalingCode::FlowControlCreditInd, self.identifier, &data, &mut frame[HEADER_SIZE..]);
+        return write_header(LE_SIGNALING_CID, size, frame) + size;
+    }
+
+    /// Core_v5.3 Vol 3, Part A, 4.25
+    fn connect(&mut self, identifier: u8, data: &[u8], response: &mut [u8]) -> usize {
+        if data.len() < 10 || !data.len().is_multiple_of(2) || data.len() > 18 {
+            return Self::reject(identifier, COMMAND_NOT_UNDERSTOOD, &[], response);
+        }
+        let spsm = u16::from_le_bytes([data[0], data[1]]);
+        let peer_mtu = u16::from_le_bytes([data[2], data[3]]);
+        let peer_mps = u16::from_le_bytes([data[4], data[5]]);
+        let peer_credits = u16::from_le_bytes([data[6], data[7]]);
+        let peer_cids = data[8..].chunks_exact(2).map(|cid| u16::from_le_bytes([cid[0], cid[1]]));
+
+        let mut rsp = [0; 8 + 10];
+        rsp[0..2].copy_from_slice(&ECFC_MTU.to_le_bytes());
+        rsp[2..4].copy_from_slice(&ECFC_MPS.to_le_bytes());
+        rsp[4..6].copy_from_slice(&ECFC_CREDITS.to_le_bytes());
+        let size = 8 + (data.len() - 8);
+        let result = if spsm != EATT_PSM {
+            SPSM_NOT_SUPPORTED
+        } else if ! self.encrypted {
+            INSUFFICIENT_ENCRYPTION
+        } else if peer_mtu < ECFC_MTU_MIN || peer_mps < ECFC_MTU_MIN {
+            INVALID_PARAMETERS
+        } else {
+            let mut result = ALL_CONNECTIONS_SUCCESSFUL;
+            for (position, peer_cid) in peer_cids.enumerate() {
+                if ! (DYNAMIC_CID_MIN..=DYNAMIC_CID_MAX).contains(&peer_cid) {
+                    result = INVALID_SOURCE_CID;
+                    continue;
+                }
+                if self.channels.iter().flatten().any(|c| c.peer_cid == peer_cid) {
+                    result = SOURCE_CID_ALREADY_ALLOCATED;
+                    continue;
+                }
+                match self.channels.iter().position(|c| c.is_none()) {
+                    Some(index) => {
+                        let local_cid = DYNAMIC_CID_MIN + index as u16;
+                        self.channels[index] = Some(CreditBasedChannel::new(local_cid, peer_cid, peer_mtu, peer_mps, peer_credits));
+                        rsp[(8 + 2 * position)..(10 + 2 * position)].copy_from_slice(&local_cid.to_le_bytes());
+                    }
+                    None => result = INSUFFICIENT_RESOURCES,
+                }
+            }
+            result
+        };
+        rsp[6..8].copy_from_slice(&result.to_le_bytes());
+        return Self::command(SignalingCode::CreditBasedConnectionRsp, identifier, &rsp[..size], response);
+    }
+
+    /// Core_v5.3 Vol 3, Part A, 4.27
+    fn reconfigure(&mut self, identifier: u8, data: &[u8], response: &mut [u8]) -> usize {
+        if data.len() < 6 || !data.len().is_multiple_of(2) {
+            return Self::reject(identifier, COMMAND_NOT_UNDERSTOOD, &[], response);
+        }
+        let peer_mtu = u16::from_le_bytes([data[0], data[1]]);
+        let peer_mps = u16::from_le_bytes([data[2], data[3]]);
+        let peer_cids = || data[4..].chunks_exact(2).map(|cid| u16::from_le_bytes([cid[0], cid[1]]));
+
+        let mut result = RECONFIGURATION_SUCCESSFUL;
+        for peer_cid in peer_cids() {
+            match self.channels.iter().flatten().find(|c| c.peer_cid == peer_cid) {
+                Some(channel) => if peer_mtu < channel.peer_mtu {
+                    result = MTU_REDUCTION_NOT_ALLOWED;
+                }
+                None => result = INVALID_DESTINATION_CID,
+            }
+        }
+        if result == RECONFIGURATION_SUCCESSFUL {
+            for channel in self.channels.iter_mut().flatten() {
+                if peer_cids().any(|peer_cid| peer_cid == channel.peer_cid) {
+                    channel.peer_mtu = peer_mtu;
+                    channel.peer_mps = peer_mps;
+                }
+            }
+        }
+        return Self::command(SignalingCode::CreditBasedReconfigureRsp, identifier, &result.to_le_bytes(), response);
+    }
+
+    /// Core_v5.3 Vol 3, Part A, 4.1
+    fn reject(identifier: u8, reason: u16, data: &[u8], response: &mut [u8]) -> usize {
+        let mut rsp = [0; 6];
+        rsp[0..2].copy_from_slice(&reason.to_le_bytes());
+        rsp[2..(2 + data.len())].copy_from_slice(data);
+        return Self::command(SignalingCode::CommandRejectRsp, identifier, &rsp[..(2 + data.len())], response);
+    }
+
+    /// write a signaling command (code:1, identifier:1, length:2, data)
+    fn command(code: SignalingCode, identifier: u8, data: &[u8], response: &mut [u8]) -> usize {
+        response[0] = code as u8;
+        response[1] = identifier;
+        response[2..4].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        response[4..(4 + data.len())].copy_from_slice(data);
+        return 4 + data.len();
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod l2cap_signaling {
+    use super::*;
+
+    const EATT_REQUEST: [u8; 16] = [SignalingCode::CreditBasedConnectionReq as u8, 1, 12, 0,
+                                   0x27, 0x00, 0x40, 0x00, 0x40, 0x00, 0x02, 0x00, 0x41, 0x00, 0x42, 0x00];
+
+    #[test]
+    fn connect_eatt() {
+        let mut signaling = Signaling::new();
+        let mut response = [0; SIGNALING_MTU];
+        // refused until the link is encrypted
+        let size = signaling.handle(&EATT_REQUEST, &mut response);
+        assert_eq!([SignalingCode::CreditBasedConnectionRsp as u8, 1, 12, 0,
+                    (ECFC_MTU & 0xFF) as u8, 0, (ECFC_MPS & 0xFF) as u8, 0, ECFC_CREDITS as u8, 0,
+                    INSUFFICIENT_ENCRYPTION as u8, 0, 0, 0, 0, 0], response[..size]);
+        assert!(signaling.channel(0).is_none());
+
+        signaling.set_encrypted(true);
+        let size = signaling.handle(&EATT_REQUEST, &mut response);
+        assert_eq!([SignalingCode::CreditBasedConnectionRsp as u8, 1, 12, 0,
+                    (ECFC_MTU & 0xFF) as u8, 0, (ECFC_MPS & 0xFF) as u8, 0, ECFC_CREDITS as u8, 0, 0, 0,
+                    0x40, 0x00, 0x41, 0x00], response[..size]);
+        let channel = signaling.channel(1).unwrap();
+        assert_eq!((0x41, 0x42, 64, 2), (channel.local_cid, channel.peer_cid, channel.peer_mtu, channel.peer_credits));
+        // no more channels
+        let size = signaling.handle(&[SignalingCode::CreditBasedConnectionReq as u8, 2, 10, 0,
+                                      0x27, 0x00, 0x40, 0x00, 0x40, 0x00, 0x02, 0x00, 0x43, 0x00], &mut response);
+        assert_eq!([INSUFFICIENT_RESOURCES as u8, 0, 0, 0], response[10..size]);
+        // unsupported PSM
+        let size = signaling.handle(&[SignalingCode::CreditBasedConnectionReq as u8, 3, 10, 0,
+                                      0x80, 0x00, 0x40, 0x00, 0x40, 0x00, 0x02, 0x00, 0x44, 0x00], &mut response);
+        assert_eq!([SPSM_NOT_SUPPORTED as u8, 0, 0, 0], response[10..size]);
+        // disconnect
+        let size = signaling.handle(&[SignalingCode::DisconnectionReq as u8, 4, 4, 0, 0x40, 0x00, 0x41, 0x00], &mut response);
+        assert_eq!([SignalingCode::DisconnectionRsp as u8, 4, 4, 0, 0x40, 0x00, 0x41, 0x00], response[..size]);
+        assert!(signaling.channel(0).is_none());
+    }
+    #[test]
+    fn unknown_command() {
+        let mut signaling = Signaling::new();
+        let mut response = [0; SIGNALING_MTU];
+        let size = signaling.handle(&[0x7E, 9, 0, 0], &mut response);
+        assert_eq!([SignalingCode::CommandRejectRsp as u8, 9, 2, 0, 0, 0], response[..size]);
+    }
+    #[test]
+    fn segmentation_and_credits() {
+        let mut signaling = Signaling::new();
+        let mut response = [0; SIGNALING_MTU];
+        signaling.set_encrypted(true);
+        signaling.handle(&EATT_REQUEST, &mut response);
+        let channel = signaling.channel_mut(0).unwrap();
+        // SDU of 64 octets needs 2 K-frames (MPS of 64 includes the SDU length)
+        let sdu = [0xAA; 64];
+        let mut frames = [0; 2 * FRAME_SIZE_MAX];
+        assert_eq!(Some(4 + 64 + 4 + 2), channel.send(&sdu, &mut frames));
+        assert_eq!([64, 0, 0x41, 0x00, 64, 0], frames[..6]);
+        assert_eq!([2, 0, 0x41, 0x00, 0xAA, 0xAA], frames[68..74]);
+        assert_eq!(0, channel.peer_credits);
+        assert_eq!(None, channel.send(&sdu, &mut frames));
+        signaling.handle(&[SignalingCode::FlowControlCreditInd as u8, 5, 4, 0, 0x41, 0x00, 0x01, 0x00], &mut response);
+        assert_eq!(1, signaling.channel(0).unwrap().peer_credits);
+        // reassembly (consuming credits)
+        let channel = signaling.channel_mut(0).unwrap();
+        assert_eq!(None, channel.receive(&[3, 0, 0x0A]));
+        assert_eq!(Some(&[0x0A, 0x0B, 0x0C][..]), channel.receive(&[0x0B, 0x0C]));
+        let size = signaling.return_credits(0, &mut frames);
+        assert_eq!([8, 0, 0x05, 0x00, SignalingCode::FlowControlCreditInd as u8, 1, 4, 0, 0x40, 0x00, 2, 0], frames[..size]);
+    }
+}
@@ -2,65 +2,487 @@
 
 pub mod link_layer;
 pub mod gap;
+pub mod att;
+pub mod gatt;
+pub mod gatt_client;
+pub mod l2cap;
+pub mod connection;
+pub mod channel_map;
+pub mod smp;
+pub mod encryption;
+pub mod bond;
+pub mod privacy;
+pub mod beacons;
+pub mod mesh;
 
 // select the hardware interface
 #[cfg(test)]
-use FakeHci as HCI;  /* implemented at end of this file */
-#[cfg(feature="nrf5x")] 
+use {FakeHci as HCI, FakeTransmission as Transmission};  /* implemented at end of this file */
+#[cfg(feature="nrf5x")]
 pub mod nrf5x;
 #[cfg(feature="nrf5x")]
-use nrf5x::{Nrf5xHci as HCI};
+use nrf5x::{Nrf5xHci as HCI, Transmission};
 
 use rtt_target::{rprintln};
 
+/// the ATT bearer over the fixed channel and an Enhanced ATT bearer per credit based channel
+const ATT_BEARERS:usize = 1 + l2cap::ECFC_CHANNELS_MAX;
+
+/// an ATT bearer and its request awaiting Ble::work
+struct AttBearer {
+    server: att::AttServer,
+    request: [u8; att::ATT_MTU_MAX as usize],
+    request_len: usize,
+    /// response awaiting credits (Enhanced ATT bearers)
+    response: [u8; att::ATT_MTU_MAX as usize],
+    response_len: usize,
+}
+impl AttBearer {
+    fn new() -> Self {
+        Self {
+            server: att::AttServer::new(),
+            request: [0; att::ATT_MTU_MAX as usize],
+            request_len: 0,
+            response: [0; att::ATT_MTU_MAX as usize],
+            response_len: 0,
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        self.request_len > 0 || self.response_len > 0
+    }
+
+    /// queue a request (one at a time per ATT flow control), returns false while busy
+    fn receive(&mut self, request: &[u8]) -> bool {
+        if self.request_len > 0 {
+            return false;
+        }
+        if request.len() <= self.request.len() {
+            self.request[..request.len()].copy_from_slice(request);
+            self.request_len = request.len();
+        }
+        return true;
+    }
+}
+
+/// the GATT client of a connection and the server's PDU awaiting Ble::work
+struct ClientBearer {
+    client: gatt_client::GattClient,
+    pdu: [u8; att::ATT_MTU_MAX as usize],
+    pdu_len: usize,
+}
+impl ClientBearer {
+    fn new() -> Self {
+        Self {
+            client: gatt_client::GattClient::new(),
+            pdu: [0; att::ATT_MTU_MAX as usize],
+            pdu_len: 0,
+        }
+    }
+
+    fn has_work(&self) -> bool {
+        self.pdu_len > 0
+    }
+
+    /// queue a PDU of the server, returns false while busy
+    fn receive(&mut self, pdu: &[u8]) -> bool {
+        if self.pdu_len > 0 {
+            return false;
+        }
+        if pdu.len() <= self.pdu.len() {
+            self.pdu[..pdu.len()].copy_from_slice(pdu);
+            self.pdu_len = pdu.len();
+        }
+        return true;
+    }
+}
+
+/// the Security Manager of a connection and its PDU awaiting Ble::work
+struct SmpChannel {
+    smp: smp::Smp,
+    request: [u8; smp::SMP_MTU],
+    request_len: usize,
+    /// the link has been encrypted (completing LE legacy pairing)
+    encrypted: bool,
+    /// the keys of the pairing have been stored as a bond
+    stored: bool,
+}
+impl SmpChannel {
+    fn has_work(&self) -> bool {
+        self.request_len > 0 || self.encrypted || self.smp.has_work()
+    }
+
+    /// queue a PDU, returns false while busy
+    fn receive(&mut self, request: &[u8]) -> bool {
+        if self.request_len > 0 {
+            return false;
+        }
+        if request.len() <= self.request.len() {
+            self.request[..request.len()].copy_from_slice(request);
+            self.request_len = request.len();
+        }
+        return true;
+    }
+}
+
 pub struct Ble<'a> {
     hci: HCI,
-    ad_fields: gap::AdFields<'a>,
+    adv_data: gap::AdvertisingData,
+    scan_rsp_data: gap::AdvertisingData,
+    /// replacements taking effect with the next advertising event (see set_advertising_data)
+    next_adv_data: Option<gap::AdvertisingData>,
+    next_scan_rsp_data: Option<gap::AdvertisingData>,
+    /// the advertising channels (bit 0 for CH37) used by the current advertising event
+    adv_channels: u8,
     buffer: link_layer::PduBuffer,
+    /// the PDU the stack responds with (the radio reads it until the transmission completes, see respond)
+    tx_buffer: link_layer::PduBuffer,
+    /// the response of the connection event is being sent (the central's next PDU is listened for once
+    /// it completes, see handle_packet)
+    responding: bool,
+    gatt: Option<gatt::GattServer<'a>>,
+    connection: Option<connection::Connection>,
+    signaling: l2cap::Signaling,
+    /// the (unenhanced) ATT bearer followed by the Enhanced ATT bearers
+    bearers: [AttBearer; ATT_BEARERS],
+    /// the GATT client over the (unenhanced) ATT bearer (see discover_primary_services)
+    client: ClientBearer,
+    /// connection time (ms) by which the outstanding indication must be confirmed
+    indication_deadline: Option<u32>,
+    /// pairing is refused unless configured (see set_pairing)
+    pairing: Option<smp::Config>,
+    /// the key pair of the OobData passed to centrals (see set_oob_data)
+    oob: Option<smp::LocalOob>,
+    smp: Option<SmpChannel>,
+    /// the key supplied for the central's encryption request
+    link_key: Option<smp::Keys>,
+    encrypted: bool,
+    /// persistent bonds (see set_bond_storage)
+    bonds: Option<bond::BondStore<'a>>,
+    /// the identity address of the connected peer (resolved from its resolvable private address)
+    peer_identity: Option<link_layer::DeviceAddress>,
+    /// advertise with a resolvable private address (see set_privacy)
+    privacy: Option<privacy::Privacy>,
+    /// the interval of the advertising events, which times the address rotation while advertising
+    adv_interval_ms: u32,
+    /// the Bluetooth Mesh advertising bearer (see set_mesh_bearer)
+    mesh: Option<mesh::bearer::AdvBearer>,
+    /// the CTE types sent in response to the central's LL_CTE_REQ (see set_cte_response)
+    cte_types: u8,
+    /// the IQ samples of the last CTE received (see iq_report)
+    iq_report: Option<link_layer::IqReport>,
+    /// the quality of the data channels of the connection
+    channels: channel_map::ChannelClassifier,
+    /// the last advertisement received (see advertising_report)
+    adv_report: Option<gap::AdvertisingReport>,
 }
 
 impl<'a> Ble<'a> {
+    /// ad structures that don't fit in the advertising data spill into the scan response data
     pub fn new(hci: HCI, ad_fields: gap::AdFields<'a>) -> Self
     {
+        let mut adv_data = [0; link_layer::ADV_DATA_SIZE_MAX];
+        let mut scan_rsp_data = [0; link_layer::ADV_DATA_SIZE_MAX];
+        let (adv_size, scan_rsp_size) = match ad_fields.try_write_split(&mut adv_data, &mut scan_rsp_data) {
+            Ok(sizes) => sizes,
+            Err(gap::AdError::Overflow { size, scan_rsp_size, omitted }) => {
+                rprintln!("advertising data omits {:?}", omitted);
+                (size, scan_rsp_size)
+            }
+            Err(error) => {
+                rprintln!("invalid advertising data {:?}", error);
+                (ad_fields.write(&mut adv_data), 0)
+            }
+        };
         Self {
             hci,
-            ad_fields,
+            adv_data: gap::AdvertisingData::from_bytes(&adv_data[..adv_size]).unwrap_or_default(),
+            scan_rsp_data: gap::AdvertisingData::from_bytes(&scan_rsp_data[..scan_rsp_size]).unwrap_or_default(),
+            next_adv_data: None,
+            next_scan_rsp_data: None,
+            adv_channels: 0,
             buffer: [0; link_layer::PDU_SIZE_MAX],
+            tx_buffer: [0; link_layer::PDU_SIZE_MAX],
+            responding: false,
+            gatt: None,
+            connection: None,
+            signaling: l2cap::Signaling::new(),
+            bearers: core::array::from_fn(|_| AttBearer::new()),
+            client: ClientBearer::new(),
+            indication_deadline: None,
+            pairing: None,
+            oob: None,
+            smp: None,
+            link_key: None,
+            encrypted: false,
+            bonds: None,
+            peer_identity: None,
+            privacy: None,
+            adv_interval_ms: privacy::ADV_INTERVAL_DEFAULT_MS,
+            mesh: None,
+            cte_types: 0,
+            iq_report: None,
+            channels: channel_map::ChannelClassifier::new(channel_map::Thresholds::default()),
+            adv_report: None,
+        }
+    }
+
+    /// create a connectable instance serving the attribute table (see attribute_table!)
+    pub fn with_attributes(hci: HCI, ad_fields: gap::AdFields<'a>, attributes: &'a [att::Attribute<'a>]) -> Self
+    {
+        let gatt = gatt::GattServer::new(attributes, &ad_fields);
+        Self {
+            gatt: Some(gatt),
+            ..Self::new(hci, ad_fields)
         }
     }
 
     pub fn is_connected(&self) -> bool {
-        // FIXME
-        return false;
+        return self.connection.is_some();
+    }
+
+    /// the advertising data (including a replacement yet to take effect)
+    pub fn advertising_data(&self) -> &gap::AdvertisingData {
+        return self.next_adv_data.as_ref().unwrap_or(&self.adv_data);
+    }
+
+    /// the scan response data (including a replacement yet to take effect)
+    pub fn scan_response_data(&self) -> &gap::AdvertisingData {
+        return self.next_scan_rsp_data.as_ref().unwrap_or(&self.scan_rsp_data);
+    }
+
+    /// replace the advertising data from the next advertising event (see start_advertising), so the
+    /// PDUs of an event (and its scan responses) never mix old and new data
+    pub fn set_advertising_data(&mut self, data: gap::AdvertisingData) {
+        self.next_adv_data = Some(data);
+    }
+
+    /// replace the scan response data from the next advertising event (see set_advertising_data)
+    pub fn set_scan_response_data(&mut self, data: gap::AdvertisingData) {
+        self.next_scan_rsp_data = Some(data);
+    }
+
+    /// allow pairing (applies from the next connection)
+    pub fn set_pairing(&mut self, config: smp::Config) {
+        self.pairing = Some(config);
+    }
+
+    /// pair out of band (LE Secure Connections) with centrals given local.data(), e.g. over NFC,
+    /// OobData received from centrals is supplied by PairingHandler::oob_data
+    pub fn set_oob_data(&mut self, local: smp::LocalOob) {
+        self.oob = Some(local);
+    }
+
+    /// persist bonds in the storage (restoring the bonds it holds)
+    pub fn set_bond_storage(&mut self, storage: &'a mut dyn bond::BondStorage) {
+        self.bonds = Some(bond::BondStore::new(storage));
+    }
+
+    /// the persistent bonds (None without bond storage)
+    pub fn bonds(&self) -> Option<&bond::BondStore<'a>> {
+        return self.bonds.as_ref();
+    }
+
+    /// delete the bond of a peer, returns false if it wasn't bonded
+    pub fn remove_bond(&mut self, identity: &link_layer::DeviceAddress) -> bool {
+        return self.bonds.as_mut().is_some_and(|bonds| bonds.remove(identity));
+    }
+
+    /// hide the identity address behind resolvable private addresses generated from the IRK (little
+    /// endian), the IRK is distributed to bonding peers. The address is replaced every
+    /// privacy::RPA_TIMEOUT_MS (see set_address_timeout) from the random octets of the handler.
+    pub fn set_privacy<H: smp::PairingHandler>(&mut self, irk: [u8; 16], handler: &mut H) {
+        let mut seed = [0; 16];
+        handler.random(&mut seed);
+        self.privacy = Some(privacy::Privacy::new(irk, seed));
+    }
+
+    /// the lifetime of a resolvable private address, which is timed by the connections and the
+    /// advertising events (scheduled by the application every adv_interval_ms)
+    pub fn set_address_timeout(&mut self, timeout_ms: u32, adv_interval_ms: u32) {
+        self.adv_interval_ms = adv_interval_ms;
+        if let Some(privacy) = self.privacy.as_mut() {
+            privacy.set_timeout(timeout_ms);
+        }
+    }
+
+    /// the local address over the air (the resolvable private address with privacy)
+    pub fn address(&self) -> link_layer::AdvA {
+        return match self.privacy.as_ref() {
+            Some(privacy) => *privacy.address(),
+            None => self.hci.adv_a,
+        };
+    }
+
+    /// ask the central to pair (Security Request), returns false if not possible now
+    pub fn request_pairing(&mut self) -> bool {
+        return match (self.connection.as_mut(), self.smp.as_ref()) {
+            (Some(connection), Some(channel)) if connection.can_send() && ! channel.smp.is_pairing() =>
+                connection.send_l2cap(l2cap::SMP_CID, &channel.smp.security_request()),
+            _ => false,
+        };
+    }
+
+    /// reply to PairingHandler::passkey_request (None if the user cancelled), then run Ble::work
+    pub fn passkey_reply(&mut self, passkey: Option<u32>) {
+        match self.smp.as_mut() {
+            Some(channel) => channel.smp.passkey_reply(passkey),
+            None => {}
+        }
+    }
+
+    /// reply to PairingHandler::confirm_request, then run Ble::work
+    pub fn confirm_reply(&mut self, confirmed: bool) {
+        match self.smp.as_mut() {
+            Some(channel) => channel.smp.confirm_reply(confirmed),
+            None => {}
+        }
+    }
+
+    /// take part in Bluetooth Mesh over the advertising bearer with the Network Transmit and Relay
+    /// Retransmit states, PDUs are sent by mesh_advertise and those received while listening on the
+    /// advertising channels are queued by handle_packet (see mesh_bearer)
+    pub fn set_mesh_bearer(&mut self, network_transmit: mesh::bearer::Transmit, relay_retransmit: mesh::bearer::Transmit) {
+        self.mesh = Some(mesh::bearer::AdvBearer::new(network_transmit, relay_retransmit));
+    }
+
+    /// the advertising bearer to queue PDUs for transmission and receive PDUs
+    pub fn mesh_bearer(&mut self) -> Option<&mut mesh::bearer::AdvBearer> {
+        return self.mesh.as_mut();
+    }
+
+    /// send the mesh PDU due at the time (ms) in a non-connectable advertising event on the three advertising
+    /// channels (call at mesh_bearer().next_transmission()), returns false if none was sent
+    pub fn mesh_advertise(&mut self, now_ms: u32, random: u8) -> bool {
+        let pdu = match self.mesh.as_mut().and_then(|bearer| bearer.poll(now_ms, random)) {
+            Some(pdu) => pdu,
+            None => return false,
+        };
+        let adv_a = self.address();
+        let adv_data = pdu.advertising_data();
+        let mut sent = true;
+        for channel in [link_layer::Channel::CH37, link_layer::Channel::CH38, link_layer::Channel::CH39] {
+            let pdu = link_layer::AdvNonConnIndPdu{adv_a: &adv_a, adv_data: adv_data.as_bytes()};
+            let pdu_slice = pdu.write(&mut self.buffer);
+            sent &= self.hci.send(pdu_slice, channel, link_layer::ADV_ACCESS_ADDRESS, link_layer::ADV_CRCINIT);
+        }
+        return sent;
+    }
+
+    /// respond to the central's requests for a constant tone extension with the CTE types (see
+    /// link_layer::CteType::bit, none if 0), returns false without direction finding hardware
+    pub fn set_cte_response(&mut self, cte_types: u8) -> bool {
+        if ! HCI::DIRECTION_FINDING {
+            return false;
+        }
+        self.cte_types = cte_types;
+        if let Some(connection) = self.connection.as_mut() {
+            connection.set_cte_response(cte_types);
+        }
+        return true;
+    }
+
+    /// request a constant tone extension from the central to sample its IQ data (see iq_report), returns
+    /// false without a connection, direction finding hardware or while a request is outstanding
+    pub fn request_cte(&mut self, cte_info: link_layer::CteInfo) -> bool {
+        return match self.connection.as_mut() {
+            Some(connection) if HCI::DIRECTION_FINDING => connection.request_cte(cte_info),
+            _ => false,
+        };
+    }
+
+    /// the IQ samples of the last CTE received (once)
+    pub fn iq_report(&mut self) -> Option<link_layer::IqReport> {
+        return self.iq_report.take();
+    }
+
+    /// the quality observed on the data channels of the connection and their classification
+    pub fn channel_classifier(&self) -> &channel_map::ChannelClassifier {
+        return &self.channels;
+    }
+
+    /// the classified channel map in the format of HCI LE Set Host Channel Classification (the central
+    /// updates the channel map of the connection, see channel_map::ChannelClassifier)
+    pub fn channel_classification(&self) -> [u8; 5] {
+        return self.channels.host_channel_classification();
+    }
+
+    /// when data channels are classified bad
+    pub fn set_channel_thresholds(&mut self, thresholds: channel_map::Thresholds) {
+        self.channels.set_thresholds(thresholds);
+    }
+
+    /// the data channels the host classifies bad are cleared in the map (Core_v5.3 Vol 4, Part E, 7.8.19)
+    pub fn set_host_channel_classification(&mut self, channel_map: u64) {
+        self.channels.set_host_classification(channel_map);
+    }
+
+    /// the RSSI (dBm) of the last packet received from the central, None without a connection (or a
+    /// sampled packet) - Core_v5.3 Vol 4, Part E, 7.5.4
+    pub fn read_rssi(&self) -> Option<i8> {
+        return self.connection.as_ref().and_then(|connection| connection.rssi());
+    }
+
+    /// the last non-connectable advertisement received while listening on an advertising channel (once)
+    pub fn advertising_report(&mut self) -> Option<gap::AdvertisingReport> {
+        return self.adv_report.take();
     }
 
     /// send out a BlueTooth non-connectable advertisement
     pub fn advertise(&mut self, channel: link_layer::Channel, pdu_type: link_layer::PDU_TYPE) -> bool {
+        return match self.start_advertising(channel, pdu_type) {
+            Some(transmission) => { transmission.wait(); true }
+            None => false,
+        };
+    }
+
+    /// begin sending the advertisement (see advertise) without awaiting it, the transmission holds on to
+    /// the PDU until the radio is done with it (None if the radio is busy)
+    pub fn start_advertising(&mut self, channel: link_layer::Channel, pdu_type: link_layer::PDU_TYPE) -> Option<Transmission<'_>> {
         // advertising channels are CH37, CH38, CH39
         debug_assert!([link_layer::Channel::CH37, link_layer::Channel::CH38, link_layer::Channel::CH39].contains(&channel));
 
-        let pdu_slice= match pdu_type {
+        // an advertising event uses each advertising channel at most once, so whichever channels the
+        // application advertises on (and in whichever order) the next event starts on a channel in use
+        let channel_bit = 1 << (channel as u8 - link_layer::Channel::CH37 as u8);
+        if self.adv_channels == 0 || (self.adv_channels & channel_bit) != 0 {
+            self.adv_channels = 0;
+            if let Some(privacy) = self.privacy.as_mut() {
+                privacy.elapse(self.adv_interval_ms);
+            }
+            if let Some(data) = self.next_adv_data.take() {
+                self.adv_data = data;
+            }
+            if let Some(data) = self.next_scan_rsp_data.take() {
+                self.scan_rsp_data = data;
+            }
+        }
+        self.adv_channels |= channel_bit;
+
+        let adv_a = self.address();
+        // the size (the PDU borrows the local address)
+        let size = match pdu_type {
 
             link_layer::PDU_TYPE::ADV_NONCONN_IND => {
                     let pdu =
-                            link_layer::AdvNonConnIndPdu{adv_a: &self.hci.adv_a,
-                                                         adv_data: &self.ad_fields};
-                    pdu.write(&mut self.buffer)
+                            link_layer::AdvNonConnIndPdu{adv_a: &adv_a,
+                                                         adv_data: self.adv_data.as_bytes()};
+                    pdu.write(&mut self.buffer).len()
             }
 
             link_layer::PDU_TYPE::ADV_IND => {
                 let pdu =
                         link_layer::AdvIndPdu{ch_sel: link_layer::ChSel::Unsupported,
-                                              adv_a: &self.hci.adv_a,
-                                              adv_data: &self.ad_fields};
-                pdu.write(&mut self.buffer)
+                                              adv_a: &adv_a,
+                                              adv_data: self.adv_data.as_bytes()};
+                pdu.write(&mut self.buffer).len()
             }
 
             _ => { panic!("not implemented") }
         };
 
-        return self.hci.send(
-            pdu_slice,
+        return self.hci.transmit(
+            &self.buffer[..size],
             channel,
             link_layer::ADV_ACCESS_ADDRESS,
             link_layer::ADV_CRCINIT,
@@ -71,52 +493,783 @@ impl<'a> Ble<'a> {
                   channel:link_layer::Channel,
                   access_address:link_layer::AccessAddress) -> bool
     {
-        self.hci.listen(&mut self.buffer, channel, access_address)
+        self.hci.listen(&mut self.buffer, channel, access_address, link_layer::ADV_CRCINIT)
     }
 
-    /// handle a received packet
-    pub fn handle_packet(&mut self) {
+    /// handle a received packet, returns true if there is work for Ble::work (or received mesh PDUs, see
+    /// mesh_bearer, or an advertising report)
+    pub fn handle_packet(&mut self) -> bool {
         // handle the hardware
-        self.hci.handle_receive();
+        if ! self.hci.handle_receive() {
+            // the response of the connection event was sent
+            if core::mem::take(&mut self.responding) {
+                if let Some(connection) = self.connection.as_ref() {
+                    Self::listen_connection(&self.hci, &mut self.buffer, connection);
+                }
+                return false;
+            }
+            // a transmission completed (see start_advertising), the address generator may await a reseed
+            return self.privacy.as_ref().is_some_and(|privacy| privacy.needs_seed());
+        }
+
+        if self.connection.is_some() {
+            return self.handle_data_packet();
+        }
 
         // determine pdu type
         match link_layer::PDU_TYPE::of(&self.buffer) {
             Some(pdu_type) => match pdu_type {
                 link_layer::PDU_TYPE::SCAN_REQ => self.handle_scan_request(),
+                link_layer::PDU_TYPE::CONNECT_IND => self.handle_connect_indication(),
+                link_layer::PDU_TYPE::ADV_NONCONN_IND => return self.handle_advertisement(),
                 _ => rprintln!("Unhandled {:?} (hex) {:X?}", pdu_type, self.buffer),
             }
             None => debug_assert!(false, "NonStandard PDU_TYPE (hex) {:X?}", self.buffer)
         }
+        return false;
+    }
+
+    /// perform the work deferred by handle_packet (i.e. ATT requests and pairing) with the application's
+    /// handler (call from a lower priority than the radio interrupt), returns true if work was done
+    pub fn work<H>(&mut self, handler: &mut H) -> bool
+        where H: gatt::CharacteristicHandler + smp::PairingHandler + gatt_client::ClientHandler
+    {
+        // persist the bonds updated by the radio interrupt (storage writes would block it)
+        let mut worked = self.bonds.as_mut().is_some_and(|bonds| bonds.flush());
+        // reseed the generator of the resolvable private addresses
+        if let Some(privacy) = self.privacy.as_mut().filter(|privacy| privacy.needs_seed()) {
+            let mut random = [0; 16];
+            handler.random(&mut random);
+            privacy.reseed(&random);
+            worked = true;
+        }
+        let connection = match self.connection.as_mut() {
+            Some(connection) => connection,
+            None => {
+                // disconnected before the requests were handled
+                self.bearers = core::array::from_fn(|_| AttBearer::new());
+                self.client = ClientBearer::new();
+                return worked;
+            }
+        };
+
+        // the central's encryption request (Core_v5.3 Vol 6, Part B, 5.1.3.1)
+        if let Some((ediv, rand)) = connection.key_request() {
+            let (peer, bonds) = (self.peer_identity.unwrap_or(connection.peer), self.bonds.as_ref());
+            let keys = self.smp.as_ref()
+                .and_then(|channel| channel.smp.long_term_key(ediv, &rand))
+                .or_else(|| bonds
+                    .and_then(|bonds| bonds.find(&peer))
+                    .filter(|bond| bond.ediv == ediv && bond.rand == rand)
+                    .map(|bond| bond.keys()));
+            let mut random = [0; 12];
+            handler.random(&mut random);
+            connection.start_encryption(keys.as_ref().map(|keys| &keys.ltk), &random);
+            self.link_key = keys;
+            worked = true;
+        }
+        match self.smp.as_mut() {
+            // responses require the transmit queue
+            Some(channel) if channel.has_work() && connection.can_send() => {
+                let now = connection.elapsed_ms();
+                let mut send = |pdu: &[u8]| { connection.send_l2cap(l2cap::SMP_CID, pdu); };
+                if channel.encrypted {
+                    channel.encrypted = false;
+                    channel.smp.link_encrypted(handler, &mut send);
+                }
+                if channel.request_len > 0 {
+                    channel.smp.handle(handler, &channel.request[..channel.request_len], now, &mut send);
+                    channel.request_len = 0;
+                }
+                channel.smp.resume(handler, &mut send);
+                worked = true;
+            }
+            _ => {}
+        }
+        // Core_v5.3 Vol 3, Part C, 9.4.2.3 (the keys of a bonding are stored)
+        if let Some(channel) = self.smp.as_mut() {
+            if channel.smp.is_pairing() {
+                channel.stored = false;
+            } else if let (false, Some(&keys)) = (channel.stored, channel.smp.keys()) {
+                channel.stored = true;
+                // the peer distributed its identity
+                if let Some(identity) = keys.peer_identity {
+                    self.peer_identity = Some(identity);
+                }
+                let identity = self.peer_identity.unwrap_or(connection.peer);
+                // the peer signs its writes with the distributed CSRK
+                if let Some(csrk) = keys.peer_csrk {
+                    self.bearers[0].server.set_signing_key(csrk, None);
+                }
+                if keys.bonded {
+                    if let Some(bonds) = self.bonds.as_mut() {
+                        let mut bond = bond::Bond::new(identity, &keys);
+                        // the configuration of the client persists from now on
+                        if let Some(gatt) = self.gatt.as_ref() {
+                            bond.cccds = gatt.client_configuration();
+                        }
+                        bonds.store(bond);
+                    }
+                }
+            }
+        }
+        for (index, bearer) in self.bearers.iter_mut().enumerate() {
+            if ! bearer.has_work() {
+                continue;
+            }
+            // wait until the previous responses have been sent
+            if ! connection.can_send() {
+                break;
+            }
+
+            let mut frames = [0; connection::L2CAP_QUEUE_SIZE];
+            let mut length = 0;
+            if bearer.request_len > 0 {
+                let request = &bearer.request[..bearer.request_len];
+                bearer.response_len = match self.gatt.as_mut() {
+                    Some(gatt) => gatt.handle(&mut bearer.server, handler, request, &mut bearer.response),
+                    None => bearer.server.handle(&mut [] as &mut [att::Attribute], request, &mut bearer.response),
+                };
+                bearer.request_len = 0;
+                if index > 0 {
+                    // the request has been consumed
+                    length += self.signaling.return_credits(index - 1, &mut frames);
+                }
+                worked = true;
+            }
+            if bearer.response_len > 0 {
+                let response = &bearer.response[..bearer.response_len];
+                if index == 0 {
+                    connection.send_l2cap(l2cap::ATT_CID, response);
+                    bearer.response_len = 0;
+                } else {
+                    match self.signaling.channel_mut(index - 1) {
+                        Some(channel) => match channel.send(response, &mut frames[length..]) {
+                            Some(size) => {
+                                length += size;
+                                bearer.response_len = 0;
+                            }
+                            // wait for credits
+                            None => {}
+                        }
+                        // the channel was closed
+                        None => bearer.response_len = 0,
+                    }
+                }
+            }
+            connection.send_l2cap_frames(&frames[..length]);
+        }
+        // the server's PDU for the client, its procedure may continue with another request
+        if self.client.has_work() && connection.can_send() {
+            let mut request = [0; att::ATT_MTU_MAX as usize];
+            let (event, size) = self.client.client.handle(&self.client.pdu[..self.client.pdu_len], &mut request);
+            if let Some(gatt_client::Event::MtuExchanged { mtu }) = event {
+                self.bearers[0].server.set_mtu(mtu);
+            }
+            if let Some(event) = event {
+                handler.client_event(event);
+            }
+            self.client.pdu_len = 0;
+            if size > 0 {
+                connection.send_l2cap(l2cap::ATT_CID, &request[..size]);
+            }
+            worked = true;
+        }
+        return worked;
+    }
+
+    /// notify the client of a characteristic's (value handle) value
+    pub fn notify(&mut self, handle: att::Handle, value: &[u8]) -> Result<(), gatt::NotifyError> {
+        let (connection, gatt) = match (self.connection.as_mut(), self.gatt.as_ref()) {
+            (Some(connection), Some(gatt)) => (connection, gatt),
+            _ => return Err(gatt::NotifyError::NotConnected),
+        };
+        if ! connection.can_send() {
+            return Err(gatt::NotifyError::Busy);
+        }
+        let mut pdu = [0; att::ATT_MTU_MAX as usize];
+        let size = gatt.notification(&self.bearers[0].server, handle, value, &mut pdu)?;
+        connection.send_l2cap(l2cap::ATT_CID, &pdu[..size]);
+        return Ok(());
+    }
+
+    /// indicate a characteristic's (value handle) value, only one indication is outstanding until
+    /// the client confirms it (see is_indication_pending)
+    pub fn indicate(&mut self, handle: att::Handle, value: &[u8]) -> Result<(), gatt::NotifyError> {
+        let (connection, gatt) = match (self.connection.as_mut(), self.gatt.as_ref()) {
+            (Some(connection), Some(gatt)) => (connection, gatt),
+            _ => return Err(gatt::NotifyError::NotConnected),
+        };
+        if ! connection.can_send() {
+            return Err(gatt::NotifyError::Busy);
+        }
+        let mut pdu = [0; att::ATT_MTU_MAX as usize];
+        let size = gatt.indication(&mut self.bearers[0].server, handle, value, &mut pdu)?;
+        connection.send_l2cap(l2cap::ATT_CID, &pdu[..size]);
+        self.indication_deadline = Some(connection.elapsed_ms() + att::ATT_TIMEOUT_MS);
+        return Ok(());
+    }
+
+    /// true while an indication awaits its confirmation
+    pub fn is_indication_pending(&self) -> bool {
+        return self.bearers[0].server.is_indication_pending();
+    }
+
+    /// discover the primary services (all, or those of the UUID) of the peer's GATT server, the results of
+    /// the client procedures are reported to the handler of Ble::work (see gatt_client::ClientHandler)
+    pub fn discover_primary_services(&mut self, uuid: Option<att::Uuid>) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| match uuid {
+            Some(uuid) => client.discover_primary_services_by_uuid(uuid, request),
+            None => client.discover_primary_services(request),
+        });
+    }
+
+    pub fn discover_secondary_services(&mut self) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.discover_secondary_services(request));
+    }
+
+    /// find the included services of a service (start..=end)
+    pub fn find_included_services(&mut self, start: att::Handle, end: att::Handle) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.find_included_services(start, end, request));
+    }
+
+    /// discover the characteristics of a service (start..=end), optionally only those of a UUID
+    pub fn discover_characteristics(&mut self, start: att::Handle, end: att::Handle, uuid: Option<att::Uuid>)
+        -> Result<(), gatt_client::ClientError>
+    {
+        return self.client_procedure(|client, request| client.discover_characteristics(start, end, uuid, request));
+    }
+
+    /// discover the descriptors of a characteristic (from its value handle + 1 to the end of the characteristic)
+    pub fn discover_descriptors(&mut self, start: att::Handle, end: att::Handle) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.discover_descriptors(start, end, request));
+    }
+
+    /// negotiate the ATT_MTU of the bearer (up to the mtu)
+    pub fn exchange_mtu(&mut self, mtu: u16) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.exchange_mtu(mtu, request));
+    }
+
+    /// read a value of the peer, a long one (beyond the ATT_MTU) is reported in parts
+    pub fn read_value(&mut self, handle: att::Handle, long: bool) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| match long {
+            true => client.read_long(handle, request),
+            false => client.read(handle, request),
+        });
+    }
+
+    /// write a value of the peer, a long one (beyond the ATT_MTU) with prepared writes
+    pub fn write_value(&mut self, handle: att::Handle, value: &[u8], long: bool) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| match long {
+            true => client.write_long(handle, value, request),
+            false => client.write(handle, value, request),
+        });
+    }
+
+    /// write a value of the peer without a response (allowed during other procedures)
+    pub fn write_without_response(&mut self, handle: att::Handle, value: &[u8]) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.write_without_response(handle, value, request));
+    }
+
+    /// write several values of the peer atomically
+    pub fn reliable_write(&mut self, values: &[(att::Handle, &[u8])]) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.reliable_write(values, request));
+    }
+
+    /// enable (or disable) the notifications and indications of a characteristic of the peer (by its CCCD)
+    pub fn subscribe(&mut self, cccd_handle: att::Handle, notify: bool, indicate: bool) -> Result<(), gatt_client::ClientError> {
+        return self.client_procedure(|client, request| client.subscribe(cccd_handle, notify, indicate, request));
+    }
+
+    /// start a procedure of the GATT client, queueing its first request
+    fn client_procedure<F>(&mut self, procedure: F) -> Result<(), gatt_client::ClientError>
+        where F: FnOnce(&mut gatt_client::GattClient, &mut [u8]) -> Result<usize, gatt_client::ClientError>
+    {
+        let connection = match self.connection.as_mut() {
+            Some(connection) => connection,
+            None => return Err(gatt_client::ClientError::NotConnected),
+        };
+        if ! connection.can_send() {
+            return Err(gatt_client::ClientError::Busy);
+        }
+        // the ATT_MTU exchanged by either client
+        self.client.client.set_mtu(self.bearers[0].server.mtu());
+        let mut request = [0; att::ATT_MTU_MAX as usize];
+        let size = procedure(&mut self.client.client, &mut request)?;
+        connection.send_l2cap(l2cap::ATT_CID, &request[..size]);
+        return Ok(());
     }
 
     fn handle_scan_request(&mut self) {
         rprintln!("sending scan response");
         // TODO verify AdvA matches
-        // FIXME provide additional advertisement data
-        let ad_fields = gap::AdFields::default();
-        let pdu = link_layer::ScanRspPdu{adv_a: &self.hci.adv_a, scan_rsp_data: &ad_fields};
-        let pdu_slice = pdu.write(&mut self.buffer);
+        let adv_a = self.address();
+        let pdu = link_layer::ScanRspPdu{adv_a: &adv_a, scan_rsp_data: self.scan_rsp_data.as_bytes()};
+        let size = pdu.write(&mut self.tx_buffer).len();
         debug_assert!(
-            self.hci.send(pdu_slice,
-                          self.hci.channel(),
-                          link_layer::ADV_ACCESS_ADDRESS,
-                          link_layer::ADV_CRCINIT,
+            self.hci.respond(&self.tx_buffer,
+                             size,
+                             self.hci.channel(),
+                             link_layer::ADV_ACCESS_ADDRESS,
+                             link_layer::ADV_CRCINIT,
         ));
     }
 
+    /// report a non-connectable advertisement and queue its mesh PDUs
+    fn handle_advertisement(&mut self) -> bool {
+        let (adv_a, adv_data) = match link_layer::AdvNonConnIndPdu::read(&self.buffer) {
+            Some(pdu) => pdu,
+            None => return false,
+        };
+        let mut work = false;
+        if let Some(data) = gap::AdvertisingData::from_bytes(adv_data) {
+            self.adv_report = Some(gap::AdvertisingReport { address: adv_a, data, rssi: self.hci.rssi() });
+            work = true;
+        }
+        if let Some(bearer) = self.mesh.as_mut() {
+            work |= bearer.handle_advertisement(adv_data);
+        }
+        return work;
+    }
+
+    fn handle_connect_indication(&mut self) {
+        // only connectable when serving attributes
+        if self.gatt.is_none() {
+            return;
+        }
+        let pdu = match link_layer::ConnectIndPdu::read(&self.buffer) {
+            Some(pdu) => pdu,
+            None => return,
+        };
+        let local = self.address();
+        if pdu.adv_a.bytes != local.bytes {
+            return;
+        }
+        rprintln!("connected to {}", pdu.init_a);
+
+        let mut connection = connection::Connection::new(pdu);
+        // advertising resumes with a new advertising event
+        self.adv_channels = 0;
+        connection.set_cte_response(self.cte_types);
+        self.channels.reset();
+        self.signaling = l2cap::Signaling::new();
+        self.bearers = core::array::from_fn(|_| AttBearer::new());
+        self.client = ClientBearer::new();
+        self.indication_deadline = None;
+        self.smp = self.pairing.map(|config| {
+            let mut smp = smp::Smp::new(config, &connection.peer, &local);
+            if let Some(privacy) = self.privacy.as_ref() {
+                smp.distribute_identity(*privacy.irk(), self.hci.adv_a);
+            }
+            if let Some(local) = self.oob {
+                smp.set_local_oob(local);
+            }
+            SmpChannel { smp, request: [0; smp::SMP_MTU], request_len: 0, encrypted: false, stored: false }
+        });
+        self.link_key = None;
+        self.encrypted = false;
+        // bonded peers are known by their identity address
+        let hci = &mut self.hci;
+        let bond = self.bonds.as_ref().and_then(|bonds| bonds.resolve(hci, &connection.peer)).copied();
+        let identity = bond.map_or(connection.peer, |bond| bond.identity);
+        self.peer_identity = Some(identity);
+        if let Some(gatt) = self.gatt.as_mut() {
+            gatt.connected(bond.map_or([0; gatt::CCCD_MAX], |bond| bond.cccds));
+        }
+        if let Some(bonds) = self.bonds.as_mut() {
+            if let Some(bond) = bond {
+                bonds.touch(&bond.identity);
+                if let Some(csrk) = bond.csrk {
+                    self.bearers[0].server.set_signing_key(csrk, bond.sign_counter);
+                }
+            }
+        }
+        // the central transmits first, within the transmit window
+        connection.set_connect_ind_time(self.hci.timestamp_us());
+        Self::listen_connection(&self.hci, &mut self.buffer, &connection);
+        self.connection = Some(connection);
+    }
+
+    fn handle_data_packet(&mut self) -> bool {
+        let connection = self.connection.as_mut().unwrap();
+        if ! self.hci.received() {
+            // the central closed the connection event, or it was missed
+            if let Some(channel) = connection.receive_timeout() {
+                self.channels.missed(channel);
+            }
+            if connection.is_terminated() {
+                return self.disconnected();
+            }
+            Self::listen_connection(&self.hci, &mut self.buffer, connection);
+            return false;
+        }
+        let rssi = self.hci.rssi();
+        if ! self.hci.crc_ok() {
+            // ignore the corrupted PDU (the central retransmits it)
+            self.channels.crc_error(connection.channel(), rssi);
+            Self::listen_connection(&self.hci, &mut self.buffer, connection);
+            return false;
+        }
+        self.channels.received(connection.channel(), rssi);
+        connection.received(self.hci.timestamp_us());
+        connection.set_rssi(rssi);
+        let header = match link_layer::DataPduHeader::read(&self.buffer) {
+            Some(header) => header,
+            None => {
+                // ignore the invalid PDU
+                Self::listen_connection(&self.hci, &mut self.buffer, connection);
+                return false;
+            }
+        };
+        connection.acknowledge(&header);
+
+        // the IQ samples of the CTE following the PDU
+        if let Some(cte_info) = header.cte_info {
+            let mut samples = [link_layer::IqSample::default(); link_layer::IQ_SAMPLES_MAX];
+            let count = self.hci.iq_samples(&mut samples);
+            if count > 0 {
+                self.iq_report = Some(link_layer::IqReport {
+                    channel: connection.channel(),
+                    cte_info,
+                    samples: heapless::Vec::from_slice(&samples[..count]).unwrap(),
+                });
+            }
+        }
+
+        if connection.is_new(&header) && ! connection.decrypt(&mut self.buffer, &mut self.hci) {
+            // Core_v5.3 Vol 6, Part B, 5.1.3.1
+            rprintln!("MIC failure");
+            connection.abort();
+        } else if connection.is_new(&header) {
+            let length = self.buffer[1] as usize;
+            let payload = &self.buffer[header.size()..(header.size() + length)];
+            let accepted = match header.llid {
+                link_layer::Llid::Control => connection.control(payload),
+                // empty PDU
+                link_layer::Llid::Continuation if payload.is_empty() => true,
+                llid => {
+                    let mut frame = [0; l2cap::FRAME_SIZE_MAX];
+                    let size = match connection.recombine(llid, payload) {
+                        Some(recombined) => {
+                            frame[..recombined.len()].copy_from_slice(recombined);
+                            recombined.len()
+                        }
+                        None => 0,
+                    };
+                    // an incomplete SDU is accepted while awaiting its continuation fragments
+                    if size == 0 || Self::handle_l2cap(connection, &mut self.signaling, &mut self.bearers, &mut self.client,
+                                                       &mut self.smp, &frame[..size]) {
+                        true
+                    } else {
+                        connection.reject_fragment();
+                        false
+                    }
+                }
+            };
+            if accepted {
+                connection.accept();
+            }
+        }
+
+        // respond within the connection event
+        let size = connection.transmit(&mut self.tx_buffer, &mut self.hci);
+        self.responding = match connection.tx_cte() {
+            Some(cte_info) => self.hci.respond_cte(&self.tx_buffer, size, connection.channel(), connection.access_address(),
+                                                   connection.crc_init(), cte_info),
+            None => self.hci.respond(&self.tx_buffer, size, connection.channel(), connection.access_address(),
+                                     connection.crc_init()),
+        };
+
+        if connection.is_terminated() {
+            return self.disconnected();
+        }
+
+        // a failed ATT transaction ends the connection (Core_v5.3 Vol 3, Part F, 3.3.3)
+        if let Some(deadline) = self.indication_deadline {
+            if ! self.bearers[0].server.is_indication_pending() {
+                self.indication_deadline = None;
+            } else if connection.elapsed_ms() >= deadline {
+                rprintln!("indication timeout");
+                connection.terminate(connection::REMOTE_USER_TERMINATED);
+            }
+        }
+
+        if let Some(channel) = self.smp.as_mut() {
+            channel.smp.check_timeout(connection.elapsed_ms());
+        }
+
+        // the bearers inherit the security of the link
+        let security = match (connection.is_encrypted(), self.link_key) {
+            (true, Some(keys)) if keys.authenticated => att::SecurityLevel::Authenticated,
+            (true, _) => att::SecurityLevel::Encrypted,
+            (false, _) => att::SecurityLevel::NoSecurity,
+        };
+        for bearer in self.bearers.iter_mut() {
+            bearer.server.set_security(security);
+        }
+        if connection.is_encrypted() != self.encrypted {
+            self.encrypted = connection.is_encrypted();
+            self.signaling.set_encrypted(self.encrypted);
+            if let (true, Some(channel)) = (self.encrypted, self.smp.as_mut()) {
+                channel.encrypted = true;
+            }
+        }
+
+        // the connection event closes unless either side has more data
+        if ! header.md && ! connection.has_more_data() {
+            // TODO supervision timeout and slave latency (requires a timer)
+            connection.next_event();
+        }
+        // the central's next PDU is listened for once the response is sent (see handle_packet)
+        if ! self.responding {
+            Self::listen_connection(&self.hci, &mut self.buffer, connection);
+        }
+        return self.bearers.iter().any(|bearer| bearer.has_work())
+               || self.client.has_work()
+               || self.smp.as_ref().is_some_and(|channel| channel.has_work())
+               || connection.key_request().is_some();
+    }
+
+    /// the connection ended, returns true if there is work for Ble::work
+    fn disconnected(&mut self) -> bool {
+        let connection = self.connection.take().unwrap();
+        rprintln!("disconnected");
+        let peer = self.peer_identity.take().unwrap_or(connection.peer);
+        if let Some(privacy) = self.privacy.as_mut() {
+            privacy.elapse(connection.elapsed_ms());
+        }
+        self.signaling = l2cap::Signaling::new();
+        self.smp = None;
+        if let Some(gatt) = self.gatt.as_mut() {
+            // persist the state of a bonded peer
+            if let Some(bonds) = self.bonds.as_mut() {
+                bonds.update(&peer, gatt.client_configuration(), self.bearers[0].server.sign_counter());
+            }
+            gatt.disconnected();
+        }
+        return self.bonds.as_ref().is_some_and(|bonds| bonds.has_work())
+               || self.privacy.as_ref().is_some_and(|privacy| privacy.needs_seed());
+    }
+
+    /// listen for the central's next PDU until its deadline, sampling the IQ data of the CTE requested from it
+    fn listen_connection(hci: &HCI, buffer: &mut link_layer::PduBuffer, connection: &connection::Connection) {
+        let deadline_us = connection.deadline_us();
+        match connection.cte_requested() {
+            Some(cte_info) => hci.listen_cte(buffer, connection.channel(), connection.access_address(), connection.crc_init(),
+                                             cte_info, deadline_us),
+            None => hci.listen_until(buffer, connection.channel(), connection.access_address(), connection.crc_init(),
+                                     deadline_us),
+        };
+    }
+
+    /// handle a received L2CAP frame, returns false if it can't be accepted yet
+    fn handle_l2cap(connection: &mut connection::Connection, signaling: &mut l2cap::Signaling,
+                    bearers: &mut [AttBearer; ATT_BEARERS], client: &mut ClientBearer, smp: &mut Option<SmpChannel>,
+                    frame: &[u8]) -> bool
+    {
+        return match l2cap::read(frame) {
+            // the server's responses, notifications and indications are for the client
+            Some((l2cap::ATT_CID, pdu)) if pdu.first().is_some_and(|opcode| att::Opcode::is_from_server(*opcode)) => {
+                client.receive(pdu)
+            }
+            Some((l2cap::ATT_CID, request)) => bearers[0].receive(request),
+            Some((l2cap::LE_SIGNALING_CID, packet)) => {
+                // responses require the transmit queue
+                if ! connection.can_send() {
+                    return false;
+                }
+                let mut response = [0; l2cap::SIGNALING_MTU];
+                let size = signaling.handle(packet, &mut response);
+                if size > 0 {
+                    connection.send_l2cap(l2cap::LE_SIGNALING_CID, &response[..size]);
+                }
+                // each credit based channel is an Enhanced ATT bearer
+                for (index, bearer) in bearers[1..].iter_mut().enumerate() {
+                    match signaling.channel(index) {
+                        Some(channel) => if ! bearer.server.is_enhanced() {
+                            *bearer = AttBearer::new();
+                            bearer.server = att::AttServer::enhanced(core::cmp::min(l2cap::ECFC_MTU, channel.peer_mtu));
+                        }
+                        None => if bearer.server.is_enhanced() {
+                            *bearer = AttBearer::new();
+                        }
+                    }
+                }
+                true
+            }
+            Some((l2cap::SMP_CID, pdu)) => match smp {
+                Some(channel) => channel.receive(pdu),
+                None => connection.send_l2cap(l2cap::SMP_CID,
+                                              &[smp::Code::PairingFailed as u8, smp::Reason::PairingNotSupported as u8]),
+            }
+            Some((cid, k_frame)) => match signaling.index(cid) {
+                Some(index) => {
+                    let bearer = &mut bearers[1 + index];
+                    if bearer.request_len > 0 {
+                        return false;
+                    }
+                    match signaling.channel_mut(index).unwrap().receive(k_frame) {
+                        Some(request) => bearer.receive(request),
+                        None => true,
+                    }
+                }
+                // unknown channel
+                None => true,
+            }
+            None => true,
+        };
+    }
 }
 
 
 
 pub struct FakeHci {
     pub adv_a: link_layer::AdvA,
+    /// the virtual distance (cm) to the peer, received packets have its RSSI (see beacons::rssi_at)
+    pub distance_cm: Option<u32>,
+    /// nothing is received by the deadline of listen_until
+    pub timed_out: bool,
+    /// a response is being sent, its completion is the next interrupt (see handle_receive)
+    pub responding: core::cell::Cell<bool>,
 }
 impl FakeHci {
+    pub const DIRECTION_FINDING:bool = true;
+    /// the RSSI (dBm) of the peer at 1m
+    pub const RSSI_1M:i8 = -59;
     pub fn send(&self, _:&[u8], _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit) -> bool
     { true }
-    pub fn listen(&self, _:&mut link_layer::PduBuffer, _:link_layer::Channel, _:link_layer::AccessAddress) -> bool
+    pub fn transmit<'a>(&'a self, _:&'a [u8], _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit) -> Option<FakeTransmission<'a>>
+    { Some(FakeTransmission(core::marker::PhantomData)) }
+    pub fn respond(&self, _:&link_layer::PduBuffer, _:usize, _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit) -> bool
+    { ! self.responding.replace(true) }
+    pub fn respond_cte(&self, _:&link_layer::PduBuffer, _:usize, _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit, _:link_layer::CteInfo) -> bool
+    { ! self.responding.replace(true) }
+    pub fn listen(&self, _:&mut link_layer::PduBuffer, _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit) -> bool
+    { true }
+    pub fn listen_until(&self, _:&mut link_layer::PduBuffer, _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit, _:u32) -> bool
+    { true }
+    pub fn listen_cte(&self, _:&mut link_layer::PduBuffer, _:link_layer::Channel, _:link_layer::AccessAddress, _:link_layer::CrcInit, _:link_layer::CteInfo, _:u32) -> bool
+    { true }
+    pub fn iq_samples(&self, _:&mut [link_layer::IqSample]) -> usize
+    { 0 }
+    pub fn handle_receive(&self) -> bool
+    { ! self.responding.take() }
+    pub fn received(&self) -> bool
+    { ! self.timed_out }
+    pub fn timestamp_us(&self) -> u32
+    { 0 }
+    pub fn crc_ok(&self) -> bool
     { true }
-    pub fn handle_receive(&self) { }
+    pub fn rssi(&self) -> Option<i8>
+    { self.distance_cm.map(|distance_cm| beacons::rssi_at(FakeHci::RSSI_1M, distance_cm)) }
     pub fn channel(&self) -> link_layer::Channel
     { link_layer::Channel::CH0 }
-}
\ No newline at end of file
+}
+/// transmissions complete immediately
+pub struct FakeTransmission<'a>(core::marker::PhantomData<&'a [u8]>);
+impl FakeTransmission<'_> {
+    pub fn is_complete(&self) -> bool
+    { true }
+    pub fn wait(self) { }
+}
+impl core::future::Future for FakeTransmission<'_> {
+    type Output = ();
+    fn poll(self: core::pin::Pin<&mut Self>, _: &mut core::task::Context<'_>) -> core::task::Poll<()>
+    { core::task::Poll::Ready(()) }
+}
+impl privacy::AddressResolver for FakeHci {
+    fn resolve(&mut self, irks: &[[u8; 16]], address: &link_layer::DeviceAddress) -> Option<usize>
+    { privacy::SoftwareResolver.resolve(irks, address) }
+}
+impl encryption::Ccm for FakeHci {
+    fn encrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> usize
+    { encryption::SoftwareCcm.encrypt(key, nonce, header, payload, length) }
+    fn decrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> Option<usize>
+    { encryption::SoftwareCcm.decrypt(key, nonce, header, payload, length) }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod gatt_client_connection {
+    use super::*;
+
+    crate::attribute_table! {
+        const ATTRIBUTES = [
+            primary_service(att::Uuid::Uuid16(0x180F)),
+        ];
+    }
+
+    /// the results of the client procedures
+    #[derive(Default)]
+    struct Application {
+        services: Vec<gatt_client::Service>,
+        notifications: Vec<(att::Handle, Vec<u8>)>,
+    }
+    impl gatt::CharacteristicHandler for Application {}
+    impl smp::PairingHandler for Application {
+        fn random(&mut self, bytes: &mut [u8]) {
+            bytes.fill(0);
+        }
+    }
+    impl gatt_client::ClientHandler for Application {
+        fn client_event(&mut self, event: gatt_client::Event) {
+            match event {
+                gatt_client::Event::Services(services) => self.services.extend(services),
+                gatt_client::Event::Notification { handle, value } => self.notifications.push((handle, value.to_vec())),
+                _ => {}
+            }
+        }
+    }
+
+    /// receive the central's PDU and complete the response, returns whether there is work
+    fn receive(ble: &mut Ble, pdu: &[u8]) -> bool {
+        ble.buffer[..pdu.len()].copy_from_slice(pdu);
+        let work = ble.handle_packet();
+        assert!(! ble.handle_packet());
+        return work;
+    }
+
+    #[test]
+    fn discovery_round_trip() {
+        let adv_a = link_layer::DeviceAddress::public([1, 2, 3, 4, 5, 6]);
+        let hci = FakeHci { adv_a, distance_cm: None, timed_out: false, responding: core::cell::Cell::new(false) };
+        let mut ble = Ble::with_attributes(hci, gap::AdFields::default(), ATTRIBUTES);
+        let mut application = Application::default();
+        assert_eq!(Err(gatt_client::ClientError::NotConnected), ble.discover_primary_services(None));
+
+        // CONNECT_IND (30ms interval, 1s supervision timeout, all channels, hop 5)
+        let mut connect_ind = vec![link_layer::PDU_TYPE::CONNECT_IND as u8, 34, 0xC0, 0xC1, 0xC2, 0xC3, 0xC4, 0xC5];
+        connect_ind.extend(adv_a.bytes);
+        connect_ind.extend([0x78, 0x56, 0x34, 0x12, 0x55, 0x55, 0x55, 1, 0, 0, 24, 0, 0, 0, 100, 0,
+                            0xFF, 0xFF, 0xFF, 0xFF, 0x1F, 5]);
+        ble.buffer[..connect_ind.len()].copy_from_slice(&connect_ind);
+        ble.handle_packet();
+        assert!(ble.is_connected());
+
+        // the request is sent in the next connection event
+        assert_eq!(Ok(()), ble.discover_primary_services(None));
+        assert_eq!(Err(gatt_client::ClientError::Busy), ble.discover_primary_services(None));
+        assert!(! receive(&mut ble, &[0x01, 0]));
+        assert_eq!([0x06, 11, 7, 0, 4, 0, 0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28], ble.tx_buffer[..13]);
+
+        // the server's response is reported to the application and the discovery continues
+        assert!(receive(&mut ble, &[0x0E, 12, 8, 0, 4, 0, 0x11, 6, 0x01, 0x00, 0x05, 0x00, 0x0D, 0x18]));
+        assert!(ble.work(&mut application));
+        assert_eq!(vec![gatt_client::Service { start: 1, end: 5, uuid: att::Uuid::Uuid16(0x180D) }], application.services);
+        assert!(! receive(&mut ble, &[0x01, 0]));
+        assert_eq!([0x06, 11, 7, 0, 4, 0, 0x10, 0x06, 0x00, 0xFF, 0xFF, 0x00, 0x28], ble.tx_buffer[..13]);
+
+        // notifications are for the client, requests for the server
+        assert!(receive(&mut ble, &[0x0E, 8, 4, 0, 4, 0, 0x1B, 0x03, 0x00, 0x2A]));
+        assert!(ble.work(&mut application));
+        assert_eq!(vec![(3, vec![0x2A])], application.notifications);
+        assert!(receive(&mut ble, &[0x02, 7, 3, 0, 4, 0, 0x0A, 0x01, 0x00]));
+        assert!(ble.work(&mut application));
+        assert!(! receive(&mut ble, &[0x0D, 0]));
+        // the GAP service declaration
+        assert_eq!([0x0A, 7, 3, 0, 4, 0, 0x0B, 0x00, 0x18], ble.tx_buffer[..9]);
+    }
+}
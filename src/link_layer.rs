@@ -8,6 +8,8 @@ use rtt_target::rprintln;
 pub const PDU_SIZE_MAX:usize = 255;
 pub type PduBuffer = [u8;PDU_SIZE_MAX];
 pub const ADV_PDU_SIZE_MAX:usize = 37;
+/// AdvData and ScanRspData of legacy advertising PDUs - Core_v5.3 Vol 6, Part B, 2.3.1
+pub const ADV_DATA_SIZE_MAX:usize = ADV_PDU_SIZE_MAX - ADDRESS_LEN;
 
 pub type AccessAddress = u32;
 /// Core_v5.3.pdf#G41.455603
@@ -23,7 +25,7 @@ pub const T_IFS_US:u8 = 150;
 
 #[derive(TryFromPrimitive)]
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 /// https://www.rfwireless-world.com/Terminology/BLE-Advertising-channels-and-Data-channels-list.html
 /// Core_v5.3.pdf#G41.455772
 pub enum Channel {
@@ -94,35 +96,110 @@ pub enum ChSel {
 }
 
 const ADDRESS_LEN:usize = 6;
-pub(crate) type Address = [u8;ADDRESS_LEN];
-pub enum TxRxAdvAddress {
-    Public(Address),
-    RandomStatic(Address),
-    PrivateStatic(Address),
-}
-impl TxRxAdvAddress {
-    fn write_address(&self, buffer: &mut [u8]) -> usize {
-        match self {
-            TxRxAdvAddress::Public(address) 
-            | TxRxAdvAddress::RandomStatic(address) 
-            | TxRxAdvAddress::PrivateStatic(address) => {
-                buffer[0..ADDRESS_LEN].copy_from_slice(address);
-            }
+/// the octets of a device address as sent (least significant octet first)
+pub type Address = [u8;ADDRESS_LEN];
+
+/// Core_v5.3 Vol 6, Part B, 1.3
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AddressKind {
+    Public,
+    RandomStatic,
+    /// changes periodically, resolvable to the identity with the IRK (see privacy)
+    ResolvablePrivate,
+    NonResolvablePrivate,
+}
+
+/// a device address, the kind determines the TxAdd/RxAdd bits of PDUs
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DeviceAddress {
+    pub kind: AddressKind,
+    pub bytes: Address,
+}
+impl DeviceAddress {
+    pub const fn public(bytes: Address) -> Self {
+        Self { kind: AddressKind::Public, bytes }
+    }
+
+    /// classify a random address by its two most significant bits - Core_v5.3 Vol 6, Part B, 1.3.2
+    pub fn random(bytes: Address) -> Self {
+        let kind = match bytes[ADDRESS_LEN - 1] >> 6 {
+            0b11 => AddressKind::RandomStatic,
+            0b01 => AddressKind::ResolvablePrivate,
+            _ => AddressKind::NonResolvablePrivate,
+        };
+        return Self { kind, bytes };
+    }
+
+    /// an address of the kind, None if the random address doesn't conform to the kind
+    pub fn new(kind: AddressKind, bytes: Address) -> Option<Self> {
+        let address = Self { kind, bytes };
+        return match address.is_valid() {
+            true => Some(address),
+            false => None,
+        };
+    }
+
+    /// the address (as received) per the TxAdd or RxAdd bit of a PDU
+    pub(crate) fn read(random: bool, bytes: &[u8]) -> Self {
+        let mut address:Address = [0; ADDRESS_LEN];
+        address.copy_from_slice(&bytes[..ADDRESS_LEN]);
+        return match random {
+            true => Self::random(address),
+            false => Self::public(address),
+        };
+    }
+
+    /// random addresses have the sub-type bits of their kind and random bits that aren't all zeros
+    /// or all ones - Core_v5.3 Vol 6, Part B, 1.3.2
+    pub fn is_valid(&self) -> bool {
+        let (sub_type, random_bits) = match self.kind {
+            AddressKind::Public => return true,
+            AddressKind::RandomStatic => (0b11, ADDRESS_LEN * 8 - 2),
+            // the random part of prand
+            AddressKind::ResolvablePrivate => (0b01, 22),
+            AddressKind::NonResolvablePrivate => (0b00, ADDRESS_LEN * 8 - 2),
         };
+        let value = u64::from_le_bytes([self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3],
+                                        self.bytes[4], self.bytes[5], 0, 0]);
+        let mask = (1_u64 << random_bits) - 1;
+        let random = (value >> (ADDRESS_LEN * 8 - 2 - random_bits)) & mask;
+        return (value >> (ADDRESS_LEN * 8 - 2)) == sub_type && random != 0 && random != mask;
+    }
+
+    /// the TxAdd/RxAdd bit
+    pub fn is_random(&self) -> bool {
+        return self.kind != AddressKind::Public;
+    }
+
+    /// identity addresses are public or random static - Core_v5.3 Vol 3, Part C, 15.1.1
+    pub fn is_identity(&self) -> bool {
+        return matches!(self.kind, AddressKind::Public | AddressKind::RandomStatic);
+    }
+
+    fn write(&self, buffer: &mut [u8]) -> usize {
+        buffer[0..ADDRESS_LEN].copy_from_slice(&self.bytes);
         return ADDRESS_LEN;
     }
 }
+impl core::fmt::Display for DeviceAddress {
+    /// most significant octet first (i.e. AA:BB:CC:DD:EE:FF)
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let b = &self.bytes;
+        return write!(f, "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}", b[5], b[4], b[3], b[2], b[1], b[0]);
+    }
+}
 /* type aliases to reflect naming in Bluetooth standard for PDUs */
-pub type AdvA = TxRxAdvAddress;
-// type TargetA = TxRxAdvAddress;
-// type InitA = TxRxAdvAddress;
-type ScanA = TxRxAdvAddress;
-type AdvData<'a> = crate::gap::AdFields<'a>;
+pub type AdvA = DeviceAddress;
+// type TargetA = DeviceAddress;
+// type InitA = DeviceAddress;
+type ScanA = DeviceAddress;
+/// encoded AD structures (see gap::AdvertisingData)
+type AdvData = [u8];
 
 pub struct AdvIndPdu<'a> {
     pub ch_sel: ChSel,
     pub adv_a: &'a AdvA,
-    pub adv_data: &'a AdvData<'a>,
+    pub adv_data: &'a AdvData,
 }
 impl<'a> AdvIndPdu<'a> {
     /// returns the used slice of the destination buffer
@@ -137,17 +214,19 @@ impl<'a> AdvIndPdu<'a> {
                     // chSel bit
                     | (match self.ch_sel { ChSel::Supported => 1, _ => 0 } << CHSEL_SHIFT)
                     // txadd bit
-                    | (match self.adv_a { TxRxAdvAddress::Public(..) => 0, _ => 1 } << TXADD_SHIFT);
+                    | ((self.adv_a.is_random() as u8) << TXADD_SHIFT);
         pdu_size += 1;
         
         // skip a byte for length (will be set at end)
         pdu_size += 1;
 
         // write the AdvA
-        pdu_size += self.adv_a.write_address(&mut buffer[pdu_size..(pdu_size+6)]);
+        pdu_size += self.adv_a.write(&mut buffer[pdu_size..(pdu_size+6)]);
 
         // append the adv_data
-        pdu_size += self.adv_data.write(&mut buffer[pdu_size..]);
+        let adv_data = &self.adv_data[..self.adv_data.len().min(ADV_DATA_SIZE_MAX)];
+        buffer[pdu_size..(pdu_size + adv_data.len())].copy_from_slice(adv_data);
+        pdu_size += adv_data.len();
 
         // set the length
         const PDU_HEADER_SIZE:usize = 2;
@@ -161,7 +240,7 @@ impl<'a> AdvIndPdu<'a> {
 
 pub struct AdvNonConnIndPdu<'a> {
     pub adv_a: &'a AdvA,
-    pub adv_data: &'a AdvData<'a>,
+    pub adv_data: &'a AdvData,
 }
 impl<'a> AdvNonConnIndPdu<'a> {
     /// returns the used slice of the destination buffer
@@ -173,17 +252,19 @@ impl<'a> AdvNonConnIndPdu<'a> {
         buffer[0] = // base pdu type
                     ((PDU_TYPE::ADV_NONCONN_IND as u8) << TYPE_SHIFT)
                     // txadd bit
-                    | (match self.adv_a { TxRxAdvAddress::Public(..) => 0, _ => 1 } << TXADD_SHIFT);
+                    | ((self.adv_a.is_random() as u8) << TXADD_SHIFT);
         pdu_size += 1;
         
         // skip a byte for length (will be set at end)
         pdu_size += 1;
 
         // write the AdvA
-        pdu_size += self.adv_a.write_address(&mut buffer[pdu_size..(pdu_size+6)]);
+        pdu_size += self.adv_a.write(&mut buffer[pdu_size..(pdu_size+6)]);
 
         // append the adv_data
-        pdu_size += self.adv_data.write(&mut buffer[pdu_size..]);
+        let adv_data = &self.adv_data[..self.adv_data.len().min(ADV_DATA_SIZE_MAX)];
+        buffer[pdu_size..(pdu_size + adv_data.len())].copy_from_slice(adv_data);
+        pdu_size += adv_data.len();
 
         // set the length
         const PDU_HEADER_SIZE:usize = 2;
@@ -191,10 +272,263 @@ impl<'a> AdvNonConnIndPdu<'a> {
 
         &buffer[0..pdu_size]
     }
+
+    /// the AdvA and AdvData of a received ADV_NONCONN_IND
+    pub(crate) fn read(pdu: &[u8]) -> Option<(AdvA, &AdvData)> {
+        const TXADD_SHIFT:usize = 6;
+        const PDU_HEADER_SIZE:usize = 2;
+        let length = pdu[1] as usize;
+        if ! matches!(PDU_TYPE::of(pdu), Some(PDU_TYPE::ADV_NONCONN_IND)) || ! (ADDRESS_LEN..=ADV_PDU_SIZE_MAX).contains(&length) {
+            return None;
+        }
+        let adv_a = DeviceAddress::read((pdu[0] >> TXADD_SHIFT) & 1 == 1, &pdu[PDU_HEADER_SIZE..]);
+        return Some((adv_a, &pdu[(PDU_HEADER_SIZE + ADDRESS_LEN)..(PDU_HEADER_SIZE + length)]));
+    }
 }
 
 // TODO pub struct AdvScanIndPdu
-// TODO pub struct AdvExtIndPdu
+/// Core_v5.3 Vol 6, Part B, 2.3.4 (AdvMode)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AdvMode {
+    NonConnectableNonScannable  = 0b00,
+    Connectable                 = 0b01,
+    Scannable                   = 0b10,
+}
+
+/// advertising data info - Core_v5.3 Vol 6, Part B, 2.3.4.5
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Adi {
+    /// advertising data ID (12 bits)
+    pub did: u16,
+    /// advertising set ID (4 bits)
+    pub sid: u8,
+}
+
+/// the extended header of the common extended advertising payload (ADV_EXT_IND, AUX_ADV_IND,
+/// AUX_SYNC_IND, AUX_CHAIN_IND, ...) - Core_v5.3 Vol 6, Part B, 2.3.4
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ExtendedHeader<'a> {
+    pub adv_mode: AdvMode,
+    pub adv_a: Option<AdvA>,
+    pub target_a: Option<DeviceAddress>,
+    /// the PDU is followed by a constant tone extension
+    pub cte_info: Option<CteInfo>,
+    pub adi: Option<Adi>,
+    /// the channel, clock accuracy, offset and PHY of the auxiliary packet (Core_v5.3 Vol 6, Part B, 2.3.4.5)
+    pub aux_ptr: Option<[u8; 3]>,
+    /// the periodic advertising train (Core_v5.3 Vol 6, Part B, 2.3.4.6)
+    pub sync_info: Option<[u8; 18]>,
+    /// tx power (dBm)
+    pub tx_power: Option<i8>,
+    /// additional controller advertising data (AD structures, i.e. BIGInfo see BigInfo::read_acad)
+    pub acad: &'a [u8],
+}
+impl<'a> ExtendedHeader<'a> {
+    /// the extended header length is a 6 bit field
+    const SIZE_MAX:usize = 63;
+    const ADV_A_FLAG:u8 = 1 << 0;
+    const TARGET_A_FLAG:u8 = 1 << 1;
+    const CTE_INFO_FLAG:u8 = 1 << 2;
+    const ADI_FLAG:u8 = 1 << 3;
+    const AUX_PTR_FLAG:u8 = 1 << 4;
+    const SYNC_INFO_FLAG:u8 = 1 << 5;
+    const TX_POWER_FLAG:u8 = 1 << 6;
+    const ADV_MODE_SHIFT:usize = 6;
+
+    pub fn new(adv_mode: AdvMode) -> Self {
+        Self {
+            adv_mode,
+            adv_a: None,
+            target_a: None,
+            cte_info: None,
+            adi: None,
+            aux_ptr: None,
+            sync_info: None,
+            tx_power: None,
+            acad: &[],
+        }
+    }
+
+    /// the size of the extended header length and AdvMode octet and of the extended header
+    pub fn size(&self) -> usize {
+        let fields = self.adv_a.map_or(0, |_| ADDRESS_LEN)
+                     + self.target_a.map_or(0, |_| ADDRESS_LEN)
+                     + self.cte_info.map_or(0, |_| 1)
+                     + self.adi.map_or(0, |_| 2)
+                     + self.aux_ptr.map_or(0, |_| 3)
+                     + self.sync_info.map_or(0, |_| 18)
+                     + self.tx_power.map_or(0, |_| 1)
+                     + self.acad.len();
+        // the extended header flags are omitted along with all fields
+        return match fields {
+            0 => 1,
+            fields => 2 + fields,
+        };
+    }
+
+    /// returns the size (None if the extended header exceeds 63 octets or the buffer)
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        let size = self.size();
+        if size - 1 > Self::SIZE_MAX || buffer.len() < size {
+            return None;
+        }
+        buffer[0] = ((size - 1) as u8) | ((self.adv_mode as u8) << Self::ADV_MODE_SHIFT);
+        if size == 1 {
+            return Some(size);
+        }
+        let mut flags = 0;
+        let mut offset = 2;
+        match self.adv_a {
+            Some(adv_a) => {
+                flags |= Self::ADV_A_FLAG;
+                offset += adv_a.write(&mut buffer[offset..]);
+            }
+            None => {}
+        }
+        match self.target_a {
+            Some(target_a) => {
+                flags |= Self::TARGET_A_FLAG;
+                offset += target_a.write(&mut buffer[offset..]);
+            }
+            None => {}
+        }
+        match self.cte_info {
+            Some(cte_info) => {
+                flags |= Self::CTE_INFO_FLAG;
+                buffer[offset] = cte_info.octet();
+                offset += 1;
+            }
+            None => {}
+        }
+        match self.adi {
+            Some(adi) => {
+                flags |= Self::ADI_FLAG;
+                let adi = (adi.did & 0x0FFF) | ((adi.sid as u16 & 0x0F) << 12);
+                buffer[offset..(offset + 2)].copy_from_slice(&adi.to_le_bytes());
+                offset += 2;
+            }
+            None => {}
+        }
+        match self.aux_ptr {
+            Some(aux_ptr) => {
+                flags |= Self::AUX_PTR_FLAG;
+                buffer[offset..(offset + 3)].copy_from_slice(&aux_ptr);
+                offset += 3;
+            }
+            None => {}
+        }
+        match self.sync_info {
+            Some(sync_info) => {
+                flags |= Self::SYNC_INFO_FLAG;
+                buffer[offset..(offset + 18)].copy_from_slice(&sync_info);
+                offset += 18;
+            }
+            None => {}
+        }
+        match self.tx_power {
+            Some(tx_power) => {
+                flags |= Self::TX_POWER_FLAG;
+                buffer[offset] = tx_power as u8;
+                offset += 1;
+            }
+            None => {}
+        }
+        buffer[offset..size].copy_from_slice(self.acad);
+        buffer[1] = flags;
+        return Some(size);
+    }
+
+    /// parse the extended header at the start of the payload per the TxAdd and RxAdd bits, returns it
+    /// with its size (None if malformed)
+    pub fn read(tx_add: bool, rx_add: bool, payload: &'a [u8]) -> Option<(Self, usize)> {
+        let first = *payload.first()?;
+        let size = 1 + (first as usize & Self::SIZE_MAX);
+        let adv_mode = match first >> Self::ADV_MODE_SHIFT {
+            0b00 => AdvMode::NonConnectableNonScannable,
+            0b01 => AdvMode::Connectable,
+            0b10 => AdvMode::Scannable,
+            _ => return None,
+        };
+        if payload.len() < size {
+            return None;
+        }
+        let mut header = Self::new(adv_mode);
+        if size == 1 {
+            return Some((header, size));
+        }
+        let flags = payload[1];
+        let mut offset = 2;
+        // the size of each present field (None if it exceeds the extended header)
+        let mut field = |flag:u8, length:usize| -> Option<Option<&'a [u8]>> {
+            if flags & flag == 0 {
+                return Some(None);
+            }
+            if offset + length > size {
+                return None;
+            }
+            offset += length;
+            return Some(Some(&payload[(offset - length)..offset]));
+        };
+        header.adv_a = field(Self::ADV_A_FLAG, ADDRESS_LEN)?.map(|bytes| DeviceAddress::read(tx_add, bytes));
+        header.target_a = field(Self::TARGET_A_FLAG, ADDRESS_LEN)?.map(|bytes| DeviceAddress::read(rx_add, bytes));
+        header.cte_info = match field(Self::CTE_INFO_FLAG, 1)? {
+            Some(bytes) => Some(CteInfo::from_octet(bytes[0])?),
+            None => None,
+        };
+        header.adi = field(Self::ADI_FLAG, 2)?.map(|bytes| {
+            let adi = u16::from_le_bytes([bytes[0], bytes[1]]);
+            Adi { did: adi & 0x0FFF, sid: (adi >> 12) as u8 }
+        });
+        header.aux_ptr = field(Self::AUX_PTR_FLAG, 3)?.map(|bytes| [bytes[0], bytes[1], bytes[2]]);
+        header.sync_info = field(Self::SYNC_INFO_FLAG, 18)?.map(|bytes| {
+            let mut sync_info = [0; 18];
+            sync_info.copy_from_slice(bytes);
+            sync_info
+        });
+        header.tx_power = field(Self::TX_POWER_FLAG, 1)?.map(|bytes| bytes[0] as i8);
+        header.acad = &payload[offset..size];
+        return Some((header, size));
+    }
+}
+
+/// ADV_EXT_IND and the auxiliary PDUs sharing its PDU type (AUX_ADV_IND, AUX_SYNC_IND, AUX_CHAIN_IND)
+/// Core_v5.3 Vol 6, Part B, 2.3.4
+pub struct AdvExtIndPdu<'a> {
+    pub header: ExtendedHeader<'a>,
+    pub adv_data: &'a AdvData,
+}
+impl<'a> AdvExtIndPdu<'a> {
+    const TXADD_SHIFT:usize = 6;
+    const RXADD_SHIFT:usize = 7;
+    const PDU_HEADER_SIZE:usize = 2;
+
+    /// returns the used slice of the destination buffer (None if the PDU doesn't fit)
+    pub fn write(&self, buffer: &'a mut [u8]) -> Option<&'a [u8]> {
+        let payload_size = self.header.size() + self.adv_data.len();
+        let pdu_size = Self::PDU_HEADER_SIZE + payload_size;
+        if payload_size > u8::MAX as usize || buffer.len() < pdu_size {
+            return None;
+        }
+        buffer[0] = (PDU_TYPE::ADV_EXT_IND as u8)
+                    | ((self.header.adv_a.is_some_and(|adv_a| adv_a.is_random()) as u8) << Self::TXADD_SHIFT)
+                    | ((self.header.target_a.is_some_and(|target_a| target_a.is_random()) as u8) << Self::RXADD_SHIFT);
+        buffer[1] = payload_size as u8;
+        let header_size = self.header.write(&mut buffer[Self::PDU_HEADER_SIZE..])?;
+        buffer[(Self::PDU_HEADER_SIZE + header_size)..pdu_size].copy_from_slice(self.adv_data);
+        return Some(&buffer[..pdu_size]);
+    }
+
+    pub fn read(pdu: &'a [u8]) -> Option<Self> {
+        if pdu.len() < Self::PDU_HEADER_SIZE || ! matches!(PDU_TYPE::of(pdu), Some(PDU_TYPE::ADV_EXT_IND)) {
+            return None;
+        }
+        let payload = pdu.get(Self::PDU_HEADER_SIZE..(Self::PDU_HEADER_SIZE + pdu[1] as usize))?;
+        let (header, size) = ExtendedHeader::read((pdu[0] >> Self::TXADD_SHIFT) & 1 == 1,
+                                                  (pdu[0] >> Self::RXADD_SHIFT) & 1 == 1,
+                                                  payload)?;
+        return Some(Self { header, adv_data: &payload[size..] });
+    }
+}
 
 pub struct ScanReqPdu<'a> {
     pub scan_a: &'a ScanA,
@@ -208,21 +542,23 @@ impl<'a> ScanReqPdu<'a> {
 
         const TYPE_SHIFT:usize = 0;
         const TXADD_SHIFT:usize = 6;
+        const RXADD_SHIFT:usize = 7;
         buffer[0] = // base pdu type
                     ((PDU_TYPE::SCAN_REQ as u8) << TYPE_SHIFT)
                     // txadd bit
-                    | (match self.adv_a { TxRxAdvAddress::Public(..) => 0, _ => 1 } << TXADD_SHIFT);
-                    // TODO set the RXADD bit per the AdvA::advertiser address
+                    | ((self.scan_a.is_random() as u8) << TXADD_SHIFT)
+                    // rxadd bit
+                    | ((self.adv_a.is_random() as u8) << RXADD_SHIFT);
         pdu_size += 1;
         
         // skip a byte for length (will be set at end)
         pdu_size += 1;
 
         // append the ScanA
-        pdu_size += self.scan_a.write_address(&mut buffer[pdu_size..(pdu_size+6)]);
+        pdu_size += self.scan_a.write(&mut buffer[pdu_size..(pdu_size+6)]);
 
         // append the AdvA
-        pdu_size += self.adv_a.write_address(&mut buffer[pdu_size..(pdu_size+6)]);
+        pdu_size += self.adv_a.write(&mut buffer[pdu_size..(pdu_size+6)]);
 
         // set the length
         const PDU_HEADER_SIZE:usize = 2;
@@ -235,7 +571,7 @@ impl<'a> ScanReqPdu<'a> {
 
 pub struct ScanRspPdu<'a> {
     pub adv_a: &'a AdvA,
-    pub scan_rsp_data: &'a AdvData<'a>,
+    pub scan_rsp_data: &'a AdvData,
 }
 impl<'a> ScanRspPdu<'a> {
     /// returns the used slice of the destination buffer
@@ -247,17 +583,19 @@ impl<'a> ScanRspPdu<'a> {
         buffer[0] = // base pdu type
                     ((PDU_TYPE::SCAN_RSP as u8) << TYPE_SHIFT)
                     // txadd bit
-                    | (match self.adv_a { TxRxAdvAddress::Public(..) => 0, _ => 1 } << TXADD_SHIFT);
+                    | ((self.adv_a.is_random() as u8) << TXADD_SHIFT);
         pdu_size += 1;
         
         // skip a byte for length (will be set at end)
         pdu_size += 1;
 
         // write the AdvA
-        pdu_size += self.adv_a.write_address(&mut buffer[pdu_size..]);
+        pdu_size += self.adv_a.write(&mut buffer[pdu_size..]);
 
         // append the adv_data
-        pdu_size += self.scan_rsp_data.write(&mut buffer[pdu_size..]);
+        let scan_rsp_data = &self.scan_rsp_data[..self.scan_rsp_data.len().min(ADV_DATA_SIZE_MAX)];
+        buffer[pdu_size..(pdu_size + scan_rsp_data.len())].copy_from_slice(scan_rsp_data);
+        pdu_size += scan_rsp_data.len();
 
         // set the length
         const PDU_HEADER_SIZE:usize = 2;
@@ -267,9 +605,757 @@ impl<'a> ScanRspPdu<'a> {
     }
 }
 
-// TODO pub struct ConnectIndPdu
+/// Core_v5.3 Vol 6, Part B, 2.3.3.1
+pub struct ConnectIndPdu {
+    pub init_a: DeviceAddress,
+    pub adv_a: DeviceAddress,
+    pub ll_data: LlData,
+}
+/// connection parameters of CONNECT_IND
+pub struct LlData {
+    pub access_address: AccessAddress,
+    pub crc_init: CrcInit,
+    /// transmit window size (1.25ms units)
+    pub win_size: u8,
+    /// transmit window offset (1.25ms units)
+    pub win_offset: u16,
+    /// connection interval (1.25ms units)
+    pub interval: u16,
+    pub latency: u16,
+    /// supervision timeout (10ms units)
+    pub timeout: u16,
+    /// 37 bit bitmap of used data channels
+    pub channel_map: u64,
+    /// hop increment (5 - 16)
+    pub hop: u8,
+    /// sleep clock accuracy
+    pub sca: u8,
+}
+impl ConnectIndPdu {
+    /// parse a received CONNECT_IND
+    pub(crate) fn read(pdu: &[u8]) -> Option<Self> {
+        const PAYLOAD_SIZE:usize = 34;
+        const TXADD_SHIFT:usize = 6;
+        const RXADD_SHIFT:usize = 7;
+        if pdu.len() < (2 + PAYLOAD_SIZE) || (pdu[1] as usize) != PAYLOAD_SIZE {
+            return None;
+        }
+        let ll_data = &pdu[14..];
+        let mut channel_map = [0; 8];
+        channel_map[..5].copy_from_slice(&ll_data[16..21]);
+        Some(Self {
+            init_a: DeviceAddress::read((pdu[0] >> TXADD_SHIFT) & 1 == 1, &pdu[2..]),
+            adv_a: DeviceAddress::read((pdu[0] >> RXADD_SHIFT) & 1 == 1, &pdu[8..]),
+            ll_data: LlData {
+                access_address: u32::from_le_bytes([ll_data[0], ll_data[1], ll_data[2], ll_data[3]]),
+                crc_init: u32::from_le_bytes([ll_data[4], ll_data[5], ll_data[6], 0]),
+                win_size: ll_data[7],
+                win_offset: u16::from_le_bytes([ll_data[8], ll_data[9]]),
+                interval: u16::from_le_bytes([ll_data[10], ll_data[11]]),
+                latency: u16::from_le_bytes([ll_data[12], ll_data[13]]),
+                timeout: u16::from_le_bytes([ll_data[14], ll_data[15]]),
+                channel_map: u64::from_le_bytes(channel_map),
+                hop: ll_data[21] & 0b11111,
+                sca: ll_data[21] >> 5,
+            }
+        })
+    }
+}
+
 // TODO pub struct AuxConnectRspPdu
 
+#[derive(TryFromPrimitive)]
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// Core_v5.3 Vol 6, Part B, 2.5.1
+pub enum CteType {
+    /// angle of arrival (the receiver switches antennas)
+    AoA             = 0,
+    /// angle of departure with 1us switching slots (the transmitter switches antennas)
+    AoD1us          = 1,
+    /// angle of departure with 2us switching slots
+    AoD2us          = 2,
+}
+impl CteType {
+    /// the bit of the type in a set of CTE types (i.e. see Ble::set_cte_response)
+    pub const fn bit(self) -> u8 {
+        return 1 << (self as u8);
+    }
+}
+
+/// the constant tone extension following a PDU - Core_v5.3 Vol 6, Part B, 2.5.1
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CteInfo {
+    /// length of the CTE (8us units, CTE_TIME_MIN - CTE_TIME_MAX)
+    pub time: u8,
+    pub cte_type: CteType,
+}
+/// the shortest CTE (16us)
+pub const CTE_TIME_MIN:u8 = 2;
+/// the longest CTE (160us)
+pub const CTE_TIME_MAX:u8 = 20;
+impl CteInfo {
+    const TYPE_SHIFT:usize = 6;
+
+    /// CTETime in the low 5 bits, CTEType in the high 2 bits
+    pub fn octet(&self) -> u8 {
+        return (self.time & 0x1F) | ((self.cte_type as u8) << Self::TYPE_SHIFT);
+    }
+
+    /// None for a reserved type or an invalid time
+    pub fn from_octet(octet: u8) -> Option<Self> {
+        let time = octet & 0x1F;
+        if ! (CTE_TIME_MIN..=CTE_TIME_MAX).contains(&time) {
+            return None;
+        }
+        return CteType::try_from(octet >> Self::TYPE_SHIFT).ok().map(|cte_type| Self { time, cte_type });
+    }
+}
+
+/// the most IQ samples of a CTE (8 reference samples and 74 sample slots) - Core_v5.3 Vol 4, Part E, 7.7.65.22
+pub const IQ_SAMPLES_MAX:usize = 82;
+
+/// an IQ sample of a CTE
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct IqSample {
+    pub i: i16,
+    pub q: i16,
+}
+
+/// the IQ samples of a received CTE, in order of the antenna switching pattern
+#[derive(Clone, PartialEq, Debug)]
+pub struct IqReport {
+    pub channel: Channel,
+    pub cte_info: CteInfo,
+    pub samples: heapless::Vec<IqSample, IQ_SAMPLES_MAX>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// Core_v5.3 Vol 6, Part B, 2.4
+pub enum Llid {
+    /// continuation fragment of an L2CAP message (or an empty PDU)
+    Continuation    = 0b01,
+    /// start of an L2CAP message (or a complete message)
+    Start           = 0b10,
+    /// LL control PDU
+    Control         = 0b11,
+}
+
+/// Core_v5.3 Vol 6, Part B, 2.4
+pub struct DataPduHeader {
+    pub llid: Llid,
+    /// next expected sequence number
+    pub nesn: bool,
+    /// sequence number
+    pub sn: bool,
+    /// more data
+    pub md: bool,
+    pub length: u8,
+    /// the CTEInfo octet (present if the CP bit is set) of a PDU followed by a constant tone extension
+    pub cte_info: Option<CteInfo>,
+}
+impl DataPduHeader {
+    /// the size without the CTEInfo octet
+    pub const SIZE:usize = 2;
+    const NESN_SHIFT:usize = 2;
+    const SN_SHIFT:usize = 3;
+    const MD_SHIFT:usize = 4;
+    const CP_SHIFT:usize = 5;
+
+    /// the size of the header, the payload follows it
+    pub fn size(&self) -> usize {
+        return Self::SIZE + self.cte_info.map_or(0, |_| 1);
+    }
+
+    /// parse the header of a received data channel PDU
+    pub(crate) fn read(pdu: &[u8]) -> Option<Self> {
+        const LLID_MASK:u8 = 0b11;
+        Some(Self {
+            llid: match pdu[0] & LLID_MASK {
+                0b01 => Llid::Continuation,
+                0b10 => Llid::Start,
+                0b11 => Llid::Control,
+                _ => return None,
+            },
+            nesn: (pdu[0] >> Self::NESN_SHIFT) & 1 == 1,
+            sn: (pdu[0] >> Self::SN_SHIFT) & 1 == 1,
+            md: (pdu[0] >> Self::MD_SHIFT) & 1 == 1,
+            length: pdu[1],
+            cte_info: match (pdu[0] >> Self::CP_SHIFT) & 1 == 1 {
+                true => Some(CteInfo::from_octet(pdu[Self::SIZE])?),
+                false => None,
+            },
+        })
+    }
+
+    /// returns the size of the header
+    pub(crate) fn write(&self, buffer: &mut [u8]) -> usize {
+        buffer[0] = (self.llid as u8)
+                    | ((self.nesn as u8) << Self::NESN_SHIFT)
+                    | ((self.sn as u8) << Self::SN_SHIFT)
+                    | ((self.md as u8) << Self::MD_SHIFT)
+                    | ((self.cte_info.is_some() as u8) << Self::CP_SHIFT);
+        buffer[1] = self.length;
+        match self.cte_info {
+            Some(cte_info) => buffer[Self::SIZE] = cte_info.octet(),
+            None => {}
+        }
+        return self.size();
+    }
+}
+
+#[derive(Debug, TryFromPrimitive, Copy, Clone, PartialEq)]
+#[repr(u8)]
+#[allow(non_camel_case_types)]
+/// Core_v5.3 Vol 6, Part B, 2.4.2
+pub enum ControlOpcode {
+    LL_CONNECTION_UPDATE_IND    = 0x00,
+    LL_CHANNEL_MAP_IND          = 0x01,
+    LL_TERMINATE_IND            = 0x02,
+    LL_ENC_REQ                  = 0x03,
+    LL_ENC_RSP                  = 0x04,
+    LL_START_ENC_REQ            = 0x05,
+    LL_START_ENC_RSP            = 0x06,
+    LL_UNKNOWN_RSP              = 0x07,
+    LL_FEATURE_REQ              = 0x08,
+    LL_FEATURE_RSP              = 0x09,
+    LL_PAUSE_ENC_REQ            = 0x0A,
+    LL_PAUSE_ENC_RSP            = 0x0B,
+    LL_VERSION_IND              = 0x0C,
+    LL_REJECT_IND               = 0x0D,
+    LL_PERIPHERAL_FEATURE_REQ   = 0x0E,
+    LL_CONNECTION_PARAM_REQ     = 0x0F,
+    LL_CONNECTION_PARAM_RSP     = 0x10,
+    LL_REJECT_EXT_IND           = 0x11,
+    LL_PING_REQ                 = 0x12,
+    LL_PING_RSP                 = 0x13,
+    LL_LENGTH_REQ               = 0x14,
+    LL_LENGTH_RSP               = 0x15,
+    LL_PHY_REQ                  = 0x16,
+    LL_PHY_RSP                  = 0x17,
+    LL_PHY_UPDATE_IND           = 0x18,
+    LL_MIN_USED_CHANNELS_IND    = 0x19,
+    LL_CTE_REQ                  = 0x1A,
+    LL_CTE_RSP                  = 0x1B,
+}
+
+/// number of data channels (CH0 - CH36)
+pub const DATA_CHANNEL_COUNT:u8 = 37;
+
+/// Core_v5.3 Vol 6, Part B, 4.5.8.2 (Channel Selection Algorithm #1)
+pub fn select_data_channel(unmapped_channel: u8, channel_map: u64) -> Channel {
+    let used = |channel:u8| (channel_map >> channel) & 1 == 1;
+    let channel = if used(unmapped_channel) {
+        unmapped_channel
+    } else {
+        // remap onto the used channels
+        let used_count = (0..DATA_CHANNEL_COUNT).filter(|c| used(*c)).count() as u8;
+        let remap_index = unmapped_channel % used_count;
+        (0..DATA_CHANNEL_COUNT).filter(|c| used(*c)).nth(remap_index as usize).unwrap()
+    };
+    return Channel::try_from(channel).unwrap();
+}
+
+#[derive(TryFromPrimitive)]
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// Core_v5.3 Vol 6, Part B, 2.3.4.8
+pub enum Phy {
+    Le1M    = 0,
+    Le2M    = 1,
+    LeCoded = 2,
+}
+
+/// encryption parameters of an encrypted BIG
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BigEncryption {
+    /// group initialization vector
+    pub giv: [u8; 8],
+    /// group session key diversifier
+    pub gskd: [u8; 16],
+}
+
+/// parameters of a BIG, sent in the ACAD of periodic advertising (see AdFields::biginfo)
+/// Core_v5.3 Vol 6, Part B, 2.3.4.8
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BigInfo {
+    /// time (in offset units) from the start of the periodic advertising packet to the next BIG anchor point (14 bits)
+    pub big_offset: u16,
+    /// false for 30us, true for 300us offset units
+    pub big_offset_units: bool,
+    /// ISO interval (1.25ms units, 12 bits)
+    pub iso_interval: u16,
+    /// number of BISes (1 - 31)
+    pub num_bis: u8,
+    /// number of subevents per BIS in each BIG event (1 - 31)
+    pub nse: u8,
+    /// burst number (1 - 7)
+    pub bn: u8,
+    /// time between subevents of a BIS (us, 20 bits)
+    pub sub_interval: u32,
+    /// pre-transmission offset (0 - 15)
+    pub pto: u8,
+    /// time between the subevents of adjacent BISes (us, 20 bits)
+    pub bis_spacing: u32,
+    /// immediate repetition count (1 - 15)
+    pub irc: u8,
+    /// maximum payload of BIS data PDUs
+    pub max_pdu: u8,
+    /// the access addresses of the BISes and of the BIG control logical transport derive from it
+    pub seed_access_address: AccessAddress,
+    /// time between SDUs (us, 20 bits)
+    pub sdu_interval: u32,
+    /// maximum SDU size (12 bits)
+    pub max_sdu: u16,
+    /// upper 16 bits of the CRC initialization value of the BISes
+    pub base_crc_init: u16,
+    /// 37 bit bitmap of used data channels
+    pub channel_map: u64,
+    pub phy: Phy,
+    /// payload counter of the first BIS data PDU of the next BIG event (39 bits)
+    pub bis_payload_count: u64,
+    /// BIS data PDUs are framed
+    pub framed: bool,
+    /// present if the BIG is encrypted
+    pub encryption: Option<BigEncryption>,
+}
+impl BigInfo {
+    /// the size of an unencrypted BIGInfo
+    pub const SIZE:usize = 33;
+    /// the size of an encrypted BIGInfo
+    pub const ENCRYPTED_SIZE:usize = 57;
+
+    pub fn size(&self) -> usize {
+        return match self.encryption {
+            Some(_) => Self::ENCRYPTED_SIZE,
+            None => Self::SIZE,
+        };
+    }
+
+    /// returns the size of the BIGInfo (the buffer must hold it)
+    pub fn write(&self, buffer: &mut [u8]) -> usize {
+        // fields are packed LSB first
+        let word0 = (self.big_offset as u32 & 0x3FFF)
+                    | ((self.big_offset_units as u32) << 14)
+                    | ((self.iso_interval as u32 & 0xFFF) << 15)
+                    | ((self.num_bis as u32 & 0x1F) << 27);
+        let word1 = (self.nse as u32 & 0x1F)
+                    | ((self.bn as u32 & 0x07) << 5)
+                    | ((self.sub_interval & 0xFFFFF) << 8)
+                    | ((self.pto as u32 & 0x0F) << 28);
+        let word2 = (self.bis_spacing & 0xFFFFF)
+                    | ((self.irc as u32 & 0x0F) << 20)
+                    | ((self.max_pdu as u32) << 24);
+        let word4 = (self.sdu_interval & 0xFFFFF) | ((self.max_sdu as u32 & 0xFFF) << 20);
+        let channel_map = (self.channel_map & 0x1F_FFFF_FFFF) | ((self.phy as u64) << 37);
+        let payload_count = (self.bis_payload_count & 0x7F_FFFF_FFFF) | ((self.framed as u64) << 39);
+
+        buffer[0..4].copy_from_slice(&word0.to_le_bytes());
+        buffer[4..8].copy_from_slice(&word1.to_le_bytes());
+        buffer[8..12].copy_from_slice(&word2.to_le_bytes());
+        // RFU
+        buffer[12] = 0;
+        buffer[13..17].copy_from_slice(&self.seed_access_address.to_le_bytes());
+        buffer[17..21].copy_from_slice(&word4.to_le_bytes());
+        buffer[21..23].copy_from_slice(&self.base_crc_init.to_le_bytes());
+        buffer[23..28].copy_from_slice(&channel_map.to_le_bytes()[..5]);
+        buffer[28..33].copy_from_slice(&payload_count.to_le_bytes()[..5]);
+        match self.encryption {
+            Some(encryption) => {
+                buffer[33..41].copy_from_slice(&encryption.giv);
+                buffer[41..57].copy_from_slice(&encryption.gskd);
+            }
+            None => {}
+        }
+        return self.size();
+    }
+
+    /// parse the BIGInfo (the payload of the ad structure, its size tells whether the BIG is encrypted)
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::SIZE && data.len() != Self::ENCRYPTED_SIZE {
+            return None;
+        }
+        let word = |index:usize| u32::from_le_bytes([data[index], data[index + 1], data[index + 2], data[index + 3]]);
+        let forty_bits = |index:usize| {
+            let mut bytes = [0; 8];
+            bytes[..5].copy_from_slice(&data[index..(index + 5)]);
+            u64::from_le_bytes(bytes)
+        };
+        let (word0, word1, word2, word4) = (word(0), word(4), word(8), word(17));
+        let channel_map = forty_bits(23);
+        let payload_count = forty_bits(28);
+        let encryption = match data.len() {
+            Self::ENCRYPTED_SIZE => {
+                let mut encryption = BigEncryption { giv: [0; 8], gskd: [0; 16] };
+                encryption.giv.copy_from_slice(&data[33..41]);
+                encryption.gskd.copy_from_slice(&data[41..57]);
+                Some(encryption)
+            }
+            _ => None,
+        };
+        Some(Self {
+            big_offset: (word0 & 0x3FFF) as u16,
+            big_offset_units: (word0 >> 14) & 1 == 1,
+            iso_interval: ((word0 >> 15) & 0xFFF) as u16,
+            num_bis: (word0 >> 27) as u8,
+            nse: (word1 & 0x1F) as u8,
+            bn: ((word1 >> 5) & 0x07) as u8,
+            sub_interval: (word1 >> 8) & 0xFFFFF,
+            pto: (word1 >> 28) as u8,
+            bis_spacing: word2 & 0xFFFFF,
+            irc: ((word2 >> 20) & 0x0F) as u8,
+            max_pdu: (word2 >> 24) as u8,
+            seed_access_address: word(13),
+            sdu_interval: word4 & 0xFFFFF,
+            max_sdu: (word4 >> 20) as u16,
+            base_crc_init: u16::from_le_bytes([data[21], data[22]]),
+            channel_map: channel_map & 0x1F_FFFF_FFFF,
+            phy: Phy::try_from(((channel_map >> 37) & 0x07) as u8).ok()?,
+            bis_payload_count: payload_count & 0x7F_FFFF_FFFF,
+            framed: (payload_count >> 39) & 1 == 1,
+            encryption,
+        })
+    }
+
+    /// the BIGInfo in the ACAD (Additional Controller Advertising Data) of an AUX_SYNC_IND
+    pub fn read_acad(acad: &[u8]) -> Option<Self> {
+        return crate::gap::ad_structures(acad)
+            .find(|(ad_type, _)| *ad_type == crate::gap::DataTypes::BIGInfo as u8)
+            .and_then(|(_, data)| Self::read(data));
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// Core_v5.3 Vol 6, Part B, 2.6
+pub enum BisLlid {
+    /// unframed BIS data PDU, the end fragment of an SDU or a complete SDU
+    UnframedEnd     = 0b00,
+    /// unframed BIS data PDU, the start or a continuation fragment of an SDU
+    UnframedStart   = 0b01,
+    /// framed BIS data PDU (segments of SDUs, see IsoSegment)
+    Framed          = 0b10,
+    /// BIG control PDU
+    Control         = 0b11,
+}
+
+/// the header of BIS PDUs (data PDUs of BISes and control PDUs of the BIG control logical transport)
+/// Core_v5.3 Vol 6, Part B, 2.6
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BisPduHeader {
+    pub llid: BisLlid,
+    /// control subevent sequence number (3 bits)
+    pub cssn: u8,
+    /// control subevent transmission flag (a BIG control PDU follows in this BIG event)
+    pub cstf: bool,
+    pub length: u8,
+}
+impl BisPduHeader {
+    pub const SIZE:usize = 2;
+    const CSSN_SHIFT:usize = 2;
+    const CSTF_SHIFT:usize = 5;
+
+    pub fn read(pdu: &[u8]) -> Option<Self> {
+        if pdu.len() < Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            llid: match pdu[0] & 0b11 {
+                0b00 => BisLlid::UnframedEnd,
+                0b01 => BisLlid::UnframedStart,
+                0b10 => BisLlid::Framed,
+                _ => BisLlid::Control,
+            },
+            cssn: (pdu[0] >> Self::CSSN_SHIFT) & 0b111,
+            cstf: (pdu[0] >> Self::CSTF_SHIFT) & 1 == 1,
+            length: pdu[1],
+        })
+    }
+
+    /// returns the size of the header
+    pub fn write(&self, buffer: &mut [u8]) -> usize {
+        buffer[0] = (self.llid as u8)
+                    | ((self.cssn & 0b111) << Self::CSSN_SHIFT)
+                    | ((self.cstf as u8) << Self::CSTF_SHIFT);
+        buffer[1] = self.length;
+        return Self::SIZE;
+    }
+}
+
+/// a segment (of an SDU) in the payload of a framed BIS data PDU - Core_v5.3 Vol 6, Part G, 6.2
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct IsoSegment<'a> {
+    /// time offset (us, 24 bits) from the reference anchor point to the SDU's synchronization reference,
+    /// present in the first segment of an SDU only (None for continuation segments)
+    pub time_offset: Option<u32>,
+    /// the last segment of the SDU
+    pub complete: bool,
+    pub data: &'a [u8],
+}
+impl<'a> IsoSegment<'a> {
+    const HEADER_SIZE:usize = 2;
+    const TIME_OFFSET_SIZE:usize = 3;
+    const SC_SHIFT:usize = 0;
+    const CMPLT_SHIFT:usize = 1;
+
+    pub fn size(&self) -> usize {
+        return Self::HEADER_SIZE + self.time_offset.map_or(0, |_| Self::TIME_OFFSET_SIZE) + self.data.len();
+    }
+
+    /// returns the size of the segment (None if it doesn't fit)
+    pub fn write(&self, buffer: &mut [u8]) -> Option<usize> {
+        let size = self.size();
+        if buffer.len() < size || size - Self::HEADER_SIZE > u8::MAX as usize {
+            return None;
+        }
+        buffer[0] = ((self.time_offset.is_none() as u8) << Self::SC_SHIFT) | ((self.complete as u8) << Self::CMPLT_SHIFT);
+        buffer[1] = (size - Self::HEADER_SIZE) as u8;
+        let mut offset = Self::HEADER_SIZE;
+        match self.time_offset {
+            Some(time_offset) => {
+                buffer[offset..(offset + Self::TIME_OFFSET_SIZE)].copy_from_slice(&time_offset.to_le_bytes()[..3]);
+                offset += Self::TIME_OFFSET_SIZE;
+            }
+            None => {}
+        }
+        buffer[offset..size].copy_from_slice(self.data);
+        return Some(size);
+    }
+
+    /// the segments of the payload of a framed BIS data PDU, up to the first malformed one
+    pub fn read(payload: &'a [u8]) -> impl Iterator<Item = IsoSegment<'a>> {
+        let mut rest = payload;
+        return core::iter::from_fn(move || {
+            if rest.len() < Self::HEADER_SIZE {
+                return None;
+            }
+            let start = (rest[0] >> Self::SC_SHIFT) & 1 == 0;
+            let length = rest[1] as usize;
+            if rest.len() < Self::HEADER_SIZE + length || (start && length < Self::TIME_OFFSET_SIZE) {
+                return None;
+            }
+            let mut data = &rest[Self::HEADER_SIZE..(Self::HEADER_SIZE + length)];
+            let time_offset = match start {
+                true => {
+                    let time_offset = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+                    data = &data[Self::TIME_OFFSET_SIZE..];
+                    Some(time_offset)
+                }
+                false => None,
+            };
+            let segment = IsoSegment { time_offset, complete: (rest[0] >> Self::CMPLT_SHIFT) & 1 == 1, data };
+            rest = &rest[(Self::HEADER_SIZE + length)..];
+            return Some(segment);
+        });
+    }
+}
+
+/// BIG control PDUs (the payload of BIS PDUs with BisLlid::Control) - Core_v5.3 Vol 6, Part B, 2.6.1
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BigControlPdu {
+    /// the channel map (37 bits) in use from the BIG event counter instant
+    ChannelMapInd { channel_map: u64, instant: u16 },
+    /// the BIG ends at the BIG event counter instant
+    TerminateInd { reason: u8, instant: u16 },
+}
+impl BigControlPdu {
+    const BIG_CHANNEL_MAP_IND:u8 = 0x00;
+    const BIG_TERMINATE_IND:u8 = 0x01;
+
+    /// returns the size of the payload (the buffer must hold 8 octets)
+    pub fn write(&self, buffer: &mut [u8]) -> usize {
+        return match *self {
+            BigControlPdu::ChannelMapInd { channel_map, instant } => {
+                buffer[0] = Self::BIG_CHANNEL_MAP_IND;
+                buffer[1..6].copy_from_slice(&(channel_map & 0x1F_FFFF_FFFF).to_le_bytes()[..5]);
+                buffer[6..8].copy_from_slice(&instant.to_le_bytes());
+                8
+            }
+            BigControlPdu::TerminateInd { reason, instant } => {
+                buffer[0] = Self::BIG_TERMINATE_IND;
+                buffer[1] = reason;
+                buffer[2..4].copy_from_slice(&instant.to_le_bytes());
+                4
+            }
+        };
+    }
+
+    /// parse the payload of a BIG control PDU
+    pub fn read(payload: &[u8]) -> Option<Self> {
+        return match (payload.first()?, payload.len()) {
+            (&Self::BIG_CHANNEL_MAP_IND, 8) => {
+                let mut channel_map = [0; 8];
+                channel_map[..5].copy_from_slice(&payload[1..6]);
+                Some(BigControlPdu::ChannelMapInd {
+                    channel_map: u64::from_le_bytes(channel_map) & 0x1F_FFFF_FFFF,
+                    instant: u16::from_le_bytes([payload[6], payload[7]]),
+                })
+            }
+            (&Self::BIG_TERMINATE_IND, 4) => Some(BigControlPdu::TerminateInd {
+                reason: payload[1],
+                instant: u16::from_le_bytes([payload[2], payload[3]]),
+            }),
+            _ => None,
+        };
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod device_address {
+    use super::*;
+
+    #[test]
+    fn kinds() {
+        assert_eq!(DeviceAddress::random([1, 2, 3, 4, 5, 0xC6]).kind, AddressKind::RandomStatic);
+        assert_eq!(DeviceAddress::random([1, 2, 3, 4, 5, 0x46]).kind, AddressKind::ResolvablePrivate);
+        assert_eq!(DeviceAddress::random([1, 2, 3, 4, 5, 0x06]).kind, AddressKind::NonResolvablePrivate);
+
+        assert!(DeviceAddress::new(AddressKind::Public, [0; 6]).is_some());
+        assert!(DeviceAddress::new(AddressKind::RandomStatic, [1, 2, 3, 4, 5, 0xC6]).is_some());
+        // sub-type bits of another kind
+        assert!(DeviceAddress::new(AddressKind::RandomStatic, [1, 2, 3, 4, 5, 0x46]).is_none());
+        assert!(DeviceAddress::new(AddressKind::NonResolvablePrivate, [1, 2, 3, 4, 5, 0x86]).is_none());
+        // random bits of all zeros or all ones
+        assert!(DeviceAddress::new(AddressKind::RandomStatic, [0xFF; 6]).is_none());
+        assert!(DeviceAddress::new(AddressKind::NonResolvablePrivate, [0; 6]).is_none());
+        assert!(DeviceAddress::new(AddressKind::ResolvablePrivate, [1, 2, 3, 0, 0, 0x40]).is_none());
+        assert!(DeviceAddress::new(AddressKind::ResolvablePrivate, [0, 0, 0, 1, 0, 0x40]).is_some());
+
+        assert_eq!(format!("{}", DeviceAddress::public([0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA])), "AA:BB:CC:DD:EE:FF");
+    }
+
+    #[test]
+    fn tx_rx_add() {
+        let public = DeviceAddress::public([1, 2, 3, 4, 5, 6]);
+        let random = DeviceAddress::random([1, 2, 3, 4, 5, 0xC6]);
+        let mut buffer = [0; PDU_SIZE_MAX];
+        assert_eq!(0x40, AdvIndPdu{ ch_sel: ChSel::Unsupported, adv_a: &random, adv_data: &[] }.write(&mut buffer)[0]);
+        assert_eq!(0x02, AdvNonConnIndPdu{ adv_a: &public, adv_data: &[] }.write(&mut buffer)[0]);
+        AdvNonConnIndPdu{ adv_a: &random, adv_data: &[0x02, 0x2B, 0x01] }.write(&mut buffer);
+        assert_eq!(Some((random, &[0x02, 0x2B, 0x01][..])), AdvNonConnIndPdu::read(&buffer));
+        assert_eq!(0x44, ScanRspPdu{ adv_a: &random, scan_rsp_data: &[] }.write(&mut buffer)[0]);
+        assert_eq!(0x43, ScanReqPdu{ scan_a: &random, adv_a: &public }.write(&mut buffer)[0]);
+        assert_eq!(0x83, ScanReqPdu{ scan_a: &public, adv_a: &random }.write(&mut buffer)[0]);
+
+        let mut pdu = [0; 36];
+        pdu[0] = 0x05 | 0x40;
+        pdu[1] = 34;
+        pdu[2..8].copy_from_slice(&[1, 2, 3, 4, 5, 0x46]);
+        pdu[8..14].copy_from_slice(&public.bytes);
+        let connect = ConnectIndPdu::read(&pdu).unwrap();
+        assert_eq!(connect.init_a.kind, AddressKind::ResolvablePrivate);
+        assert_eq!(connect.adv_a, public);
+    }
+}
+#[cfg(test)]
+mod constant_tone_extension {
+    use super::*;
+
+    #[test]
+    fn cte_info() {
+        let cte_info = CteInfo { time: 20, cte_type: CteType::AoD1us };
+        assert_eq!(0x54, cte_info.octet());
+        assert_eq!(Some(cte_info), CteInfo::from_octet(0x54));
+        // reserved type, too short
+        assert_eq!(None, CteInfo::from_octet(0xC4));
+        assert_eq!(None, CteInfo::from_octet(0x01));
+
+        let header = DataPduHeader { llid: Llid::Control, nesn: false, sn: true, md: false, length: 1, cte_info: Some(cte_info) };
+        let mut buffer = [0; 4];
+        assert_eq!(3, header.write(&mut buffer));
+        assert_eq!([0x2B, 1, 0x54], buffer[..3]);
+        let read = DataPduHeader::read(&buffer).unwrap();
+        assert_eq!((Some(cte_info), 3), (read.cte_info, read.size()));
+    }
+
+    #[test]
+    fn extended_header() {
+        let adv_a = DeviceAddress::random([1, 2, 3, 4, 5, 0xC6]);
+        let acad = [0x02, 0x01, 0x06];
+        let header = ExtendedHeader {
+            adv_a: Some(adv_a),
+            cte_info: Some(CteInfo { time: 2, cte_type: CteType::AoA }),
+            adi: Some(Adi { did: 0x123, sid: 4 }),
+            tx_power: Some(-8),
+            acad: &acad,
+            ..ExtendedHeader::new(AdvMode::NonConnectableNonScannable)
+        };
+        let mut buffer = [0; PDU_SIZE_MAX];
+        let pdu = AdvExtIndPdu { header, adv_data: &[0x02, 0x0A, 0x00] }.write(&mut buffer).unwrap();
+        // TxAdd, extended header length and flags, AdvA, CTEInfo, ADI and TxPower
+        assert_eq!([0x47, 18, 14, 0x4D], pdu[..4]);
+        assert_eq!([0x02, 0x23, 0x41, 0xF8], pdu[10..14]);
+        let read = AdvExtIndPdu::read(pdu).unwrap();
+        assert_eq!(header, read.header);
+        assert_eq!([0x02, 0x0A, 0x00], read.adv_data);
+
+        // no extended header fields
+        let pdu = AdvExtIndPdu { header: ExtendedHeader::new(AdvMode::Connectable), adv_data: &[] }.write(&mut buffer).unwrap();
+        assert_eq!([0x07, 1, 0x40], pdu);
+        // a field beyond the extended header
+        assert!(AdvExtIndPdu::read(&[0x07, 3, 0x02, 0x01, 0x00]).is_none());
+    }
+}
+#[cfg(test)]
+mod broadcast_isochronous {
+    use super::*;
+
+    #[test]
+    fn biginfo() {
+        let mut biginfo = BigInfo {
+            big_offset: 0x123, big_offset_units: false, iso_interval: 8, num_bis: 2, nse: 4, bn: 2,
+            sub_interval: 2500, pto: 1, bis_spacing: 5000, irc: 2, max_pdu: 40, seed_access_address: 0x12345678,
+            sdu_interval: 10000, max_sdu: 40, base_crc_init: 0xABCD, channel_map: 0x1F_FFFF_FFFF,
+            phy: Phy::LeCoded, bis_payload_count: 0x7F_0000_0001, framed: true, encryption: None,
+        };
+        let mut buffer = [0; BigInfo::ENCRYPTED_SIZE];
+        assert_eq!(BigInfo::SIZE, biginfo.write(&mut buffer));
+        // BIG_Offset, BIG_Offset_Units, ISO_Interval and Num_BIS
+        assert_eq!([0x23, 0x01, 0x04, 0x10], buffer[..4]);
+        // ChM and PHY, bisPayloadCount and Framing
+        assert_eq!([0xFF, 0xFF, 0xFF, 0xFF, 0x5F], buffer[23..28]);
+        assert_eq!([0x01, 0x00, 0x00, 0x00, 0xFF], buffer[28..33]);
+        assert_eq!(Some(biginfo), BigInfo::read(&buffer[..BigInfo::SIZE]));
+
+        biginfo.encryption = Some(BigEncryption { giv: [1; 8], gskd: [2; 16] });
+        assert_eq!(BigInfo::ENCRYPTED_SIZE, biginfo.write(&mut buffer));
+        assert_eq!(Some(biginfo), BigInfo::read(&buffer));
+        assert_eq!(None, BigInfo::read(&buffer[..40]));
+    }
+
+    #[test]
+    fn pdus() {
+        let header = BisPduHeader { llid: BisLlid::Framed, cssn: 5, cstf: true, length: 12 };
+        let mut buffer = [0; 32];
+        assert_eq!(2, header.write(&mut buffer));
+        assert_eq!([0x36, 12], buffer[..2]);
+        assert_eq!(Some(header), BisPduHeader::read(&buffer));
+
+        // an SDU split across two segments
+        let first = IsoSegment { time_offset: Some(0x010203), complete: false, data: &[1, 2, 3] };
+        let last = IsoSegment { time_offset: None, complete: true, data: &[4, 5] };
+        let size = first.write(&mut buffer).unwrap();
+        assert_eq!([0x00, 6, 0x03, 0x02, 0x01], buffer[..5]);
+        let size = size + last.write(&mut buffer[size..]).unwrap();
+        assert_eq!(8 + 4, size);
+        let segments: Vec<IsoSegment> = IsoSegment::read(&buffer[..size]).collect();
+        assert_eq!(vec![first, last], segments);
+        assert_eq!(None, first.write(&mut buffer[..7]));
+
+        let channel_map = BigControlPdu::ChannelMapInd { channel_map: 0x1F_0000_FFFF, instant: 0x1234 };
+        assert_eq!(8, channel_map.write(&mut buffer));
+        assert_eq!([0x00, 0xFF, 0xFF, 0x00, 0x00, 0x1F, 0x34, 0x12], buffer[..8]);
+        assert_eq!(Some(channel_map), BigControlPdu::read(&buffer[..8]));
+        let terminate = BigControlPdu::TerminateInd { reason: 0x13, instant: 7 };
+        assert_eq!(4, terminate.write(&mut buffer));
+        assert_eq!(Some(terminate), BigControlPdu::read(&buffer[..4]));
+        assert_eq!(None, BigControlPdu::read(&buffer[..3]));
+    }
+}
+
 
 
 // archived code (to be deleted)
@@ -301,33 +1387,6 @@ impl<'a> ScanRspPdu<'a> {
 //     pub txpower: Option<i8>
 // }
 
-// /// Core_v5.3-5.pdf#G41.685952
-// pub enum AuxAdvMode {
-//     NonConnectableNonScannable = 0b00,
-//     ConnectableNonScannable = 0b01,
-//     NonConnectableScannable = 0b100,
-// }
-
-// #[derive(Copy,Clone)]
-// /// Core_v5.3-5.pdf#G41.1317593
-// pub struct CteInfo {
-//     time: u8,   /* 5 bits */
-//     cte_type: CteType,
-// }
-
-// #[derive(Copy,Clone)]
-// enum CteType {
-//     // AoAConstantToneExtension = 0,
-//     // AoAConstantToneExtensionWith1uSlots = 1,
-//     // AoAConstantToneExtensionWith2uSlots = 2,
-// }
-// /// Core_v5.3-5.pdf#G41.693403
-// #[derive(Copy,Clone)]
-// pub struct Adi {
-//     pub did: u16,   /* 12 bits data ID */
-//     pub sid: u8,    /* 4 bits set ID */
-// }
-
 // #[derive(Copy,Clone)]
 // /// Core_v5.3-5.pdf#G41.693502
 // pub struct AuxPtr {
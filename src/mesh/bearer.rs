@@ -0,0 +1,260 @@
+use crate::gap::{self, AdvertisingData};
+use crate::link_layer::ADV_DATA_SIZE_MAX;
+
+/// the largest mesh PDU (a single AD structure filling the advertising data)
+pub const PDU_SIZE_MAX:usize = ADV_DATA_SIZE_MAX - 2;
+/// PDUs awaiting (re)transmission
+pub const TRANSMIT_QUEUE_SIZE:usize = 8;
+/// received PDUs awaiting AdvBearer::receive
+pub const RECEIVE_QUEUE_SIZE:usize = 4;
+/// the random delay added to each advertising event (advDelay) - Core_v5.3 Vol 6, Part B, 4.4.2.2.1
+const ADV_DELAY_MAX_MS:u32 = 10;
+
+/// the AD types of the advertising bearer - Mesh Profile v1.0.1, 3.3.1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AdType {
+    /// provisioning bearer (PB-ADV)
+    PbAdv = 0x29,
+    MeshMessage = 0x2A,
+    MeshBeacon = 0x2B,
+}
+impl AdType {
+    fn of(ad_type: u8) -> Option<Self> {
+        return match ad_type {
+            0x29 => Some(AdType::PbAdv),
+            0x2A => Some(AdType::MeshMessage),
+            0x2B => Some(AdType::MeshBeacon),
+            _ => None,
+        };
+    }
+}
+
+/// a PDU of the advertising bearer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pdu {
+    pub ad_type: AdType,
+    pub data: heapless::Vec<u8, PDU_SIZE_MAX>,
+}
+impl Pdu {
+    /// None if the data exceeds PDU_SIZE_MAX
+    pub fn new(ad_type: AdType, data: &[u8]) -> Option<Self> {
+        return heapless::Vec::from_slice(data).ok().map(|data| Self { ad_type, data });
+    }
+
+    /// the advertising data of the PDU (its only AD structure)
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut bytes = [0; ADV_DATA_SIZE_MAX];
+        bytes[0] = (1 + self.data.len()) as u8;
+        bytes[1] = self.ad_type as u8;
+        bytes[2..(2 + self.data.len())].copy_from_slice(&self.data);
+        return AdvertisingData::from_bytes(&bytes[..(2 + self.data.len())]).unwrap();
+    }
+}
+
+/// the mesh PDUs of received advertising data (AD structures of other types are ignored)
+pub fn read(adv_data: &[u8]) -> impl Iterator<Item = Pdu> + '_ {
+    return gap::ad_structures(adv_data)
+        .filter_map(|(ad_type, data)| Pdu::new(AdType::of(ad_type)?, data));
+}
+
+/// the transmissions of a PDU, per the Network Transmit and Relay Retransmit states
+/// Mesh Profile v1.0.1, 4.2.19 and 4.2.20
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transmit {
+    /// transmissions following the first (0 to 7)
+    pub count: u8,
+    /// the interval between transmissions is (interval_steps + 1) * 10ms (0 to 31)
+    pub interval_steps: u8,
+}
+impl Transmit {
+    /// the state (count in the low 3 bits, interval steps in the high 5 bits)
+    pub const fn from_state(state: u8) -> Self {
+        return Self { count: state & 0x07, interval_steps: state >> 3 };
+    }
+
+    pub const fn state(&self) -> u8 {
+        return (self.count & 0x07) | (self.interval_steps << 3);
+    }
+
+    pub const fn interval_ms(&self) -> u32 {
+        return ((self.interval_steps & 0x1F) as u32 + 1) * 10;
+    }
+}
+
+/// reasons a PDU wasn't queued
+#[derive(Debug, PartialEq)]
+pub enum BearerError {
+    /// the transmit queue is full (retry later)
+    Busy,
+}
+
+/// a queued PDU and its remaining transmissions
+struct Transmission {
+    pdu: Pdu,
+    /// transmissions after the next one
+    remaining: u8,
+    interval_ms: u32,
+    /// time (ms) of the next transmission
+    due_ms: u32,
+}
+
+/// whether the time (ms) has reached the deadline (wrapping)
+fn reached(now_ms: u32, deadline_ms: u32) -> bool {
+    return (now_ms.wrapping_sub(deadline_ms) as i32) >= 0;
+}
+
+/// the advertising bearer, which sends each PDU in non-connectable advertising events on the three
+/// advertising channels (see Ble::mesh_advertise) and queues the mesh PDUs received while scanning
+/// Mesh Profile v1.0.1, 3.3.1
+pub struct AdvBearer {
+    network_transmit: Transmit,
+    relay_retransmit: Transmit,
+    transmissions: heapless::Vec<Transmission, TRANSMIT_QUEUE_SIZE>,
+    received: heapless::Deque<Pdu, RECEIVE_QUEUE_SIZE>,
+}
+impl AdvBearer {
+    pub fn new(network_transmit: Transmit, relay_retransmit: Transmit) -> Self {
+        Self {
+            network_transmit,
+            relay_retransmit,
+            transmissions: heapless::Vec::new(),
+            received: heapless::Deque::new(),
+        }
+    }
+
+    pub fn set_network_transmit(&mut self, transmit: Transmit) { self.network_transmit = transmit; }
+
+    pub fn set_relay_retransmit(&mut self, transmit: Transmit) { self.relay_retransmit = transmit; }
+
+    /// queue a PDU originating from this node (per the Network Transmit state), the first transmission
+    /// follows a random delay of random % 11 ms
+    pub fn send(&mut self, pdu: Pdu, now_ms: u32, random: u8) -> Result<(), BearerError> {
+        let transmit = self.network_transmit;
+        return self.queue(pdu, transmit, now_ms, random);
+    }
+
+    /// queue a received PDU for relaying (per the Relay Retransmit state), the first transmission follows
+    /// a random delay of random % 11 ms
+    pub fn relay(&mut self, pdu: Pdu, now_ms: u32, random: u8) -> Result<(), BearerError> {
+        let transmit = self.relay_retransmit;
+        return self.queue(pdu, transmit, now_ms, random);
+    }
+
+    fn queue(&mut self, pdu: Pdu, transmit: Transmit, now_ms: u32, random: u8) -> Result<(), BearerError> {
+        let transmission = Transmission {
+            pdu,
+            remaining: transmit.count & 0x07,
+            interval_ms: transmit.interval_ms(),
+            due_ms: now_ms.wrapping_add(random as u32 % (ADV_DELAY_MAX_MS + 1)),
+        };
+        return self.transmissions.push(transmission).map_err(|_| BearerError::Busy);
+    }
+
+    /// time (ms) of the next transmission (None while nothing is queued)
+    pub fn next_transmission(&self) -> Option<u32> {
+        let now_ms = self.transmissions.first()?.due_ms;
+        return self.transmissions.iter()
+            .map(|transmission| transmission.due_ms)
+            .min_by_key(|due_ms| due_ms.wrapping_sub(now_ms) as i32);
+    }
+
+    /// the PDU to transmit now (the most overdue, or the first queued of those equally overdue), rescheduled
+    /// after its interval plus a random delay of random % 11 ms, or removed following its last transmission
+    pub fn poll(&mut self, now_ms: u32, random: u8) -> Option<Pdu> {
+        let index = self.transmissions.iter()
+            .enumerate()
+            .filter(|(_, transmission)| reached(now_ms, transmission.due_ms))
+            .max_by_key(|(index, transmission)| (now_ms.wrapping_sub(transmission.due_ms), core::cmp::Reverse(*index)))
+            .map(|(index, _)| index)?;
+        let transmission = &mut self.transmissions[index];
+        if transmission.remaining == 0 {
+            return Some(self.transmissions.remove(index).pdu);
+        }
+        transmission.remaining -= 1;
+        transmission.due_ms = now_ms
+            .wrapping_add(transmission.interval_ms)
+            .wrapping_add(random as u32 % (ADV_DELAY_MAX_MS + 1));
+        return Some(transmission.pdu.clone());
+    }
+
+    /// queue the mesh PDUs of received advertising data, returns false if none were queued (PDUs are
+    /// dropped while the receive queue is full)
+    pub fn handle_advertisement(&mut self, adv_data: &[u8]) -> bool {
+        let mut queued = false;
+        for pdu in read(adv_data) {
+            queued |= self.received.push_back(pdu).is_ok();
+        }
+        return queued;
+    }
+
+    /// the oldest received PDU
+    pub fn receive(&mut self) -> Option<Pdu> {
+        return self.received.pop_front();
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod advertising_bearer {
+    use super::*;
+
+    #[test]
+    fn pdus() {
+        let pdu = Pdu::new(AdType::MeshBeacon, &[0x00, 0x01, 0x02]).unwrap();
+        let data = pdu.advertising_data();
+        assert_eq!([0x04, 0x2B, 0x00, 0x01, 0x02], data.as_bytes());
+        assert!(Pdu::new(AdType::MeshMessage, &[0; PDU_SIZE_MAX]).is_some());
+        assert!(Pdu::new(AdType::MeshMessage, &[0; PDU_SIZE_MAX + 1]).is_none());
+
+        // other AD types are ignored
+        let adv_data = [0x02, 0x01, 0x06, 0x03, 0x2A, 0xAA, 0xBB, 0x02, 0x29, 0xCC];
+        let pdus: Vec<Pdu> = read(&adv_data).collect();
+        assert_eq!(vec![Pdu::new(AdType::MeshMessage, &[0xAA, 0xBB]).unwrap(), Pdu::new(AdType::PbAdv, &[0xCC]).unwrap()], pdus);
+
+        let mut bearer = AdvBearer::new(Transmit::from_state(0), Transmit::from_state(0));
+        assert!(! bearer.handle_advertisement(&[0x02, 0x01, 0x06]));
+        assert!(bearer.handle_advertisement(&adv_data));
+        assert_eq!(Some(AdType::MeshMessage), bearer.receive().map(|pdu| pdu.ad_type));
+        assert_eq!(Some(AdType::PbAdv), bearer.receive().map(|pdu| pdu.ad_type));
+        assert_eq!(None, bearer.receive());
+    }
+
+    #[test]
+    fn transmissions() {
+        let transmit = Transmit { count: 2, interval_steps: 1 };
+        assert_eq!(transmit, Transmit::from_state(transmit.state()));
+        assert_eq!(0x0A, transmit.state());
+        assert_eq!(20, transmit.interval_ms());
+
+        let mut bearer = AdvBearer::new(transmit, Transmit { count: 0, interval_steps: 0 });
+        let message = Pdu::new(AdType::MeshMessage, &[1]).unwrap();
+        bearer.send(message.clone(), 1000, 5).unwrap();
+        assert_eq!(Some(1005), bearer.next_transmission());
+        assert_eq!(None, bearer.poll(1004, 0));
+        // the first and 2 retransmissions at the interval plus a random delay
+        assert_eq!(Some(message.clone()), bearer.poll(1005, 13));
+        assert_eq!(Some(1005 + 20 + 2), bearer.next_transmission());
+        assert_eq!(None, bearer.poll(1026, 0));
+        assert_eq!(Some(message.clone()), bearer.poll(1027, 0));
+        assert_eq!(Some(message.clone()), bearer.poll(1047, 0));
+        assert_eq!(None, bearer.next_transmission());
+        assert_eq!(None, bearer.poll(2000, 0));
+
+        // relayed PDUs per the Relay Retransmit state, the most overdue first
+        let relayed = Pdu::new(AdType::MeshMessage, &[2]).unwrap();
+        bearer.send(message.clone(), u32::MAX - 5, 0).unwrap();
+        bearer.relay(relayed.clone(), u32::MAX - 1, 10).unwrap();
+        assert_eq!(Some(u32::MAX - 5), bearer.next_transmission());
+        assert_eq!(Some(message.clone()), bearer.poll(10, 0));
+        assert_eq!(Some(relayed), bearer.poll(10, 0));
+        assert_eq!(Some(30), bearer.next_transmission());
+
+        for _ in 0..(TRANSMIT_QUEUE_SIZE - 1) {
+            bearer.send(message.clone(), 0, 0).unwrap();
+        }
+        assert_eq!(Err(BearerError::Busy), bearer.send(message, 0, 0));
+    }
+}
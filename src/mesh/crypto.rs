@@ -0,0 +1,132 @@
+use aes::Aes128;
+use aes::cipher::generic_array::GenericArray;
+use ccm::{AeadInPlace, KeyInit};
+use ccm::consts::{U4, U8, U13};
+use crate::smp::aes_cmac;
+
+type Aes128Ccm32 = ccm::Ccm<Aes128, U4, U13>;
+type Aes128Ccm64 = ccm::Ccm<Aes128, U8, U13>;
+
+/// salt generation function s1 - Mesh Profile v1.0.1, 3.8.2.4
+pub(crate) fn s1(m: &[u8]) -> [u8; 16] {
+    return aes_cmac(&[0; 16], &[m]);
+}
+
+/// key derivation function k1 - Mesh Profile v1.0.1, 3.8.2.5
+pub(crate) fn k1(n: &[u8], salt: &[u8; 16], p: &[u8]) -> [u8; 16] {
+    let t = aes_cmac(salt, &[n]);
+    return aes_cmac(&t, &[p]);
+}
+
+/// network key material derivation function k2, returns the NID, EncryptionKey and PrivacyKey
+/// Mesh Profile v1.0.1, 3.8.2.6
+pub(crate) fn k2(n: &[u8; 16], p: &[u8]) -> (u8, [u8; 16], [u8; 16]) {
+    let t = aes_cmac(&s1(b"smk2"), &[n]);
+    let t1 = aes_cmac(&t, &[p, &[0x01]]);
+    let t2 = aes_cmac(&t, &[&t1, p, &[0x02]]);
+    let t3 = aes_cmac(&t, &[&t2, p, &[0x03]]);
+    return (t1[15] & 0x7F, t2, t3);
+}
+
+/// derivation function k3 (of the Network ID) - Mesh Profile v1.0.1, 3.8.2.7
+pub(crate) fn k3(n: &[u8; 16]) -> [u8; 8] {
+    let t = aes_cmac(&s1(b"smk3"), &[n]);
+    let mut id = [0; 8];
+    id.copy_from_slice(&aes_cmac(&t, &[b"id64", &[0x01]])[8..]);
+    return id;
+}
+
+/// derivation function k4 (of the AID for the upper transport layer) - Mesh Profile v1.0.1, 3.8.2.8
+#[allow(unused)]
+pub(crate) fn k4(n: &[u8; 16]) -> u8 {
+    let t = aes_cmac(&s1(b"smk4"), &[n]);
+    return aes_cmac(&t, &[b"id6", &[0x01]])[15] & 0x3F;
+}
+
+/// AES-CCM encryption (in place) appending a MIC of 4 or 8 octets, returns the resulting length
+/// Mesh Profile v1.0.1, 3.8.2.3
+pub(crate) fn ccm_encrypt(key: &[u8; 16], nonce: &[u8; 13], payload: &mut [u8], length: usize, mic_size: usize) -> usize {
+    let nonce = GenericArray::from_slice(nonce);
+    match mic_size {
+        8 => {
+            let mic = Aes128Ccm64::new(GenericArray::from_slice(key))
+                .encrypt_in_place_detached(nonce, &[], &mut payload[..length]).unwrap();
+            payload[length..(length + 8)].copy_from_slice(&mic);
+        }
+        _ => {
+            let mic = Aes128Ccm32::new(GenericArray::from_slice(key))
+                .encrypt_in_place_detached(nonce, &[], &mut payload[..length]).unwrap();
+            payload[length..(length + 4)].copy_from_slice(&mic);
+        }
+    }
+    return length + mic_size;
+}
+
+/// AES-CCM decryption (in place) of a payload ending with a MIC of 4 or 8 octets, returns the length
+/// without the MIC (None if the MIC doesn't match)
+pub(crate) fn ccm_decrypt(key: &[u8; 16], nonce: &[u8; 13], payload: &mut [u8], length: usize, mic_size: usize) -> Option<usize> {
+    let length = length.checked_sub(mic_size)?;
+    let nonce = GenericArray::from_slice(nonce);
+    let (payload, mic) = payload[..(length + mic_size)].split_at_mut(length);
+    let result = match mic_size {
+        8 => Aes128Ccm64::new(GenericArray::from_slice(key))
+            .decrypt_in_place_detached(nonce, &[], payload, GenericArray::from_slice(mic)),
+        _ => Aes128Ccm32::new(GenericArray::from_slice(key))
+            .decrypt_in_place_detached(nonce, &[], payload, GenericArray::from_slice(mic)),
+    };
+    return result.ok().map(|_| length);
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod mesh_crypto {
+    use super::*;
+
+    fn hex<const N: usize>(text: &str) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[(2 * index)..(2 * index + 2)], 16).unwrap();
+        }
+        return bytes;
+    }
+
+    // Mesh Profile v1.0.1, 8.1
+    #[test]
+    fn sample_data() {
+        assert_eq!(hex::<16>("b73cefbd641ef2ea598c2b6efb62f79c"), s1(b"test"));
+
+        let n = hex::<16>("3216d1509884b533248541792b877f98");
+        let salt = hex::<16>("2ba14ffa0df84a2831938d57d276cab4");
+        let p = hex::<16>("5a09d60797eeb4478aada59db3352a0d");
+        assert_eq!(hex::<16>("f6ed15a8934afbe7d83e8dcb57fcf5d7"), k1(&n, &salt, &p));
+
+        let (nid, encryption_key, privacy_key) = k2(&hex::<16>("f7a2a44f8e8a8029064f173ddc1e2b00"), &[0x00]);
+        assert_eq!(0x7F, nid);
+        assert_eq!(hex::<16>("9f589181a0f50de73c8070c7a6d27f46"), encryption_key);
+        assert_eq!(hex::<16>("4c715bd4a64b938f99b453351653124f"), privacy_key);
+
+        assert_eq!(hex::<8>("ff046958233db014"), k3(&hex::<16>("f7a2a44f8e8a8029064f173ddc1e2b00")));
+        assert_eq!(0x38, k4(&hex::<16>("3216d1509884b533248541792b877f98")));
+    }
+
+    #[test]
+    fn ccm() {
+        let key = [0x11; 16];
+        let nonce = [0x22; 13];
+        for mic_size in [4, 8] {
+            let mut payload = [0; 16];
+            payload[..5].copy_from_slice(b"hello");
+            let length = ccm_encrypt(&key, &nonce, &mut payload, 5, mic_size);
+            assert_eq!(5 + mic_size, length);
+            assert_ne!(b"hello", &payload[..5]);
+            let mut tampered = payload;
+            tampered[0] ^= 1;
+            assert_eq!(None, ccm_decrypt(&key, &nonce, &mut tampered, length, mic_size));
+            assert_eq!(Some(5), ccm_decrypt(&key, &nonce, &mut payload, length, mic_size));
+            assert_eq!(b"hello", &payload[..5]);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Bluetooth Mesh - Mesh Profile v1.0.1
+
+pub mod bearer;
+pub mod network;
+pub mod provisioning;
+mod crypto;
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod simulation {
+    use super::*;
+    use bearer::{AdvBearer, Transmit};
+    use provisioning::{Device, Provisioner, ProvisioningData, ProvisioningError, Outgoing};
+    use network::Network;
+
+    const UUID:[u8; 16] = [0xDE; 16];
+    const DATA:ProvisioningData = ProvisioningData {
+        net_key: [0x7D; 16],
+        key_index: 0,
+        flags: 0,
+        iv_index: 0x12345678,
+        address: 0x0002,
+    };
+    const RETRANSMIT_MS:u32 = 200;
+
+    /// a deterministic random source
+    fn random(seed: u8) -> impl FnMut(&mut [u8]) {
+        let mut state = seed;
+        return move |bytes: &mut [u8]| {
+            for byte in bytes {
+                state = state.wrapping_mul(97).wrapping_add(13);
+                *byte = state;
+            }
+        };
+    }
+
+    fn bearer() -> AdvBearer {
+        return AdvBearer::new(Transmit { count: 2, interval_steps: 1 }, Transmit { count: 1, interval_steps: 0 });
+    }
+
+    /// a bearer and the handler of the PDUs it receives
+    type Node<'a> = (&'a mut AdvBearer, &'a mut dyn FnMut(&bearer::Pdu, &mut Outgoing));
+
+    /// pass the advertisements of each node to the other (every ms) until both bearers are idle
+    fn exchange(nodes: &mut [Node<'_>; 2], now_ms: &mut u32) {
+        loop {
+            *now_ms += 1;
+            for (from, to) in [(0, 1), (1, 0)] {
+                if let Some(pdu) = nodes[from].0.poll(*now_ms, *now_ms as u8) {
+                    nodes[to].0.handle_advertisement(pdu.advertising_data().as_bytes());
+                }
+            }
+            for (bearer, handle) in nodes.iter_mut() {
+                while let Some(pdu) = bearer.receive() {
+                    let mut out = Outgoing::new();
+                    handle(&pdu, &mut out);
+                    for pdu in out {
+                        // dropped while busy (and retransmitted)
+                        let _ = bearer.send(pdu, *now_ms, 0);
+                    }
+                }
+            }
+            if nodes.iter().all(|(bearer, _)| bearer.next_transmission().is_none()) {
+                return;
+            }
+        }
+    }
+
+    /// provision the device with the provisioner over their advertising bearers
+    fn provision(device_oob: Option<[u8; 16]>, provisioner_oob: Option<[u8; 16]>) -> (Provisioner, Device) {
+        let mut device = Device::new(UUID, device_oob, &mut random(1));
+        let mut provisioner = Provisioner::new(UUID, DATA, provisioner_oob, &mut random(2));
+        let (mut provisioner_bearer, mut device_bearer) = (bearer(), bearer());
+        let mut now_ms = 0;
+        while !provisioner.is_complete() && provisioner.error().is_none() && now_ms < 10_000 {
+            let (mut provisioner_out, mut device_out) = (Outgoing::new(), Outgoing::new());
+            provisioner.retransmit(&mut provisioner_out);
+            device.retransmit(&mut device_out);
+            for pdu in provisioner_out {
+                let _ = provisioner_bearer.send(pdu, now_ms, 0);
+            }
+            for pdu in device_out {
+                let _ = device_bearer.send(pdu, now_ms, 0);
+            }
+            let start_ms = now_ms;
+            exchange(&mut [(&mut provisioner_bearer, &mut |pdu, out| provisioner.handle(pdu, out)),
+                           (&mut device_bearer, &mut |pdu, out| device.handle(pdu, out))], &mut now_ms);
+            now_ms = now_ms.max(start_ms + RETRANSMIT_MS);
+        }
+        return (provisioner, device);
+    }
+
+    #[test]
+    fn provisioning() {
+        let (provisioner, device) = provision(None, None);
+        assert!(provisioner.is_complete());
+        let (data, device_key) = device.provisioned().unwrap();
+        assert_eq!(DATA, *data);
+        assert_eq!(Some(device_key), provisioner.device_key());
+
+        // authenticated with the Static OOB value
+        let (provisioner, device) = provision(Some([0x55; 16]), Some([0x55; 16]));
+        assert!(provisioner.is_complete());
+        assert!(device.provisioned().is_some());
+        let (provisioner, device) = provision(Some([0x55; 16]), Some([0xAA; 16]));
+        assert_eq!(Some(ProvisioningError::ConfirmationFailed), provisioner.error());
+        assert!(device.provisioned().is_none());
+    }
+
+    #[test]
+    fn network() {
+        let (_, device) = provision(None, None);
+        let (data, _) = device.provisioned().unwrap();
+        let mut node = Network::new(&data.net_key, data.iv_index, data.address);
+        let mut provisioner = Network::new(&DATA.net_key, DATA.iv_index, 0x0001);
+        let (mut provisioner_bearer, mut node_bearer) = (bearer(), bearer());
+        let mut now_ms = 0;
+
+        provisioner_bearer.send(provisioner.send(false, 3, data.address, &[0x01, 0x02, 0x03]).unwrap(), now_ms, 0).unwrap();
+        let mut delivered = Vec::new();
+        let mut relayed = Vec::new();
+        exchange(&mut [(&mut provisioner_bearer, &mut |_, _| {}),
+                       (&mut node_bearer, &mut |pdu, _| {
+                           // the retransmissions are dropped by the network cache
+                           if let Some(received) = node.receive(&pdu.data) {
+                               if received.deliver {
+                                   delivered.push(received.pdu.transport_pdu.to_vec());
+                               }
+                               relayed.extend(received.relay);
+                           }
+                       })], &mut now_ms);
+        assert_eq!(vec![vec![0x01, 0x02, 0x03]], delivered);
+        assert!(relayed.is_empty());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::smp;
+use super::bearer::{self, AdType};
+use super::crypto;
+
+/// element addresses - Mesh Profile v1.0.1, 3.4.2
+pub type Address = u16;
+pub const UNASSIGNED_ADDRESS:Address = 0x0000;
+pub const ALL_RELAYS:Address = 0xFFFD;
+pub const ALL_NODES:Address = 0xFFFF;
+
+/// the largest sequence number (24 bits) - Mesh Profile v1.0.1, 3.4.4.6
+pub const SEQ_MAX:u32 = 0xFF_FFFF;
+/// the largest lower transport PDU (of an access message, control messages have a larger NetMIC)
+pub const TRANSPORT_PDU_SIZE_MAX:usize = bearer::PDU_SIZE_MAX - HEADER_SIZE - NET_MIC_SIZE_ACCESS;
+/// recently received PDUs, which are neither processed nor relayed again - Mesh Profile v1.0.1, 3.4.6.5
+pub const NETWORK_CACHE_SIZE:usize = 16;
+/// source addresses whose sequence numbers are tracked - Mesh Profile v1.0.1, 3.8.8
+pub const REPLAY_LIST_SIZE:usize = 8;
+
+/// IVI and NID, CTL and TTL, SEQ, SRC and DST
+const HEADER_SIZE:usize = 9;
+/// the obfuscated CTL, TTL, SEQ and SRC
+const OBFUSCATED_RANGE:core::ops::Range<usize> = 1..7;
+const NET_MIC_SIZE_ACCESS:usize = 4;
+const NET_MIC_SIZE_CONTROL:usize = 8;
+
+pub fn is_unicast(address: Address) -> bool {
+    return address != UNASSIGNED_ADDRESS && (address & 0x8000) == 0;
+}
+
+/// the keys of a network derived from its NetKey - Mesh Profile v1.0.1, 3.8.6.3
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NetworkKeys {
+    pub nid: u8,
+    pub encryption_key: [u8; 16],
+    pub privacy_key: [u8; 16],
+    pub network_id: [u8; 8],
+}
+impl NetworkKeys {
+    pub fn new(net_key: &[u8; 16]) -> Self {
+        let (nid, encryption_key, privacy_key) = crypto::k2(net_key, &[0x00]);
+        return Self { nid, encryption_key, privacy_key, network_id: crypto::k3(net_key) };
+    }
+}
+
+/// a network PDU (decrypted) - Mesh Profile v1.0.1, 3.4.4
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkPdu {
+    /// a control message (rather than an access message)
+    pub ctl: bool,
+    pub ttl: u8,
+    pub seq: u32,
+    pub src: Address,
+    pub dst: Address,
+    pub transport_pdu: heapless::Vec<u8, TRANSPORT_PDU_SIZE_MAX>,
+}
+impl NetworkPdu {
+    fn mic_size(ctl: bool) -> usize {
+        return if ctl { NET_MIC_SIZE_CONTROL } else { NET_MIC_SIZE_ACCESS };
+    }
+
+    /// Mesh Profile v1.0.1, 3.8.5.1
+    fn nonce(ctl_ttl: u8, seq_src: &[u8], iv_index: u32) -> [u8; 13] {
+        let mut nonce = [0; 13];
+        nonce[1] = ctl_ttl;
+        nonce[2..7].copy_from_slice(seq_src);
+        nonce[9..].copy_from_slice(&iv_index.to_be_bytes());
+        return nonce;
+    }
+
+    /// the PECB of the obfuscation - Mesh Profile v1.0.1, 3.8.7.3
+    fn pecb(keys: &NetworkKeys, iv_index: u32, encrypted: &[u8]) -> [u8; 16] {
+        let mut plaintext = [0; 16];
+        plaintext[5..9].copy_from_slice(&iv_index.to_be_bytes());
+        plaintext[9..].copy_from_slice(&encrypted[..7]);
+        return smp::e(&keys.privacy_key, &plaintext);
+    }
+
+    /// the PDU encrypted and obfuscated with the keys at the IV index (None if the transport PDU doesn't
+    /// fit a control message's NetMIC)
+    pub fn encrypt(&self, keys: &NetworkKeys, iv_index: u32) -> Option<bearer::Pdu> {
+        let mic_size = Self::mic_size(self.ctl);
+        let length = HEADER_SIZE + self.transport_pdu.len();
+        if length + mic_size > bearer::PDU_SIZE_MAX {
+            return None;
+        }
+        let mut pdu = [0; bearer::PDU_SIZE_MAX];
+        pdu[0] = ((iv_index as u8 & 1) << 7) | keys.nid;
+        pdu[1] = ((self.ctl as u8) << 7) | (self.ttl & 0x7F);
+        pdu[2..5].copy_from_slice(&self.seq.to_be_bytes()[1..]);
+        pdu[5..7].copy_from_slice(&self.src.to_be_bytes());
+        pdu[7..9].copy_from_slice(&self.dst.to_be_bytes());
+        pdu[HEADER_SIZE..length].copy_from_slice(&self.transport_pdu);
+
+        let nonce = Self::nonce(pdu[1], &pdu[2..7], iv_index);
+        let length = 7 + crypto::ccm_encrypt(&keys.encryption_key, &nonce, &mut pdu[7..], length - 7, mic_size);
+        let pecb = Self::pecb(keys, iv_index, &pdu[7..length]);
+        for (byte, mask) in pdu[OBFUSCATED_RANGE].iter_mut().zip(pecb.iter()) {
+            *byte ^= mask;
+        }
+        return bearer::Pdu::new(AdType::MeshMessage, &pdu[..length]);
+    }
+
+    /// deobfuscate and decrypt a PDU with the keys (of a matching NID) at the IV index
+    pub fn decrypt(data: &[u8], keys: &NetworkKeys, iv_index: u32) -> Option<Self> {
+        if data.len() < HEADER_SIZE + 1 + NET_MIC_SIZE_ACCESS || data.len() > bearer::PDU_SIZE_MAX
+            || (data[0] & 0x7F) != keys.nid || (data[0] >> 7) != (iv_index as u8 & 1) {
+            return None;
+        }
+        let mut pdu = [0; bearer::PDU_SIZE_MAX];
+        pdu[..data.len()].copy_from_slice(data);
+        let pecb = Self::pecb(keys, iv_index, &pdu[7..]);
+        for (byte, mask) in pdu[OBFUSCATED_RANGE].iter_mut().zip(pecb.iter()) {
+            *byte ^= mask;
+        }
+        let ctl = (pdu[1] >> 7) == 1;
+        let nonce = Self::nonce(pdu[1], &pdu[2..7], iv_index);
+        let length = 7 + crypto::ccm_decrypt(&keys.encryption_key, &nonce, &mut pdu[7..], data.len() - 7, Self::mic_size(ctl))?;
+        if length <= HEADER_SIZE {
+            return None;
+        }
+        return Some(Self {
+            ctl,
+            ttl: pdu[1] & 0x7F,
+            seq: u32::from_be_bytes([0, pdu[2], pdu[3], pdu[4]]),
+            src: u16::from_be_bytes([pdu[5], pdu[6]]),
+            dst: u16::from_be_bytes([pdu[7], pdu[8]]),
+            transport_pdu: heapless::Vec::from_slice(&pdu[HEADER_SIZE..length]).ok()?,
+        });
+    }
+}
+
+/// a PDU received by the network layer
+#[derive(Clone, Debug, PartialEq)]
+pub struct Received {
+    pub pdu: NetworkPdu,
+    /// addressed to this node (and not replayed) for the lower transport layer
+    pub deliver: bool,
+    /// the PDU to relay (with its TTL decremented, see bearer::AdvBearer::relay)
+    pub relay: Option<bearer::Pdu>,
+}
+
+/// the network layer of a node (with a single element) in a single subnet
+/// Mesh Profile v1.0.1, 3.4
+pub struct Network {
+    keys: NetworkKeys,
+    iv_index: u32,
+    /// the unicast address of the element
+    address: Address,
+    /// the sequence number of the next PDU
+    seq: u32,
+    relay: bool,
+    /// the SRC and SEQ of recently received PDUs
+    cache: heapless::Deque<(Address, u32), NETWORK_CACHE_SIZE>,
+    /// the IV index and last SEQ received from each source
+    replay: heapless::Vec<(Address, u32, u32), REPLAY_LIST_SIZE>,
+}
+impl Network {
+    /// join the network of the NetKey (e.g. per provisioning::ProvisioningData)
+    pub fn new(net_key: &[u8; 16], iv_index: u32, address: Address) -> Self {
+        Self {
+            keys: NetworkKeys::new(net_key),
+            iv_index,
+            address,
+            seq: 0,
+            relay: false,
+            cache: heapless::Deque::new(),
+            replay: heapless::Vec::new(),
+        }
+    }
+
+    pub fn keys(&self) -> &NetworkKeys { &self.keys }
+
+    pub fn address(&self) -> Address { self.address }
+
+    pub fn iv_index(&self) -> u32 { self.iv_index }
+
+    /// the sequence number of the next PDU
+    pub fn seq(&self) -> u32 { self.seq }
+
+    /// relay PDUs for other nodes (the Relay feature)
+    pub fn set_relay(&mut self, enabled: bool) {
+        self.relay = enabled;
+    }
+
+    /// move to a greater IV index (i.e. upon the IV Update procedure), which restarts the sequence numbers
+    /// Mesh Profile v1.0.1, 3.10.5
+    pub fn set_iv_index(&mut self, iv_index: u32) {
+        if iv_index > self.iv_index {
+            self.iv_index = iv_index;
+            self.seq = 0;
+        }
+    }
+
+    /// encrypt a lower transport PDU with the next sequence number, None if the sequence numbers are
+    /// exhausted (until the IV index is updated) or the transport PDU is too long
+    pub fn send(&mut self, ctl: bool, ttl: u8, dst: Address, transport_pdu: &[u8]) -> Option<bearer::Pdu> {
+        if self.seq > SEQ_MAX {
+            return None;
+        }
+        let pdu = NetworkPdu {
+            ctl,
+            ttl,
+            seq: self.seq,
+            src: self.address,
+            dst,
+            transport_pdu: heapless::Vec::from_slice(transport_pdu).ok()?,
+        };
+        let encrypted = pdu.encrypt(&self.keys, self.iv_index)?;
+        self.seq += 1;
+        // our own PDUs relayed back are discarded
+        self.cache(pdu.src, pdu.seq);
+        return Some(encrypted);
+    }
+
+    /// process a received Mesh Message, None if it isn't of this network or was recently received
+    /// Mesh Profile v1.0.1, 3.4.6.4
+    pub fn receive(&mut self, data: &[u8]) -> Option<Received> {
+        // the IVI selects the current IV index or the previous one (during the IV Update procedure)
+        let iv_index = match data.first() {
+            Some(byte) if (byte >> 7) == (self.iv_index as u8 & 1) => self.iv_index,
+            Some(_) => self.iv_index.checked_sub(1)?,
+            None => return None,
+        };
+        let pdu = NetworkPdu::decrypt(data, &self.keys, iv_index)?;
+        if !is_unicast(pdu.src) || pdu.dst == UNASSIGNED_ADDRESS || self.cache.iter().any(|&entry| entry == (pdu.src, pdu.seq)) {
+            return None;
+        }
+        self.cache(pdu.src, pdu.seq);
+
+        let local = pdu.dst == self.address || pdu.dst == ALL_NODES || (self.relay && pdu.dst == ALL_RELAYS);
+        let deliver = local && self.replay_check(pdu.src, iv_index, pdu.seq);
+        let relay = match self.relay && pdu.ttl >= 2 && pdu.dst != self.address {
+            true => NetworkPdu { ttl: pdu.ttl - 1, ..pdu.clone() }.encrypt(&self.keys, iv_index),
+            false => None,
+        };
+        return Some(Received { pdu, deliver, relay });
+    }
+
+    fn cache(&mut self, src: Address, seq: u32) {
+        if self.cache.is_full() {
+            self.cache.pop_front();
+        }
+        let _ = self.cache.push_back((src, seq));
+    }
+
+    /// whether the SEQ (at the IV index) follows those previously received from the source, which is then
+    /// recorded (PDUs of sources beyond REPLAY_LIST_SIZE are rejected) - Mesh Profile v1.0.1, 3.8.8
+    fn replay_check(&mut self, src: Address, iv_index: u32, seq: u32) -> bool {
+        match self.replay.iter_mut().find(|(address, _, _)| *address == src) {
+            Some(entry) => {
+                if iv_index < entry.1 || (iv_index == entry.1 && seq <= entry.2) {
+                    return false;
+                }
+                *entry = (src, iv_index, seq);
+                return true;
+            }
+            None => {}
+        }
+        return self.replay.push((src, iv_index, seq)).is_ok();
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod network_layer {
+    use super::*;
+    use core::convert::TryInto;
+
+    fn hex(text: &str) -> Vec<u8> {
+        return (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..(index + 2)], 16).unwrap()).collect();
+    }
+
+    // Mesh Profile v1.0.1, 8.3.1 (message #1)
+    #[test]
+    fn sample_data() {
+        let net_key: [u8; 16] = hex("7dd7364cd842ad18c17c2b820c84c3d6").try_into().unwrap();
+        let keys = NetworkKeys::new(&net_key);
+        assert_eq!(0x68, keys.nid);
+        assert_eq!(hex("0953fa93e7caac9638f58820220a398e"), keys.encryption_key);
+        assert_eq!(hex("8b84eedec100067d670971dd2aa700cf"), keys.privacy_key);
+
+        let pdu = NetworkPdu {
+            ctl: true,
+            ttl: 0,
+            seq: 1,
+            src: 0x1201,
+            dst: 0xFFFD,
+            transport_pdu: heapless::Vec::from_slice(&hex("034b50057e400000010000")).unwrap(),
+        };
+        let encrypted = pdu.encrypt(&keys, 0x12345678).unwrap();
+        assert_eq!(hex("68eca487516765b5e5bfdacbaf6cb7fb6bff871f035444ce83a670df"), encrypted.data[..]);
+        assert_eq!(Some(pdu), NetworkPdu::decrypt(&encrypted.data, &keys, 0x12345678));
+        assert_eq!(None, NetworkPdu::decrypt(&encrypted.data, &keys, 0x12345676));
+    }
+
+    #[test]
+    fn relay_and_replay() {
+        let net_key = [0x5A; 16];
+        let mut sender = Network::new(&net_key, 1, 0x0001);
+        let mut relay = Network::new(&net_key, 1, 0x0002);
+        relay.set_relay(true);
+        let mut other = Network::new(&[0xA5; 16], 1, 0x0003);
+
+        let pdu = sender.send(false, 5, 0x0003, &[0x01, 0x02]).unwrap();
+        assert_eq!(1, sender.seq());
+        assert_eq!(None, other.receive(&pdu.data));
+        // relayed with a decremented TTL but not delivered
+        let received = relay.receive(&pdu.data).unwrap();
+        assert!(! received.deliver);
+        assert_eq!(4, NetworkPdu::decrypt(&received.relay.unwrap().data, relay.keys(), 1).unwrap().ttl);
+        // the network cache drops the retransmissions
+        assert_eq!(None, relay.receive(&pdu.data));
+        assert_eq!(None, sender.receive(&pdu.data));
+
+        // delivered once per sequence number
+        let pdu = sender.send(false, 1, 0x0002, &[0x03]).unwrap();
+        let received = relay.receive(&pdu.data).unwrap();
+        assert!(received.deliver);
+        assert_eq!(None, received.relay);
+        assert_eq!([0x03], received.pdu.transport_pdu[..]);
+        for _ in 0..NETWORK_CACHE_SIZE {
+            relay.receive(&sender.send(false, 0, 0x0002, &[0x04]).unwrap().data);
+        }
+        assert!(! relay.receive(&pdu.data).unwrap().deliver);
+
+        // the IVI selects the previous IV index
+        let old = sender.send(false, 0, 0x0002, &[0x05]).unwrap();
+        relay.set_iv_index(2);
+        assert!(relay.receive(&old.data).unwrap().deliver);
+        sender.set_iv_index(2);
+        assert_eq!(0, sender.seq());
+        assert!(relay.receive(&sender.send(false, 0, 0x0002, &[0x06]).unwrap().data).unwrap().deliver);
+        assert_eq!(None, sender.send(true, 0, 0x0002, &[0; TRANSPORT_PDU_SIZE_MAX]));
+    }
+}
@@ -0,0 +1,790 @@
+use core::convert::TryFrom;
+use num_enum::{TryFromPrimitive};
+use crate::smp;
+use super::bearer::{self, AdType};
+use super::crypto;
+use super::network::Address;
+
+/// the largest provisioning PDU (the Public Key PDU)
+pub const PROVISIONING_PDU_SIZE_MAX:usize = 1 + 64;
+/// PB-ADV PDUs sent upon a received one (an acknowledgement followed by a segmented transaction)
+pub const OUTGOING_MAX:usize = 4;
+/// PB-ADV PDUs to send on the advertising bearer
+pub type Outgoing = heapless::Vec<bearer::Pdu, OUTGOING_MAX>;
+
+/// Link ID and Transaction Number
+const PB_ADV_HEADER_SIZE:usize = 5;
+/// SegN and GPCF, TotalLength and FCS
+const START_HEADER_SIZE:usize = 4;
+const START_DATA_SIZE_MAX:usize = bearer::PDU_SIZE_MAX - PB_ADV_HEADER_SIZE - START_HEADER_SIZE;
+const CONTINUATION_DATA_SIZE_MAX:usize = bearer::PDU_SIZE_MAX - PB_ADV_HEADER_SIZE - 1;
+
+/// Generic Provisioning Control Format - Mesh Profile v1.0.1, 5.3.1
+const GPCF_TRANSACTION_START:u8 = 0b00;
+const GPCF_TRANSACTION_ACK:u8 = 0b01;
+const GPCF_TRANSACTION_CONTINUATION:u8 = 0b10;
+const GPCF_BEARER_CONTROL:u8 = 0b11;
+
+/// bearer control opcodes - Mesh Profile v1.0.1, 5.3.2
+const LINK_OPEN:u8 = 0x00;
+const LINK_ACK:u8 = 0x01;
+const LINK_CLOSE:u8 = 0x02;
+
+/// Invite, Capabilities and Start parameters followed by the public keys of the provisioner and device
+const CONFIRMATION_INPUTS_SIZE:usize = 1 + 11 + 5 + 64 + 64;
+const INPUTS_CAPABILITIES:usize = 1;
+const INPUTS_START:usize = 12;
+const INPUTS_PROVISIONER_KEY:usize = 17;
+const INPUTS_DEVICE_KEY:usize = 81;
+
+const ALGORITHM_FIPS_P256:u16 = 0x0001;
+const STATIC_OOB_AVAILABLE:u8 = 0x01;
+const AUTH_NO_OOB:u8 = 0x00;
+const AUTH_STATIC_OOB:u8 = 0x01;
+
+/// reasons of closing a link - Mesh Profile v1.0.1, 5.3.2.3
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum CloseReason {
+    Success = 0x00,
+    Timeout = 0x01,
+    Fail = 0x02,
+}
+
+/// Mesh Profile v1.0.1, 5.4.1
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+enum PduType {
+    Invite          = 0x00,
+    Capabilities    = 0x01,
+    Start           = 0x02,
+    PublicKey       = 0x03,
+    InputComplete   = 0x04,
+    Confirmation    = 0x05,
+    Random          = 0x06,
+    Data            = 0x07,
+    Complete        = 0x08,
+    Failed          = 0x09,
+}
+
+/// error codes of the Provisioning Failed PDU - Mesh Profile v1.0.1, 5.4.1.10
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum ProvisioningError {
+    Prohibited              = 0x00,
+    InvalidPdu              = 0x01,
+    InvalidFormat           = 0x02,
+    UnexpectedPdu           = 0x03,
+    ConfirmationFailed      = 0x04,
+    OutOfResources          = 0x05,
+    DecryptionFailed        = 0x06,
+    UnexpectedError         = 0x07,
+    CannotAssignAddresses   = 0x08,
+}
+
+/// the network of a provisioned node - Mesh Profile v1.0.1, 5.4.2.5
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProvisioningData {
+    pub net_key: [u8; 16],
+    pub key_index: u16,
+    /// Key Refresh (bit 0) and IV Update (bit 1)
+    pub flags: u8,
+    pub iv_index: u32,
+    /// the unicast address of the primary element
+    pub address: Address,
+}
+impl ProvisioningData {
+    const SIZE:usize = 25;
+
+    fn write(&self) -> [u8; Self::SIZE] {
+        let mut data = [0; Self::SIZE];
+        data[..16].copy_from_slice(&self.net_key);
+        data[16..18].copy_from_slice(&self.key_index.to_be_bytes());
+        data[18] = self.flags;
+        data[19..23].copy_from_slice(&self.iv_index.to_be_bytes());
+        data[23..].copy_from_slice(&self.address.to_be_bytes());
+        return data;
+    }
+
+    fn read(data: &[u8]) -> Self {
+        let mut net_key = [0; 16];
+        net_key.copy_from_slice(&data[..16]);
+        return Self {
+            net_key,
+            key_index: u16::from_be_bytes([data[16], data[17]]),
+            flags: data[18],
+            iv_index: u32::from_be_bytes([data[19], data[20], data[21], data[22]]),
+            address: u16::from_be_bytes([data[23], data[24]]),
+        };
+    }
+}
+
+/// the Frame Check Sequence of a transaction (3GPP TS 27.010) - Mesh Profile v1.0.1, 5.3.1.1
+fn fcs(data: &[u8]) -> u8 {
+    let mut crc:u8 = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if (crc & 1) == 1 { (crc >> 1) ^ 0xE0 } else { crc >> 1 };
+        }
+    }
+    return 0xFF - crc;
+}
+
+/// transaction numbers wrap within the range of their side
+fn next_transaction(transaction: u8) -> u8 {
+    return (transaction & 0x80) | (transaction.wrapping_add(1) & 0x7F);
+}
+
+/// the events of a transaction on a link
+enum Transaction {
+    /// our transaction was acknowledged
+    Acknowledged,
+    /// a provisioning PDU was received
+    Received(heapless::Vec<u8, PROVISIONING_PDU_SIZE_MAX>),
+}
+
+/// a PB-ADV link carrying provisioning PDUs in (segmented) transactions - Mesh Profile v1.0.1, 5.2.1, 5.3
+struct Link {
+    id: u32,
+    /// the number of our current transaction (0x00 to 0x7F by the provisioner, 0x80 to 0xFF by the device)
+    transaction: u8,
+    /// the PDU of our transaction awaiting its acknowledgement
+    pending: heapless::Vec<u8, PROVISIONING_PDU_SIZE_MAX>,
+    /// the number of the incoming transaction being reassembled
+    rx_transaction: Option<u8>,
+    /// the received segments (bit per segment index)
+    rx_segments: u64,
+    rx_last_segment: u8,
+    rx_length: usize,
+    rx_fcs: u8,
+    rx_buffer: [u8; PROVISIONING_PDU_SIZE_MAX],
+    /// the last incoming transaction completed (acknowledged again when retransmitted)
+    rx_completed: Option<u8>,
+}
+impl Link {
+    fn new(id: u32, provisioner: bool) -> Self {
+        Self {
+            id,
+            transaction: if provisioner { 0x00 } else { 0x80 },
+            pending: heapless::Vec::new(),
+            rx_transaction: None,
+            rx_segments: 0,
+            rx_last_segment: 0,
+            rx_length: 0,
+            rx_fcs: 0,
+            rx_buffer: [0; PROVISIONING_PDU_SIZE_MAX],
+            rx_completed: None,
+        }
+    }
+
+    fn pdu(&self, transaction: u8, generic: &[u8]) -> bearer::Pdu {
+        let mut data = [0; bearer::PDU_SIZE_MAX];
+        data[..4].copy_from_slice(&self.id.to_be_bytes());
+        data[4] = transaction;
+        data[PB_ADV_HEADER_SIZE..(PB_ADV_HEADER_SIZE + generic.len())].copy_from_slice(generic);
+        return bearer::Pdu::new(AdType::PbAdv, &data[..(PB_ADV_HEADER_SIZE + generic.len())]).unwrap();
+    }
+
+    /// a bearer control PDU (of transaction number 0)
+    fn control(&self, opcode: u8, parameters: &[u8]) -> bearer::Pdu {
+        let mut generic = [0; 1 + 16];
+        generic[0] = (opcode << 2) | GPCF_BEARER_CONTROL;
+        generic[1..(1 + parameters.len())].copy_from_slice(parameters);
+        return self.pdu(0, &generic[..(1 + parameters.len())]);
+    }
+
+    /// start a transaction of the provisioning PDU
+    fn send(&mut self, pdu: &[u8], out: &mut Outgoing) {
+        self.pending = heapless::Vec::from_slice(pdu).unwrap();
+        self.segments(out);
+    }
+
+    /// the segments of the pending transaction (retransmitted until acknowledged)
+    fn segments(&self, out: &mut Outgoing) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let first = self.pending.len().min(START_DATA_SIZE_MAX);
+        let continuations = self.pending[first..].chunks(CONTINUATION_DATA_SIZE_MAX);
+        let mut generic = [0; bearer::PDU_SIZE_MAX - PB_ADV_HEADER_SIZE];
+        generic[0] = ((continuations.len() as u8) << 2) | GPCF_TRANSACTION_START;
+        generic[1..3].copy_from_slice(&(self.pending.len() as u16).to_be_bytes());
+        generic[3] = fcs(&self.pending);
+        generic[START_HEADER_SIZE..(START_HEADER_SIZE + first)].copy_from_slice(&self.pending[..first]);
+        let _ = out.push(self.pdu(self.transaction, &generic[..(START_HEADER_SIZE + first)]));
+        for (index, segment) in continuations.enumerate() {
+            generic[0] = (((index + 1) as u8) << 2) | GPCF_TRANSACTION_CONTINUATION;
+            generic[1..(1 + segment.len())].copy_from_slice(segment);
+            let _ = out.push(self.pdu(self.transaction, &generic[..(1 + segment.len())]));
+        }
+    }
+
+    /// handle a transaction PDU of the link (acknowledging completed transactions)
+    fn receive(&mut self, transaction: u8, generic: &[u8], out: &mut Outgoing) -> Option<Transaction> {
+        let segment = generic[0] >> 2;
+        match generic[0] & 0b11 {
+            GPCF_TRANSACTION_ACK => {
+                if transaction != self.transaction || self.pending.is_empty() {
+                    return None;
+                }
+                self.acknowledged();
+                return Some(Transaction::Acknowledged);
+            }
+            _ if self.rx_completed == Some(transaction) => {
+                let _ = out.push(self.pdu(transaction, &[GPCF_TRANSACTION_ACK]));
+                return None;
+            }
+            // retransmissions of earlier transactions
+            _ if self.rx_completed.is_some_and(|completed| transaction != next_transaction(completed)) => return None,
+            GPCF_TRANSACTION_START => {
+                if generic.len() < START_HEADER_SIZE {
+                    return None;
+                }
+                let length = u16::from_be_bytes([generic[1], generic[2]]) as usize;
+                let data = &generic[START_HEADER_SIZE..];
+                // SegN is the number of continuations the total length requires
+                let last_segment = length.saturating_sub(START_DATA_SIZE_MAX).div_ceil(CONTINUATION_DATA_SIZE_MAX);
+                if length > PROVISIONING_PDU_SIZE_MAX || segment as usize != last_segment
+                    || data.len() != length.min(START_DATA_SIZE_MAX) {
+                    return None;
+                }
+                if self.rx_transaction != Some(transaction) {
+                    self.rx_transaction = Some(transaction);
+                    self.rx_segments = 0;
+                }
+                self.rx_last_segment = segment;
+                self.rx_length = length;
+                self.rx_fcs = generic[3];
+                self.rx_buffer[..data.len()].copy_from_slice(data);
+                self.rx_segments |= 1;
+            }
+            GPCF_TRANSACTION_CONTINUATION => {
+                // continuations are dropped until their start (which is retransmitted)
+                let offset = START_DATA_SIZE_MAX + (segment as usize).checked_sub(1)? * CONTINUATION_DATA_SIZE_MAX;
+                let data = &generic[1..];
+                if self.rx_transaction != Some(transaction) || (self.rx_segments & 1) == 0
+                    || segment > self.rx_last_segment || offset + data.len() > self.rx_length {
+                    return None;
+                }
+                self.rx_buffer[offset..(offset + data.len())].copy_from_slice(data);
+                self.rx_segments |= 1 << segment;
+            }
+            _ => return None,
+        }
+
+        let all_segments = 1u64.checked_shl(self.rx_last_segment as u32 + 1)?.wrapping_sub(1);
+        if self.rx_segments != all_segments {
+            return None;
+        }
+        self.rx_transaction = None;
+        let pdu = heapless::Vec::from_slice(&self.rx_buffer[..self.rx_length]).unwrap();
+        if fcs(&pdu) != self.rx_fcs {
+            return None;
+        }
+        self.rx_completed = Some(transaction);
+        let _ = out.push(self.pdu(transaction, &[GPCF_TRANSACTION_ACK]));
+        // the peer's response also acknowledges our transaction
+        if !self.pending.is_empty() {
+            self.acknowledged();
+        }
+        return Some(Transaction::Received(pdu));
+    }
+
+    fn acknowledged(&mut self) {
+        self.pending.clear();
+        self.transaction = next_transaction(self.transaction);
+    }
+}
+
+/// the values of a provisioning session - Mesh Profile v1.0.1, 5.4.2
+struct Session {
+    secret: p256::SecretKey,
+    /// X followed by Y (MSB first, as sent)
+    public_key: [u8; 64],
+    random: [u8; 16],
+    auth_value: [u8; 16],
+    inputs: [u8; CONFIRMATION_INPUTS_SIZE],
+    ecdh_secret: [u8; 32],
+    confirmation_salt: [u8; 16],
+    confirmation_key: [u8; 16],
+    /// the confirmation received from the peer
+    peer_confirmation: [u8; 16],
+}
+impl Session {
+    fn new(random: &mut dyn FnMut(&mut [u8])) -> Self {
+        let (secret, public_key) = smp::key_pair(random);
+        let mut session = Self {
+            secret,
+            public_key,
+            random: [0; 16],
+            auth_value: [0; 16],
+            inputs: [0; CONFIRMATION_INPUTS_SIZE],
+            ecdh_secret: [0; 32],
+            confirmation_salt: [0; 16],
+            confirmation_key: [0; 16],
+            peer_confirmation: [0; 16],
+        };
+        random(&mut session.random);
+        return session;
+    }
+
+    /// the ECDH secret with the peer's public key (X followed by Y) and the confirmation key (once the
+    /// confirmation inputs are complete) - Mesh Profile v1.0.1, 5.4.2.3, 5.4.2.4
+    fn agree(&mut self, peer: &[u8]) -> Result<(), ProvisioningError> {
+        // a reflected key would let the peer impersonate us
+        if peer == self.public_key {
+            return Err(ProvisioningError::InvalidFormat);
+        }
+        let mut sec1 = [0x04; 1 + 64];
+        sec1[1..].copy_from_slice(peer);
+        let peer = p256::PublicKey::from_sec1_bytes(&sec1).map_err(|_| ProvisioningError::InvalidFormat)?;
+        let shared = p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), peer.as_affine());
+        self.ecdh_secret.copy_from_slice(shared.raw_secret_bytes());
+        self.confirmation_salt = crypto::s1(&self.inputs);
+        self.confirmation_key = crypto::k1(&self.ecdh_secret, &self.confirmation_salt, b"prck");
+        return Ok(());
+    }
+
+    fn confirmation(&self, random: &[u8; 16]) -> [u8; 16] {
+        return smp::aes_cmac(&self.confirmation_key, &[random, &self.auth_value]);
+    }
+
+    /// the session key, session nonce and device key - Mesh Profile v1.0.1, 5.4.2.5
+    fn keys(&self, provisioner_random: &[u8; 16], device_random: &[u8; 16]) -> ([u8; 16], [u8; 13], [u8; 16]) {
+        let mut salt = [0; 48];
+        salt[..16].copy_from_slice(&self.confirmation_salt);
+        salt[16..32].copy_from_slice(provisioner_random);
+        salt[32..].copy_from_slice(device_random);
+        let salt = crypto::s1(&salt);
+        let mut nonce = [0; 13];
+        nonce.copy_from_slice(&crypto::k1(&self.ecdh_secret, &salt, b"prsn")[3..]);
+        return (crypto::k1(&self.ecdh_secret, &salt, b"prsk"), nonce, crypto::k1(&self.ecdh_secret, &salt, b"prdk"));
+    }
+}
+
+/// the PB-ADV PDU parsed as link ID, transaction number and Generic Provisioning PDU
+fn read(pdu: &bearer::Pdu) -> Option<(u32, u8, &[u8])> {
+    if pdu.ad_type != AdType::PbAdv || pdu.data.len() <= PB_ADV_HEADER_SIZE {
+        return None;
+    }
+    let id = u32::from_be_bytes([pdu.data[0], pdu.data[1], pdu.data[2], pdu.data[3]]);
+    return Some((id, pdu.data[4], &pdu.data[PB_ADV_HEADER_SIZE..]));
+}
+
+fn random_value(pdu: &[u8]) -> [u8; 16] {
+    let mut value = [0; 16];
+    value.copy_from_slice(&pdu[1..17]);
+    return value;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DeviceState { Invite, Start, PublicKey, Confirmation, Random, Data, Complete, Failed }
+
+/// an unprovisioned device, which is provisioned over PB-ADV links (opened by a provisioner)
+/// Mesh Profile v1.0.1, 5.4.3
+pub struct Device {
+    uuid: [u8; 16],
+    /// the Static OOB authentication value (if any)
+    static_oob: Option<[u8; 16]>,
+    link: Option<Link>,
+    state: DeviceState,
+    session: Session,
+    provisioned: Option<(ProvisioningData, [u8; 16])>,
+}
+impl Device {
+    /// the key pair and random value of the session are generated with the random source
+    pub fn new(uuid: [u8; 16], static_oob: Option<[u8; 16]>, random: &mut dyn FnMut(&mut [u8])) -> Self {
+        Self {
+            uuid,
+            static_oob,
+            link: None,
+            state: DeviceState::Invite,
+            session: Session::new(random),
+            provisioned: None,
+        }
+    }
+
+    pub fn uuid(&self) -> &[u8; 16] { &self.uuid }
+
+    /// the provisioning data and device key once provisioned
+    pub fn provisioned(&self) -> Option<&(ProvisioningData, [u8; 16])> {
+        return self.provisioned.as_ref();
+    }
+
+    /// resend our unacknowledged transaction (call periodically while the link is open)
+    pub fn retransmit(&self, out: &mut Outgoing) {
+        match self.link.as_ref() {
+            Some(link) => link.segments(out),
+            None => {}
+        }
+    }
+
+    /// handle a received PB-ADV PDU, the responses are added to out
+    pub fn handle(&mut self, pdu: &bearer::Pdu, out: &mut Outgoing) {
+        let (id, transaction, generic) = match read(pdu) {
+            Some(fields) => fields,
+            None => return,
+        };
+        if (generic[0] & 0b11) == GPCF_BEARER_CONTROL {
+            match (generic[0] >> 2, self.link.as_ref()) {
+                (LINK_OPEN, None) if generic[1..] == self.uuid && self.state != DeviceState::Complete => {
+                    let link = Link::new(id, false);
+                    let _ = out.push(link.control(LINK_ACK, &[]));
+                    self.link = Some(link);
+                    self.state = DeviceState::Invite;
+                }
+                // the provisioner retransmits until acknowledged
+                (LINK_OPEN, Some(link)) if link.id == id => { let _ = out.push(link.control(LINK_ACK, &[])); }
+                (LINK_CLOSE, Some(link)) if link.id == id => self.link = None,
+                _ => {}
+            }
+            return;
+        }
+        let link = match self.link.as_mut() {
+            Some(link) if link.id == id => link,
+            _ => return,
+        };
+        match link.receive(transaction, generic, out) {
+            Some(Transaction::Received(pdu)) => {
+                let mut response = [0; PROVISIONING_PDU_SIZE_MAX];
+                let length = match self.provisioning_pdu(&pdu, &mut response) {
+                    Ok(length) => length,
+                    Err(error) => {
+                        self.state = DeviceState::Failed;
+                        response[..2].copy_from_slice(&[PduType::Failed as u8, error as u8]);
+                        2
+                    }
+                };
+                let link = self.link.as_mut().unwrap();
+                link.send(&response[..length], out);
+            }
+            _ => {}
+        }
+    }
+
+    /// handle a provisioning PDU, returns the length of the response
+    fn provisioning_pdu(&mut self, pdu: &[u8], response: &mut [u8]) -> Result<usize, ProvisioningError> {
+        let pdu_type = PduType::try_from(pdu[0] & 0x3F).map_err(|_| ProvisioningError::InvalidPdu)?;
+        let length = match pdu_type {
+            PduType::Invite => 2,
+            PduType::Start => 6,
+            PduType::PublicKey => 65,
+            PduType::Confirmation | PduType::Random => 17,
+            PduType::Data => 1 + ProvisioningData::SIZE + 8,
+            _ => return Err(ProvisioningError::UnexpectedPdu),
+        };
+        if pdu.len() != length {
+            return Err(ProvisioningError::InvalidFormat);
+        }
+        let session = &mut self.session;
+        match (pdu_type, self.state) {
+            (PduType::Invite, DeviceState::Invite) => {
+                session.inputs[0] = pdu[1];
+                let capabilities = &mut session.inputs[INPUTS_CAPABILITIES..INPUTS_START];
+                // a single element, no OOB public key nor output or input OOB
+                capabilities.fill(0);
+                capabilities[0] = 1;
+                capabilities[1..3].copy_from_slice(&ALGORITHM_FIPS_P256.to_be_bytes());
+                capabilities[4] = if self.static_oob.is_some() { STATIC_OOB_AVAILABLE } else { 0 };
+                response[0] = PduType::Capabilities as u8;
+                response[1..12].copy_from_slice(capabilities);
+                self.state = DeviceState::Start;
+                return Ok(12);
+            }
+            (PduType::Start, DeviceState::Start) => {
+                // algorithm, public key type, authentication method, action and size
+                session.auth_value = match (pdu[1], pdu[2], pdu[3], pdu[4], pdu[5]) {
+                    (0x00, 0x00, AUTH_NO_OOB, 0x00, 0x00) => [0; 16],
+                    (0x00, 0x00, AUTH_STATIC_OOB, 0x00, 0x00) => self.static_oob.ok_or(ProvisioningError::InvalidFormat)?,
+                    _ => return Err(ProvisioningError::InvalidFormat),
+                };
+                session.inputs[INPUTS_START..INPUTS_PROVISIONER_KEY].copy_from_slice(&pdu[1..]);
+                self.state = DeviceState::PublicKey;
+                // nothing to respond
+                return Ok(0);
+            }
+            (PduType::PublicKey, DeviceState::PublicKey) => {
+                session.inputs[INPUTS_PROVISIONER_KEY..INPUTS_DEVICE_KEY].copy_from_slice(&pdu[1..]);
+                session.inputs[INPUTS_DEVICE_KEY..].copy_from_slice(&session.public_key);
+                session.agree(&pdu[1..])?;
+                response[0] = PduType::PublicKey as u8;
+                response[1..65].copy_from_slice(&session.public_key);
+                self.state = DeviceState::Confirmation;
+                return Ok(65);
+            }
+            (PduType::Confirmation, DeviceState::Confirmation) => {
+                session.peer_confirmation = random_value(pdu);
+                response[0] = PduType::Confirmation as u8;
+                response[1..17].copy_from_slice(&session.confirmation(&session.random));
+                self.state = DeviceState::Random;
+                return Ok(17);
+            }
+            (PduType::Random, DeviceState::Random) => {
+                let provisioner_random = random_value(pdu);
+                if session.confirmation(&provisioner_random) != session.peer_confirmation {
+                    return Err(ProvisioningError::ConfirmationFailed);
+                }
+                // keep the provisioner's random for the session keys
+                session.peer_confirmation = provisioner_random;
+                response[0] = PduType::Random as u8;
+                response[1..17].copy_from_slice(&session.random);
+                self.state = DeviceState::Data;
+                return Ok(17);
+            }
+            (PduType::Data, DeviceState::Data) => {
+                let (session_key, nonce, device_key) = session.keys(&session.peer_confirmation, &session.random);
+                let mut data = [0; ProvisioningData::SIZE + 8];
+                data.copy_from_slice(&pdu[1..]);
+                crypto::ccm_decrypt(&session_key, &nonce, &mut data, ProvisioningData::SIZE + 8, 8).ok_or(ProvisioningError::DecryptionFailed)?;
+                self.provisioned = Some((ProvisioningData::read(&data), device_key));
+                response[0] = PduType::Complete as u8;
+                self.state = DeviceState::Complete;
+                return Ok(1);
+            }
+            _ => return Err(ProvisioningError::UnexpectedPdu),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ProvisionerState { LinkOpen, Capabilities, Start, PublicKey, Confirmation, Random, Complete, Closed }
+
+/// a provisioner of a device (by its UUID) over a PB-ADV link - Mesh Profile v1.0.1, 5.4.3
+pub struct Provisioner {
+    uuid: [u8; 16],
+    data: ProvisioningData,
+    /// the Static OOB authentication value of the device (if known)
+    static_oob: Option<[u8; 16]>,
+    link: Link,
+    state: ProvisionerState,
+    session: Session,
+    device_random: [u8; 16],
+    device_key: Option<[u8; 16]>,
+    error: Option<ProvisioningError>,
+}
+impl Provisioner {
+    /// the link ID, key pair and random value of the session are generated with the random source
+    pub fn new(uuid: [u8; 16], data: ProvisioningData, static_oob: Option<[u8; 16]>,
+               random: &mut dyn FnMut(&mut [u8])) -> Self
+    {
+        let mut id = [0; 4];
+        random(&mut id);
+        Self {
+            uuid,
+            data,
+            static_oob,
+            link: Link::new(u32::from_be_bytes(id), true),
+            state: ProvisionerState::LinkOpen,
+            session: Session::new(random),
+            device_random: [0; 16],
+            device_key: None,
+            error: None,
+        }
+    }
+
+    /// the device key once the device has completed provisioning
+    pub fn device_key(&self) -> Option<&[u8; 16]> {
+        return self.device_key.as_ref();
+    }
+
+    /// the reason provisioning failed (the link has been closed)
+    pub fn error(&self) -> Option<ProvisioningError> {
+        return self.error;
+    }
+
+    /// open the link (upon creation) or resend our unacknowledged transaction (call periodically)
+    pub fn retransmit(&self, out: &mut Outgoing) {
+        match self.state {
+            ProvisionerState::LinkOpen => { let _ = out.push(self.link.control(LINK_OPEN, &self.uuid)); }
+            ProvisionerState::Complete | ProvisionerState::Closed => {}
+            _ => self.link.segments(out),
+        }
+    }
+
+    /// handle a received PB-ADV PDU, the responses are added to out
+    pub fn handle(&mut self, pdu: &bearer::Pdu, out: &mut Outgoing) {
+        let (_, transaction, generic) = match read(pdu) {
+            Some(fields) if fields.0 == self.link.id => fields,
+            _ => return,
+        };
+        if (generic[0] & 0b11) == GPCF_BEARER_CONTROL {
+            if (generic[0] >> 2) == LINK_ACK && self.state == ProvisionerState::LinkOpen {
+                self.session.inputs[0] = 0;
+                self.state = ProvisionerState::Capabilities;
+                // no attention timer
+                self.link.send(&[PduType::Invite as u8, 0], out);
+            }
+            return;
+        }
+        let result = match self.link.receive(transaction, generic, out) {
+            Some(Transaction::Acknowledged) if self.state == ProvisionerState::Start => {
+                let mut response = [0; PROVISIONING_PDU_SIZE_MAX];
+                response[0] = PduType::PublicKey as u8;
+                response[1..].copy_from_slice(&self.session.public_key);
+                self.session.inputs[INPUTS_PROVISIONER_KEY..INPUTS_DEVICE_KEY].copy_from_slice(&self.session.public_key);
+                self.state = ProvisionerState::PublicKey;
+                self.link.send(&response, out);
+                Ok(())
+            }
+            Some(Transaction::Received(pdu)) => self.provisioning_pdu(&pdu, out),
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => {}
+            Err(error) => {
+                self.error = Some(error);
+                self.state = ProvisionerState::Closed;
+                let _ = out.push(self.link.control(LINK_CLOSE, &[CloseReason::Fail as u8]));
+            }
+        }
+    }
+
+    fn provisioning_pdu(&mut self, pdu: &[u8], out: &mut Outgoing) -> Result<(), ProvisioningError> {
+        let pdu_type = PduType::try_from(pdu[0] & 0x3F).map_err(|_| ProvisioningError::InvalidPdu)?;
+        let length = match pdu_type {
+            PduType::Capabilities => 12,
+            PduType::PublicKey => 65,
+            PduType::Confirmation | PduType::Random => 17,
+            PduType::Complete => 1,
+            PduType::Failed => 2,
+            _ => return Err(ProvisioningError::UnexpectedPdu),
+        };
+        if pdu.len() != length {
+            return Err(ProvisioningError::InvalidFormat);
+        }
+        let session = &mut self.session;
+        match (pdu_type, self.state) {
+            (PduType::Failed, _) => {
+                return Err(ProvisioningError::try_from(pdu[1]).unwrap_or(ProvisioningError::UnexpectedError));
+            }
+            (PduType::Capabilities, ProvisionerState::Capabilities) => {
+                if (u16::from_be_bytes([pdu[2], pdu[3]]) & ALGORITHM_FIPS_P256) == 0 {
+                    return Err(ProvisioningError::InvalidFormat);
+                }
+                session.inputs[INPUTS_CAPABILITIES..INPUTS_START].copy_from_slice(&pdu[1..]);
+                let auth_method = match self.static_oob {
+                    Some(value) if (pdu[5] & STATIC_OOB_AVAILABLE) != 0 => {
+                        session.auth_value = value;
+                        AUTH_STATIC_OOB
+                    }
+                    _ => AUTH_NO_OOB,
+                };
+                let start = [PduType::Start as u8, 0x00, 0x00, auth_method, 0x00, 0x00];
+                session.inputs[INPUTS_START..INPUTS_PROVISIONER_KEY].copy_from_slice(&start[1..]);
+                self.state = ProvisionerState::Start;
+                self.link.send(&start, out);
+            }
+            (PduType::PublicKey, ProvisionerState::PublicKey) => {
+                session.inputs[INPUTS_DEVICE_KEY..].copy_from_slice(&pdu[1..]);
+                session.agree(&pdu[1..])?;
+                let mut confirmation = [0; 17];
+                confirmation[0] = PduType::Confirmation as u8;
+                confirmation[1..].copy_from_slice(&session.confirmation(&session.random));
+                self.state = ProvisionerState::Confirmation;
+                self.link.send(&confirmation, out);
+            }
+            (PduType::Confirmation, ProvisionerState::Confirmation) => {
+                session.peer_confirmation = random_value(pdu);
+                let mut random = [0; 17];
+                random[0] = PduType::Random as u8;
+                random[1..].copy_from_slice(&session.random);
+                self.state = ProvisionerState::Random;
+                self.link.send(&random, out);
+            }
+            (PduType::Random, ProvisionerState::Random) => {
+                self.device_random = random_value(pdu);
+                if session.confirmation(&self.device_random) != session.peer_confirmation {
+                    return Err(ProvisioningError::ConfirmationFailed);
+                }
+                let (session_key, nonce, device_key) = session.keys(&session.random, &self.device_random);
+                let mut data = [0; 1 + ProvisioningData::SIZE + 8];
+                data[0] = PduType::Data as u8;
+                data[1..(1 + ProvisioningData::SIZE)].copy_from_slice(&self.data.write());
+                crypto::ccm_encrypt(&session_key, &nonce, &mut data[1..], ProvisioningData::SIZE, 8);
+                self.device_key = Some(device_key);
+                self.state = ProvisionerState::Complete;
+                self.link.send(&data, out);
+            }
+            (PduType::Complete, ProvisionerState::Complete) => {
+                self.state = ProvisionerState::Closed;
+                let _ = out.push(self.link.control(LINK_CLOSE, &[CloseReason::Success as u8]));
+            }
+            _ => return Err(ProvisioningError::UnexpectedPdu),
+        }
+        return Ok(());
+    }
+
+    /// whether the device has confirmed receiving the provisioning data
+    pub fn is_complete(&self) -> bool {
+        return self.state == ProvisionerState::Closed && self.error.is_none();
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod pb_adv {
+    use super::*;
+
+    #[test]
+    fn transactions() {
+        // 3GPP TS 27.010, annex B
+        assert_eq!(0x1C, fcs(&[0x03, 0x3F, 0x01]));
+
+        let mut provisioner = Link::new(1, true);
+        let mut device = Link::new(1, false);
+        let pdu: Vec<u8> = (0..65).collect();
+        let mut out = Outgoing::new();
+        provisioner.send(&pdu, &mut out);
+        assert_eq!(3, out.len());
+        assert_eq!([0, 0, 0, 1, 0x00, (2 << 2) | GPCF_TRANSACTION_START, 0, 65, fcs(&pdu)], out[0].data[..9]);
+        assert_eq!([0, 0, 0, 1, 0x00, (2 << 2) | GPCF_TRANSACTION_CONTINUATION], out[2].data[..6]);
+
+        // reassembled in any order once started
+        let mut acks = Outgoing::new();
+        let segments: Vec<(u8, Vec<u8>)> = out.iter().map(|pdu| {
+            let (_, transaction, generic) = read(pdu).unwrap();
+            (transaction, generic.to_vec())
+        }).collect();
+        assert!(device.receive(segments[1].0, &segments[1].1, &mut acks).is_none());
+        assert!(device.receive(segments[0].0, &segments[0].1, &mut acks).is_none());
+        assert!(device.receive(segments[2].0, &segments[2].1, &mut acks).is_none());
+        assert!(device.receive(segments[1].0, &segments[1].1, &mut acks).is_some());
+        // the retransmission is acknowledged again
+        assert!(device.receive(segments[0].0, &segments[0].1, &mut acks).is_none());
+        assert_eq!(2, acks.len());
+        assert_eq!(acks[0], acks[1]);
+
+        let (_, transaction, generic) = read(&acks[0]).unwrap();
+        assert!(matches!(provisioner.receive(transaction, generic, &mut out), Some(Transaction::Acknowledged)));
+        assert_eq!(0x01, provisioner.transaction);
+        assert!(provisioner.receive(transaction, generic, &mut out).is_none());
+    }
+
+    #[test]
+    fn segment_count() {
+        let mut device = Link::new(1, false);
+        let mut acks = Outgoing::new();
+        // a SegN beyond the segments of the length (63 would overflow the segment mask)
+        let mut start = [(63 << 2) | GPCF_TRANSACTION_START, 0, 2, 0, 0x01, 0x02];
+        start[3] = fcs(&start[4..]);
+        assert!(device.receive(0x00, &start, &mut acks).is_none());
+        start[0] = (1 << 2) | GPCF_TRANSACTION_START;
+        assert!(device.receive(0x00, &start, &mut acks).is_none());
+        assert!(acks.is_empty());
+
+        // a length requiring continuations
+        let pdu: Vec<u8> = (0..(START_DATA_SIZE_MAX as u8 + 1)).collect();
+        let mut start = vec![GPCF_TRANSACTION_START, 0, pdu.len() as u8, fcs(&pdu)];
+        start.extend_from_slice(&pdu[..START_DATA_SIZE_MAX]);
+        assert!(device.receive(0x00, &start, &mut acks).is_none());
+        start[0] = (1 << 2) | GPCF_TRANSACTION_START;
+        assert!(device.receive(0x00, &start, &mut acks).is_none());
+        let continuation = [(1 << 2) | GPCF_TRANSACTION_CONTINUATION, pdu[START_DATA_SIZE_MAX]];
+        assert!(matches!(device.receive(0x00, &continuation, &mut acks), Some(Transaction::Received(received)) if received == pdu[..]));
+        assert_eq!(1, acks.len());
+    }
+}
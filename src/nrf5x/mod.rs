@@ -1,18 +1,49 @@
 use core::convert::TryFrom;
 
-
-use nrf52832_hal::{pac};
-
-use crate::{link_layer};
+// choose the hardware pac
+#[cfg(feature="nrf51")]
+use nrf51_hal::pac;
+#[cfg(feature="nrf52810")]
+use nrf52810_hal::pac;
+#[cfg(feature="nrf52811")]
+use nrf52811_hal::pac;
+#[cfg(feature="nrf52832")]
+use nrf52832_hal::pac;
+#[cfg(feature="nrf52833")]
+use nrf52833_hal::pac;
+#[cfg(feature="nrf52840")]
+use nrf52840_hal::pac;
+
+use crate::{link_layer, encryption, bond, privacy};
 use pac::{FICR, ficr::deviceaddrtype::DEVICEADDRTYPE_A};
-use pac::{RADIO};
+use pac::{RADIO, CCM, AAR, NVMC, TIMER0};
 use core::ptr::{write_volatile, read_volatile};
-use core::sync::atomic::{compiler_fence, Ordering};
+use core::sync::atomic::{compiler_fence, AtomicBool, Ordering};
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 use rtt_target::{rprintln};
 
 pub struct Nrf5xHci {
     radio: RADIO,
+    /// AES-CCM peripheral (encryption is performed in software without it)
+    ccm: Option<CCM>,
+    /// address resolver (resolvable private addresses are resolved in software without it)
+    aar: Option<AAR>,
+    /// times the packets received (connection events aren't timed without it, see enable_timer)
+    timer: Option<TIMER0>,
+    /// the duration (us) of the preamble and access address, which precede the ADDRESS event
+    address_us: u32,
     pub(crate) adv_a: link_layer::AdvA, // hw address
+    /// the radio's DISABLED event ends a transmission (rather than a reception, see handle_receive)
+    transmitting: AtomicBool,
+    /// IQ samples written by the DFE (see listen_cte)
+    #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+    iq: [u32; link_layer::IQ_SAMPLES_MAX],
+    /// AoA sampling with 2us (rather than 1us) switch and sample slots
+    #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+    aoa_slot_2us: bool,
 }
 
 
@@ -24,8 +55,10 @@ impl Nrf5xHci {
 
         // NOTE: the unsafe blocks are required per the PAC
         //      as they perform direct register access, their is no real concern.
+        let address_us;
         match mode {
             RadioMode::Ble1Mbit => {
+                address_us = 40;
                 radio.mode.write(|w| w.mode().ble_1mbit());
                 radio.pcnf0.write(|w| unsafe{ w
                     .s0len().set_bit()
@@ -35,6 +68,7 @@ impl Nrf5xHci {
                 });
             }
             RadioMode::Ble2Mbit => {
+                address_us = 24;
                 radio.mode.write(|w| w.mode().ble_2mbit());
                 radio.pcnf0.write(|w| unsafe{ w
                     .s0len().set_bit()
@@ -66,21 +100,50 @@ impl Nrf5xHci {
             .len().three());    // CRC32 (3 bytes)
         radio.crcpoly.write(|w| unsafe{ w.crcpoly().bits(link_layer::CRC_POLYNOMIAL) });
 
-        // configure interframe spacing per BLE spec
-        radio.tifs.write(|w| unsafe{ w.tifs().bits(link_layer::T_IFS_US) });
-
-        // TODO support encryption (CCM)
-        // TODO support privacy (AAR)
+        // configure interframe spacing per BLE spec (the width of TIFS differs between the PACs)
+        radio.tifs.write(|w| unsafe{ w.tifs().bits(link_layer::T_IFS_US as _) });
 
         // configure for maximum power
         radio.txpower.write(|w| w.txpower().pos4d_bm());
 
         Self{
             radio,
+            ccm: None,
+            aar: None,
+            timer: None,
+            address_us,
             adv_a : Self::get_address(ficr),
+            transmitting: AtomicBool::new(false),
+            #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+            iq: [0; link_layer::IQ_SAMPLES_MAX],
+            #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+            aoa_slot_2us: false,
         }
     }
 
+    /// encrypt links with the CCM peripheral (rather than in software)
+    pub fn enable_ccm(&mut self, ccm: CCM) {
+        ccm.enable.write(|w| w.enable().enabled());
+        self.ccm = Some(ccm);
+    }
+
+    /// resolve private addresses with the AAR peripheral (rather than in software)
+    pub fn enable_aar(&mut self, aar: AAR) {
+        self.aar = Some(aar);
+    }
+
+    /// time connection events with the TIMER0 peripheral (1MHz) and the pre-programmed PPI channels (the
+    /// ADDRESS event captures CC[1] and COMPARE[1] disables the radio), without it the central's packets are
+    /// awaited indefinitely (missed connection events and supervision timeouts aren't detected)
+    pub fn enable_timer(&mut self, timer: TIMER0) {
+        timer.bitmode.write(|w| w.bitmode()._32bit());
+        timer.prescaler.write(|w| unsafe{ w.prescaler().bits(4) });
+        timer.tasks_clear.write(|w| unsafe{ w.bits(1) });
+        timer.tasks_start.write(|w| unsafe{ w.bits(1) });
+        ppi::enable(ppi::RADIO_ADDRESS_TIMER0_CAPTURE1);
+        self.timer = Some(timer);
+    }
+
     /// get the hardware address
     fn get_address(ficr:FICR) -> link_layer::AdvA {
         let mut address:link_layer::Address = [0; 6];
@@ -88,8 +151,8 @@ impl Nrf5xHci {
         address[..2].copy_from_slice( &(ficr.deviceaddr[1].read().bits() as u16).to_be_bytes());
 
         return match ficr.deviceaddrtype.read().deviceaddrtype().variant() {
-            DEVICEADDRTYPE_A::PUBLIC => link_layer::AdvA::Public(address),
-            DEVICEADDRTYPE_A::RANDOM => link_layer::AdvA::RandomStatic(address),
+            DEVICEADDRTYPE_A::PUBLIC => link_layer::AdvA::public(address),
+            DEVICEADDRTYPE_A::RANDOM => link_layer::AdvA{ kind: link_layer::AddressKind::RandomStatic, bytes: address },
         }
     }
 
@@ -146,12 +209,53 @@ impl Nrf5xHci {
         ! self.radio.state.read().state().is_disabled()
     }
 
-    /// send a PDU (hardware takes care of preamble, access-address, and CRC)
+    /// send a PDU (hardware takes care of preamble, access-address, and CRC), busy-waiting for its transmission
+    /// (the application's own advertising, e.g. Ble::mesh_advertise, the stack's responses use respond)
     pub(crate) fn send(&self,
                         pdu:&[u8],
                         channel:link_layer::Channel,
                         access_address:link_layer::AccessAddress,
                         crcinit:link_layer::CrcInit) -> bool
+    {
+        return match self.transmit(pdu, channel, access_address, crcinit) {
+            Some(transmission) => { transmission.wait(); true }
+            None => false,
+        };
+    }
+
+    /// begin sending a PDU, the radio holds on to it until the returned transmission completes
+    /// (None if the radio is busy)
+    pub fn transmit<'a>(&'a self,
+                        pdu:&'a [u8],
+                        channel:link_layer::Channel,
+                        access_address:link_layer::AccessAddress,
+                        crcinit:link_layer::CrcInit) -> Option<Transmission<'a>>
+    {
+        // the DISABLED event only interrupts an awaited transmission (see Transmission::poll)
+        if ! self.start_transmission(pdu, channel, access_address, crcinit, false) {
+            return None;
+        }
+        return Some(Transmission { hci: self, _pdu: pdu });
+    }
+
+    /// begin sending a PDU from the stack's own buffer, which the radio reads until the DISABLED interrupt
+    /// ends the transmission (handle_receive returns false), returns false if the radio is busy
+    pub(crate) fn respond(&self,
+                          buffer:&link_layer::PduBuffer,
+                          length:usize,
+                          channel:link_layer::Channel,
+                          access_address:link_layer::AccessAddress,
+                          crcinit:link_layer::CrcInit) -> bool
+    {
+        return self.start_transmission(&buffer[..length], channel, access_address, crcinit, true);
+    }
+
+    fn start_transmission(&self,
+                          pdu:&[u8],
+                          channel:link_layer::Channel,
+                          access_address:link_layer::AccessAddress,
+                          crcinit:link_layer::CrcInit,
+                          interrupt:bool) -> bool
     {
         assert!(pdu.len() < link_layer::PDU_SIZE_MAX);
 
@@ -163,9 +267,6 @@ impl Nrf5xHci {
         // setup the radio channel
         self.set_channel(channel, access_address);
 
-        // TODO support encryption (CCM)
-        // TODO support privacy (AAR)
-
         // initialize the crc value
         self.radio.crcinit.write(|w| unsafe{ w.crcinit().bits(crcinit) });
         // set the hardware buffer
@@ -176,24 +277,49 @@ impl Nrf5xHci {
             .ready_start().enabled()
             .end_disable().enabled()
         );
+        if interrupt {
+            self.radio.intenset.write(|w| w.disabled().set());
+        } else {
+            self.radio.intenclr.write(|w| w.disabled().clear());
+        }
 
         // "Preceding reads and writes cannot be moved past subsequent writes."
         compiler_fence(Ordering::Release);
         // kick off the transmission
+        self.transmitting.store(true, Ordering::Release);
         self.radio.tasks_txen.write(|w| unsafe{ w.bits(1) });
 
-        // await send completion (required as the radio is holding onto the buffer data)
-        // TODO return a Future that holds on to the buffer (to allow work during transmission)
-        while ! self.radio.state.read().state().is_disabled() {}
-
-        return true
+        return true;
     }
 
     /// begin listening for a PDU
     pub fn listen(&self,
                   buffer:&mut link_layer::PduBuffer,
                   channel:link_layer::Channel,
-                  access_address:link_layer::AccessAddress) -> bool
+                  access_address:link_layer::AccessAddress,
+                  crcinit:link_layer::CrcInit) -> bool
+    {
+        return self.start_listening(buffer, channel, access_address, crcinit, None);
+    }
+
+    /// begin listening for a PDU which begins by the time (us, see timestamp_us), the radio is disabled
+    /// otherwise (see received)
+    pub fn listen_until(&self,
+                        buffer:&mut link_layer::PduBuffer,
+                        channel:link_layer::Channel,
+                        access_address:link_layer::AccessAddress,
+                        crcinit:link_layer::CrcInit,
+                        deadline_us:u32) -> bool
+    {
+        return self.start_listening(buffer, channel, access_address, crcinit, Some(deadline_us));
+    }
+
+    fn start_listening(&self,
+                       buffer:&mut link_layer::PduBuffer,
+                       channel:link_layer::Channel,
+                       access_address:link_layer::AccessAddress,
+                       crcinit:link_layer::CrcInit,
+                       deadline_us:Option<u32>) -> bool
     {
         // abort if the radio is busy
         if ! self.radio.state.read().state().is_disabled() {
@@ -202,6 +328,8 @@ impl Nrf5xHci {
 
         // setup the radio channel
         self.set_channel(channel, access_address);
+        // initialize the crc value
+        self.radio.crcinit.write(|w| unsafe{ w.crcinit().bits(crcinit) });
 
         // set the hardware buffer
         self.radio.packetptr.write(|w| unsafe{ w.bits(buffer.as_ptr() as u32) });
@@ -210,11 +338,28 @@ impl Nrf5xHci {
         self.radio.intenset.write(|w| w.disabled().set());
 
         // configure radio to automatically disable itself after reception
+        // and to sample the RSSI once the address matched (see rssi)
+        self.radio.events_rssiend.reset();
+        self.radio.events_address.reset();
         self.radio.shorts.write(|w| w
             .ready_start().enabled()
             .end_disable().enabled()
+            .address_rssistart().enabled()
+            .disabled_rssistop().enabled()
         );
 
+        // the radio is disabled unless the ADDRESS event (capturing CC[1]) precedes COMPARE[1]
+        if let (Some(timer), Some(deadline_us)) = (self.timer.as_ref(), deadline_us) {
+            let now_us = Self::now_us(timer);
+            let remaining_us = match deadline_us.wrapping_add(self.address_us + RX_MARGIN_US).wrapping_sub(now_us) {
+                // the deadline has passed
+                remaining_us if remaining_us > i32::MAX as u32 => 0,
+                remaining_us => remaining_us,
+            };
+            timer.cc[1].write(|w| unsafe{ w.bits(now_us.wrapping_add(remaining_us.max(RX_TIMEOUT_MIN_US))) });
+            ppi::enable(ppi::TIMER0_COMPARE1_RADIO_DISABLE);
+        }
+
         // "Preceding reads and writes cannot be moved past subsequent writes."
         compiler_fence(Ordering::Release);
 
@@ -224,6 +369,489 @@ impl Nrf5xHci {
         return true
     }
 
-    /// clear the interrupt flag
-    pub fn handle_receive(&self) { self.radio.events_disabled.reset(); }
-}
\ No newline at end of file
+    fn now_us(timer: &TIMER0) -> u32 {
+        timer.tasks_capture[0].write(|w| unsafe{ w.bits(1) });
+        return timer.cc[0].read().bits();
+    }
+
+    /// whether a packet was received (rather than the radio being disabled by the deadline of listen_until)
+    pub fn received(&self) -> bool {
+        return self.radio.events_address.read().bits() != 0;
+    }
+
+    /// the time (us, of the timer see enable_timer) the received packet began, 0 without the timer
+    pub fn timestamp_us(&self) -> u32 {
+        return match self.timer.as_ref() {
+            Some(timer) => timer.cc[1].read().bits().wrapping_sub(self.address_us),
+            None => 0,
+        };
+    }
+
+    /// whether the received packet passed the CRC check
+    pub fn crc_ok(&self) -> bool {
+        return self.radio.crcstatus.read().crcstatus().is_crcok();
+    }
+
+    /// the RSSI (dBm) of the received packet, None if it wasn't sampled
+    pub fn rssi(&self) -> Option<i8> {
+        if self.radio.events_rssiend.read().bits() == 0 {
+            return None;
+        }
+        // RSSISAMPLE is the magnitude of the (negative) dBm
+        return Some(-(self.radio.rssisample.read().rssisample().bits() as i8));
+    }
+
+    /// clear the interrupt flag, returns false if it ended a transmission (waking its task, see
+    /// Transmission, or a response, see respond) rather than a reception
+    pub fn handle_receive(&self) -> bool {
+        self.radio.events_disabled.reset();
+        ppi::disable(ppi::TIMER0_COMPARE1_RADIO_DISABLE);
+        #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+        self.disable_dfe();
+        if self.transmitting.swap(false, Ordering::AcqRel) {
+            TX_WAKER.wake();
+            return false;
+        }
+        return true;
+    }
+}
+
+/// a PDU being sent, the radio holds on to its buffer until the DISABLED event
+/// (await it or wait for it, the RADIO interrupt wakes the awaiting task via handle_receive), dropping
+/// it waits for the completion
+#[must_use]
+pub struct Transmission<'a> {
+    hci: &'a Nrf5xHci,
+    _pdu: &'a [u8],
+}
+impl Transmission<'_> {
+    pub fn is_complete(&self) -> bool {
+        return ! self.hci.is_busy();
+    }
+
+    /// busy-wait for the completion
+    pub fn wait(self) {
+        // see drop
+    }
+}
+impl Drop for Transmission<'_> {
+    /// EasyDMA reads the PDU until the DISABLED event, so the borrow can't end before it
+    fn drop(&mut self) {
+        while ! self.is_complete() {}
+        // completed without the interrupt (which would take the DISABLED event for a reception), it is
+        // masked before the completion is claimed
+        self.hci.radio.intenclr.write(|w| w.disabled().clear());
+        if self.hci.transmitting.swap(false, Ordering::AcqRel) {
+            self.hci.radio.events_disabled.reset();
+            pac::NVIC::unpend(pac::Interrupt::RADIO);
+        }
+    }
+}
+impl Future for Transmission<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_complete() {
+            return Poll::Ready(());
+        }
+        TX_WAKER.register(cx.waker());
+        // wake upon the DISABLED event
+        self.hci.radio.intenset.write(|w| w.disabled().set());
+        // completed before the waker was registered
+        if self.is_complete() {
+            return Poll::Ready(());
+        }
+        return Poll::Pending;
+    }
+}
+
+/// the waker of the awaited Transmission, the interrupt doesn't wake it while it's being registered
+/// (so Transmission::poll checks the completion after registering)
+struct TxWaker {
+    locked: AtomicBool,
+    waker: UnsafeCell<Option<Waker>>,
+}
+unsafe impl Sync for TxWaker {}
+impl TxWaker {
+    fn register(&self, waker: &Waker) {
+        if ! self.locked.swap(true, Ordering::Acquire) {
+            unsafe{ *self.waker.get() = Some(waker.clone()); }
+            self.locked.store(false, Ordering::Release);
+        }
+    }
+
+    fn wake(&self) {
+        if ! self.locked.swap(true, Ordering::Acquire) {
+            let waker = unsafe{ (*self.waker.get()).take() };
+            self.locked.store(false, Ordering::Release);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+static TX_WAKER:TxWaker = TxWaker { locked: AtomicBool::new(false), waker: UnsafeCell::new(None) };
+
+/// the antenna switching of direction finding
+pub struct DfeConfig<'a> {
+    /// the GPIO pins (P0) driving the antenna switch (DFEGPIO[0] - DFEGPIO[7])
+    pub gpio_pins: &'a [u8],
+    /// the GPIO states selecting the antennas, the first outside the CTE, the second during its guard and
+    /// reference periods and the rest in turn during its switch slots
+    pub pattern: &'a [u8],
+    /// 2us (rather than 1us) switch and sample slots when sampling AoA CTEs
+    pub aoa_slot_2us: bool,
+}
+
+/// the direction finding extension (DFE) of the nRF52833 and nRF52840, which switches antennas while
+/// sending AoD CTEs and samples the IQ data of received CTEs (switching antennas for AoA)
+impl Nrf5xHci {
+    /// CTEs are supported (see Ble::set_cte_response)
+    pub const DIRECTION_FINDING:bool = cfg!(any(feature="nrf52833", feature="nrf52840"));
+
+    /// configure the antenna switching (required for AoD transmission and AoA sampling)
+    #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+    pub fn configure_dfe(&mut self, config: &DfeConfig) {
+        for (index, pin) in config.gpio_pins.iter().take(dfe::GPIO_COUNT).enumerate() {
+            dfe::write(dfe::PSEL_DFEGPIO + 4 * index as u32, *pin as u32 & 0x1F);
+        }
+        dfe::write(dfe::CLEARPATTERN, 1);
+        for state in config.pattern {
+            dfe::write(dfe::SWITCHPATTERN, *state as u32);
+        }
+        self.aoa_slot_2us = config.aoa_slot_2us;
+    }
+
+    /// begin sending a data PDU (with the CP bit and CTEInfo) followed by its constant tone extension from the
+    /// stack's own buffer (see respond), the DFE is disabled once the transmission ends (see handle_receive)
+    pub(crate) fn respond_cte(&self,
+                              buffer:&link_layer::PduBuffer,
+                              length:usize,
+                              channel:link_layer::Channel,
+                              access_address:link_layer::AccessAddress,
+                              crcinit:link_layer::CrcInit,
+                              cte_info:link_layer::CteInfo) -> bool
+    {
+        #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+        {
+            if self.is_busy() { return false; }
+            // the CTEInfo octet follows the length (S1)
+            self.radio.pcnf0.modify(|_, w| unsafe{ w.s1len().bits(8) });
+            let (mode, spacing) = match cte_info.cte_type {
+                link_layer::CteType::AoA => (dfe::MODE_AOA, dfe::SPACING_4US),
+                link_layer::CteType::AoD1us => (dfe::MODE_AOD, dfe::SPACING_2US),
+                link_layer::CteType::AoD2us => (dfe::MODE_AOD, dfe::SPACING_4US),
+            };
+            dfe::write(dfe::DFECTRL1, dfe::ctrl1(cte_info.time, spacing));
+            dfe::write(dfe::DFEMODE, mode);
+        }
+        // no direction finding hardware (CTEs are never requested, see DIRECTION_FINDING)
+        #[cfg(not(any(feature="nrf52833", feature="nrf52840")))]
+        let _ = cte_info;
+        return self.respond(buffer, length, channel, access_address, crcinit);
+    }
+
+    /// begin listening for a data PDU (which begins by the time, see listen_until) sampling the IQ data of its
+    /// CTE of (at least) the requested length (see iq_samples), the CTEInfo of PDUs with the CP bit follows
+    /// their length (S1)
+    pub fn listen_cte(&self,
+                      buffer:&mut link_layer::PduBuffer,
+                      channel:link_layer::Channel,
+                      access_address:link_layer::AccessAddress,
+                      crcinit:link_layer::CrcInit,
+                      cte_info:link_layer::CteInfo,
+                      deadline_us:u32) -> bool
+    {
+        #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+        {
+            if self.is_busy() { return false; }
+            // parse the CP bit and CTEInfo (S1) of received PDUs
+            self.radio.pcnf0.modify(|_, w| w.s1incl().include());
+            dfe::write(dfe::CTEINLINECONF, dfe::CTE_INLINE_DATA_PDU);
+            let spacing = match (cte_info.cte_type, self.aoa_slot_2us) {
+                (link_layer::CteType::AoA, false) | (link_layer::CteType::AoD1us, _) => dfe::SPACING_2US,
+                _ => dfe::SPACING_4US,
+            };
+            dfe::write(dfe::DFECTRL1, dfe::ctrl1(cte_info.time, spacing));
+            dfe::write(dfe::DFEPACKET_PTR, self.iq.as_ptr() as u32);
+            dfe::write(dfe::DFEPACKET_MAXCNT, link_layer::IQ_SAMPLES_MAX as u32);
+            // AoA switches the antennas while receiving
+            dfe::write(dfe::DFEMODE, dfe::MODE_AOA);
+        }
+        #[cfg(not(any(feature="nrf52833", feature="nrf52840")))]
+        let _ = cte_info;
+        return self.listen_until(buffer, channel, access_address, crcinit, deadline_us);
+    }
+
+    /// the IQ samples of the CTE of the received PDU, returns their count
+    pub fn iq_samples(&self, samples: &mut [link_layer::IqSample]) -> usize {
+        #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+        {
+            // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+            compiler_fence(Ordering::Acquire);
+            let count = (dfe::read(dfe::DFEPACKET_AMOUNT) as usize).min(self.iq.len()).min(samples.len());
+            for (sample, word) in samples.iter_mut().zip(self.iq[..count].iter()) {
+                // I in the low and Q in the high half word
+                *sample = link_layer::IqSample { i: *word as i16, q: (*word >> 16) as i16 };
+            }
+            return count;
+        }
+        #[cfg(not(any(feature="nrf52833", feature="nrf52840")))]
+        {
+            let _ = samples;
+            return 0;
+        }
+    }
+
+    #[cfg(any(feature="nrf52833", feature="nrf52840"))]
+    fn disable_dfe(&self) {
+        dfe::write(dfe::DFEMODE, dfe::MODE_DISABLED);
+        dfe::write(dfe::CTEINLINECONF, 0);
+        self.radio.pcnf0.modify(|_, w| unsafe{ w.s1len().bits(0).s1incl().automatic() });
+    }
+}
+
+/// the packet's address ends this long (us) after the deadline of listen_until at the latest (the radio's
+/// timing is left to its jitter)
+const RX_MARGIN_US:u32 = 16;
+/// the radio listens at least this long (us), i.e. once the deadline has passed
+const RX_TIMEOUT_MIN_US:u32 = 50;
+
+/// the pre-programmed PPI channels timing the radio (see enable_timer)
+mod ppi {
+    use super::pac;
+
+    pub const TIMER0_COMPARE1_RADIO_DISABLE:u32 = 22;
+    pub const RADIO_ADDRESS_TIMER0_CAPTURE1:u32 = 26;
+
+    /// CHENSET and CHENCLR only affect the channel (the rest of the PPI is left to the application)
+    pub fn enable(channel: u32) {
+        unsafe{ (*pac::PPI::ptr()).chenset.write(|w| w.bits(1 << channel)) };
+    }
+
+    pub fn disable(channel: u32) {
+        unsafe{ (*pac::PPI::ptr()).chenclr.write(|w| w.bits(1 << channel)) };
+    }
+}
+
+/// the DFE registers of the RADIO (missing from the nRF52832 PAC)
+/// https://infocenter.nordicsemi.com/pdf/nRF52833_PS_v1.5.pdf (6.20.12 Direction finding)
+#[cfg(any(feature="nrf52833", feature="nrf52840"))]
+mod dfe {
+    use core::ptr::{write_volatile, read_volatile};
+
+    const RADIO:u32 = 0x4000_1000;
+    pub const DFEMODE:u32 = 0x900;
+    pub const CTEINLINECONF:u32 = 0x904;
+    pub const DFECTRL1:u32 = 0x910;
+    pub const SWITCHPATTERN:u32 = 0x928;
+    pub const CLEARPATTERN:u32 = 0x92C;
+    pub const PSEL_DFEGPIO:u32 = 0x930;
+    pub const DFEPACKET_PTR:u32 = 0x950;
+    pub const DFEPACKET_MAXCNT:u32 = 0x954;
+    pub const DFEPACKET_AMOUNT:u32 = 0x958;
+    pub const GPIO_COUNT:usize = 8;
+
+    pub const MODE_DISABLED:u32 = 0;
+    pub const MODE_AOD:u32 = 2;
+    pub const MODE_AOA:u32 = 3;
+
+    /// CTEINLINECTRLEN, CTEINFOINS1 (data PDUs), S0CONF and S0MASK of the CP bit
+    pub const CTE_INLINE_DATA_PDU:u32 = (1 << 0) | (1 << 3) | (0x20 << 16) | (0x20 << 24);
+
+    /// TSWITCHSPACING and TSAMPLESPACING values
+    pub const SPACING_4US:u32 = 1;
+    pub const SPACING_2US:u32 = 2;
+    /// TSAMPLESPACINGREF of 1us (8 reference samples)
+    const SPACING_REF_1US:u32 = 3;
+
+    /// NUMBEROF8US, DFEINEXTENSION (the CTE), the switch and sample spacing and IQ samples
+    pub fn ctrl1(time: u8, spacing: u32) -> u32 {
+        return (time as u32 & 0x3F) | (1 << 7) | (spacing << 8) | (SPACING_REF_1US << 12) | (spacing << 16);
+    }
+
+    pub fn write(offset: u32, value: u32) {
+        unsafe{ write_volatile((RADIO + offset) as *mut u32, value) };
+    }
+
+    pub fn read(offset: u32) -> u32 {
+        return unsafe{ read_volatile((RADIO + offset) as *const u32) };
+    }
+}
+
+/// largest packet processed by the CCM (header, length, S1 and a 27 octet payload with its MIC)
+const CCM_PACKET_SIZE:usize = 3 + 27 + encryption::MIC_SIZE;
+
+impl Nrf5xHci {
+    /// encrypt or decrypt a packet with the CCM peripheral (in place), returns the resulting length
+    fn ccm_crypt(ccm: &CCM, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize,
+                 decrypt: bool) -> Option<usize>
+    {
+        if length > (CCM_PACKET_SIZE - 3) {
+            return None;
+        }
+        // CCM data structure: key (MSB first), 39 bit packet counter, direction bit and IV
+        let mut cnf = [0_u8; 33];
+        cnf[..16].copy_from_slice(key);
+        cnf[16..21].copy_from_slice(&nonce[..5]);
+        cnf[20] &= 0x7F;
+        cnf[24] = nonce[4] >> 7;
+        cnf[25..].copy_from_slice(&nonce[5..]);
+        // packets are the header, length and S1 (RFU) followed by the payload
+        let mut input = [0_u8; CCM_PACKET_SIZE];
+        input[0] = header;
+        input[1] = length as u8;
+        input[3..(3 + length)].copy_from_slice(&payload[..length]);
+        let mut output = [0_u8; CCM_PACKET_SIZE];
+        let mut scratch = [0_u8; 43];
+
+        // the CCM shares its registers with the AAR
+        ccm.enable.write(|w| w.enable().enabled());
+        ccm.mode.write(|w| {
+            let w = if decrypt { w.mode().decryption() } else { w.mode().encryption() };
+            w.datarate()._1mbit().length().default()
+        });
+        ccm.cnfptr.write(|w| unsafe{ w.bits(cnf.as_ptr() as u32) });
+        ccm.inptr.write(|w| unsafe{ w.bits(input.as_ptr() as u32) });
+        ccm.outptr.write(|w| unsafe{ w.bits(output.as_mut_ptr() as u32) });
+        ccm.scratchptr.write(|w| unsafe{ w.bits(scratch.as_mut_ptr() as u32) });
+        ccm.events_endcrypt.reset();
+        ccm.events_error.reset();
+        ccm.shorts.write(|w| w.endksgen_crypt().enabled());
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+        ccm.tasks_ksgen.write(|w| unsafe{ w.bits(1) });
+        while ccm.events_endcrypt.read().bits() == 0 && ccm.events_error.read().bits() == 0 {}
+        compiler_fence(Ordering::Acquire);
+
+        if ccm.events_error.read().bits() != 0 || (decrypt && ccm.micstatus.read().micstatus().is_check_failed()) {
+            return None;
+        }
+        let length = output[1] as usize;
+        payload[..length].copy_from_slice(&output[3..(3 + length)]);
+        return Some(length);
+    }
+}
+impl encryption::Ccm for Nrf5xHci {
+    fn encrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> usize {
+        return match self.ccm.as_ref() {
+            Some(ccm) => match Self::ccm_crypt(ccm, key, nonce, header, payload, length, false) {
+                Some(length) => length,
+                None => encryption::SoftwareCcm.encrypt(key, nonce, header, payload, length),
+            }
+            None => encryption::SoftwareCcm.encrypt(key, nonce, header, payload, length),
+        };
+    }
+
+    fn decrypt(&mut self, key: &[u8; 16], nonce: &[u8; 13], header: u8, payload: &mut [u8], length: usize) -> Option<usize> {
+        return match self.ccm.as_ref() {
+            Some(ccm) => Self::ccm_crypt(ccm, key, nonce, header, payload, length, true),
+            None => encryption::SoftwareCcm.decrypt(key, nonce, header, payload, length),
+        };
+    }
+}
+
+/// flash page (the unit of erasure)
+const FLASH_PAGE_SIZE:u32 = 4096;
+
+/// bond storage in flash, a page per bond (reserve bond::BONDS_MAX pages at the base address, i.e. at
+/// the end of FLASH in memory.x)
+pub struct Nrf5xFlashStorage {
+    nvmc: NVMC,
+    base: u32,
+}
+impl Nrf5xFlashStorage {
+    pub fn new(nvmc: NVMC, base: u32) -> Self {
+        debug_assert!(base.is_multiple_of(FLASH_PAGE_SIZE), "bond storage must be page aligned");
+        Self { nvmc, base }
+    }
+
+    fn address(&self, slot: usize) -> u32 {
+        return self.base + (slot as u32) * FLASH_PAGE_SIZE;
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().is_busy() {}
+    }
+}
+impl bond::BondStorage for Nrf5xFlashStorage {
+    fn read(&mut self, slot: usize, record: &mut [u8; bond::RECORD_SIZE]) -> bool {
+        let address = self.address(slot) as *const u8;
+        for (index, byte) in record.iter_mut().enumerate() {
+            *byte = unsafe{ read_volatile(address.add(index)) };
+        }
+        // erased flash reads as ones
+        return record[..4] != [0xFF; 4];
+    }
+
+    fn write(&mut self, slot: usize, record: &[u8; bond::RECORD_SIZE]) {
+        // flash bits can only be cleared, the page is erased first
+        self.erase(slot);
+        let address = self.address(slot) as *mut u32;
+        self.nvmc.config.write(|w| w.wen().wen());
+        for (index, word) in record.chunks(4).enumerate() {
+            unsafe{ write_volatile(address.add(index), u32::from_le_bytes([word[0], word[1], word[2], word[3]])) };
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+    }
+
+    fn erase(&mut self, slot: usize) {
+        self.nvmc.config.write(|w| w.wen().een());
+        self.nvmc.erasepage().write(|w| unsafe{ w.bits(self.address(slot)) });
+        self.wait_ready();
+        self.nvmc.config.write(|w| w.wen().ren());
+    }
+}
+
+/// the most IRKs the AAR resolves against
+const AAR_IRKS_MAX:usize = 16;
+
+impl Nrf5xHci {
+    /// resolve an address with the AAR peripheral (None if unresolved)
+    fn aar_resolve(aar: &AAR, irks: &[[u8; 16]], address: &link_layer::Address) -> Option<usize> {
+        // IRKs are held MSB first
+        let mut keys = [[0_u8; 16]; AAR_IRKS_MAX];
+        for (key, irk) in keys.iter_mut().zip(irks.iter()) {
+            for (to, from) in key.iter_mut().zip(irk.iter().rev()) {
+                *to = *from;
+            }
+        }
+        // the address follows the header, length and S1 of the packet
+        let mut packet = [0_u8; 3 + 6];
+        packet[3..].copy_from_slice(address);
+        let mut scratch = [0_u8; 3];
+
+        aar.enable.write(|w| w.enable().enabled());
+        aar.nirk.write(|w| unsafe{ w.nirk().bits(irks.len() as u8) });
+        aar.irkptr.write(|w| unsafe{ w.bits(keys.as_ptr() as u32) });
+        aar.addrptr.write(|w| unsafe{ w.bits(packet.as_ptr() as u32) });
+        aar.scratchptr.write(|w| unsafe{ w.bits(scratch.as_mut_ptr() as u32) });
+        aar.events_end.reset();
+        aar.events_resolved.reset();
+        aar.events_notresolved.reset();
+
+        // "Preceding reads and writes cannot be moved past subsequent writes."
+        compiler_fence(Ordering::Release);
+        aar.tasks_start.write(|w| unsafe{ w.bits(1) });
+        while aar.events_end.read().bits() == 0 {}
+        compiler_fence(Ordering::Acquire);
+
+        let resolved = match aar.events_resolved.read().bits() {
+            0 => None,
+            _ => Some(aar.status.read().status().bits() as usize),
+        };
+        aar.enable.write(|w| w.enable().disabled());
+        return resolved;
+    }
+}
+impl privacy::AddressResolver for Nrf5xHci {
+    fn resolve(&mut self, irks: &[[u8; 16]], address: &link_layer::DeviceAddress) -> Option<usize> {
+        return match (self.aar.as_ref(), address.kind) {
+            (Some(aar), link_layer::AddressKind::ResolvablePrivate) if ! irks.is_empty() && irks.len() <= AAR_IRKS_MAX =>
+                Self::aar_resolve(aar, irks, &address.bytes),
+            _ => privacy::SoftwareResolver.resolve(irks, address),
+        };
+    }
+}
@@ -0,0 +1,192 @@
+use crate::link_layer::{DeviceAddress, AddressKind};
+use crate::smp;
+
+/// recommended lifetime of a resolvable private address (T_GAP(private_addr_int))
+/// Core_v5.3 Vol 3, Part C, Appendix A
+pub const RPA_TIMEOUT_MS:u32 = 15 * 60 * 1000;
+/// interval of the advertising events assumed to time the address rotation (see
+/// Ble::set_address_timeout)
+pub const ADV_INTERVAL_DEFAULT_MS:u32 = 1000;
+
+/// the hash of prand (both little endian, as sent) with the IRK (little endian)
+fn hash(irk: &[u8; 16], prand: &[u8; 3]) -> [u8; 3] {
+    let mut key = [0; 16];
+    for (to, from) in key.iter_mut().zip(irk.iter().rev()) {
+        *to = *from;
+    }
+    let hash = smp::ah(&key, &[prand[2], prand[1], prand[0]]);
+    return [hash[2], hash[1], hash[0]];
+}
+
+/// generate a resolvable private address from the IRK (little endian) and 3 random octets
+/// Core_v5.3 Vol 6, Part B, 1.3.2.2
+pub fn resolvable_private_address(irk: &[u8; 16], random: &[u8; 3]) -> DeviceAddress {
+    // the two most significant bits of prand are 0b01, the random part isn't all zeros or ones
+    let mut prand = *random;
+    prand[2] = (prand[2] & 0x3F) | 0x40;
+    match prand {
+        [0x00, 0x00, 0x40] => prand[0] = 0x01,
+        [0xFF, 0xFF, 0x7F] => prand[0] = 0xFE,
+        _ => {}
+    }
+    let hash = hash(irk, &prand);
+    return DeviceAddress {
+        kind: AddressKind::ResolvablePrivate,
+        bytes: [hash[0], hash[1], hash[2], prand[0], prand[1], prand[2]],
+    };
+}
+
+/// whether the address is a resolvable private address generated with the IRK (little endian)
+/// Core_v5.3 Vol 6, Part B, 1.3.2.3
+pub fn resolves(irk: &[u8; 16], address: &DeviceAddress) -> bool {
+    let bytes = &address.bytes;
+    return address.kind == AddressKind::ResolvablePrivate && hash(irk, &[bytes[3], bytes[4], bytes[5]]) == bytes[..3];
+}
+
+/// resolution of resolvable private addresses against a list of IRKs
+pub trait AddressResolver {
+    /// the index of the IRK (little endian) resolving the address (None if unresolved)
+    fn resolve(&mut self, irks: &[[u8; 16]], address: &DeviceAddress) -> Option<usize>;
+}
+
+/// address resolution in software (for hardware without an address resolver)
+pub struct SoftwareResolver;
+impl AddressResolver for SoftwareResolver {
+    fn resolve(&mut self, irks: &[[u8; 16]], address: &DeviceAddress) -> Option<usize> {
+        return irks.iter().position(|irk| resolves(irk, address));
+    }
+}
+
+/// the local resolvable private address, which replaces the identity address over the air and is
+/// replaced once it expires
+/// Core_v5.3 Vol 3, Part C, 10.7
+pub struct Privacy {
+    /// the local IRK (little endian)
+    irk: [u8; 16],
+    address: DeviceAddress,
+    /// key of the AES counter mode generating the random part of the addresses, so an address can
+    /// be replaced without awaiting a random source (it is reseeded after each rotation, see needs_seed)
+    seed: [u8; 16],
+    counter: u32,
+    reseed: bool,
+    timeout_ms: u32,
+    /// time since the address was generated
+    age_ms: u32,
+}
+impl Privacy {
+    /// generate the first address from the IRK (little endian) and 16 random octets
+    pub fn new(irk: [u8; 16], seed: [u8; 16]) -> Self {
+        let mut privacy = Self {
+            irk,
+            address: DeviceAddress { kind: AddressKind::ResolvablePrivate, bytes: [0; 6] },
+            seed,
+            counter: 0,
+            reseed: false,
+            timeout_ms: RPA_TIMEOUT_MS,
+            age_ms: 0,
+        };
+        let random = privacy.next_random();
+        privacy.rotate(&random);
+        return privacy;
+    }
+
+    pub fn irk(&self) -> &[u8; 16] { &self.irk }
+
+    /// the current resolvable private address
+    pub fn address(&self) -> &DeviceAddress { &self.address }
+
+    /// the lifetime of an address (RPA_TIMEOUT_MS by default)
+    pub fn set_timeout(&mut self, timeout_ms: u32) { self.timeout_ms = timeout_ms; }
+
+    /// true once an address was replaced, until fresh random octets are mixed in (see reseed)
+    pub fn needs_seed(&self) -> bool { self.reseed }
+
+    /// mix 16 random octets into the generator
+    pub fn reseed(&mut self, random: &[u8; 16]) {
+        for (to, from) in self.seed.iter_mut().zip(random.iter()) {
+            *to ^= *from;
+        }
+        self.reseed = false;
+    }
+
+    /// age the address, returns true if it expired and was replaced
+    pub fn elapse(&mut self, elapsed_ms: u32) -> bool {
+        self.age_ms = self.age_ms.saturating_add(elapsed_ms);
+        if self.age_ms < self.timeout_ms {
+            return false;
+        }
+        let random = self.next_random();
+        self.rotate(&random);
+        self.reseed = true;
+        return true;
+    }
+
+    /// generate a new address now
+    pub fn rotate(&mut self, random: &[u8; 3]) {
+        self.address = resolvable_private_address(&self.irk, random);
+        self.age_ms = 0;
+    }
+
+    fn next_random(&mut self) -> [u8; 3] {
+        self.counter = self.counter.wrapping_add(1);
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(&self.counter.to_le_bytes());
+        let random = smp::e(&self.seed, &block);
+        return [random[0], random[1], random[2]];
+    }
+}
+
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod resolvable_private_address {
+    use super::*;
+
+    // Core_v5.3 Vol 3, Part H, D.7 (little endian)
+    const IRK:[u8; 16] = [0x9B, 0x7D, 0x39, 0x0A, 0xA6, 0x10, 0x10, 0x34, 0x05, 0xAD, 0xC8, 0x57, 0xA3, 0x34, 0x02, 0xEC];
+
+    #[test]
+    fn resolvable_private_addresses() {
+        let address = resolvable_private_address(&IRK, &[0x94, 0x81, 0x70]);
+        assert_eq!(address, DeviceAddress::random([0xAA, 0xFB, 0x0D, 0x94, 0x81, 0x70]));
+        assert_eq!(address.kind, AddressKind::ResolvablePrivate);
+        assert!(address.is_valid());
+        assert!(resolves(&IRK, &address));
+        assert!(! resolves(&[0; 16], &address));
+        assert!(! resolves(&IRK, &DeviceAddress { kind: AddressKind::RandomStatic, ..address }));
+
+        let mut privacy = Privacy::new(IRK, [0; 16]);
+        assert!(resolves(&IRK, privacy.address()));
+        privacy.rotate(&[0; 3]);
+        assert_eq!(privacy.address().bytes[3..], [0x01, 0x00, 0x40]);
+        privacy.rotate(&[0x12, 0x34, 0xFF]);
+        assert_eq!(privacy.address().bytes[3..], [0x12, 0x34, 0x7F]);
+
+        let irks = [[0; 16], [1; 16], IRK];
+        assert_eq!(Some(2), SoftwareResolver.resolve(&irks, privacy.address()));
+        assert_eq!(None, SoftwareResolver.resolve(&irks[..2], privacy.address()));
+    }
+
+    #[test]
+    fn rotation() {
+        let mut privacy = Privacy::new(IRK, [0x5A; 16]);
+        privacy.set_timeout(1000);
+        let first = *privacy.address();
+        assert!(! privacy.elapse(999));
+        assert_eq!(first, *privacy.address());
+        assert!(! privacy.needs_seed());
+
+        assert!(privacy.elapse(1));
+        let second = *privacy.address();
+        assert_ne!(first, second);
+        assert!(resolves(&IRK, &second));
+        assert!(privacy.needs_seed());
+        privacy.reseed(&[0x11; 16]);
+        assert!(! privacy.needs_seed());
+        // expires again regardless of the seed
+        assert!(privacy.elapse(1000));
+        assert_ne!(second, *privacy.address());
+    }
+}
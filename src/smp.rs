@@ -0,0 +1,1634 @@
+use num_enum::{TryFromPrimitive};
+use core::convert::{TryFrom, TryInto};
+use aes::Aes128;
+use aes::cipher::{BlockEncrypt, KeyInit, generic_array::GenericArray};
+use cmac::{Cmac, Mac};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use crate::link_layer::DeviceAddress;
+
+/// largest SMP PDU (Pairing Public Key) - Core_v5.3 Vol 3, Part H, 3.2
+pub const SMP_MTU:usize = 65;
+/// a pairing procedure fails if a command isn't received within 30s
+/// Core_v5.3 Vol 3, Part H, 3.4
+pub const SMP_TIMEOUT_MS:u32 = 30_000;
+/// Core_v5.3 Vol 3, Part H, 2.3.4
+pub const ENCRYPTION_KEY_SIZE_MIN:u8 = 7;
+pub const ENCRYPTION_KEY_SIZE_MAX:u8 = 16;
+
+/// Core_v5.3 Vol 3, Part H, 3.3
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Code {
+    PairingRequest          = 0x01,
+    PairingResponse         = 0x02,
+    PairingConfirm          = 0x03,
+    PairingRandom           = 0x04,
+    PairingFailed           = 0x05,
+    EncryptionInformation   = 0x06,
+    CentralIdentification   = 0x07,
+    IdentityInformation     = 0x08,
+    IdentityAddressInformation = 0x09,
+    SigningInformation      = 0x0A,
+    SecurityRequest         = 0x0B,
+    PairingPublicKey        = 0x0C,
+    PairingDhKeyCheck       = 0x0D,
+    KeypressNotification    = 0x0E,
+}
+
+/// Core_v5.3 Vol 3, Part H, 3.5.1 (Table 3.4)
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum IoCapability {
+    DisplayOnly     = 0x00,
+    DisplayYesNo    = 0x01,
+    KeyboardOnly    = 0x02,
+    NoInputNoOutput = 0x03,
+    KeyboardDisplay = 0x04,
+}
+
+/// AuthReq flags - Core_v5.3 Vol 3, Part H, 3.5.1
+pub const AUTH_REQ_BONDING:u8   = 0x01;
+pub const AUTH_REQ_MITM:u8      = 0x04;
+pub const AUTH_REQ_SC:u8        = 0x08;
+pub const AUTH_REQ_KEYPRESS:u8  = 0x10;
+pub const AUTH_REQ_CT2:u8       = 0x20;
+
+/// key distribution flags - Core_v5.3 Vol 3, Part H, 3.6.1
+pub const KEY_DIST_ENC:u8       = 0x01;
+pub const KEY_DIST_ID:u8        = 0x02;
+pub const KEY_DIST_SIGN:u8      = 0x04;
+pub const KEY_DIST_LINK:u8      = 0x08;
+
+/// Pairing Failed reasons - Core_v5.3 Vol 3, Part H, 3.5.5
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Reason {
+    PasskeyEntryFailed          = 0x01,
+    OobNotAvailable             = 0x02,
+    AuthenticationRequirements  = 0x03,
+    ConfirmValueFailed          = 0x04,
+    PairingNotSupported         = 0x05,
+    EncryptionKeySize           = 0x06,
+    CommandNotSupported         = 0x07,
+    UnspecifiedReason           = 0x08,
+    RepeatedAttempts            = 0x09,
+    InvalidParameters           = 0x0A,
+    DhKeyCheckFailed            = 0x0B,
+    NumericComparisonFailed     = 0x0C,
+    BrEdrPairingInProgress      = 0x0D,
+    CrossTransportKeyDerivationNotAllowed = 0x0E,
+    KeyRejected                 = 0x0F,
+}
+
+/// association model - Core_v5.3 Vol 3, Part H, 2.3.5.1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Method {
+    JustWorks,
+    NumericComparison,
+    /// the passkey is displayed locally and entered on the peer
+    PasskeyDisplay,
+    /// the passkey is entered locally (displayed or entered on the peer)
+    PasskeyInput,
+    /// the TK (LE legacy pairing) or the OobData (LE Secure Connections) was exchanged out of band
+    OutOfBand,
+}
+impl Method {
+    /// select the association model as the responder (Core_v5.3 Vol 3, Part H, 2.3.5.1 Table 2.8)
+    pub fn select(initiator: IoCapability, responder: IoCapability, mitm: bool, secure_connections: bool) -> Method {
+        use IoCapability::*;
+        if ! mitm {
+            return Method::JustWorks;
+        }
+        return match (initiator, responder) {
+            (NoInputNoOutput, _) | (_, NoInputNoOutput) => Method::JustWorks,
+            (DisplayOnly, DisplayOnly) | (DisplayOnly, DisplayYesNo) | (DisplayYesNo, DisplayOnly) => Method::JustWorks,
+            // numeric comparison requires LE Secure Connections
+            (DisplayYesNo, DisplayYesNo) if ! secure_connections => Method::JustWorks,
+            (DisplayYesNo, KeyboardDisplay) | (KeyboardDisplay, KeyboardDisplay) if ! secure_connections => Method::PasskeyInput,
+            (KeyboardDisplay, DisplayYesNo) if ! secure_connections => Method::PasskeyDisplay,
+            (DisplayYesNo, DisplayYesNo) | (DisplayYesNo, KeyboardDisplay)
+            | (KeyboardDisplay, DisplayYesNo) | (KeyboardDisplay, KeyboardDisplay) => Method::NumericComparison,
+            (KeyboardOnly, DisplayOnly) | (KeyboardOnly, DisplayYesNo) | (KeyboardOnly, KeyboardDisplay)
+            | (KeyboardDisplay, DisplayOnly) => Method::PasskeyDisplay,
+            (_, KeyboardOnly) | (DisplayOnly, KeyboardDisplay) => Method::PasskeyInput,
+        };
+    }
+}
+
+/// local pairing capabilities and requirements
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub io_capability: IoCapability,
+    /// store the keys for future connections
+    pub bonding: bool,
+    /// require protection against man-in-the-middle attacks (refuses Just Works)
+    pub mitm: bool,
+    /// allow LE legacy pairing with centrals lacking LE Secure Connections (passively eavesdroppable)
+    pub allow_legacy: bool,
+    /// smallest acceptable encryption key size (ENCRYPTION_KEY_SIZE_MIN..=ENCRYPTION_KEY_SIZE_MAX)
+    pub key_size_min: u8,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            io_capability: IoCapability::NoInputNoOutput,
+            bonding: true,
+            mitm: false,
+            allow_legacy: false,
+            key_size_min: ENCRYPTION_KEY_SIZE_MAX,
+        }
+    }
+}
+
+/// the keys resulting from pairing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keys {
+    /// long term key (little endian, reduced to the key size)
+    pub ltk: [u8; 16],
+    pub key_size: u8,
+    /// identifies the LTK of LE legacy pairing (zero for LE Secure Connections)
+    pub ediv: u16,
+    pub rand: [u8; 8],
+    /// MITM protected (i.e. not Just Works)
+    pub authenticated: bool,
+    pub secure_connections: bool,
+    pub bonded: bool,
+    /// identity resolving key distributed by the peer (little endian)
+    pub peer_irk: Option<[u8; 16]>,
+    /// identity address distributed by the peer
+    pub peer_identity: Option<DeviceAddress>,
+    /// connection signature resolving key distributed by the peer (little endian)
+    pub peer_csrk: Option<[u8; 16]>,
+}
+
+/// LE Secure Connections OOB data - Core_v5.3 Vol 3, Part H, 2.3.5.6.4
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OobData {
+    /// random value r (MSB first)
+    pub random: [u8; 16],
+    /// confirm value C = f4(PKx, PKx, r, 0) (MSB first)
+    pub confirm: [u8; 16],
+}
+impl OobData {
+    /// the OOB data of AD or EIR structures (e.g. read from an NFC tag)
+    /// Core Specification Supplement, Part A, 1.6
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let (mut random, mut confirm) = (None, None);
+        for (ad_type, value) in crate::gap::ad_structures(data) {
+            match (ad_type, value.len()) {
+                (ad_type, 16) if ad_type == crate::gap::DataTypes::LeSecureConnectionsRandomValue as u8 => random = Some(swap::<16>(value)),
+                (ad_type, 16) if ad_type == crate::gap::DataTypes::LeSecureConnectionsConfirmValue as u8 => confirm = Some(swap::<16>(value)),
+                _ => {}
+            }
+        }
+        return Some(Self { random: random?, confirm: confirm? });
+    }
+}
+
+/// the local key pair and the OobData derived from it, which is passed to initiators out of band
+/// (see Ble::set_oob_data)
+#[derive(Copy, Clone)]
+pub struct LocalOob {
+    secret: [u8; 32],
+    data: OobData,
+}
+impl LocalOob {
+    /// generate a key pair and OobData from cryptographically secure random numbers
+    pub fn generate(random: &mut dyn FnMut(&mut [u8])) -> Self {
+        let (secret, public) = key_pair(random);
+        let mut r = [0; 16];
+        random(&mut r);
+        let x:&[u8; 32] = public[..32].try_into().unwrap();
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&secret.to_bytes());
+        return Self { secret: bytes, data: OobData { random: r, confirm: f4(x, x, &r, 0) } };
+    }
+
+    pub fn data(&self) -> &OobData { &self.data }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Failure {
+    /// pairing was refused locally (sent in Pairing Failed)
+    Local(Reason),
+    /// pairing was refused by the peer
+    Peer(Reason),
+    /// the peer failed to respond within SMP_TIMEOUT_MS
+    Timeout,
+}
+
+/// the application's part in pairing, called from Ble::work
+pub trait PairingHandler {
+    /// fill with cryptographically secure random numbers (i.e. from a hardware RNG)
+    fn random(&mut self, bytes: &mut [u8]);
+
+    /// display the passkey (000000 to 999999) for the user to enter on the peer
+    fn display_passkey(&mut self, _passkey: u32) {}
+
+    /// the passkey the user entered, None to reply later (see Ble::passkey_reply)
+    fn passkey_request(&mut self) -> Option<u32> {
+        return None;
+    }
+
+    /// whether the user confirms both devices display the value (000000 to 999999),
+    /// None to reply later (see Ble::confirm_reply)
+    fn confirm_request(&mut self, _value: u32) -> Option<bool> {
+        return None;
+    }
+
+    /// the TK (MSB first) exchanged out of band with the initiator for LE legacy pairing
+    fn legacy_oob_data(&mut self) -> Option<[u8; 16]> {
+        return None;
+    }
+
+    /// the OobData received out of band from the initiator for LE Secure Connections
+    fn oob_data(&mut self) -> Option<OobData> {
+        return None;
+    }
+
+    fn pairing_complete(&mut self, _keys: &Keys) {}
+
+    fn pairing_failed(&mut self, _failure: Failure) {}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// waiting for the initiator's public key
+    PublicKey,
+    /// waiting for the initiator's nonce (Just Works and Numeric Comparison)
+    Random,
+    /// waiting for the initiator's commitment of the passkey bit (round)
+    PasskeyConfirm(u8),
+    /// waiting for the initiator's nonce of the passkey bit (round)
+    PasskeyRandom(u8),
+    /// waiting for the initiator's DHKey check
+    DhKeyCheck,
+    /// waiting for the initiator's confirm value (LE legacy pairing)
+    LegacyConfirm,
+    /// waiting for the initiator's random value (LE legacy pairing)
+    LegacyRandom,
+    /// waiting for the link to be encrypted with the STK (or the LTK before key distribution)
+    Encrypting,
+    /// waiting for the keys of the initiator - Core_v5.3 Vol 3, Part H, 3.6.1
+    Distribution,
+    Complete,
+    /// no further pairing until reconnection (Core_v5.3 Vol 3, Part H, 3.4)
+    TimedOut,
+}
+
+/// passkey entry uses a bit per round - Core_v5.3 Vol 3, Part H, 2.3.5.6.3
+const PASSKEY_ROUNDS:u8 = 20;
+const PASSKEY_MAX:u32 = 1_000_000;
+
+/// the Security Manager (responder role) of a connection
+pub struct Smp {
+    config: Config,
+    state: State,
+    method: Method,
+    /// initiator (A) and responder (B) addresses for f5/f6 (type followed by the address, MSB first)
+    a: [u8; 7],
+    b: [u8; 7],
+    /// AuthReq, OOB data flag and IO capability of the initiator (A) and responder (B) for f6
+    io_cap_a: [u8; 3],
+    io_cap_b: [u8; 3],
+    key_size: u8,
+    bonding: bool,
+    secure_connections: bool,
+    /// keys distributed by the responder
+    key_dist: u8,
+    /// keys awaited from the initiator
+    peer_key_dist: u8,
+    /// the local IRK (little endian) and identity address distributed when bonding
+    identity: Option<([u8; 16], DeviceAddress)>,
+    peer_irk: Option<[u8; 16]>,
+    peer_identity: Option<DeviceAddress>,
+    peer_csrk: Option<[u8; 16]>,
+    /// the Pairing Request and Response (as sent) for c1
+    preq: [u8; 7],
+    pres: [u8; 7],
+    oob: [u8; 16],
+    /// the key pair of the OobData passed to initiators
+    local_oob: Option<LocalOob>,
+    /// OOB random values of the initiator (A) and responder (B) for f6 (zero if not exchanged)
+    ra: [u8; 16],
+    rb: [u8; 16],
+    stk: [u8; 16],
+    secret: Option<p256::SecretKey>,
+    /// public keys X followed by Y (MSB first) of the initiator (A) and responder (B)
+    pka: [u8; 64],
+    pkb: [u8; 64],
+    dhkey: [u8; 32],
+    na: [u8; 16],
+    nb: [u8; 16],
+    passkey: Option<u32>,
+    /// the initiator's commitment (passkey entry)
+    peer_confirm: Option<[u8; 16]>,
+    /// the user's approval (None while awaited)
+    confirmed: Option<bool>,
+    /// the initiator's DHKey check (held until the user approves)
+    peer_check: Option<[u8; 16]>,
+    mac_key: [u8; 16],
+    ltk: [u8; 16],
+    deadline: Option<u32>,
+    keys: Option<Keys>,
+    /// user input or a timeout awaiting Smp::resume
+    pending: bool,
+    failure: Option<Failure>,
+}
+
+impl Smp {
+    pub fn new(config: Config, initiator: &DeviceAddress, responder: &DeviceAddress) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+            method: Method::JustWorks,
+            a: Self::address(initiator),
+            b: Self::address(responder),
+            io_cap_a: [0; 3],
+            io_cap_b: [0; 3],
+            key_size: ENCRYPTION_KEY_SIZE_MAX,
+            bonding: false,
+            secure_connections: false,
+            key_dist: 0,
+            peer_key_dist: 0,
+            identity: None,
+            peer_irk: None,
+            peer_identity: None,
+            peer_csrk: None,
+            preq: [0; 7],
+            pres: [0; 7],
+            oob: [0; 16],
+            local_oob: None,
+            ra: [0; 16],
+            rb: [0; 16],
+            stk: [0; 16],
+            secret: None,
+            pka: [0; 64],
+            pkb: [0; 64],
+            dhkey: [0; 32],
+            na: [0; 16],
+            nb: [0; 16],
+            passkey: None,
+            peer_confirm: None,
+            confirmed: None,
+            peer_check: None,
+            mac_key: [0; 16],
+            ltk: [0; 16],
+            deadline: None,
+            keys: None,
+            pending: false,
+            failure: None,
+        }
+    }
+
+    /// distribute the local IRK (little endian) and identity address to bonding initiators (privacy)
+    pub fn distribute_identity(&mut self, irk: [u8; 16], identity: DeviceAddress) {
+        self.identity = Some((irk, identity));
+    }
+
+    /// pair out of band with initiators holding the OobData (LE Secure Connections)
+    pub fn set_local_oob(&mut self, local: LocalOob) {
+        self.local_oob = Some(local);
+    }
+
+    /// the keys of the completed pairing
+    pub fn keys(&self) -> Option<&Keys> {
+        if self.state == State::Distribution {
+            return None;
+        }
+        return self.keys.as_ref();
+    }
+
+    /// the key the central requests to encrypt the link with (by EDIV and Rand), the STK during
+    /// LE legacy pairing, otherwise the key of the completed pairing
+    pub fn long_term_key(&self, ediv: u16, rand: &[u8; 8]) -> Option<Keys> {
+        return match (self.state, self.keys) {
+            (State::Encrypting, _) if ediv == 0 && *rand == [0; 8] => Some(self.stk_keys()),
+            (_, Some(keys)) if keys.ediv == ediv && keys.rand == *rand => Some(keys),
+            _ => None,
+        };
+    }
+
+    fn stk_keys(&self) -> Keys {
+        return Keys {
+            ltk: self.stk,
+            key_size: self.key_size,
+            ediv: 0,
+            rand: [0; 8],
+            authenticated: self.method != Method::JustWorks,
+            secure_connections: self.secure_connections,
+            bonded: false,
+            peer_irk: None,
+            peer_identity: None,
+            peer_csrk: None,
+        };
+    }
+
+    /// the link was encrypted with the session key, completes pairing by distributing the keys
+    pub fn link_encrypted<H: PairingHandler>(&mut self, handler: &mut H, send: &mut dyn FnMut(&[u8])) {
+        if self.state != State::Encrypting {
+            return;
+        }
+        let mut keys = self.stk_keys();
+        // the link is encrypted with the LTK of LE Secure Connections
+        keys.bonded = self.secure_connections && self.bonding;
+        // Core_v5.3 Vol 3, Part H, 3.6.2 and 3.6.3
+        if self.key_dist & KEY_DIST_ENC != 0 {
+            handler.random(&mut keys.ltk);
+            Self::shorten(&mut keys.ltk, self.key_size);
+            let mut ediv = [0; 2];
+            handler.random(&mut ediv);
+            keys.ediv = u16::from_le_bytes(ediv);
+            handler.random(&mut keys.rand);
+            keys.bonded = true;
+
+            let mut pdu = [0; 17];
+            pdu[0] = Code::EncryptionInformation as u8;
+            pdu[1..].copy_from_slice(&keys.ltk);
+            send(&pdu);
+            let mut pdu = [0; 11];
+            pdu[0] = Code::CentralIdentification as u8;
+            pdu[1..3].copy_from_slice(&ediv);
+            pdu[3..].copy_from_slice(&keys.rand);
+            send(&pdu);
+        }
+        // Core_v5.3 Vol 3, Part H, 3.6.4 and 3.6.5
+        match (self.key_dist & KEY_DIST_ID != 0, self.identity) {
+            (true, Some((irk, identity))) => {
+                let mut pdu = [0; 17];
+                pdu[0] = Code::IdentityInformation as u8;
+                pdu[1..].copy_from_slice(&irk);
+                send(&pdu);
+                let mut pdu = [0; 8];
+                pdu[0] = Code::IdentityAddressInformation as u8;
+                pdu[1] = identity.is_random() as u8;
+                pdu[2..].copy_from_slice(&identity.bytes);
+                send(&pdu);
+                keys.bonded = true;
+            }
+            _ => {}
+        }
+        if self.peer_key_dist != 0 {
+            // the initiator distributes its keys next
+            self.stk = keys.ltk;
+            self.keys = Some(keys);
+            self.state = State::Distribution;
+            return;
+        }
+        self.complete(handler, keys);
+    }
+
+    pub fn is_pairing(&self) -> bool {
+        return ! matches!(self.state, State::Idle | State::Complete | State::TimedOut);
+    }
+
+    /// true if user input (or a timeout) awaits Smp::resume
+    pub fn has_work(&self) -> bool {
+        return self.pending;
+    }
+
+    /// Security Request PDU asking the initiator to pair - Core_v5.3 Vol 3, Part H, 3.6.7
+    pub fn security_request(&self) -> [u8; 2] {
+        return [Code::SecurityRequest as u8, self.auth_req()];
+    }
+
+    /// the passkey the user entered (None if cancelled)
+    pub fn passkey_reply(&mut self, passkey: Option<u32>) {
+        match passkey {
+            Some(passkey) => self.passkey = Some(passkey % PASSKEY_MAX),
+            None => self.confirmed = Some(false),
+        }
+        self.pending = true;
+    }
+
+    /// whether the user confirmed the numeric comparison
+    pub fn confirm_reply(&mut self, confirmed: bool) {
+        self.confirmed = Some(confirmed);
+        self.pending = true;
+    }
+
+    /// fail pairing if the initiator stalls (connection time in ms)
+    pub fn check_timeout(&mut self, now_ms: u32) {
+        match self.deadline {
+            Some(deadline) if now_ms >= deadline => {
+                self.state = State::TimedOut;
+                self.deadline = None;
+                self.failure = Some(Failure::Timeout);
+                self.pending = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// continue pairing after user input (or report a timeout)
+    pub fn resume<H: PairingHandler>(&mut self, handler: &mut H, send: &mut dyn FnMut(&[u8])) {
+        if ! self.pending {
+            return;
+        }
+        self.pending = false;
+        match self.failure.take() {
+            Some(failure) => return handler.pairing_failed(failure),
+            None => {}
+        }
+        let result = match self.confirmed {
+            Some(false) => Err(self.user_failure()),
+            _ => self.passkey_commit(handler, send)
+                     .and_then(|_| self.legacy_commit(handler, send))
+                     .and_then(|_| self.check(handler, send)),
+        };
+        self.finish(handler, result, send);
+    }
+
+    /// handle a received SMP PDU (connection time in ms), responses are passed to send
+    pub fn handle<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8], now_ms: u32,
+                                     send: &mut dyn FnMut(&[u8]))
+    {
+        if self.state == State::TimedOut {
+            return;
+        }
+        let code = match pdu.first().map(|code| Code::try_from(*code)) {
+            Some(Ok(code)) => code,
+            _ => return self.finish(handler, Err(Reason::CommandNotSupported), send),
+        };
+        self.deadline = Some(now_ms + SMP_TIMEOUT_MS);
+
+        let result = match code {
+            Code::PairingRequest => self.pairing_request(handler, pdu, send),
+            Code::PairingPublicKey => self.public_key(handler, pdu, send),
+            Code::PairingConfirm => self.pairing_confirm(handler, pdu, send),
+            Code::PairingRandom => self.pairing_random(handler, pdu, send),
+            Code::PairingDhKeyCheck => self.dhkey_check(handler, pdu, send),
+            Code::PairingFailed => {
+                self.state = State::Idle;
+                self.deadline = None;
+                let reason = pdu.get(1).and_then(|reason| Reason::try_from(*reason).ok());
+                handler.pairing_failed(Failure::Peer(reason.unwrap_or(Reason::UnspecifiedReason)));
+                Ok(())
+            }
+            Code::KeypressNotification => Ok(()),
+            Code::IdentityInformation | Code::IdentityAddressInformation => self.identity_information(handler, pdu),
+            Code::SigningInformation => self.signing_information(handler, pdu),
+            _ => Err(Reason::CommandNotSupported),
+        };
+        self.finish(handler, result, send);
+    }
+
+    /// Core_v5.3 Vol 3, Part H, 3.5.1
+    fn pairing_request<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8],
+                                          send: &mut dyn FnMut(&[u8])) -> Result<(), Reason>
+    {
+        if pdu.len() != 7 {
+            return Err(Reason::InvalidParameters);
+        }
+        let io_capability = IoCapability::try_from(pdu[1]).map_err(|_| Reason::InvalidParameters)?;
+        let auth_req = pdu[3];
+        let secure_connections = auth_req & AUTH_REQ_SC != 0;
+        if ! secure_connections && ! self.config.allow_legacy {
+            return Err(Reason::AuthenticationRequirements);
+        }
+        // Core_v5.3 Vol 3, Part H, 2.3.4
+        let key_size = pdu[4];
+        if !(ENCRYPTION_KEY_SIZE_MIN..=ENCRYPTION_KEY_SIZE_MAX).contains(&key_size) || key_size < self.config.key_size_min {
+            return Err(Reason::EncryptionKeySize);
+        }
+        let (oob, peer_oob) = match secure_connections {
+            true => (None, handler.oob_data()),
+            false => (handler.legacy_oob_data(), None),
+        };
+        // the initiator can't have our OobData if we never generated it
+        if secure_connections && pdu[2] != 0 && self.local_oob.is_none() {
+            return Err(Reason::OobNotAvailable);
+        }
+        let mitm = self.config.mitm || (auth_req & AUTH_REQ_MITM != 0);
+        let method = match (oob, peer_oob) {
+            (Some(_), _) if pdu[2] != 0 => Method::OutOfBand,
+            // LE Secure Connections uses OOB if either device has the OOB data of the other
+            (_, Some(_)) => Method::OutOfBand,
+            _ if secure_connections && pdu[2] != 0 => Method::OutOfBand,
+            _ => Method::select(io_capability, self.config.io_capability, mitm, secure_connections),
+        };
+        if self.config.mitm && method == Method::JustWorks {
+            return Err(Reason::AuthenticationRequirements);
+        }
+
+        // restart pairing
+        self.bonding = self.config.bonding && (auth_req & AUTH_REQ_BONDING != 0);
+        self.method = method;
+        self.key_size = key_size;
+        self.passkey = None;
+        self.peer_confirm = None;
+        self.peer_check = None;
+        self.keys = None;
+        self.secure_connections = secure_connections;
+        self.peer_irk = None;
+        self.peer_identity = None;
+        self.peer_csrk = None;
+        // Core_v5.3 Vol 3, Part H, 2.3.5.6.4
+        self.peer_confirm = peer_oob.map(|data| data.confirm);
+        self.ra = peer_oob.map_or([0; 16], |data| data.random);
+        self.rb = match (pdu[2] != 0, self.local_oob) {
+            (true, Some(local)) => local.data.random,
+            _ => [0; 16],
+        };
+        self.confirmed = match method {
+            Method::NumericComparison => None,
+            _ => Some(true),
+        };
+        // the LTK of LE Secure Connections is derived rather than distributed, the initiator's CSRK
+        // verifies its signed writes
+        let (key_dist, peer_key_dist) = match self.bonding {
+            true => ((if secure_connections { 0 } else { KEY_DIST_ENC })
+                     | (if self.identity.is_some() { KEY_DIST_ID } else { 0 }),
+                     KEY_DIST_ID | KEY_DIST_SIGN),
+            false => (0, 0),
+        };
+        self.key_dist = pdu[6] & key_dist;
+        self.peer_key_dist = pdu[5] & peer_key_dist;
+
+        let response = [Code::PairingResponse as u8,
+                        self.config.io_capability as u8,
+                        (oob.is_some() || peer_oob.is_some()) as u8,
+                        self.auth_req(),
+                        ENCRYPTION_KEY_SIZE_MAX,
+                        self.peer_key_dist,
+                        self.key_dist];
+        self.preq.copy_from_slice(pdu);
+        self.pres = response;
+        self.io_cap_a = [auth_req, pdu[2], pdu[1]];
+        self.io_cap_b = [response[3], response[2], response[1]];
+        send(&response);
+
+        if ! secure_connections {
+            // Core_v5.3 Vol 3, Part H, 2.3.5.2 - 2.3.5.4
+            match method {
+                Method::OutOfBand => self.oob = oob.unwrap(),
+                Method::PasskeyDisplay | Method::PasskeyInput => self.passkey_start(handler),
+                _ => {}
+            }
+            self.state = State::LegacyConfirm;
+            return Ok(());
+        }
+
+        // the OobData commits to the local public key, otherwise a key pair is generated for this pairing
+        // (Core_v5.3 Vol 3, Part H, 2.3.5.6.1)
+        let (secret, public) = match (method, self.local_oob) {
+            (Method::OutOfBand, Some(local)) => {
+                let secret = p256::SecretKey::from_slice(&local.secret).map_err(|_| Reason::UnspecifiedReason)?;
+                let public = public_key(&secret);
+                (secret, public)
+            }
+            _ => key_pair(&mut |bytes: &mut [u8]| handler.random(bytes)),
+        };
+        self.pkb = public;
+        self.secret = Some(secret);
+
+        self.state = State::PublicKey;
+        return Ok(());
+    }
+
+    /// display or request the passkey
+    fn passkey_start<H: PairingHandler>(&mut self, handler: &mut H) {
+        match self.method {
+            Method::PasskeyDisplay => {
+                let mut bytes = [0; 4];
+                handler.random(&mut bytes);
+                let passkey = u32::from_le_bytes(bytes) % PASSKEY_MAX;
+                handler.display_passkey(passkey);
+                self.passkey = Some(passkey);
+            }
+            Method::PasskeyInput => {
+                self.passkey = handler.passkey_request().map(|passkey| passkey % PASSKEY_MAX);
+            }
+            _ => {}
+        }
+    }
+
+    /// Core_v5.3 Vol 3, Part H, 3.5.6
+    fn public_key<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8],
+                                     send: &mut dyn FnMut(&[u8])) -> Result<(), Reason>
+    {
+        if self.state != State::PublicKey {
+            return Err(Reason::UnspecifiedReason);
+        }
+        if pdu.len() != SMP_MTU {
+            return Err(Reason::InvalidParameters);
+        }
+        self.pka[..32].copy_from_slice(&swap::<32>(&pdu[1..33]));
+        self.pka[32..].copy_from_slice(&swap::<32>(&pdu[33..65]));
+        // a reflected key would let the peer impersonate us
+        if self.pka == self.pkb {
+            return Err(Reason::DhKeyCheckFailed);
+        }
+        let mut sec1 = [0x04; 1 + 64];
+        sec1[1..].copy_from_slice(&self.pka);
+        let peer = p256::PublicKey::from_sec1_bytes(&sec1).map_err(|_| Reason::DhKeyCheckFailed)?;
+        let secret = self.secret.as_ref().ok_or(Reason::UnspecifiedReason)?;
+        let shared = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+        self.dhkey.copy_from_slice(shared.raw_secret_bytes());
+
+        let mut response = [0; SMP_MTU];
+        response[0] = Code::PairingPublicKey as u8;
+        response[1..33].copy_from_slice(&swap::<32>(&self.pkb[..32]));
+        response[33..65].copy_from_slice(&swap::<32>(&self.pkb[32..]));
+        send(&response);
+
+        match self.method {
+            // Core_v5.3 Vol 3, Part H, 2.3.5.6.2
+            Method::JustWorks | Method::NumericComparison => {
+                handler.random(&mut self.nb);
+                let confirm = f4(self.pkb[..32].try_into().unwrap(), self.pka[..32].try_into().unwrap(), &self.nb, 0);
+                send(&Self::value_pdu(Code::PairingConfirm, &confirm));
+                self.state = State::Random;
+            }
+            // Core_v5.3 Vol 3, Part H, 2.3.5.6.3
+            Method::PasskeyDisplay | Method::PasskeyInput => {
+                self.passkey_start(handler);
+                self.state = State::PasskeyConfirm(0);
+            }
+            // Core_v5.3 Vol 3, Part H, 2.3.5.6.4
+            Method::OutOfBand => {
+                let pkax:&[u8; 32] = self.pka[..32].try_into().unwrap();
+                match self.peer_confirm.take() {
+                    Some(ca) if f4(pkax, pkax, &self.ra, 0) != ca => return Err(Reason::ConfirmValueFailed),
+                    _ => {}
+                }
+                handler.random(&mut self.nb);
+                self.state = State::Random;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Core_v5.3 Vol 3, Part H, 3.5.3
+    fn pairing_confirm<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8],
+                                          send: &mut dyn FnMut(&[u8])) -> Result<(), Reason>
+    {
+        if pdu.len() != 17 {
+            return Err(Reason::InvalidParameters);
+        }
+        match self.state {
+            State::PasskeyConfirm(_) => {
+                self.peer_confirm = Some(swap::<16>(&pdu[1..]));
+                return self.passkey_commit(handler, send);
+            }
+            State::LegacyConfirm => {
+                self.peer_confirm = Some(swap::<16>(&pdu[1..]));
+                return self.legacy_commit(handler, send);
+            }
+            _ => return Err(Reason::UnspecifiedReason),
+        }
+    }
+
+    /// commit to the round's passkey bit once both the passkey and the initiator's commitment are known
+    fn passkey_commit<H: PairingHandler>(&mut self, handler: &mut H, send: &mut dyn FnMut(&[u8])) -> Result<(), Reason> {
+        match (self.state, self.passkey, self.peer_confirm) {
+            (State::PasskeyConfirm(round), Some(passkey), Some(_)) => {
+                handler.random(&mut self.nb);
+                let r = 0x80 | ((passkey >> round) & 1) as u8;
+                let confirm = f4(self.pkb[..32].try_into().unwrap(), self.pka[..32].try_into().unwrap(), &self.nb, r);
+                send(&Self::value_pdu(Code::PairingConfirm, &confirm));
+                self.state = State::PasskeyRandom(round);
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// the TK of LE legacy pairing (None until the passkey is entered)
+    fn tk(&self) -> Option<[u8; 16]> {
+        let mut tk = [0; 16];
+        match self.method {
+            Method::JustWorks | Method::NumericComparison => {}
+            Method::PasskeyDisplay | Method::PasskeyInput => tk[12..].copy_from_slice(&self.passkey?.to_be_bytes()),
+            Method::OutOfBand => tk = self.oob,
+        }
+        return Some(tk);
+    }
+
+    /// the confirm value of LE legacy pairing (Core_v5.3 Vol 3, Part H, 2.3.5.5)
+    fn legacy_confirm(&self, tk: &[u8; 16], rand: &[u8; 16]) -> [u8; 16] {
+        return c1(tk, rand, &self.preq, &self.pres, self.a[0], self.b[0],
+                  self.a[1..].try_into().unwrap(), self.b[1..].try_into().unwrap());
+    }
+
+    /// send Sconfirm once both the TK and Mconfirm are known
+    fn legacy_commit<H: PairingHandler>(&mut self, handler: &mut H, send: &mut dyn FnMut(&[u8])) -> Result<(), Reason> {
+        match (self.state, self.tk(), self.peer_confirm) {
+            (State::LegacyConfirm, Some(tk), Some(_)) => {
+                handler.random(&mut self.nb);
+                let confirm = self.legacy_confirm(&tk, &self.nb);
+                send(&Self::value_pdu(Code::PairingConfirm, &confirm));
+                self.state = State::LegacyRandom;
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// Core_v5.3 Vol 3, Part H, 3.5.4
+    fn pairing_random<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8],
+                                         send: &mut dyn FnMut(&[u8])) -> Result<(), Reason>
+    {
+        if pdu.len() != 17 {
+            return Err(Reason::InvalidParameters);
+        }
+        let nonce = swap::<16>(&pdu[1..]);
+        let pkax:&[u8; 32] = self.pka[..32].try_into().unwrap();
+        let pkbx:&[u8; 32] = self.pkb[..32].try_into().unwrap();
+        match self.state {
+            State::Random => {
+                self.na = nonce;
+                send(&Self::value_pdu(Code::PairingRandom, &self.nb));
+                if self.method == Method::NumericComparison {
+                    let value = g2(pkax, pkbx, &self.na, &self.nb) % PASSKEY_MAX;
+                    self.confirmed = handler.confirm_request(value);
+                }
+            }
+            State::PasskeyRandom(round) => {
+                let r = 0x80 | ((self.passkey.unwrap_or(0) >> round) & 1) as u8;
+                if Some(f4(pkax, pkbx, &nonce, r)) != self.peer_confirm {
+                    return Err(Reason::ConfirmValueFailed);
+                }
+                self.peer_confirm = None;
+                self.na = nonce;
+                send(&Self::value_pdu(Code::PairingRandom, &self.nb));
+                if round + 1 < PASSKEY_ROUNDS {
+                    self.state = State::PasskeyConfirm(round + 1);
+                    return Ok(());
+                }
+            }
+            State::LegacyRandom => {
+                let tk = self.tk().ok_or(Reason::UnspecifiedReason)?;
+                if Some(self.legacy_confirm(&tk, &nonce)) != self.peer_confirm {
+                    return Err(Reason::ConfirmValueFailed);
+                }
+                self.na = nonce;
+                send(&Self::value_pdu(Code::PairingRandom, &self.nb));
+                // Core_v5.3 Vol 3, Part H, 2.3.5.5
+                let mut stk = swap::<16>(&s1(&tk, &self.nb, &self.na));
+                Self::shorten(&mut stk, self.key_size);
+                self.stk = stk;
+                self.state = State::Encrypting;
+                return Ok(());
+            }
+            _ => return Err(Reason::UnspecifiedReason),
+        }
+        // Core_v5.3 Vol 3, Part H, 2.3.5.6.5
+        let (mac_key, ltk) = f5(&self.dhkey, &self.na, &self.nb, &self.a, &self.b);
+        self.mac_key = mac_key;
+        self.ltk = ltk;
+        self.state = State::DhKeyCheck;
+        return self.check(handler, send);
+    }
+
+    /// Core_v5.3 Vol 3, Part H, 3.5.7
+    fn dhkey_check<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8],
+                                      send: &mut dyn FnMut(&[u8])) -> Result<(), Reason>
+    {
+        if pdu.len() != 17 {
+            return Err(Reason::InvalidParameters);
+        }
+        if self.state != State::DhKeyCheck {
+            return Err(Reason::UnspecifiedReason);
+        }
+        self.peer_check = Some(swap::<16>(&pdu[1..]));
+        return self.check(handler, send);
+    }
+
+    /// verify the initiator's DHKey check once the user approves, completing pairing
+    fn check<H: PairingHandler>(&mut self, handler: &mut H, send: &mut dyn FnMut(&[u8])) -> Result<(), Reason> {
+        if self.state != State::DhKeyCheck {
+            return Ok(());
+        }
+        match self.confirmed {
+            Some(true) => {}
+            Some(false) => return Err(self.user_failure()),
+            None => return Ok(()),
+        }
+        let ea = match self.peer_check {
+            Some(ea) => ea,
+            None => return Ok(()),
+        };
+
+        let (mut ra, mut rb) = ([0; 16], [0; 16]);
+        match self.method {
+            Method::PasskeyDisplay | Method::PasskeyInput => {
+                ra[12..].copy_from_slice(&self.passkey.unwrap_or(0).to_be_bytes());
+                rb = ra;
+            }
+            Method::OutOfBand => (ra, rb) = (self.ra, self.rb),
+            _ => {}
+        }
+        if f6(&self.mac_key, &self.na, &self.nb, &rb, &self.io_cap_a, &self.a, &self.b) != ea {
+            return Err(Reason::DhKeyCheckFailed);
+        }
+        let eb = f6(&self.mac_key, &self.nb, &self.na, &ra, &self.io_cap_b, &self.b, &self.a);
+        send(&Self::value_pdu(Code::PairingDhKeyCheck, &eb));
+
+        let mut ltk = swap::<16>(&self.ltk);
+        Self::shorten(&mut ltk, self.key_size);
+        if (self.key_dist | self.peer_key_dist) != 0 {
+            // keys are distributed once the link is encrypted with the LTK
+            self.stk = ltk;
+            self.state = State::Encrypting;
+            return Ok(());
+        }
+        self.complete(handler, Keys {
+            ltk,
+            key_size: self.key_size,
+            ediv: 0,
+            rand: [0; 8],
+            authenticated: self.method != Method::JustWorks,
+            secure_connections: true,
+            bonded: self.bonding,
+            peer_irk: None,
+            peer_identity: None,
+            peer_csrk: None,
+        });
+        return Ok(());
+    }
+
+    /// the initiator's IRK and identity address - Core_v5.3 Vol 3, Part H, 3.6.4 and 3.6.5
+    fn identity_information<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8]) -> Result<(), Reason> {
+        if self.state != State::Distribution || self.peer_key_dist & KEY_DIST_ID == 0 {
+            return Err(Reason::UnspecifiedReason);
+        }
+        match (pdu[0], pdu.len()) {
+            (code, 17) if code == Code::IdentityInformation as u8 => {
+                let mut irk = [0; 16];
+                irk.copy_from_slice(&pdu[1..]);
+                self.peer_irk = Some(irk);
+                return Ok(());
+            }
+            (code, 8) if code == Code::IdentityAddressInformation as u8 && self.peer_irk.is_some() => {
+                let identity = DeviceAddress::read(pdu[1] != 0, &pdu[2..]);
+                if ! identity.is_identity() {
+                    return Err(Reason::InvalidParameters);
+                }
+                self.peer_identity = Some(identity);
+                self.peer_key_dist &= !KEY_DIST_ID;
+            }
+            _ => return Err(Reason::InvalidParameters),
+        }
+        return self.distributed(handler);
+    }
+
+    /// the initiator's CSRK - Core_v5.3 Vol 3, Part H, 3.6.6
+    fn signing_information<H: PairingHandler>(&mut self, handler: &mut H, pdu: &[u8]) -> Result<(), Reason> {
+        if self.state != State::Distribution || self.peer_key_dist & KEY_DIST_SIGN == 0 {
+            return Err(Reason::UnspecifiedReason);
+        }
+        if pdu.len() != 17 {
+            return Err(Reason::InvalidParameters);
+        }
+        let mut csrk = [0; 16];
+        csrk.copy_from_slice(&pdu[1..]);
+        self.peer_csrk = Some(csrk);
+        self.peer_key_dist &= !KEY_DIST_SIGN;
+        return self.distributed(handler);
+    }
+
+    /// completes pairing once the initiator has distributed all its keys
+    fn distributed<H: PairingHandler>(&mut self, handler: &mut H) -> Result<(), Reason> {
+        if self.peer_key_dist == 0 {
+            let mut keys = self.keys.ok_or(Reason::UnspecifiedReason)?;
+            keys.peer_irk = self.peer_irk;
+            keys.peer_identity = self.peer_identity;
+            keys.peer_csrk = self.peer_csrk;
+            keys.bonded = true;
+            self.complete(handler, keys);
+        }
+        return Ok(());
+    }
+
+    fn complete<H: PairingHandler>(&mut self, handler: &mut H, keys: Keys) {
+        self.keys = Some(keys);
+        self.secret = None;
+        self.state = State::Complete;
+        self.deadline = None;
+        handler.pairing_complete(&keys);
+    }
+
+    /// the key (little endian) is shortened to the negotiated size (Core_v5.3 Vol 3, Part H, 2.3.4)
+    fn shorten(key: &mut [u8; 16], key_size: u8) {
+        for byte in key[key_size as usize..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn finish<H: PairingHandler>(&mut self, handler: &mut H, result: Result<(), Reason>, send: &mut dyn FnMut(&[u8])) {
+        match result {
+            Ok(()) => {}
+            Err(reason) => {
+                send(&[Code::PairingFailed as u8, reason as u8]);
+                self.state = State::Idle;
+                self.deadline = None;
+                self.secret = None;
+                handler.pairing_failed(Failure::Local(reason));
+            }
+        }
+    }
+
+    fn user_failure(&self) -> Reason {
+        return match self.method {
+            Method::NumericComparison => Reason::NumericComparisonFailed,
+            _ => Reason::PasskeyEntryFailed,
+        };
+    }
+
+    fn auth_req(&self) -> u8 {
+        return (if self.config.bonding { AUTH_REQ_BONDING } else { 0 })
+               | (if self.config.mitm { AUTH_REQ_MITM } else { 0 })
+               | AUTH_REQ_SC;
+    }
+
+    /// a PDU carrying a 128-bit value (sent little endian)
+    fn value_pdu(code: Code, value: &[u8; 16]) -> [u8; 17] {
+        let mut pdu = [0; 17];
+        pdu[0] = code as u8;
+        pdu[1..].copy_from_slice(&swap::<16>(value));
+        return pdu;
+    }
+
+    /// the address as used by f5/f6 (Core_v5.3 Vol 3, Part H, 2.2.7)
+    fn address(address: &DeviceAddress) -> [u8; 7] {
+        let mut bytes = [0; 7];
+        bytes[0] = address.is_random() as u8;
+        // addresses are held as sent (little endian)
+        bytes[1..].copy_from_slice(&swap::<6>(&address.bytes));
+        return bytes;
+    }
+}
+
+/// generate a P-256 key pair, the public key is X followed by Y (MSB first)
+pub(crate) fn key_pair(random: &mut dyn FnMut(&mut [u8])) -> (p256::SecretKey, [u8; 64]) {
+    let secret = loop {
+        let mut bytes = [0; 32];
+        random(&mut bytes);
+        match p256::SecretKey::from_slice(&bytes) {
+            Ok(secret) => break secret,
+            Err(_) => {}
+        }
+    };
+    let public = public_key(&secret);
+    return (secret, public);
+}
+
+fn public_key(secret: &p256::SecretKey) -> [u8; 64] {
+    let public = secret.public_key().to_encoded_point(false);
+    let mut bytes = [0; 64];
+    bytes[..32].copy_from_slice(public.x().unwrap());
+    bytes[32..].copy_from_slice(public.y().unwrap());
+    return bytes;
+}
+
+/// reverse the octet order (on-air values are little endian, the crypto functions are big endian)
+fn swap<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut swapped = [0; N];
+    for (to, from) in swapped.iter_mut().zip(bytes.iter().rev()) {
+        *to = *from;
+    }
+    return swapped;
+}
+
+/// security function e (AES-128) - Core_v5.3 Vol 3, Part H, 2.2.1
+pub(crate) fn e(key: &[u8; 16], plaintext: &[u8; 16]) -> [u8; 16] {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut block = GenericArray::clone_from_slice(plaintext);
+    cipher.encrypt_block(&mut block);
+    let mut result = [0; 16];
+    result.copy_from_slice(&block);
+    return result;
+}
+
+pub(crate) fn aes_cmac(key: &[u8; 16], parts: &[&[u8]]) -> [u8; 16] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(key).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    let mut result = [0; 16];
+    result.copy_from_slice(&mac.finalize().into_bytes());
+    return result;
+}
+
+/// random address hash (prand and the hash are 24 bits) - Core_v5.3 Vol 3, Part H, 2.2.2
+pub fn ah(k: &[u8; 16], r: &[u8; 3]) -> [u8; 3] {
+    let mut padded = [0; 16];
+    padded[13..].copy_from_slice(r);
+    let value = e(k, &padded);
+    return [value[13], value[14], value[15]];
+}
+
+/// LE legacy pairing confirm value, preq and pres as sent - Core_v5.3 Vol 3, Part H, 2.2.3
+#[allow(clippy::too_many_arguments)]
+pub fn c1(k: &[u8; 16], r: &[u8; 16], preq: &[u8; 7], pres: &[u8; 7],
+          iat: u8, rat: u8, ia: &[u8; 6], ra: &[u8; 6]) -> [u8; 16]
+{
+    let mut p1 = [0; 16];
+    p1[..7].copy_from_slice(&swap::<7>(pres));
+    p1[7..14].copy_from_slice(&swap::<7>(preq));
+    p1[14] = rat;
+    p1[15] = iat;
+    let mut p2 = [0; 16];
+    p2[4..10].copy_from_slice(ia);
+    p2[10..].copy_from_slice(ra);
+
+    let mut value = [0; 16];
+    for (index, byte) in value.iter_mut().enumerate() {
+        *byte = r[index] ^ p1[index];
+    }
+    let mut value = e(k, &value);
+    for (index, byte) in value.iter_mut().enumerate() {
+        *byte ^= p2[index];
+    }
+    return e(k, &value);
+}
+
+/// LE legacy pairing STK generation - Core_v5.3 Vol 3, Part H, 2.2.4
+pub fn s1(k: &[u8; 16], r1: &[u8; 16], r2: &[u8; 16]) -> [u8; 16] {
+    let mut r = [0; 16];
+    r[..8].copy_from_slice(&r1[8..]);
+    r[8..].copy_from_slice(&r2[8..]);
+    return e(k, &r);
+}
+
+/// LE Secure Connections confirm value - Core_v5.3 Vol 3, Part H, 2.2.6
+pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
+    return aes_cmac(x, &[u, v, &[z]]);
+}
+
+/// LE Secure Connections key generation, returns (MacKey, LTK) - Core_v5.3 Vol 3, Part H, 2.2.7
+pub fn f5(w: &[u8; 32], n1: &[u8; 16], n2: &[u8; 16], a1: &[u8; 7], a2: &[u8; 7]) -> ([u8; 16], [u8; 16]) {
+    const SALT:[u8; 16] = [0x6C, 0x88, 0x83, 0x91, 0xAA, 0xF5, 0xA5, 0x38, 0x60, 0x37, 0x0B, 0xDB, 0x5A, 0x60, 0x83, 0xBE];
+    const KEY_ID:[u8; 4] = *b"btle";
+    const LENGTH:[u8; 2] = 256_u16.to_be_bytes();
+    let t = aes_cmac(&SALT, &[w]);
+    let mac_key = aes_cmac(&t, &[&[0], &KEY_ID, n1, n2, a1, a2, &LENGTH]);
+    let ltk = aes_cmac(&t, &[&[1], &KEY_ID, n1, n2, a1, a2, &LENGTH]);
+    return (mac_key, ltk);
+}
+
+/// LE Secure Connections check value - Core_v5.3 Vol 3, Part H, 2.2.8
+pub fn f6(w: &[u8; 16], n1: &[u8; 16], n2: &[u8; 16], r: &[u8; 16], io_cap: &[u8; 3], a1: &[u8; 7], a2: &[u8; 7]) -> [u8; 16] {
+    return aes_cmac(w, &[n1, n2, r, io_cap, a1, a2]);
+}
+
+/// LE Secure Connections numeric comparison value (use modulo 10^6) - Core_v5.3 Vol 3, Part H, 2.2.9
+pub fn g2(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], y: &[u8; 16]) -> u32 {
+    let value = aes_cmac(x, &[u, v, y]);
+    return u32::from_be_bytes([value[12], value[13], value[14], value[15]]);
+}
+
+
+// ------------------------TESTS--------------------------------------
+
+#[cfg(test)]
+mod security_manager {
+    use super::*;
+
+    fn hex<const N: usize>(text: &str) -> [u8; N] {
+        let mut bytes = [0; N];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * index..2 * index + 2], 16).unwrap();
+        }
+        return bytes;
+    }
+
+    // Core_v5.3 Vol 3, Part H, D.1 - D.5
+    const U:&str = "20b003d2f297be2c5e2c83a7e9f9a5b9eff49111acf4fddbcc0301480e359de6";
+    const V:&str = "55188b3d32f6bb9a900afcfbeed4e72a59cb9ac2f19d7cfb6b4fdd49f47fc5fd";
+    const X:&str = "d5cb8454d177733effffb2ec712baeab";
+
+    #[test]
+    fn crypto_functions() {
+        assert_eq!(f4(&hex(U), &hex(V), &hex(X), 0), hex::<16>("f2c916f107a9bd1cf1eda1bea974872d"));
+
+        let w = hex::<32>("ec0234a357c8ad05341010a60a397d9b99796b13b4f866f1868d34f373bfa698");
+        let n2 = hex::<16>("a6e8e7cc25a75f6e216583f7ff3dc4cf");
+        let a1 = hex::<7>("0056123737bfce");
+        let a2 = hex::<7>("00a713702dcfc1");
+        let (mac_key, ltk) = f5(&w, &hex(X), &n2, &a1, &a2);
+        assert_eq!(mac_key, hex::<16>("2965f176a1084a02fd3f6a20ce636e20"));
+        assert_eq!(ltk, hex::<16>("6986791169d7cd23980522b594750a38"));
+
+        let r = hex::<16>("12a3343bb453bb5408da42d20c2d0fc8");
+        assert_eq!(f6(&mac_key, &hex(X), &n2, &r, &[0x01, 0x01, 0x02], &a1, &a2),
+                   hex::<16>("e3c473989cd0e8c5d26c0b09da958f61"));
+
+        assert_eq!(g2(&hex(U), &hex(V), &hex(X), &n2), 0x2f9ed5ba);
+        assert_eq!(g2(&hex(U), &hex(V), &hex(X), &n2) % 1_000_000, 938554);
+    }
+
+    #[test]
+    fn legacy_crypto_functions() {
+        // Core_v5.3 Vol 3, Part H, 2.2.3 and 2.2.4
+        let preq = swap::<7>(&hex::<7>("07071000000101"));
+        let pres = swap::<7>(&hex::<7>("05000800000302"));
+        assert_eq!(c1(&[0; 16], &hex("5783D52156AD6F0E6388274EC6702EE0"), &preq, &pres,
+                      1, 0, &hex("A1A2A3A4A5A6"), &hex("B1B2B3B4B5B6")),
+                   hex::<16>("1e1e3fef878988ead2a74dc5bef13b86"));
+        assert_eq!(s1(&[0; 16], &hex("000F0E0D0C0B0A091122334455667788"), &hex("010203040506070899AABBCCDDEEFF00")),
+                   hex::<16>("9a1fe1f0e8b0f49b5b4216ae796da062"));
+        // Core_v5.3 Vol 3, Part H, D.7
+        assert_eq!(ah(&hex("ec0234a357c8ad05341010a60a397d9b"), &hex("708194")), hex::<3>("0dfbaa"));
+    }
+
+    #[test]
+    fn association_model() {
+        use IoCapability::*;
+        assert_eq!(Method::select(KeyboardDisplay, KeyboardDisplay, false, true), Method::JustWorks);
+        assert_eq!(Method::select(NoInputNoOutput, KeyboardDisplay, true, true), Method::JustWorks);
+        assert_eq!(Method::select(DisplayYesNo, DisplayYesNo, true, true), Method::NumericComparison);
+        assert_eq!(Method::select(KeyboardOnly, DisplayOnly, true, true), Method::PasskeyDisplay);
+        assert_eq!(Method::select(DisplayOnly, KeyboardOnly, true, true), Method::PasskeyInput);
+        assert_eq!(Method::select(KeyboardOnly, KeyboardOnly, true, true), Method::PasskeyInput);
+        // LE legacy pairing lacks numeric comparison
+        assert_eq!(Method::select(DisplayYesNo, DisplayYesNo, true, false), Method::JustWorks);
+        assert_eq!(Method::select(KeyboardDisplay, DisplayYesNo, true, false), Method::PasskeyDisplay);
+        assert_eq!(Method::select(KeyboardDisplay, KeyboardDisplay, true, false), Method::PasskeyInput);
+    }
+
+    /// deterministic "random" numbers and recorded user interaction
+    struct Device {
+        seed: u8,
+        passkey: Option<u32>,
+        displayed: Option<u32>,
+        compared: Option<u32>,
+        oob: Option<OobData>,
+        keys: Option<Keys>,
+        failure: Option<Failure>,
+    }
+    impl Device {
+        fn new(seed: u8) -> Self {
+            Self { seed, passkey: None, displayed: None, compared: None, oob: None, keys: None, failure: None }
+        }
+    }
+    impl PairingHandler for Device {
+        fn random(&mut self, bytes: &mut [u8]) {
+            for byte in bytes.iter_mut() {
+                self.seed = self.seed.wrapping_mul(75).wrapping_add(74);
+                *byte = self.seed;
+            }
+        }
+        fn display_passkey(&mut self, passkey: u32) { self.displayed = Some(passkey); }
+        fn passkey_request(&mut self) -> Option<u32> { self.passkey }
+        fn confirm_request(&mut self, value: u32) -> Option<bool> { self.compared = Some(value); None }
+        fn oob_data(&mut self) -> Option<OobData> { self.oob }
+        fn pairing_complete(&mut self, keys: &Keys) { self.keys = Some(*keys); }
+        fn pairing_failed(&mut self, failure: Failure) { self.failure = Some(failure); }
+    }
+
+    const INITIATOR:DeviceAddress = DeviceAddress { kind: crate::link_layer::AddressKind::RandomStatic, bytes: [0xCE, 0xBF, 0x37, 0x37, 0x12, 0x56] };
+    const RESPONDER:DeviceAddress = DeviceAddress::public([0xC1, 0xCF, 0x2D, 0x70, 0x13, 0xA7]);
+
+    /// the initiator's side of LE Secure Connections (Core_v5.3 Vol 3, Part H, 2.3.5.6)
+    struct Initiator {
+        secret: p256::SecretKey,
+        pka: [u8; 64],
+        io_cap: [u8; 3],
+    }
+    impl Initiator {
+        fn new() -> Self {
+            let secret = p256::SecretKey::from_slice(&[0x3F; 32]).unwrap();
+            let public = secret.public_key().to_encoded_point(false);
+            let mut pka = [0; 64];
+            pka[..32].copy_from_slice(public.x().unwrap());
+            pka[32..].copy_from_slice(public.y().unwrap());
+            Self { secret, pka, io_cap: [0; 3] }
+        }
+
+        fn pairing_request(&mut self, io_capability: IoCapability, auth_req: u8) -> [u8; 7] {
+            self.io_cap = [auth_req, 0, io_capability as u8];
+            return [0x01, io_capability as u8, 0, auth_req, 16, 0, 0];
+        }
+
+        fn public_key(&self) -> [u8; SMP_MTU] {
+            let mut pdu = [0; SMP_MTU];
+            pdu[0] = 0x0C;
+            pdu[1..33].copy_from_slice(&swap::<32>(&self.pka[..32]));
+            pdu[33..].copy_from_slice(&swap::<32>(&self.pka[32..]));
+            return pdu;
+        }
+    }
+
+    /// run the responder, collecting its PDUs
+    fn exchange(smp: &mut Smp, device: &mut Device, pdu: &[u8]) -> Vec<Vec<u8>> {
+        let mut sent = Vec::new();
+        smp.handle(device, pdu, 0, &mut |pdu: &[u8]| sent.push(pdu.to_vec()));
+        return sent;
+    }
+
+    /// exchange public keys, returning the responder's public key (X, Y) and the DHKey
+    fn exchange_keys(initiator: &Initiator, smp: &mut Smp, device: &mut Device) -> ([u8; 64], [u8; 32], Vec<Vec<u8>>) {
+        let sent = exchange(smp, device, &initiator.public_key());
+        assert_eq!(sent[0][0], 0x0C);
+        let mut pkb = [0; 64];
+        pkb[..32].copy_from_slice(&swap::<32>(&sent[0][1..33]));
+        pkb[32..].copy_from_slice(&swap::<32>(&sent[0][33..]));
+        let mut sec1 = [0x04; 65];
+        sec1[1..].copy_from_slice(&pkb);
+        let peer = p256::PublicKey::from_sec1_bytes(&sec1).unwrap();
+        let mut dhkey = [0; 32];
+        dhkey.copy_from_slice(p256::ecdh::diffie_hellman(initiator.secret.to_nonzero_scalar(), peer.as_affine()).raw_secret_bytes());
+        return (pkb, dhkey, sent[1..].to_vec());
+    }
+
+    /// complete the DHKey checks, returning the initiator's LTK (little endian)
+    #[allow(clippy::too_many_arguments)]
+    fn authenticate(initiator: &Initiator, smp: &mut Smp, device: &mut Device, pairing_response: &[u8],
+                    dhkey: &[u8; 32], na: &[u8; 16], nb: &[u8; 16], ra: &[u8; 16], rb: &[u8; 16]) -> [u8; 16]
+    {
+        let a = Smp::address(&INITIATOR);
+        let b = Smp::address(&RESPONDER);
+        let (mac_key, ltk) = f5(dhkey, na, nb, &a, &b);
+        let ea = f6(&mac_key, na, nb, rb, &initiator.io_cap, &a, &b);
+        let sent = exchange(smp, device, &Smp::value_pdu(Code::PairingDhKeyCheck, &ea));
+        let io_cap_b = [pairing_response[3], pairing_response[2], pairing_response[1]];
+        let eb = f6(&mac_key, nb, na, ra, &io_cap_b, &b, &a);
+        assert_eq!(sent, vec![Smp::value_pdu(Code::PairingDhKeyCheck, &eb).to_vec()]);
+        return swap::<16>(&ltk);
+    }
+
+    #[test]
+    fn just_works() {
+        let mut device = Device::new(1);
+        let mut smp = Smp::new(Config::default(), &INITIATOR, &RESPONDER);
+        let mut initiator = Initiator::new();
+
+        let request = initiator.pairing_request(IoCapability::KeyboardDisplay, AUTH_REQ_BONDING | AUTH_REQ_SC);
+        let response = exchange(&mut smp, &mut device, &request).remove(0);
+        assert_eq!(response, vec![0x02, 0x03, 0, AUTH_REQ_BONDING | AUTH_REQ_SC, 16, 0, 0]);
+
+        let (pkb, dhkey, sent) = exchange_keys(&initiator, &mut smp, &mut device);
+        // the responder commits to its nonce
+        let cb = swap::<16>(&sent[0][1..]);
+
+        let na = [0x5A; 16];
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+        let nb = swap::<16>(&sent[0][1..]);
+        assert_eq!(cb, f4(pkb[..32].try_into().unwrap(), initiator.pka[..32].try_into().unwrap(), &nb, 0));
+
+        let ltk = authenticate(&initiator, &mut smp, &mut device, &response, &dhkey, &na, &nb, &[0; 16], &[0; 16]);
+        let keys = device.keys.unwrap();
+        assert_eq!(keys.ltk, ltk);
+        assert!(! keys.authenticated);
+        assert!(keys.bonded);
+        assert_eq!(smp.keys(), Some(&keys));
+        assert!(! smp.is_pairing());
+    }
+
+    #[test]
+    fn identity_distribution() {
+        let mut device = Device::new(5);
+        let mut smp = Smp::new(Config::default(), &INITIATOR, &RESPONDER);
+        smp.distribute_identity([0x11; 16], RESPONDER);
+        let mut initiator = Initiator::new();
+
+        let mut request = initiator.pairing_request(IoCapability::NoInputNoOutput, AUTH_REQ_BONDING | AUTH_REQ_SC);
+        request[5] = KEY_DIST_ENC | KEY_DIST_ID | KEY_DIST_SIGN;
+        request[6] = KEY_DIST_ENC | KEY_DIST_ID;
+        let response = exchange(&mut smp, &mut device, &request).remove(0);
+        assert_eq!(response, vec![0x02, 0x03, 0, AUTH_REQ_BONDING | AUTH_REQ_SC, 16, KEY_DIST_ID | KEY_DIST_SIGN, KEY_DIST_ID]);
+
+        let (_, dhkey, sent) = exchange_keys(&initiator, &mut smp, &mut device);
+        assert_eq!(sent.len(), 1);
+        let na = [0x5A; 16];
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+        let nb = swap::<16>(&sent[0][1..]);
+        let ltk = authenticate(&initiator, &mut smp, &mut device, &response, &dhkey, &na, &nb, &[0; 16], &[0; 16]);
+
+        // keys are distributed over the link encrypted with the LTK
+        assert!(device.keys.is_none() && smp.is_pairing());
+        assert_eq!(smp.long_term_key(0, &[0; 8]).map(|keys| keys.ltk), Some(ltk));
+        let mut sent = Vec::new();
+        smp.link_encrypted(&mut device, &mut |pdu: &[u8]| sent.push(pdu.to_vec()));
+        let mut identity = vec![0x09, 0x00];
+        identity.extend_from_slice(&RESPONDER.bytes);
+        assert_eq!(sent, vec![[vec![0x08], vec![0x11; 16]].concat(), identity]);
+        assert!(smp.keys().is_none());
+
+        // the initiator distributes its identity and then its CSRK
+        let peer_identity = DeviceAddress::random([1, 2, 3, 4, 5, 0xC6]);
+        assert!(exchange(&mut smp, &mut device, &[[0x08].as_ref(), &[0x22; 16]].concat()).is_empty());
+        assert!(exchange(&mut smp, &mut device, &[0x09, 0x01, 1, 2, 3, 4, 5, 0xC6]).is_empty());
+        assert!(device.keys.is_none() && smp.is_pairing());
+        assert!(exchange(&mut smp, &mut device, &[[0x0A].as_ref(), &[0x33; 16]].concat()).is_empty());
+        let keys = device.keys.unwrap();
+        assert_eq!(keys.ltk, ltk);
+        assert_eq!(keys.peer_irk, Some([0x22; 16]));
+        assert_eq!(keys.peer_identity, Some(peer_identity));
+        assert_eq!(keys.peer_csrk, Some([0x33; 16]));
+        assert!(keys.bonded && ! smp.is_pairing());
+
+        // the bond keeps the CSRK (to verify signed writes of reconnections)
+        let bond = crate::bond::Bond::new(peer_identity, &keys);
+        assert_eq!(bond.csrk, Some([0x33; 16]));
+        assert_eq!(bond.keys().peer_csrk, Some([0x33; 16]));
+    }
+
+    #[test]
+    fn numeric_comparison() {
+        let mut device = Device::new(2);
+        let config = Config { io_capability: IoCapability::DisplayYesNo, bonding: false, mitm: true, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+        let mut initiator = Initiator::new();
+
+        let request = initiator.pairing_request(IoCapability::DisplayYesNo, AUTH_REQ_MITM | AUTH_REQ_SC);
+        exchange(&mut smp, &mut device, &request);
+        let (pkb, dhkey, _) = exchange_keys(&initiator, &mut smp, &mut device);
+        let na = [0xA5; 16];
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+        let nb = swap::<16>(&sent[0][1..]);
+        let value = g2(initiator.pka[..32].try_into().unwrap(), pkb[..32].try_into().unwrap(), &na, &nb) % 1_000_000;
+        assert_eq!(device.compared, Some(value));
+
+        // the check is held until the user confirms
+        let a = Smp::address(&INITIATOR);
+        let b = Smp::address(&RESPONDER);
+        let (mac_key, _) = f5(&dhkey, &na, &nb, &a, &b);
+        let ea = f6(&mac_key, &na, &nb, &[0; 16], &initiator.io_cap, &a, &b);
+        assert!(exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingDhKeyCheck, &ea)).is_empty());
+
+        smp.confirm_reply(false);
+        assert!(smp.has_work());
+        let mut sent = Vec::new();
+        smp.resume(&mut device, &mut |pdu: &[u8]| sent.push(pdu.to_vec()));
+        assert_eq!(sent, vec![vec![0x05, Reason::NumericComparisonFailed as u8]]);
+        assert_eq!(device.failure, Some(Failure::Local(Reason::NumericComparisonFailed)));
+    }
+
+    #[test]
+    fn passkey_entry() {
+        let mut device = Device::new(3);
+        let config = Config { io_capability: IoCapability::DisplayOnly, bonding: true, mitm: true, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+        let mut initiator = Initiator::new();
+
+        let request = initiator.pairing_request(IoCapability::KeyboardOnly, AUTH_REQ_MITM | AUTH_REQ_SC);
+        let response = exchange(&mut smp, &mut device, &request).remove(0);
+        let (pkb, dhkey, sent) = exchange_keys(&initiator, &mut smp, &mut device);
+        assert!(sent.is_empty());
+        let passkey = device.displayed.unwrap();
+        assert!(passkey < 1_000_000);
+
+        let pkax:[u8; 32] = initiator.pka[..32].try_into().unwrap();
+        let pkbx:[u8; 32] = pkb[..32].try_into().unwrap();
+        let (mut na, mut nb) = ([0; 16], [0; 16]);
+        for round in 0..20 {
+            let r = 0x80 | ((passkey >> round) & 1) as u8;
+            na = [round as u8; 16];
+            let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingConfirm, &f4(&pkax, &pkbx, &na, r)));
+            let cb = swap::<16>(&sent[0][1..]);
+            let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+            nb = swap::<16>(&sent[0][1..]);
+            assert_eq!(cb, f4(&pkbx, &pkax, &nb, r));
+        }
+        let mut r = [0; 16];
+        r[12..].copy_from_slice(&passkey.to_be_bytes());
+        let ltk = authenticate(&initiator, &mut smp, &mut device, &response, &dhkey, &na, &nb, &r, &r);
+        assert_eq!(device.keys.unwrap().ltk, ltk);
+        assert!(device.keys.unwrap().authenticated);
+    }
+
+    #[test]
+    fn wrong_passkey() {
+        let mut device = Device::new(4);
+        let config = Config { io_capability: IoCapability::KeyboardOnly, bonding: true, mitm: true, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+        let mut initiator = Initiator::new();
+
+        let request = initiator.pairing_request(IoCapability::DisplayOnly, AUTH_REQ_MITM | AUTH_REQ_SC);
+        exchange(&mut smp, &mut device, &request);
+        let (pkb, _, _) = exchange_keys(&initiator, &mut smp, &mut device);
+        let pkax:[u8; 32] = initiator.pka[..32].try_into().unwrap();
+        let pkbx:[u8; 32] = pkb[..32].try_into().unwrap();
+
+        // the commitment is held until the user enters the passkey
+        let na = [0x11; 16];
+        let passkey:u32 = 123456;
+        let r = 0x80 | (passkey & 1) as u8;
+        assert!(exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingConfirm, &f4(&pkax, &pkbx, &na, r))).is_empty());
+        smp.passkey_reply(Some(passkey + 1));
+        let mut sent = Vec::new();
+        smp.resume(&mut device, &mut |pdu: &[u8]| sent.push(pdu.to_vec()));
+        assert_eq!(sent[0][0], Code::PairingConfirm as u8);
+
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+        assert_eq!(sent, vec![vec![0x05, Reason::ConfirmValueFailed as u8]]);
+        assert!(device.keys.is_none());
+    }
+
+    #[test]
+    fn out_of_band() {
+        let mut device = Device::new(7);
+        let local = LocalOob::generate(&mut |bytes: &mut [u8]| device.random(bytes));
+        let config = Config { mitm: true, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+        smp.set_local_oob(local);
+        let mut initiator = Initiator::new();
+        // both devices received the OobData of the other
+        let ra = [0x3C; 16];
+        let pkax:[u8; 32] = initiator.pka[..32].try_into().unwrap();
+        device.oob = Some(OobData { random: ra, confirm: f4(&pkax, &pkax, &ra, 0) });
+
+        let mut request = initiator.pairing_request(IoCapability::NoInputNoOutput, AUTH_REQ_SC);
+        request[2] = 1;
+        initiator.io_cap[1] = 1;
+        let response = exchange(&mut smp, &mut device, &request).remove(0);
+        assert_eq!(response[2], 1);
+        // the responder's public key is the one committed to by its OobData, there's no Pairing Confirm
+        let (pkb, dhkey, sent) = exchange_keys(&initiator, &mut smp, &mut device);
+        assert!(sent.is_empty());
+        let pkbx:[u8; 32] = pkb[..32].try_into().unwrap();
+        assert_eq!(local.data().confirm, f4(&pkbx, &pkbx, &local.data().random, 0));
+
+        let na = [0x5A; 16];
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &na));
+        let nb = swap::<16>(&sent[0][1..]);
+        let ltk = authenticate(&initiator, &mut smp, &mut device, &response, &dhkey, &na, &nb, &ra, &local.data().random);
+        let keys = device.keys.unwrap();
+        assert_eq!(keys.ltk, ltk);
+        assert!(keys.authenticated);
+
+        // the initiator's public key doesn't match its OobData
+        device.oob = Some(OobData { random: [0x3D; 16], ..device.oob.unwrap() });
+        exchange(&mut smp, &mut device, &request);
+        let sent = exchange(&mut smp, &mut device, &initiator.public_key());
+        assert_eq!(sent[1], vec![0x05, Reason::ConfirmValueFailed as u8]);
+
+        // the initiator has OobData that was never generated
+        let mut smp = Smp::new(Config::default(), &INITIATOR, &RESPONDER);
+        assert_eq!(exchange(&mut smp, &mut device, &request), vec![vec![0x05, Reason::OobNotAvailable as u8]]);
+    }
+
+    #[test]
+    fn legacy_just_works() {
+        let mut device = Device::new(6);
+        let config = Config { allow_legacy: true, key_size_min: 10, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+
+        let request = [0x01, 0x04, 0, AUTH_REQ_BONDING, 10, 0, KEY_DIST_ENC | KEY_DIST_ID];
+        let response = exchange(&mut smp, &mut device, &request).remove(0);
+        // only the LTK is distributed
+        assert_eq!(response, vec![0x02, 0x03, 0, AUTH_REQ_BONDING | AUTH_REQ_SC, 16, 0, KEY_DIST_ENC]);
+        let response:[u8; 7] = response.try_into().unwrap();
+
+        let a = Smp::address(&INITIATOR);
+        let b = Smp::address(&RESPONDER);
+        let confirm = |rand: &[u8; 16]| c1(&[0; 16], rand, &request, &response, a[0], b[0],
+                                           a[1..].try_into().unwrap(), b[1..].try_into().unwrap());
+        let mrand = [0x77; 16];
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingConfirm, &confirm(&mrand)));
+        let sconfirm = swap::<16>(&sent[0][1..]);
+        let sent = exchange(&mut smp, &mut device, &Smp::value_pdu(Code::PairingRandom, &mrand));
+        let srand = swap::<16>(&sent[0][1..]);
+        assert_eq!(sconfirm, confirm(&srand));
+
+        let mut stk = swap::<16>(&s1(&[0; 16], &srand, &mrand));
+        stk[10..].copy_from_slice(&[0; 6]);
+        assert_eq!(smp.long_term_key(0, &[0; 8]).map(|keys| keys.ltk), Some(stk));
+        assert!(device.keys.is_none());
+
+        // the keys are distributed over the encrypted link
+        let mut sent = Vec::new();
+        smp.link_encrypted(&mut device, &mut |pdu: &[u8]| sent.push(pdu.to_vec()));
+        let keys = device.keys.unwrap();
+        assert_eq!(sent[0][0], Code::EncryptionInformation as u8);
+        assert_eq!(sent[0][1..], keys.ltk);
+        assert_eq!(keys.ltk[10..], [0; 6]);
+        assert_eq!(sent[1][0], Code::CentralIdentification as u8);
+        assert_eq!(sent[1][1..3], keys.ediv.to_le_bytes());
+        assert_eq!(sent[1][3..], keys.rand);
+        assert!(keys.bonded && ! keys.secure_connections && ! keys.authenticated);
+    }
+
+    #[test]
+    fn requirements() {
+        let mut device = Device::new(5);
+        let config = Config { io_capability: IoCapability::DisplayYesNo, bonding: true, mitm: true, ..Config::default() };
+        let mut smp = Smp::new(config, &INITIATOR, &RESPONDER);
+
+        // MITM can't be provided to a NoInputNoOutput initiator
+        let sent = exchange(&mut smp, &mut device, &[0x01, 0x03, 0, AUTH_REQ_SC, 16, 0, 0]);
+        assert_eq!(sent, vec![vec![0x05, Reason::AuthenticationRequirements as u8]]);
+        // too short a key
+        let sent = exchange(&mut smp, &mut device, &[0x01, 0x01, 0, AUTH_REQ_SC, 6, 0, 0]);
+        assert_eq!(sent, vec![vec![0x05, Reason::EncryptionKeySize as u8]]);
+        let sent = exchange(&mut smp, &mut device, &[0x01, 0x01, 0, AUTH_REQ_SC, 15, 0, 0]);
+        assert_eq!(sent, vec![vec![0x05, Reason::EncryptionKeySize as u8]]);
+        // LE legacy pairing is forbidden by default
+        let sent = exchange(&mut smp, &mut device, &[0x01, 0x01, 0, AUTH_REQ_MITM, 16, 0, 0]);
+        assert_eq!(sent, vec![vec![0x05, Reason::AuthenticationRequirements as u8]]);
+
+        // the initiator must respond within 30s
+        exchange(&mut smp, &mut device, &[0x01, 0x01, 0, AUTH_REQ_SC, 16, 0, 0]);
+        assert!(smp.is_pairing());
+        smp.check_timeout(SMP_TIMEOUT_MS - 1);
+        assert!(! smp.has_work());
+        smp.check_timeout(SMP_TIMEOUT_MS);
+        smp.resume(&mut device, &mut |_: &[u8]| {});
+        assert_eq!(device.failure, Some(Failure::Timeout));
+        assert!(exchange(&mut smp, &mut device, &[0x01, 0x01, 0, AUTH_REQ_SC, 16, 0, 0]).is_empty());
+    }
+}